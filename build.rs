@@ -0,0 +1,9 @@
+fn main() -> Result<(), Box<dyn std::error::Error>>
+{
+    // Use a vendored protoc, so building does not need one installed
+    std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+    tonic_build::configure()
+        .build_client(false)
+        .compile_protos(&["proto/phoenix.proto"], &["proto"])?;
+    Ok(())
+}
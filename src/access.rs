@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+use std::hash::{BuildHasher, RandomState};
+use std::sync::Mutex;
+
+use rand::Rng;
+
+use crate::protocol::DbKey;
+
+/// The number of independently locked shards the tracker is split into.
+const SHARD_COUNT: usize = 16;
+
+/// On average only one access in `ACCESS_SAMPLE_RATE` updates the tracker.
+const ACCESS_SAMPLE_RATE: u32 = 8;
+
+/// The clock value given to a key when a sampled access is recorded.
+const CLOCK_MAX: u8 = u8::MAX;
+
+/// Approximate access-recency tracker.
+///
+/// Every tracked key has an 8-bit clock which is reset to `CLOCK_MAX` when an access is sampled and decremented
+/// by each aging pass, so a higher clock means a more recently used key. Keys whose clock reaches zero are
+/// dropped, which makes them indistinguishable from keys that were never accessed.
+///
+/// The tracker lives next to the database instead of inside it, so reads only take a short lock on one shard
+/// and never the database write lock.
+#[derive(Debug)]
+pub struct AccessTracker
+{
+    shards: Vec<Mutex<HashMap<DbKey, u8>>>,
+    hasher: RandomState,
+}
+
+impl Default for AccessTracker
+{
+    fn default() -> Self
+    {
+        Self {
+            shards: (0..SHARD_COUNT).map(|_| Mutex::new(HashMap::new())).collect(),
+            hasher: RandomState::new(),
+        }
+    }
+}
+
+impl AccessTracker
+{
+    /// Records an access to a key. Only a sample of the calls update the tracker to keep hot paths cheap.
+    pub fn record(&self, key: &str)
+    {
+        if rand::thread_rng().gen_ratio(1, ACCESS_SAMPLE_RATE) {
+            self.shard(key).lock().unwrap().insert(key.to_string(), CLOCK_MAX);
+        }
+    }
+
+    /// Stops tracking a key, for example after it has been deleted.
+    pub fn forget(&self, key: &str)
+    {
+        self.shard(key).lock().unwrap().remove(key);
+    }
+
+    /// Decrements the clock of every tracked key, dropping keys which reach zero.
+    pub fn age(&self)
+    {
+        for shard in &self.shards {
+            shard.lock().unwrap().retain(|_, clock| {
+                *clock -= 1;
+                *clock > 0
+            });
+        }
+    }
+
+    /// Returns up to `count` of the most recently used keys along with their clock, hottest first.
+    pub fn hottest(&self, count: usize) -> Vec<(DbKey, u8)>
+    {
+        let mut keys: Vec<(DbKey, u8)> = self
+            .shards
+            .iter()
+            .flat_map(|shard| {
+                let shard = shard.lock().unwrap();
+                shard.iter().map(|(key, clock)| (key.clone(), *clock)).collect::<Vec<_>>()
+            })
+            .collect();
+
+        keys.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        keys.truncate(count);
+        keys
+    }
+
+    fn shard(&self, key: &str) -> &Mutex<HashMap<DbKey, u8>>
+    {
+        &self.shards[self.hasher.hash_one(key) as usize % SHARD_COUNT]
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn test_sampled_access_tracking()
+    {
+        let tracker = AccessTracker::default();
+
+        // Enough accesses to make sure at least one of them is sampled
+        for _ in 0..1000 {
+            tracker.record("cold");
+        }
+        tracker.age();
+        for _ in 0..1000 {
+            tracker.record("hot");
+        }
+
+        let hottest = tracker.hottest(1);
+        assert_eq!(hottest, vec![("hot".to_string(), CLOCK_MAX)]);
+    }
+
+    #[test]
+    fn test_aging_drops_cold_keys()
+    {
+        let tracker = AccessTracker::default();
+        for _ in 0..1000 {
+            tracker.record("key");
+        }
+
+        tracker.age();
+        assert_eq!(tracker.hottest(1), vec![("key".to_string(), CLOCK_MAX - 1)]);
+
+        for _ in 0..CLOCK_MAX {
+            tracker.age();
+        }
+        assert!(tracker.hottest(1).is_empty());
+    }
+
+    #[test]
+    fn test_forget()
+    {
+        let tracker = AccessTracker::default();
+        for _ in 0..1000 {
+            tracker.record("key");
+        }
+
+        tracker.forget("key");
+        assert!(tracker.hottest(1).is_empty());
+    }
+}
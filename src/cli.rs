@@ -1,4 +1,9 @@
-use clap::Parser;
+use std::path::{Component, Path, PathBuf};
+
+use clap::{Parser, Subcommand, ValueEnum};
+
+use crate::frame::MAX_FRAME_LEN;
+use crate::services::ttl::ExpiryStream;
 
 /// Represents the command-line arguments for the server configuration
 #[derive(Parser, Debug, Clone)]
@@ -14,6 +19,54 @@ pub struct Cli
     #[arg(short = 'a', long, default_value = "127.0.0.1")]
     pub(crate) addr: String,
 
+    /// Allow other processes to listen on the same port, used to hand over to a new server without downtime
+    #[arg(long, default_value_t = false)]
+    pub(crate) reuse_port: bool,
+
+    /// Seconds to wait for open connections, HTTP requests and gRPC calls to end when shutting down
+    #[arg(long, default_value_t = 30)]
+    pub(crate) drain_timeout: u64,
+
+    /// Optional number of seconds a client may stay silent before its connection is closed, never by default
+    #[arg(long)]
+    pub(crate) idle_timeout: Option<u64>,
+
+    /// Optional PEM file holding the certificate chain to serve TLS with on `--port`, along with `--tls-key`
+    #[arg(long, requires = "tls_key")]
+    pub(crate) tls_cert: Option<PathBuf>,
+
+    /// Optional PEM file holding the private key of `--tls-cert`
+    #[arg(long, requires = "tls_cert")]
+    pub(crate) tls_key: Option<PathBuf>,
+
+    /// Optional port to also serve the HTTP API on
+    #[arg(long)]
+    pub(crate) http_port: Option<u16>,
+
+    /// Optional port to also serve the gRPC service of `proto/phoenix.proto` on
+    #[arg(long)]
+    pub(crate) grpc_port: Option<u16>,
+
+    /// Optional port to also serve the Redis protocol (RESP2) on, for Redis clients such as `redis-cli`
+    #[arg(long)]
+    pub(crate) resp_port: Option<u16>,
+
+    /// Send anonymous usage statistics to the `--telemetry-endpoint` once a day. Off unless given
+    #[arg(long, default_value_t = false)]
+    pub(crate) telemetry: bool,
+
+    /// Optional `http://` URL usage statistics are posted to when telemetry is enabled
+    #[arg(long)]
+    pub(crate) telemetry_endpoint: Option<String>,
+
+    /// Optional `host:port` of another server to read missing keys from and mirror writes to
+    #[arg(long)]
+    pub(crate) upstream: Option<String>,
+
+    /// Seconds the keys read through from the upstream server are kept before they are read from it again
+    #[arg(long, default_value_t = 300)]
+    pub(crate) upstream_ttl: u64,
+
     /// Optional username for authentication
     #[arg(short = 'u', long)]
     pub(crate) username: Option<String>,
@@ -26,7 +79,159 @@ pub struct Cli
     #[arg(short = 'd', long, default_value_t = false)]
     pub(crate) debug_mode: bool,
 
-    /// Log level (error, warn, info, debug, trace)
+    /// Maximum size in bytes of a single command sent to the server. Larger commands fail and close the connection
+    #[arg(long, default_value_t = MAX_FRAME_LEN)]
+    pub(crate) max_message_size: usize,
+
+    /// Maximum size in bytes of the values returned by a single response. Larger results are truncated
+    #[arg(long, default_value_t = 1024 * 1024)]
+    pub(crate) max_response_size: usize,
+
+    /// Experimental commands and protocol fields to enable, separated by commas
+    #[arg(long, value_delimiter = ',')]
+    pub(crate) experimental: Vec<String>,
+
+    /// The directory holding the files named by commands, such as the ones written by `EXPORT` or read by `IMPORT`
+    #[arg(long, default_value = ".")]
+    pub(crate) data_dir: PathBuf,
+
+    /// The file snapshots of the database are saved to
+    #[arg(long, default_value = "phoenix-db.snapshot")]
+    pub(crate) snapshot_path: PathBuf,
+
+    /// Optional file to record inserts and deletes to before applying them, replayed on start up after a crash
+    #[arg(long)]
+    pub(crate) wal_path: Option<PathBuf>,
+
+    /// Size in bytes the write-ahead log grows to before it is compacted in the background
+    #[arg(long, default_value_t = 64 * 1024 * 1024)]
+    pub(crate) wal_compact_size: u64,
+
+    /// How often the write-ahead log is synced to disk, trading write latency for durability
+    #[arg(long, value_enum, default_value_t = Durability::Everysec)]
+    pub(crate) durability: Durability,
+
+    /// Optional file holding the key snapshots and the write-ahead log are encrypted with, as 64 hex digits. The
+    /// key is read from the `PHOENIX_DB_ENCRYPTION_KEY` environment variable if unset
+    #[arg(long)]
+    pub(crate) encryption_key_file: Option<PathBuf>,
+
+    /// Optional estimated memory use in bytes at which writes are rejected, instead of evicting entries. Reads and
+    /// deletes are still served
+    #[arg(long)]
+    pub(crate) max_memory: Option<usize>,
+
+    /// Percentage of `--max-memory` the memory use has to fall below for writes to be accepted again
+    #[arg(long, default_value_t = 90, value_parser = clap::value_parser!(u8).range(1..=100))]
+    pub(crate) memory_resume_percent: u8,
+
+    /// Shortest number of seconds between TTL sweeps, used while many entries are expiring
+    #[arg(long, default_value_t = 1)]
+    pub(crate) ttl_sweep_min: u64,
+
+    /// Longest number of seconds between TTL sweeps, used while few entries are expiring
+    #[arg(long, default_value_t = 60)]
+    pub(crate) ttl_sweep_max: u64,
+
+    /// Keep the keys in order, enabling `RANGE` and ordered `SCAN`s at the cost of slower writes
+    #[arg(long, default_value_t = false)]
+    pub(crate) ordered_keys: bool,
+
+    /// Optional file shared with the other node of an HA pair, holding the lease of the node accepting writes
+    #[arg(long, requires = "node_id")]
+    pub(crate) lease_file: Option<PathBuf>,
+
+    /// The id this node holds the primary lease under, unique within its HA pair
+    #[arg(long)]
+    pub(crate) node_id: Option<String>,
+
+    /// Seconds the primary lease lasts unless renewed, the longest writes are unavailable after the primary fails
+    #[arg(long, default_value_t = 10)]
+    pub(crate) lease_ttl: u64,
+
+    /// Track when each key changed, enabling `BACKUP INCREMENTAL` at the cost of slower writes
+    #[arg(long, default_value_t = false)]
+    pub(crate) incremental_backups: bool,
+
+    /// Milliseconds a read command may run before it fails with a timeout, unlimited if unset
+    #[arg(long)]
+    pub(crate) read_timeout_ms: Option<u64>,
+
+    /// Milliseconds a write command may run before it fails with a timeout, unlimited if unset
+    #[arg(long)]
+    pub(crate) write_timeout_ms: Option<u64>,
+
+    /// Milliseconds a bulk command, such as `LOOKUP *` or `SCAN`, may run before it fails with a timeout, unlimited
+    /// if unset
+    #[arg(long)]
+    pub(crate) bulk_timeout_ms: Option<u64>,
+
+    /// Push the entries expiring under a key pattern to a stream, as `PATTERN=STREAM`. A pattern ending with `*`
+    /// matches every key starting with the rest of it. Can be repeated
+    #[arg(long)]
+    pub(crate) expiry_stream: Vec<ExpiryStream>,
+
+    /// Log level (error, warn, info, debug, trace), can be changed at runtime with `CONFIG SET log-level`
     #[arg(short = 'l', long, default_value = "info")]
     pub(crate) log_level: String,
+
+    /// A tool to run instead of the server
+    #[command(subcommand)]
+    pub(crate) tool: Option<Tool>,
+}
+
+impl Cli
+{
+    /// Resolves a file named by a client under `--data-dir`, so commands cannot reach files outside of it.
+    ///
+    /// # Returns
+    ///
+    /// The path of the file, or an error message if the name is absolute or steps out of the directory.
+    pub fn data_path(&self, name: &str) -> Result<PathBuf, String>
+    {
+        let path = Path::new(name);
+        let confined = path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+        if name.is_empty() || !confined {
+            return Err(format!(
+                "File '{}' must be a relative path inside the data directory, without '..'.",
+                name
+            ));
+        }
+        Ok(self.data_dir.join(path))
+    }
+}
+
+/// When writes recorded in the write-ahead log are synced to disk.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Durability
+{
+    /// Sync before every write is acknowledged, so no acknowledged write is lost.
+    Always,
+    /// Sync once per second, so up to a second of writes can be lost on a power failure.
+    Everysec,
+    /// Leave syncing to the operating system.
+    No,
+}
+
+/// Tools run from the command line instead of the server.
+#[derive(Subcommand, Debug, Clone)]
+pub enum Tool
+{
+    /// Compare the keys of two servers or snapshots, reporting added, removed and changed keys
+    Diff
+    {
+        /// The snapshot path or `host:port` of the server to compare from
+        #[arg(long)]
+        source: String,
+
+        /// The snapshot path or `host:port` of the server to compare to
+        #[arg(long)]
+        target: String,
+
+        /// Only compare the keys starting with this prefix
+        #[arg(long)]
+        prefix: Option<String>,
+    },
 }
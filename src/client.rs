@@ -0,0 +1,203 @@
+use futures::future::{BoxFuture, FutureExt};
+use serde_json::json;
+use tokio::net::TcpStream;
+
+use crate::commands::normalize_command_name;
+use crate::frame::{read_frame, write_frame, MAX_FRAME_LEN};
+use crate::protocol::{NetActions, NetCommand, NetResponse};
+
+/// A connection to another phoenix-db server, used to forward commands to it.
+///
+/// The connection is opened on the first request and kept for the following ones. If a request fails the
+/// connection is dropped and the next request opens a new one.
+///
+/// Each connection starts with a `HELLO` agreeing on the largest frame the server accepts. `INSERT *` commands
+/// larger than that are split into several smaller ones, other commands fail without being sent.
+#[derive(Debug)]
+pub struct Client
+{
+    /// The `host:port` of the server.
+    addr: String,
+    /// The open connection, if any.
+    stream: Option<TcpStream>,
+    /// The largest frame agreed on with the server of the open connection.
+    max_frame: usize,
+}
+
+impl Client
+{
+    /// Creates a client for the server at `addr`, without connecting yet.
+    pub fn new(addr: impl Into<String>) -> Self
+    {
+        Self {
+            addr: addr.into(),
+            stream: None,
+            max_frame: MAX_FRAME_LEN,
+        }
+    }
+
+    /// Sends a command and waits for its response.
+    ///
+    /// # Returns
+    ///
+    /// The response of the server, or a description of why no response was received. Responses with an
+    /// error action are returned as they are.
+    pub async fn request(&mut self, command: &NetCommand<'_>) -> Result<NetResponse, String>
+    {
+        let result = self.try_request(command).await;
+        if result.is_err() {
+            self.stream = None;
+        }
+        result
+    }
+
+    fn try_request<'a>(&'a mut self, command: &'a NetCommand<'_>) -> BoxFuture<'a, Result<NetResponse, String>>
+    {
+        async move {
+            self.connect().await?;
+            let payload = serde_json::to_vec(command).map_err(|e| e.to_string())?;
+            if payload.len() <= self.max_frame {
+                return self.exchange(&payload).await;
+            }
+
+            // Bulk inserts are sent in halves until each fits, stopping at the first error
+            let halves = match normalize_command_name(command.name).as_str() {
+                "INSERT *" => split_insert(command),
+                _ => None,
+            };
+            let Some((first, second)) = halves else {
+                return Err(format!(
+                    "the command of {} bytes is larger than the {} bytes the server accepts",
+                    payload.len(),
+                    self.max_frame
+                ));
+            };
+            let response = self.try_request(&first).await?;
+            if response.action == NetActions::Error {
+                return Ok(response);
+            }
+            self.try_request(&second).await
+        }
+        .boxed()
+    }
+
+    /// Opens the connection if there is none, agreeing on the largest frame with `HELLO`.
+    async fn connect(&mut self) -> Result<(), String>
+    {
+        if self.stream.is_some() {
+            return Ok(());
+        }
+        self.stream = Some(TcpStream::connect(&self.addr).await.map_err(|e| e.to_string())?);
+
+        let mut hello = crate::services::command("HELLO", vec![]);
+        hello.keys = None;
+        hello.args = Some(vec![json!({ "max_frame_size": MAX_FRAME_LEN })]);
+        let response = self.exchange(&serde_json::to_vec(&hello).map_err(|e| e.to_string())?).await?;
+
+        // Servers predating `HELLO` reply with an error and accept frames of up to `MAX_FRAME_LEN`
+        self.max_frame = response
+            .value
+            .and_then(|value| value["max_frame_size"].as_u64())
+            .map_or(MAX_FRAME_LEN, |max_frame| max_frame as usize);
+        Ok(())
+    }
+
+    /// Sends an encoded command on the open connection and reads its response.
+    async fn exchange(&mut self, payload: &[u8]) -> Result<NetResponse, String>
+    {
+        let stream = self.stream.as_mut().ok_or("not connected")?;
+        write_frame(stream, payload).await.map_err(|e| e.to_string())?;
+
+        let frame = read_frame(stream, MAX_FRAME_LEN)
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or("connection closed before a response was received")?;
+        let response: NetResponse = serde_json::from_slice(&frame).map_err(|e| e.to_string())?;
+        if response.action == NetActions::ShuttingDown {
+            return Err("the server is shutting down".to_string());
+        }
+        Ok(response)
+    }
+}
+
+/// Splits the entries of an `INSERT *` in two commands, or `None` if it holds a single entry.
+fn split_insert<'a>(command: &NetCommand<'a>) -> Option<(NetCommand<'a>, NetCommand<'a>)>
+{
+    let keys = command.keys.as_ref()?;
+    if keys.len() < 2 {
+        return None;
+    }
+    let mid = keys.len() / 2;
+
+    let half = |start: usize, end: usize| NetCommand {
+        name: command.name,
+        keys: Some(keys[start..end].to_vec()),
+        values: command
+            .values
+            .as_ref()
+            .map(|values| values[start.min(values.len())..end.min(values.len())].to_vec()),
+        ttls: command
+            .ttls
+            .as_ref()
+            .map(|ttls| ttls[start.min(ttls.len())..end.min(ttls.len())].to_vec()),
+        args: command.args.clone(),
+        tags: command.tags.clone(),
+        batch: None,
+        durability: command.durability,
+        fields: command.fields.clone(),
+        progress: command.progress,
+        id: command.id,
+    };
+    Some((half(0, mid), half(mid, keys.len())))
+}
+
+#[cfg(test)]
+mod test
+{
+    use std::sync::Arc;
+
+    use clap::Parser;
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::cli::Cli;
+    use crate::protocol::{DbEngine, DbValue};
+
+    #[tokio::test]
+    async fn test_split_oversized_inserts()
+    {
+        let engine = Arc::new(DbEngine::new(Cli::parse_from(["phoenix-db", "--max-message-size", "4096"])));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_engine = engine.clone();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            crate::services::tcp::execute(stream, server_engine).await
+        });
+
+        let keys: Vec<String> = (0..100).map(|n| format!("key:{}", n)).collect();
+        let mut insert = crate::services::command("INSERT *", keys.iter().map(String::as_str).collect());
+        insert.values = Some(
+            (0..100)
+                .map(|_| DbValue {
+                    value: json!("x".repeat(100)),
+                    expires_at: None,
+                })
+                .collect(),
+        );
+
+        let mut client = Client::new(addr.to_string());
+        let response = client.request(&insert).await.unwrap();
+        assert_eq!(response.action, NetActions::Command);
+        assert_eq!(client.max_frame, 4096);
+        assert_eq!(engine.connection.read().len(), 100);
+
+        // A single entry cannot be split
+        let mut insert = crate::services::command("INSERT", vec!["big"]);
+        insert.values = Some(vec![DbValue {
+            value: json!("x".repeat(5000)),
+            expires_at: None,
+        }]);
+        assert!(client.request(&insert).await.is_err());
+    }
+}
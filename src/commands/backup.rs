@@ -0,0 +1,344 @@
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use futures::future::{BoxFuture, FutureExt};
+use rand::seq::IteratorRandom;
+use serde_json::json;
+use tokio::sync::Mutex;
+
+use crate::commands::dump::{write_line, DumpLine};
+use crate::commands::namespace::ExportEntry;
+use crate::commands::CommandArgs;
+use crate::crypto::Cipher;
+use crate::protocol::{unix_millis, DbEngine, JsonValue, NetActions, NetResponse};
+use crate::snapshot;
+use crate::store::ChangeSet;
+
+/// The number of keys `BACKUP VERIFY` samples when none is given.
+const DEFAULT_VERIFY_SAMPLE: usize = 100;
+
+/// The incremental backups taken with `BACKUP INCREMENTAL`.
+#[derive(Debug, Default)]
+pub struct Backups
+{
+    /// The generation of the store the last backup is current as of, or `None` before the first backup.
+    ///
+    /// Held for the whole backup, so backups run one at a time.
+    last_generation: Mutex<Option<u64>>,
+}
+
+/// Writes the keys of `changes` to `path`, one JSON document per line, each sealed with `cipher` if given.
+///
+/// Keys still in the map are written as entries, the others as deleted keys.
+///
+/// # Returns
+///
+/// The number of entries and deleted keys written.
+fn write_backup(changes: &ChangeSet, path: &Path, cipher: Option<&Cipher>) -> io::Result<(usize, usize)>
+{
+    let temp_path = path.with_extension("tmp");
+    let (mut keys, mut deleted) = (0, 0);
+
+    let mut writer = BufWriter::new(File::create(&temp_path)?);
+    for key in &changes.keys {
+        let line = match changes.map.get(key) {
+            Some(data) => {
+                keys += 1;
+                DumpLine::Entry(ExportEntry {
+                    key: key.clone(),
+                    data: data.clone(),
+                })
+            }
+            None => {
+                deleted += 1;
+                DumpLine::Deleted {
+                    key: key.clone(),
+                    deleted: true,
+                }
+            }
+        };
+        write_line(&mut writer, &line, cipher)?;
+    }
+    writer.flush()?;
+    writer.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+
+    fs::rename(&temp_path, path)?;
+    Ok((keys, deleted))
+}
+
+/// Executes a `BACKUP INCREMENTAL` command, writing the keys changed since the last backup to a file.
+///
+/// The first backup holds every key. The following ones hold the keys inserted or deleted since the one before,
+/// so restoring means running `IMPORT` on the first backup and then on every following one, in order. Requires
+/// the server to run with `--incremental-backups`.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` holding the `[path]` argument, a path on the server.
+/// * `engine` - The database engine to back up.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with the number of keys written and
+/// deleted, and whether the backup was a `full` one.
+pub fn backup_incremental_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let path = match args {
+            CommandArgs::WithArgs(_, params) => match params.first().and_then(JsonValue::as_str) {
+                Some(path) => match engine.db_config.data_path(path) {
+                    Ok(path) => path,
+                    Err(message) => return Ok(backup_error(message)),
+                },
+                None => return Ok(backup_error("BACKUP INCREMENTAL requires a path.".to_string())),
+            },
+            _ => return Ok(backup_error("Invalid arguments for backup incremental.".to_string())),
+        };
+
+        let mut last_generation = engine.backups.last_generation.lock().await;
+        let Some(changes) = engine.connection.changes_since(last_generation.unwrap_or(0)).await else {
+            return Ok(backup_error(
+                "Incremental backups require the server to run with --incremental-backups.".to_string(),
+            ));
+        };
+
+        let full = last_generation.is_none();
+        let generation = changes.generation;
+        let cipher = engine.cipher.clone();
+        let backup = move || write_backup(&changes, &path, cipher.as_deref());
+        let (keys, deleted) = match tokio::task::spawn_blocking(backup).await {
+            Ok(Ok(written)) => written,
+            Ok(Err(e)) => return Ok(backup_error(format!("Failed to back up: {}", e))),
+            Err(e) => return Ok(backup_error(format!("Failed to back up: {}", e))),
+        };
+
+        *last_generation = Some(generation);
+        engine.connection.forget_deleted(generation).await;
+
+        Ok(NetResponse {
+            action: NetActions::Command,
+            value: Some(json!({ "keys": keys, "deleted": deleted, "full": full })),
+            error: None,
+            ..Default::default()
+        })
+    }
+    .boxed()
+}
+
+/// Executes a `BACKUP VERIFY` command, checking a snapshot against a random sample of the live keys.
+///
+/// Each sampled key that has not expired must be in the snapshot with the same value and expiry. Keys written
+/// since the snapshot was taken are reported as well, so the check is meant for snapshots taken while the
+/// database is quiet or just after a `SAVE`.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` holding the `[path, count]` arguments. `path` is a snapshot on the server,
+///   `--snapshot-path` by default, and `count` the number of keys to sample, 100 by default.
+/// * `engine` - The database engine holding the live keys.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with the number of keys `sampled`, the
+/// keys `missing` from the snapshot, the keys whose value is `different` in it, and whether the snapshot
+/// `matches`.
+pub fn backup_verify_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let params = match args {
+            CommandArgs::WithArgs(_, params) => params,
+            _ => vec![],
+        };
+        let path = match params.first().and_then(JsonValue::as_str) {
+            Some(path) => match engine.db_config.data_path(path) {
+                Ok(path) => path,
+                Err(message) => return Ok(backup_error(message)),
+            },
+            None => engine.db_config.snapshot_path.clone(),
+        };
+        let count = params
+            .get(1)
+            .and_then(JsonValue::as_u64)
+            .map_or(DEFAULT_VERIFY_SAMPLE, |count| count as usize);
+
+        let cipher = engine.cipher.clone();
+        let saved = match tokio::task::spawn_blocking(move || snapshot::read(&path, cipher.as_deref())).await {
+            Ok(Ok(map)) => map,
+            Ok(Err(e)) => return Ok(backup_error(format!("Failed to read the snapshot: {}", e))),
+            Err(e) => return Ok(backup_error(format!("Failed to read the snapshot: {}", e))),
+        };
+
+        let now = unix_millis();
+        let live = engine.connection.read();
+        let sample = live
+            .iter()
+            .filter(|(_, data)| !data.is_expired(now))
+            .choose_multiple(&mut rand::thread_rng(), count);
+
+        let (mut missing, mut different) = (vec![], vec![]);
+        for (key, data) in &sample {
+            match saved.get(*key) {
+                None => missing.push(key.to_string()),
+                Some(saved_data) if saved_data != *data => different.push(key.to_string()),
+                Some(_) => {}
+            }
+        }
+
+        Ok(NetResponse {
+            action: NetActions::Command,
+            value: Some(json!({
+                "sampled": sample.len(),
+                "missing": missing,
+                "different": different,
+                "matches": missing.is_empty() && different.is_empty(),
+            })),
+            error: None,
+            ..Default::default()
+        })
+    }
+    .boxed()
+}
+
+/// Builds an error response for the `BACKUP` commands.
+fn backup_error(message: String) -> NetResponse
+{
+    NetResponse {
+        action: NetActions::Error,
+        value: None,
+        error: Some(message),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use clap::Parser;
+
+    use super::*;
+    use crate::cli::Cli;
+    use crate::commands::dump::import_command;
+    use crate::protocol::DbValue;
+
+    #[tokio::test]
+    async fn test_backup_incremental()
+    {
+        let dir = std::env::temp_dir();
+        let full = format!("phoenix-db-backup-{}-0.jsonl", std::process::id());
+        let incremental = format!("phoenix-db-backup-{}-1.jsonl", std::process::id());
+        let engine = Arc::new(DbEngine::new(Cli::parse_from([
+            "phoenix-db",
+            "--incremental-backups",
+            "--data-dir",
+            dir.to_str().unwrap(),
+        ])));
+        let value = |n: i32| DbValue {
+            value: json!(n),
+            expires_at: None,
+        };
+
+        let mut db_write = engine.connection.write().await;
+        for key in ["a", "b", "c"] {
+            db_write.insert(key.to_string(), value(1));
+        }
+        drop(db_write);
+
+        let args = CommandArgs::WithArgs(None, vec![json!(full)]);
+        let response = backup_incremental_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.value, Some(json!({ "keys": 3, "deleted": 0, "full": true })));
+
+        let mut db_write = engine.connection.write().await;
+        db_write.insert("b".to_string(), value(2));
+        db_write.remove("c");
+        drop(db_write);
+
+        let args = CommandArgs::WithArgs(None, vec![json!(incremental)]);
+        let response = backup_incremental_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.value, Some(json!({ "keys": 1, "deleted": 1, "full": false })));
+
+        // Importing the backups in order restores the database
+        let restored = Arc::new(DbEngine::new(Cli::parse_from([
+            "phoenix-db",
+            "--data-dir",
+            dir.to_str().unwrap(),
+        ])));
+        for name in [&full, &incremental] {
+            let args = CommandArgs::WithArgs(None, vec![json!(name)]);
+            import_command(args, restored.clone()).await.unwrap();
+            fs::remove_file(dir.join(name)).unwrap();
+        }
+        assert_eq!(*restored.connection.read(), *engine.connection.read());
+    }
+
+    #[tokio::test]
+    async fn test_backup_verify()
+    {
+        let dir = std::env::temp_dir();
+        let name = format!("phoenix-db-verify-{}.snap", std::process::id());
+        let path = dir.join(&name);
+        let engine = Arc::new(DbEngine::new(Cli::parse_from([
+            "phoenix-db",
+            "--data-dir",
+            dir.to_str().unwrap(),
+        ])));
+        let value = |n: i32| DbValue {
+            value: json!(n),
+            expires_at: None,
+        };
+
+        let mut db_write = engine.connection.write().await;
+        for n in 0..10 {
+            db_write.insert(format!("key:{}", n), value(n));
+        }
+        drop(db_write);
+        snapshot::write(&engine.connection.read(), &path, None).unwrap();
+
+        let verify = |count: i32| {
+            let args = CommandArgs::WithArgs(None, vec![json!(name), json!(count)]);
+            backup_verify_command(args, engine.clone())
+        };
+        let response = verify(5).await.unwrap();
+        assert_eq!(
+            response.value,
+            Some(json!({ "sampled": 5, "missing": [], "different": [], "matches": true }))
+        );
+
+        // Sampling every key finds the ones changed since the snapshot
+        let mut db_write = engine.connection.write().await;
+        db_write.insert("key:3".to_string(), value(30));
+        db_write.insert("key:10".to_string(), value(10));
+        drop(db_write);
+        let response = verify(100).await.unwrap();
+        assert_eq!(
+            response.value,
+            Some(json!({ "sampled": 11, "missing": ["key:10"], "different": ["key:3"], "matches": false }))
+        );
+        fs::remove_file(&path).unwrap();
+
+        let response = verify(5).await.unwrap();
+        assert_eq!(response.action, NetActions::Error);
+
+        let args = CommandArgs::WithArgs(None, vec![json!("../phoenix-db.snapshot")]);
+        let response = backup_verify_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.action, NetActions::Error);
+    }
+
+    #[tokio::test]
+    async fn test_backup_incremental_requires_tracking()
+    {
+        let engine = Arc::new(DbEngine::new(Cli::parse_from(["phoenix-db"])));
+
+        let args = CommandArgs::WithArgs(None, vec![json!("backup.jsonl")]);
+        let response = backup_incremental_command(args, engine).await.unwrap();
+        assert_eq!(response.action, NetActions::Error);
+    }
+}
@@ -0,0 +1,70 @@
+use crate::protocol::{json_size, JsonValue};
+
+/// Tracks how much of the maximum response size is left while a command collects its results.
+///
+/// Commands returning many values take each one through `try_take` and stop as soon as it refuses, replying
+/// with `truncated` set and a cursor pointing at the first value left out.
+pub struct ResponseBudget
+{
+    /// The number of bytes that can still be added to the response.
+    remaining: usize,
+    /// Whether a value has been taken yet.
+    started: bool,
+}
+
+impl ResponseBudget
+{
+    /// Creates a budget allowing `limit` bytes of values.
+    pub fn new(limit: usize) -> Self
+    {
+        Self {
+            remaining: limit,
+            started: false,
+        }
+    }
+
+    /// Reserves room for a value, returning `false` if it does not fit in what is left.
+    ///
+    /// The first value is always accepted, even if it is larger than the whole budget, so that a client
+    /// following the cursor always makes progress.
+    pub fn try_take(&mut self, value: &JsonValue) -> bool
+    {
+        let size = json_size(value);
+
+        if self.started && size > self.remaining {
+            return false;
+        }
+
+        self.started = true;
+        self.remaining = self.remaining.saturating_sub(size);
+        true
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_budget_stops_once_full()
+    {
+        let mut budget = ResponseBudget::new(10);
+
+        assert!(budget.try_take(&json!("abc"))); // 5 bytes
+        assert!(budget.try_take(&json!("ab"))); // 4 bytes
+        assert!(!budget.try_take(&json!("ab")));
+        assert!(budget.try_take(&json!(1)));
+    }
+
+    #[test]
+    fn test_budget_always_takes_first_value()
+    {
+        let mut budget = ResponseBudget::new(1);
+
+        assert!(budget.try_take(&json!("a value larger than the budget")));
+        assert!(!budget.try_take(&json!(1)));
+    }
+}
@@ -0,0 +1,115 @@
+use std::io;
+
+use crate::commands::derived::refresh_derived;
+use crate::protocol::{DbEngine, DbKey};
+use crate::store::StoreWriteGuard;
+use crate::upstream::UpstreamWrite;
+use crate::wal::WalRecord;
+
+/// The keys written by [`commit`], whose dependents are brought up to date once the write lock is released.
+#[must_use = "the derived keys depending on the written keys are only refreshed by `Committed::refresh`"]
+#[derive(Debug, Default)]
+pub struct Committed
+{
+    /// The keys inserted or deleted.
+    keys: Vec<DbKey>,
+}
+
+impl Committed
+{
+    /// Returns the keys inserted or deleted.
+    pub fn keys(&self) -> &[DbKey]
+    {
+        &self.keys
+    }
+
+    /// Recomputes the derived keys depending on the written keys. Refreshing takes the write lock again, so it is
+    /// called once the lock of the commit is released.
+    pub async fn refresh(self, engine: &DbEngine)
+    {
+        refresh_derived(engine, &self.keys).await;
+    }
+}
+
+/// Records a batch of writes in the write-ahead log and applies them through `db_write`.
+///
+/// Every command writing to the database goes through here rather than straight to [`crate::wal::Wal::commit`],
+/// so whatever follows the written keys is kept up to date whichever command wrote them. Once applied, the tags of
+/// the written keys are removed and the writes are mirrored to the upstream server, if there is one.
+///
+/// # Returns
+///
+/// The written keys, to refresh once `db_write` is dropped. Nothing is applied or mirrored if the records cannot
+/// be appended to the log.
+pub async fn commit(engine: &DbEngine, db_write: &mut StoreWriteGuard<'_>, records: Vec<WalRecord>)
+    -> io::Result<Committed>
+{
+    commit_tagged(engine, db_write, records, Some(&[])).await
+}
+
+/// Same as [`commit`], replacing the tags of the inserted keys by `tags`, or keeping them if `tags` is `None`.
+/// Deleted keys always lose their tags.
+pub async fn commit_tagged(
+    engine: &DbEngine,
+    db_write: &mut StoreWriteGuard<'_>,
+    records: Vec<WalRecord>,
+    tags: Option<&[String]>,
+) -> io::Result<Committed>
+{
+    let Some(upstream) = &engine.upstream else {
+        return apply(engine, db_write, records, tags).await;
+    };
+
+    let writes: Vec<UpstreamWrite> = records
+        .iter()
+        .map(|record| match record {
+            WalRecord::Insert(key, value) => UpstreamWrite::Insert(key.clone(), value.clone()),
+            WalRecord::Delete(key) => UpstreamWrite::Delete(key.clone()),
+        })
+        .collect();
+    let committed = apply(engine, db_write, records, tags).await?;
+    // Mirrored under the write lock, so a read through the upstream server cannot overwrite the keys in between
+    for write in writes {
+        upstream.write_behind(write);
+    }
+    Ok(committed)
+}
+
+/// Same as [`commit`], without mirroring the writes to the upstream server.
+///
+/// Used for the writes the upstream server has no use for: values read through it, derived keys, whose
+/// definitions only exist on this server, and keys migrated away from this server.
+pub async fn commit_local(
+    engine: &DbEngine,
+    db_write: &mut StoreWriteGuard<'_>,
+    records: Vec<WalRecord>,
+) -> io::Result<Committed>
+{
+    apply(engine, db_write, records, Some(&[])).await
+}
+
+/// Commits the records and updates the tag index under the same write lock, so `BYTAG` commands never see the
+/// tags of a value that was replaced or deleted.
+async fn apply(
+    engine: &DbEngine,
+    db_write: &mut StoreWriteGuard<'_>,
+    records: Vec<WalRecord>,
+    tags: Option<&[String]>,
+) -> io::Result<Committed>
+{
+    let inserted: Vec<bool> = records.iter().map(|record| matches!(record, WalRecord::Insert(..))).collect();
+    let keys: Vec<DbKey> = records.iter().map(|record| record.key().clone()).collect();
+    engine.wal.commit(db_write, records).await?;
+
+    let mut index = engine.tags.write().await;
+    for (key, inserted) in keys.iter().zip(inserted) {
+        match tags {
+            Some(tags) if inserted => index.set_tags(key, tags),
+            None if inserted => {}
+            _ => index.remove_key(key),
+        }
+    }
+    drop(index);
+
+    Ok(Committed { keys })
+}
@@ -0,0 +1,118 @@
+use std::error::Error;
+use std::sync::Arc;
+
+use futures::future::{BoxFuture, FutureExt};
+use serde_json::json;
+
+use crate::commands::CommandArgs;
+use crate::protocol::{DbEngine, NetActions, NetResponse};
+use crate::wal;
+
+/// Executes a `COMPACT` command, rewriting the write-ahead log to only hold the live keys.
+///
+/// Other commands keep being served while the log is rewritten, only the client sending `COMPACT` waits.
+///
+/// # Arguments
+///
+/// * `_args` - Unused, `COMPACT` takes no arguments.
+/// * `engine` - The database engine holding the write-ahead log.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with the size of the log before and after
+/// it was compacted.
+pub fn compact_command(
+    _args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let response = match wal::compact(engine).await {
+            Ok(report) => NetResponse {
+                action: NetActions::Command,
+                value: Some(json!({ "size_before": report.size_before, "size_after": report.size_after })),
+                error: None,
+                ..Default::default()
+            },
+            Err(e) => NetResponse {
+                action: NetActions::Error,
+                value: None,
+                error: Some(format!("Failed to compact the write-ahead log: {}", e)),
+                ..Default::default()
+            },
+        };
+
+        Ok(response)
+    }
+    .boxed()
+}
+
+#[cfg(test)]
+mod test
+{
+    use clap::Parser;
+
+    use super::*;
+    use crate::cli::{Cli, Durability};
+    use crate::commands::delete::delete_command;
+    use crate::commands::insert::insert_command;
+    use crate::protocol::DbValue;
+    use crate::wal::{Wal, WalRecord};
+
+    #[tokio::test]
+    async fn test_compact()
+    {
+        let path = std::env::temp_dir().join(format!("phoenix-db-test-{}-compact.wal", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut engine = DbEngine::new(Cli::parse_from(["phoenix-db"]));
+        engine.wal = Wal::open(&path, Durability::No, None).unwrap();
+        let engine = Arc::new(engine);
+
+        for n in 0..10 {
+            let value = DbValue {
+                value: json!(n),
+                expires_at: None,
+            };
+            let args = CommandArgs::Single(Some(format!("key:{}", n % 2)), Some(value));
+            insert_command(args, engine.clone()).await.unwrap();
+        }
+        let args = CommandArgs::Single(Some("key:0".to_string()), None);
+        delete_command(args, engine.clone()).await.unwrap();
+
+        let response = compact_command(CommandArgs::WithArgs(None, vec![]), engine.clone())
+            .await
+            .unwrap();
+        let value = response.value.unwrap();
+        assert!(value["size_after"].as_u64() < value["size_before"].as_u64());
+
+        // Writes made after the compaction are still appended to the log
+        let args = CommandArgs::Single(Some("key:1".to_string()), None);
+        delete_command(args, engine.clone()).await.unwrap();
+
+        let records = Wal::replay(&path, None).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            records,
+            vec![
+                WalRecord::Insert(
+                    "key:1".to_string(),
+                    DbValue {
+                        value: json!(9),
+                        expires_at: None
+                    }
+                ),
+                WalRecord::Delete("key:1".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_compact_without_wal()
+    {
+        let engine = Arc::new(DbEngine::new(Cli::parse_from(["phoenix-db"])));
+
+        let response = compact_command(CommandArgs::WithArgs(None, vec![]), engine).await.unwrap();
+        assert_eq!(response.action, NetActions::Error);
+    }
+}
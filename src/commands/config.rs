@@ -0,0 +1,171 @@
+use std::error::Error;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use futures::future::{BoxFuture, FutureExt};
+use serde_json::json;
+use tracing::info;
+
+use crate::commands::CommandArgs;
+use crate::logging;
+use crate::protocol::{DbEngine, JsonValue, NetActions, NetResponse};
+
+/// The settings that can be read with `CONFIG GET` and changed with `CONFIG SET` while the server runs.
+const SETTINGS: [&str; 2] = ["compression", "log-level"];
+
+/// Executes a `CONFIG SET` command, changing a setting while the server runs.
+///
+/// `log-level` can be changed to one of `error`, `warn`, `info`, `debug` or `trace`, and `compression` turned
+/// `off` or back `on`. Turning compression off refuses it to the clients connecting afterwards, while those already
+/// compressing go on until they reconnect. The change lasts until the server stops, it is not written back to the
+/// command line.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` holding the name of the setting and its `[value]`.
+/// * `engine` - The database engine holding the compression switch.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with `OK`, or an error if the setting or
+/// its value is not valid.
+pub fn config_set_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let response = match args {
+            CommandArgs::WithArgs(Some(setting), params) if setting == "compression" => {
+                match params.first().and_then(JsonValue::as_str) {
+                    Some(value @ ("on" | "off")) => {
+                        engine.compression.store(value == "on", Ordering::Relaxed);
+                        info!("Compression turned {} with CONFIG SET", value);
+                        NetResponse {
+                            action: NetActions::Command,
+                            value: Some("OK".to_string().into()),
+                            error: None,
+                            ..Default::default()
+                        }
+                    }
+                    _ => config_error("compression must be on or off.".to_string()),
+                }
+            }
+            CommandArgs::WithArgs(Some(setting), params) if setting == "log-level" => {
+                match params.first().and_then(JsonValue::as_str).and_then(logging::parse_level) {
+                    Some(level) => match logging::set_level(level) {
+                        Ok(()) => {
+                            info!("Log level changed to {} with CONFIG SET", level);
+                            NetResponse {
+                                action: NetActions::Command,
+                                value: Some("OK".to_string().into()),
+                                error: None,
+                                ..Default::default()
+                            }
+                        }
+                        Err(error) => config_error(error),
+                    },
+                    None => config_error("log-level must be one of error, warn, info, debug or trace.".to_string()),
+                }
+            }
+            CommandArgs::WithArgs(Some(setting), _) => config_error(unknown_setting(&setting)),
+            _ => config_error("CONFIG SET requires the name of a setting.".to_string()),
+        };
+
+        Ok(response)
+    }
+    .boxed()
+}
+
+/// Executes a `CONFIG GET` command, reading the current value of a setting.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` holding the name of the setting.
+/// * `engine` - The database engine holding the compression switch.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with the value of the setting.
+pub fn config_get_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let response = match args {
+            CommandArgs::WithArgs(Some(setting), _) if setting == "compression" => NetResponse {
+                action: NetActions::Command,
+                value: Some(json!(if engine.compression.load(Ordering::Relaxed) {
+                    "on"
+                } else {
+                    "off"
+                })),
+                error: None,
+                ..Default::default()
+            },
+            CommandArgs::WithArgs(Some(setting), _) if setting == "log-level" => NetResponse {
+                action: NetActions::Command,
+                value: Some(json!(logging::level().map(|level| level.to_string().to_lowercase()))),
+                error: None,
+                ..Default::default()
+            },
+            CommandArgs::WithArgs(Some(setting), _) => config_error(unknown_setting(&setting)),
+            _ => config_error("CONFIG GET requires the name of a setting.".to_string()),
+        };
+
+        Ok(response)
+    }
+    .boxed()
+}
+
+fn unknown_setting(setting: &str) -> String
+{
+    format!("Unknown setting '{}', the settings are: {}.", setting, SETTINGS.join(", "))
+}
+
+fn config_error(message: String) -> NetResponse
+{
+    NetResponse {
+        action: NetActions::Error,
+        value: None,
+        error: Some(message),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use clap::Parser;
+
+    use super::*;
+    use crate::cli::Cli;
+
+    #[tokio::test]
+    async fn test_config_set()
+    {
+        let engine = Arc::new(DbEngine::new(Cli::parse_from(["phoenix-db"])));
+        let set = |setting: &str, value: &str| CommandArgs::WithArgs(Some(setting.to_string()), vec![json!(value)]);
+
+        let response = config_set_command(set("log-level", "loud"), engine.clone()).await.unwrap();
+        assert_eq!(response.action, NetActions::Error);
+        let response = config_set_command(set("port", "7000"), engine.clone()).await.unwrap();
+        assert!(response.error.unwrap().starts_with("Unknown setting 'port'"));
+
+        let response = config_set_command(set("compression", "off"), engine.clone()).await.unwrap();
+        assert_eq!(response.action, NetActions::Command);
+        let get = CommandArgs::WithArgs(Some("compression".to_string()), vec![]);
+        let response = config_get_command(get, engine.clone()).await.unwrap();
+        assert_eq!(response.value, Some(json!("off")));
+        assert!(config_set_command(set("compression", "maybe"), engine.clone())
+            .await
+            .unwrap()
+            .error
+            .is_some());
+
+        // Tests do not install the global subscriber, so there is no level to change
+        let response = config_set_command(set("log-level", "debug"), engine.clone()).await.unwrap();
+        assert_eq!(response.error.as_deref(), Some("Logging is not initialized."));
+    }
+}
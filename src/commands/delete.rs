@@ -1,10 +1,15 @@
+use std::collections::HashSet;
 use std::error::Error;
+use std::sync::Arc;
 
 use futures::future::BoxFuture;
 use futures::FutureExt;
 
-use crate::commands::CommandArgs;
-use crate::protocol::{Database, JsonValue, NetActions, NetResponse};
+use crate::commands::commit::commit;
+use crate::commands::insert::wal_error;
+use crate::commands::{progress, CommandArgs};
+use crate::protocol::{DbEngine, JsonValue, NetActions, NetResponse};
+use crate::wal::WalRecord;
 
 /// Executes a delete command on the database.
 ///
@@ -14,29 +19,48 @@ use crate::protocol::{Database, JsonValue, NetActions, NetResponse};
 /// # Arguments
 ///
 /// * `args` - The arguments for the command, which could be a single key or multiple keys for bulk deletion.
-/// * `db` - The database instance used for deletion.
+/// * `engine` - The database engine used for deletion.
 ///
 /// # Returns
 ///
 /// A `BoxFuture` that resolves to a `Result` containing a `NetResponse`. The response indicates the success
 /// or failure of the deletion operation.
-pub fn delete_command(args: CommandArgs, db: Database) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+pub fn delete_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
 {
     async move {
+        let db = &engine.connection;
         let response = match args {
             CommandArgs::Single(Some(key), ..) => {
-                let mut db_write = db.write().await;
-                if db_write.remove(&key).is_some() {
+                let committed = {
+                    let mut db_write = db.write().await;
+                    let committed = if db_write.contains_key(&key) {
+                        match commit(&engine, &mut db_write, vec![WalRecord::Delete(key.clone())]).await {
+                            Ok(committed) => Some(committed),
+                            Err(e) => return Ok(wal_error(e)),
+                        }
+                    } else {
+                        None
+                    };
+                    engine.access.forget(&key);
+                    committed
+                };
+                if let Some(committed) = committed {
+                    committed.refresh(&engine).await;
                     NetResponse {
                         action: NetActions::Command,
                         value: Some("OK".to_string().into()),
                         error: None,
+                        ..Default::default()
                     }
                 } else {
                     NetResponse {
                         action: NetActions::Error,
                         value: None,
                         error: Some(format!("Key '{}' not found.", key)),
+                        ..Default::default()
                     }
                 }
             }
@@ -44,26 +68,45 @@ pub fn delete_command(args: CommandArgs, db: Database) -> BoxFuture<'static, Res
                 action: NetActions::Error,
                 value: None,
                 error: Some("No key provided for delete.".to_string()),
+                ..Default::default()
             },
             // Returns the deleted keys
             CommandArgs::Many(pairs) => {
                 let mut db_write = db.write().await;
                 let mut results = vec![];
-                for pair in pairs {
+                let mut found = HashSet::new();
+                let total = pairs.len();
+                for (index, pair) in pairs.into_iter().enumerate() {
+                    progress::report(index, total, 0);
                     if let Some(key) = pair.key {
-                        if db_write.remove(&key).is_some() {
+                        engine.access.forget(&key);
+                        if db_write.contains_key(&key) && found.insert(key.clone()) {
                             results.push(key);
                         }
                     }
                 }
+
+                let records = results.iter().map(|key| WalRecord::Delete(key.clone())).collect();
+                let committed = match commit(&engine, &mut db_write, records).await {
+                    Ok(committed) => committed,
+                    Err(e) => return Ok(wal_error(e)),
+                };
+                drop(db_write);
+                committed.refresh(&engine).await;
+
                 NetResponse {
                     action: NetActions::Command,
-                    value: Some(JsonValue::Array(
-                        results.into_iter().map(|key| JsonValue::String(key)).collect(),
-                    )),
+                    value: Some(JsonValue::Array(results.into_iter().map(JsonValue::String).collect())),
                     error: None,
+                    ..Default::default()
                 }
             }
+            CommandArgs::WithArgs(..) => NetResponse {
+                action: NetActions::Error,
+                value: None,
+                error: Some("Invalid arguments for delete.".to_string()),
+                ..Default::default()
+            },
         };
 
         Ok(response)
@@ -74,30 +117,22 @@ pub fn delete_command(args: CommandArgs, db: Database) -> BoxFuture<'static, Res
 #[cfg(test)]
 mod test
 {
-    use std::collections::HashMap;
-    use std::sync::Arc;
-
     use serde_json::json;
-    use tokio::sync::RwLock;
 
     use super::*;
     use crate::protocol::DbValue;
-
-    // Helper function to create a new in-memory database
-    fn create_fake_db() -> Database
-    {
-        Arc::new(RwLock::new(HashMap::new()))
-    }
+    use crate::testing::create_fake_engine;
 
     #[tokio::test]
     async fn test_single_delete_existing_key()
     {
-        let db = create_fake_db();
+        let engine = create_fake_engine();
+        let db = &engine.connection;
         let key = "test_key".to_string();
 
         let data = DbValue {
             value: json!("test_value"),
-            expires_in: None,
+            expires_at: None,
         };
 
         {
@@ -106,25 +141,25 @@ mod test
         }
 
         let args = CommandArgs::Single(Some(key.clone()), None);
-        let response = delete_command(args, db.clone()).await.unwrap();
+        let response = delete_command(args, engine.clone()).await.unwrap();
 
         // Check that the response indicates success and the key is removed
         assert_eq!(response.action, NetActions::Command);
         assert_eq!(response.value, Some("OK".to_string().into()));
         assert!(response.error.is_none());
 
-        let db_read = db.read().await;
+        let db_read = db.read();
         assert!(db_read.get(&key).is_none());
     }
 
     #[tokio::test]
     async fn test_single_delete_missing_key()
     {
-        let db = create_fake_db();
+        let engine = create_fake_engine();
         let key = "non_existent_key".to_string();
 
         let args = CommandArgs::Single(Some(key.clone()), None);
-        let response = delete_command(args, db.clone()).await.unwrap();
+        let response = delete_command(args, engine.clone()).await.unwrap();
 
         // Check that the response indicates an error due to the missing key
         assert_eq!(response.action, NetActions::Error);
@@ -135,9 +170,9 @@ mod test
     #[tokio::test]
     async fn test_single_delete_no_key_provided()
     {
-        let db = create_fake_db();
+        let engine = create_fake_engine();
         let args = CommandArgs::Single(None, None);
-        let response = delete_command(args, db.clone()).await.unwrap();
+        let response = delete_command(args, engine.clone()).await.unwrap();
 
         // Check that the response indicates an error due to missing key
         assert_eq!(response.action, NetActions::Error);
@@ -148,16 +183,17 @@ mod test
     #[tokio::test]
     async fn test_bulk_delete()
     {
-        let db = create_fake_db();
+        let engine = create_fake_engine();
+        let db = &engine.connection;
         let key1 = "key1".to_string();
         let key2 = "key2".to_string();
         let data = DbValue {
             value: json!("value1"),
-            expires_in: None,
+            expires_at: None,
         };
         let data2 = DbValue {
             value: json!("value2"),
-            expires_in: None,
+            expires_at: None,
         };
 
         {
@@ -170,16 +206,16 @@ mod test
             crate::commands::CommandParams {
                 key: Some(key1.clone()),
                 value: None,
-                ttl: None,
+                expires_at: None,
             },
             crate::commands::CommandParams {
                 key: Some(key2.clone()),
                 value: None,
-                ttl: None,
+                expires_at: None,
             },
         ]);
 
-        let response = delete_command(args, db.clone()).await.unwrap();
+        let response = delete_command(args, engine.clone()).await.unwrap();
 
         // Check that the response indicates success and the keys are removed
         assert_eq!(response.action, NetActions::Command);
@@ -192,7 +228,7 @@ mod test
         );
         assert!(response.error.is_none());
 
-        let db_read = db.read().await;
+        let db_read = db.read();
         assert!(db_read.get(&key1).is_none());
         assert!(db_read.get(&key2).is_none());
     }
@@ -200,12 +236,13 @@ mod test
     #[tokio::test]
     async fn test_bulk_delete_missing_keys()
     {
-        let db = create_fake_db();
+        let engine = create_fake_engine();
+        let db = &engine.connection;
         let key1 = "key1".to_string();
         let key2 = "key2".to_string();
         let data = DbValue {
             value: json!("value1"),
-            expires_in: None,
+            expires_at: None,
         };
 
         {
@@ -217,23 +254,23 @@ mod test
             crate::commands::CommandParams {
                 key: Some(key1.clone()),
                 value: None,
-                ttl: None,
+                expires_at: None,
             },
             crate::commands::CommandParams {
                 key: Some(key2.clone()),
                 value: None,
-                ttl: None,
+                expires_at: None,
             },
         ]);
 
-        let response = delete_command(args, db.clone()).await.unwrap();
+        let response = delete_command(args, engine.clone()).await.unwrap();
 
         // Check that the response indicates success for the key that was deleted and error for the missing key
         assert_eq!(response.action, NetActions::Command);
         assert_eq!(response.value, Some(JsonValue::Array(vec![JsonValue::String(key1.clone()),])));
         assert!(response.error.is_none());
 
-        let db_read = db.read().await;
+        let db_read = db.read();
         assert!(db_read.get(&key1).is_none());
         assert!(db_read.get(&key2).is_none()); // key2 was missing, so should still be absent
     }
@@ -0,0 +1,369 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::error::Error;
+use std::sync::Arc;
+
+use futures::future::{BoxFuture, FutureExt};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::RwLock;
+use tracing::error;
+
+use crate::commands::commit::commit_local;
+use crate::commands::insert::wal_error;
+use crate::commands::CommandArgs;
+use crate::protocol::{DbEngine, DbKey, DbValue, JsonValue, NetActions, NetResponse};
+use crate::store::DbMap;
+use crate::wal::WalRecord;
+
+/// How deep a chain of derived keys, each derived from the next, is recomputed after a write.
+const MAX_DERIVED_DEPTH: usize = 8;
+
+/// Keys whose value is computed from other keys, registered with `DERIVE SET`.
+pub type DerivedKeys = RwLock<DerivedIndex>;
+
+/// The derived keys along with the keys they depend on.
+#[derive(Debug, Default)]
+pub struct DerivedIndex
+{
+    /// The definition of each derived key.
+    definitions: HashMap<DbKey, Derivation>,
+    /// The derived keys depending on each source key.
+    dependents: HashMap<DbKey, BTreeSet<DbKey>>,
+}
+
+/// How the value of a derived key is computed.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Derivation
+{
+    /// The aggregation computing the value.
+    op: Aggregation,
+    /// The keys the value is computed from.
+    sources: Vec<DbKey>,
+    /// The field to aggregate when the source values are objects.
+    #[serde(default)]
+    field: Option<String>,
+}
+
+/// A built-in aggregation over the values of the source keys. Source values holding an array are aggregated
+/// element by element.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum Aggregation
+{
+    Sum,
+    Count,
+    Min,
+    Max,
+    Avg,
+}
+
+impl Derivation
+{
+    /// Computes the value from the current content of the database.
+    fn compute(&self, db: &DbMap) -> JsonValue
+    {
+        let items = self
+            .sources
+            .iter()
+            .filter_map(|source| db.get(source))
+            .flat_map(|data| match &data.value {
+                JsonValue::Array(items) => items.iter().collect(),
+                value => vec![value],
+            })
+            .filter_map(|item| match &self.field {
+                Some(field) => item.get(field),
+                None => Some(item),
+            });
+
+        if self.op == Aggregation::Count {
+            return json!(items.count());
+        }
+
+        let numbers: Vec<f64> = items.filter_map(JsonValue::as_f64).collect();
+        let result = match self.op {
+            Aggregation::Sum => Some(numbers.iter().sum()),
+            Aggregation::Min => numbers.iter().copied().reduce(f64::min),
+            Aggregation::Max => numbers.iter().copied().reduce(f64::max),
+            Aggregation::Avg if !numbers.is_empty() => Some(numbers.iter().sum::<f64>() / numbers.len() as f64),
+            Aggregation::Avg | Aggregation::Count => None,
+        };
+
+        result.map(|number| json!(number)).unwrap_or(JsonValue::Null)
+    }
+}
+
+impl DerivedIndex
+{
+    fn insert(&mut self, key: DbKey, derivation: Derivation)
+    {
+        self.remove(&key);
+        for source in &derivation.sources {
+            self.dependents.entry(source.clone()).or_default().insert(key.clone());
+        }
+        self.definitions.insert(key, derivation);
+    }
+
+    fn remove(&mut self, key: &str) -> Option<Derivation>
+    {
+        let derivation = self.definitions.remove(key)?;
+        for source in &derivation.sources {
+            if let Some(dependents) = self.dependents.get_mut(source) {
+                dependents.remove(key);
+                if dependents.is_empty() {
+                    self.dependents.remove(source);
+                }
+            }
+        }
+        Some(derivation)
+    }
+}
+
+/// Recomputes the derived keys depending on the written keys, then the ones depending on those, and so on.
+///
+/// Called by the commands writing to the database once their write is done. A key is recomputed at most once per
+/// call, so keys derived from each other do not loop forever.
+///
+/// # Arguments
+///
+/// * `engine` - The database engine holding the derived keys.
+/// * `written` - The keys that were inserted or deleted.
+pub async fn refresh_derived<'a>(engine: &DbEngine, written: impl IntoIterator<Item = &'a DbKey>)
+{
+    let index = engine.derived.read().await;
+    if index.definitions.is_empty() {
+        return;
+    }
+
+    let mut changed: Vec<DbKey> = written.into_iter().cloned().collect();
+    let mut refreshed = HashSet::new();
+
+    for _ in 0..MAX_DERIVED_DEPTH {
+        let stale: BTreeSet<&DbKey> = changed
+            .iter()
+            .filter_map(|key| index.dependents.get(key))
+            .flatten()
+            .filter(|key| !refreshed.contains(*key))
+            .collect();
+        if stale.is_empty() {
+            break;
+        }
+
+        let mut db_write = engine.connection.write().await;
+        for key in &stale {
+            let value = index.definitions[*key].compute(&db_write);
+            let records = vec![WalRecord::Insert((*key).clone(), DbValue { value, expires_at: None })];
+            if let Err(e) = engine.wal.commit(&mut db_write, records).await {
+                error!("Failed to refresh the derived key {}: {}", key, e);
+            }
+            refreshed.insert((*key).clone());
+        }
+        changed = stale.into_iter().cloned().collect();
+    }
+}
+
+/// Executes a `DERIVE SET` command, declaring a key whose value is computed from other keys.
+///
+/// The value is computed right away, then again every time one of the source keys is written.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` holding the derived key and the `[definition]` argument, such as
+///   `{ "op": "sum", "sources": ["cart:items"], "field": "price" }`.
+/// * `engine` - The database engine storing the derived keys.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with the computed value.
+pub fn derive_set_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let response = match args {
+            CommandArgs::WithArgs(Some(key), mut params) if !params.is_empty() => {
+                match serde_json::from_value::<Derivation>(params.swap_remove(0)) {
+                    Ok(derivation) if derivation.sources.contains(&key) => {
+                        derived_error(format!("Key '{}' cannot be derived from itself.", key))
+                    }
+                    Ok(derivation) => {
+                        let (value, committed) = {
+                            let mut db_write = engine.connection.write().await;
+                            let value = derivation.compute(&db_write);
+                            let data = DbValue {
+                                value: value.clone(),
+                                expires_at: None,
+                            };
+                            match commit_local(&engine, &mut db_write, vec![WalRecord::Insert(key.clone(), data)]).await {
+                                Ok(committed) => (value, committed),
+                                Err(e) => return Ok(wal_error(e)),
+                            }
+                        };
+                        engine.derived.write().await.insert(key, derivation);
+                        committed.refresh(&engine).await;
+
+                        NetResponse {
+                            action: NetActions::Command,
+                            value: Some(value),
+                            error: None,
+                            ..Default::default()
+                        }
+                    }
+                    Err(e) => derived_error(format!("Invalid derivation: {}", e)),
+                }
+            }
+            CommandArgs::WithArgs(Some(_), _) => derived_error("DERIVE SET requires a definition.".to_string()),
+            _ => derived_error("No key provided for derive.".to_string()),
+        };
+
+        Ok(response)
+    }
+    .boxed()
+}
+
+/// Executes a `DERIVE GET` command, returning the definition of a derived key.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` holding the derived key.
+/// * `engine` - The database engine storing the derived keys.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with the definition, if the key is
+/// derived.
+pub fn derive_get_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let response = match args {
+            CommandArgs::WithArgs(Some(key), _) => NetResponse {
+                action: NetActions::Command,
+                value: engine
+                    .derived
+                    .read()
+                    .await
+                    .definitions
+                    .get(&key)
+                    .and_then(|derivation| serde_json::to_value(derivation).ok()),
+                error: None,
+                ..Default::default()
+            },
+            _ => derived_error("No key provided for derive.".to_string()),
+        };
+
+        Ok(response)
+    }
+    .boxed()
+}
+
+/// Executes a `DERIVE DELETE` command, turning a derived key back into a regular one.
+///
+/// The last computed value is kept and is no longer updated.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` holding the derived key.
+/// * `engine` - The database engine storing the derived keys.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` indicating the result of the command.
+pub fn derive_delete_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let response = match args {
+            CommandArgs::WithArgs(Some(key), _) => {
+                if engine.derived.write().await.remove(&key).is_some() {
+                    NetResponse {
+                        action: NetActions::Command,
+                        value: Some("OK".to_string().into()),
+                        error: None,
+                        ..Default::default()
+                    }
+                } else {
+                    derived_error(format!("Key '{}' is not derived.", key))
+                }
+            }
+            _ => derived_error("No key provided for derive.".to_string()),
+        };
+
+        Ok(response)
+    }
+    .boxed()
+}
+
+/// Builds an error response for the derive commands.
+fn derived_error(message: String) -> NetResponse
+{
+    NetResponse {
+        action: NetActions::Error,
+        value: None,
+        error: Some(message),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use crate::commands::delete::delete_command;
+    use crate::commands::insert::insert_command;
+    use crate::testing::create_fake_engine;
+
+    async fn insert(engine: &Arc<DbEngine>, key: &str, value: JsonValue)
+    {
+        let args = CommandArgs::Single(Some(key.to_string()), Some(DbValue { value, expires_at: None }));
+        insert_command(args, engine.clone()).await.unwrap();
+    }
+
+    fn get(engine: &Arc<DbEngine>, key: &str) -> Option<JsonValue>
+    {
+        engine.connection.read().get(key).map(|data| data.value.clone())
+    }
+
+    #[tokio::test]
+    async fn test_derived_key_follows_its_sources()
+    {
+        let engine = create_fake_engine();
+        insert(&engine, "cart:items", json!([{ "price": 2 }, { "price": 3 }])).await;
+
+        let definition = json!({ "op": "sum", "sources": ["cart:items"], "field": "price" });
+        let args = CommandArgs::WithArgs(Some("cart:total".to_string()), vec![definition]);
+        let response = derive_set_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.value, Some(json!(5.0)));
+
+        // Keys derived from derived keys are recomputed as well
+        let definition = json!({ "op": "max", "sources": ["cart:total", "cart:shipping"] });
+        let args = CommandArgs::WithArgs(Some("cart:highest".to_string()), vec![definition]);
+        derive_set_command(args, engine.clone()).await.unwrap();
+
+        insert(&engine, "cart:items", json!([{ "price": 2 }, { "price": 3 }, { "price": 5 }])).await;
+        assert_eq!(get(&engine, "cart:total"), Some(json!(10.0)));
+        assert_eq!(get(&engine, "cart:highest"), Some(json!(10.0)));
+
+        insert(&engine, "cart:shipping", json!(12)).await;
+        assert_eq!(get(&engine, "cart:highest"), Some(json!(12.0)));
+
+        let args = CommandArgs::Single(Some("cart:items".to_string()), None);
+        delete_command(args, engine.clone()).await.unwrap();
+        assert_eq!(get(&engine, "cart:total"), Some(json!(0.0)));
+    }
+
+    #[tokio::test]
+    async fn test_derived_key_cannot_depend_on_itself()
+    {
+        let engine = create_fake_engine();
+
+        let definition = json!({ "op": "count", "sources": ["total"] });
+        let args = CommandArgs::WithArgs(Some("total".to_string()), vec![definition]);
+        let response = derive_set_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.action, NetActions::Error);
+    }
+}
@@ -0,0 +1,427 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use futures::future::{BoxFuture, FutureExt};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::commands::commit::commit;
+use crate::commands::insert::wal_error;
+use crate::commands::namespace::ExportEntry;
+use crate::commands::reference::{check_references, reference_error};
+use crate::commands::transform::apply_transforms;
+use crate::commands::CommandArgs;
+use crate::crypto::{from_hex, to_hex, Cipher};
+use crate::protocol::{DbEngine, DbKey, JsonValue, NetActions, NetResponse};
+use crate::store::DbMap;
+use crate::wal::WalRecord;
+
+/// A line of a file written with an encryption key, holding the hex digits of the line sealed by the cipher.
+#[derive(Serialize, Deserialize)]
+struct SealedLine
+{
+    sealed: String,
+}
+
+/// Writes `line` as a JSON document followed by a newline, sealed with `cipher` if given.
+pub(crate) fn write_line(writer: &mut impl Write, line: &impl Serialize, cipher: Option<&Cipher>) -> io::Result<()>
+{
+    match cipher {
+        Some(cipher) => {
+            let sealed = cipher.seal(&serde_json::to_vec(line)?)?;
+            serde_json::to_writer(&mut *writer, &SealedLine { sealed: to_hex(&sealed) })?;
+        }
+        None => serde_json::to_writer(&mut *writer, line)?,
+    }
+    writer.write_all(b"\n")
+}
+
+/// Parses a line written by [`write_line`], opening it with `cipher` if it is sealed.
+///
+/// Files written without a key stay readable by a server that has one, but a sealed line fails to parse without
+/// the key it was sealed with.
+pub(crate) fn parse_line<T: DeserializeOwned>(line: &str, cipher: Option<&Cipher>) -> io::Result<T>
+{
+    let Ok(SealedLine { sealed }) = serde_json::from_str(line) else {
+        return Ok(serde_json::from_str(line)?);
+    };
+    let cipher = cipher.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the file is encrypted, the server must be started with its encryption key to read it",
+        )
+    })?;
+    Ok(serde_json::from_slice(&cipher.open(&from_hex(&sealed)?)?)?)
+}
+
+/// Writes every entry of `map` to `path`, one JSON document per line, each sealed with `cipher` if given.
+///
+/// # Returns
+///
+/// The size of the dump in bytes.
+fn write_dump(map: &DbMap, path: &Path, cipher: Option<&Cipher>) -> io::Result<u64>
+{
+    let temp_path = path.with_extension("tmp");
+
+    let mut writer = BufWriter::new(File::create(&temp_path)?);
+    for (key, data) in map.iter() {
+        let entry = ExportEntry {
+            key: key.clone(),
+            data: data.clone(),
+        };
+        write_line(&mut writer, &entry, cipher)?;
+    }
+    writer.flush()?;
+
+    let file = writer.into_inner().map_err(|e| e.into_error())?;
+    file.sync_all()?;
+    let size = file.metadata()?.len();
+
+    fs::rename(&temp_path, path)?;
+    Ok(size)
+}
+
+/// A line of a dump, holding either an entry or, in an incremental backup, a deleted key.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum DumpLine
+{
+    /// A key deleted since the previous backup, written as `{"key": ..., "deleted": true}`.
+    Deleted
+    {
+        /// The deleted key.
+        key: DbKey,
+        /// Always `true`, telling deleted keys apart from entries.
+        deleted: bool,
+    },
+    /// An entry along with its TTL.
+    Entry(ExportEntry),
+}
+
+/// Reads a dump written by [`write_dump`] or `BACKUP INCREMENTAL` from `path`, opening its lines with `cipher`.
+///
+/// # Returns
+///
+/// The entries of the dump and the keys it records as deleted.
+fn read_dump(path: &Path, cipher: Option<&Cipher>) -> io::Result<(Vec<ExportEntry>, Vec<DbKey>)>
+{
+    let mut entries = vec![];
+    let mut deleted = vec![];
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        match parse_line(&line, cipher)? {
+            DumpLine::Entry(entry) => entries.push(entry),
+            DumpLine::Deleted { key, deleted: true } => deleted.push(key),
+            DumpLine::Deleted { key, deleted: false } => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("the line of '{}' has no value", key),
+                ))
+            }
+        }
+    }
+    Ok((entries, deleted))
+}
+
+/// Executes an `EXPORT` command, writing every entry of the database along with its TTL to a JSON file.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` holding the `[file]` argument, a path on the server.
+/// * `engine` - The database engine to export.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with the number of keys exported and the
+/// size of the file.
+pub fn export_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let path = match args {
+            CommandArgs::WithArgs(_, params) => match params.first().and_then(JsonValue::as_str) {
+                Some(path) => match engine.db_config.data_path(path) {
+                    Ok(path) => path,
+                    Err(message) => return Ok(dump_error(message)),
+                },
+                None => return Ok(dump_error("EXPORT requires a file.".to_string())),
+            },
+            _ => return Ok(dump_error("Invalid arguments for export.".to_string())),
+        };
+
+        let map = engine.connection.read();
+        let keys = map.len();
+        let cipher = engine.cipher.clone();
+        let response = match tokio::task::spawn_blocking(move || write_dump(&map, &path, cipher.as_deref())).await {
+            Ok(Ok(size)) => NetResponse {
+                action: NetActions::Command,
+                value: Some(json!({ "keys": keys, "size": size })),
+                error: None,
+                ..Default::default()
+            },
+            Ok(Err(e)) => dump_error(format!("Failed to export: {}", e)),
+            Err(e) => dump_error(format!("Failed to export: {}", e)),
+        };
+
+        Ok(response)
+    }
+    .boxed()
+}
+
+/// Executes an `IMPORT` command, loading a file written by `EXPORT`.
+///
+/// By default the entries are merged into the database, overwriting the keys they share with it. With the
+/// `replace` mode every other key is deleted, so the database ends up holding exactly the dump. Either way the
+/// keys an incremental backup records as deleted are deleted, and the import is applied at once, readers never
+/// see it half done.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` holding the `[file, mode]` arguments, where `mode` is `merge` or
+///   `replace` and defaults to `merge`.
+/// * `engine` - The database engine to import into.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with the number of keys imported and
+/// deleted.
+pub fn import_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let (path, replace) = match args {
+            CommandArgs::WithArgs(_, params) => {
+                let replace = match parse_mode(params.get(1)) {
+                    Ok(replace) => replace,
+                    Err(message) => return Ok(dump_error(message)),
+                };
+                match params.first().and_then(JsonValue::as_str) {
+                    Some(path) => match engine.db_config.data_path(path) {
+                        Ok(path) => (path, replace),
+                        Err(message) => return Ok(dump_error(message)),
+                    },
+                    None => return Ok(dump_error("IMPORT requires a file.".to_string())),
+                }
+            }
+            _ => return Ok(dump_error("Invalid arguments for import.".to_string())),
+        };
+
+        let cipher = engine.cipher.clone();
+        let (entries, removed) = match tokio::task::spawn_blocking(move || read_dump(&path, cipher.as_deref())).await {
+            Ok(Ok(dump)) => dump,
+            Ok(Err(e)) => return Ok(dump_error(format!("Failed to import: {}", e))),
+            Err(e) => return Ok(dump_error(format!("Failed to import: {}", e))),
+        };
+
+        Ok(apply_dump(&engine, entries, removed, replace).await)
+    }
+    .boxed()
+}
+
+/// Applies the entries and deleted keys of a dump at once, deleting every other key if `replace` is set. The values
+/// go through the transforms and reference checks of their keys, as inserted ones do.
+///
+/// # Returns
+///
+/// A `NetResponse` with the number of keys written and deleted.
+pub(crate) async fn apply_dump(
+    engine: &Arc<DbEngine>,
+    mut entries: Vec<ExportEntry>,
+    removed: Vec<DbKey>,
+    replace: bool,
+) -> NetResponse
+{
+    for entry in &mut entries {
+        apply_transforms(engine, &entry.key, &mut entry.data.value).await;
+    }
+    let written: Vec<DbKey> = entries.iter().map(|entry| entry.key.clone()).collect();
+    let imported: HashSet<&str> = written.iter().map(String::as_str).collect();
+    let mut warnings = Vec::new();
+    for entry in &entries {
+        match check_references(engine, &entry.key, &entry.data.value, &imported).await {
+            Ok(entry_warnings) => warnings.extend(entry_warnings),
+            Err(e) => return reference_error(e),
+        }
+    }
+
+    let (deleted, committed) = {
+        let mut db_write = engine.connection.write().await;
+
+        let stale: Vec<DbKey> = if replace {
+            db_write
+                .keys()
+                .filter(|key| !imported.contains(key.as_str()))
+                .cloned()
+                .collect()
+        } else {
+            removed.into_iter().filter(|key| db_write.contains_key(key)).collect()
+        };
+
+        let records: Vec<WalRecord> = stale
+            .iter()
+            .map(|key| WalRecord::Delete(key.clone()))
+            .chain(entries.into_iter().map(|entry| WalRecord::Insert(entry.key, entry.data)))
+            .collect();
+        let committed = match commit(engine, &mut db_write, records).await {
+            Ok(committed) => committed,
+            Err(e) => return wal_error(e),
+        };
+        for key in &stale {
+            engine.access.forget(key);
+        }
+
+        (stale.len(), committed)
+    };
+    let keys = committed.keys().len();
+    committed.refresh(engine).await;
+
+    NetResponse {
+        action: NetActions::Command,
+        value: Some(json!({ "keys": keys - deleted, "deleted": deleted })),
+        error: None,
+        warnings,
+        ..Default::default()
+    }
+}
+
+/// Parses the `merge` or `replace` mode of an import, defaulting to `merge`.
+///
+/// # Returns
+///
+/// Whether the keys missing from the import are deleted, or an error message for an unknown mode.
+pub(crate) fn parse_mode(mode: Option<&JsonValue>) -> Result<bool, String>
+{
+    match mode.map(|mode| mode.as_str().map(str::to_lowercase)) {
+        None => Ok(false),
+        Some(Some(mode)) if mode == "merge" => Ok(false),
+        Some(Some(mode)) if mode == "replace" => Ok(true),
+        Some(_) => Err("Import mode must be 'merge' or 'replace'.".to_string()),
+    }
+}
+
+/// Builds an error response for the `EXPORT` and `IMPORT` commands.
+fn dump_error(message: String) -> NetResponse
+{
+    NetResponse {
+        action: NetActions::Error,
+        value: None,
+        error: Some(message),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use crate::protocol::DbValue;
+    use crate::testing::create_engine_in_temp_dir;
+
+    async fn insert(engine: &DbEngine, entries: &[(&str, JsonValue)])
+    {
+        let mut db_write = engine.connection.write().await;
+        for (key, value) in entries {
+            db_write.insert(
+                key.to_string(),
+                DbValue {
+                    value: value.clone(),
+                    expires_at: Some(1_700_000_000_000),
+                },
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_export_and_import()
+    {
+        let name = format!("phoenix-db-test-{}.dump", std::process::id());
+        let path = std::env::temp_dir().join(&name);
+        let file = || json!(name);
+
+        let source = create_engine_in_temp_dir();
+        insert(&source, &[("a", json!(1)), ("b", json!({ "n": 2 }))]).await;
+        let response = export_command(CommandArgs::WithArgs(None, vec![file()]), source)
+            .await
+            .unwrap();
+        assert_eq!(response.value.unwrap()["keys"], json!(2));
+
+        // Merging keeps the keys missing from the dump
+        let target = create_engine_in_temp_dir();
+        insert(&target, &[("a", json!(0)), ("c", json!(3))]).await;
+        let response = import_command(CommandArgs::WithArgs(None, vec![file()]), target.clone())
+            .await
+            .unwrap();
+        assert_eq!(response.value, Some(json!({ "keys": 2, "deleted": 0 })));
+        assert_eq!(target.connection.read().get("a").unwrap().value, json!(1));
+        assert!(target.connection.read().get("c").is_some());
+
+        // Replacing deletes them
+        let args = CommandArgs::WithArgs(None, vec![file(), json!("replace")]);
+        let response = import_command(args, target.clone()).await.unwrap();
+        assert_eq!(response.value, Some(json!({ "keys": 2, "deleted": 1 })));
+        fs::remove_file(&path).unwrap();
+
+        let db_read = target.connection.read();
+        assert!(db_read.get("c").is_none());
+        assert_eq!(db_read.get("b").unwrap().expires_at, Some(1_700_000_000_000));
+    }
+
+    #[test]
+    fn test_encrypted_dump()
+    {
+        let path = std::env::temp_dir().join(format!("phoenix-db-test-{}-sealed.dump", std::process::id()));
+        let cipher = Cipher::new(&[7; 32]);
+        let mut map = DbMap::new();
+        map.insert(
+            "secret".to_string(),
+            DbValue {
+                value: json!("hidden"),
+                expires_at: None,
+            },
+        );
+
+        // Entries are sealed, and cannot be read without the key
+        write_dump(&map, &path, Some(&cipher)).unwrap();
+        assert!(!fs::read_to_string(&path).unwrap().contains("hidden"));
+        assert!(read_dump(&path, None).is_err());
+        let (entries, _) = read_dump(&path, Some(&cipher)).unwrap();
+        assert_eq!(
+            (entries[0].key.as_str(), &entries[0].data.value),
+            ("secret", &json!("hidden"))
+        );
+
+        // Dumps written without a key stay readable with one
+        write_dump(&map, &path, None).unwrap();
+        assert_eq!(read_dump(&path, Some(&cipher)).unwrap().0.len(), 1);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_files_outside_the_data_directory_are_rejected()
+    {
+        let engine = create_engine_in_temp_dir();
+        for file in ["/etc/passwd", "../phoenix-db.dump", "dumps/../../phoenix-db.dump", ""] {
+            let response = export_command(CommandArgs::WithArgs(None, vec![json!(file)]), engine.clone())
+                .await
+                .unwrap();
+            assert_eq!(response.action, NetActions::Error, "{}", file);
+
+            let response = import_command(CommandArgs::WithArgs(None, vec![json!(file)]), engine.clone())
+                .await
+                .unwrap();
+            assert_eq!(response.action, NetActions::Error, "{}", file);
+        }
+    }
+}
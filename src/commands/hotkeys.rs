@@ -0,0 +1,66 @@
+use std::error::Error;
+use std::sync::Arc;
+
+use futures::future::{BoxFuture, FutureExt};
+use serde_json::json;
+
+use crate::commands::CommandArgs;
+use crate::protocol::{DbEngine, JsonValue, NetActions, NetResponse};
+
+/// The number of keys `HOTKEYS` returns when no count is given.
+const DEFAULT_HOTKEYS_COUNT: usize = 10;
+
+/// Executes a `HOTKEYS` command, listing the most recently read keys.
+///
+/// The result comes from the sampled access tracker, so it is an approximation: rarely read keys may be
+/// missing and keys read in quick succession share the same clock.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` optionally holding the `[count]` of keys to return.
+/// * `engine` - The database engine holding the access tracker.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with the keys and their clock, hottest
+/// first.
+pub fn hotkeys_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let response = match args {
+            CommandArgs::WithArgs(_, params) => {
+                let count = params
+                    .first()
+                    .and_then(JsonValue::as_u64)
+                    .map(|count| count as usize)
+                    .unwrap_or(DEFAULT_HOTKEYS_COUNT);
+
+                let keys = engine
+                    .access
+                    .hottest(count)
+                    .into_iter()
+                    .map(|(key, clock)| json!({ "key": key, "clock": clock }))
+                    .collect();
+
+                NetResponse {
+                    action: NetActions::Command,
+                    value: Some(JsonValue::Array(keys)),
+                    error: None,
+                    ..Default::default()
+                }
+            }
+            _ => NetResponse {
+                action: NetActions::Error,
+                value: None,
+                error: Some("Invalid arguments for hotkeys.".to_string()),
+                ..Default::default()
+            },
+        };
+
+        Ok(response)
+    }
+    .boxed()
+}
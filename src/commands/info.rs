@@ -0,0 +1,128 @@
+use std::error::Error;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use futures::future::{BoxFuture, FutureExt};
+use serde_json::json;
+
+use crate::commands::CommandArgs;
+use crate::protocol::{DbEngine, JsonValue, NetActions, NetResponse};
+
+/// Executes an `INFO` command, reporting server statistics.
+///
+/// # Arguments
+///
+/// * `_args` - Unused, `INFO` takes no arguments.
+/// * `engine` - The database engine to report on.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with the statistics grouped by section.
+pub fn info_command(
+    _args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let metrics = &engine.metrics;
+        let last_save = engine.snapshots.last_save();
+        let write_waits = engine.connection.write_waits();
+        let write_wait_buckets: serde_json::Map<String, JsonValue> = write_waits
+            .buckets()
+            .into_iter()
+            .map(|(label, count)| (label.to_string(), json!(count)))
+            .collect();
+
+        let info = json!({
+            "server": {
+                "version": env!("CARGO_PKG_VERSION"),
+            },
+            "stats": {
+                "commands_processed": metrics.commands_processed.get(),
+                "read_commands": metrics.read_commands.get(),
+                "write_commands": metrics.write_commands.get(),
+                "bulk_commands": metrics.bulk_commands.get(),
+                "admin_commands": metrics.admin_commands.get(),
+                "keyspace_hits": metrics.keyspace_hits.get(),
+                "keyspace_misses": metrics.keyspace_misses.get(),
+                "bytes_read": metrics.bytes_read.get(),
+                "bytes_written": metrics.bytes_written.get(),
+                "accept_failures": metrics.accept_failures.get(),
+                "read_timeouts": metrics.read_timeouts.get(),
+                "write_timeouts": metrics.write_timeouts.get(),
+                "bulk_timeouts": metrics.bulk_timeouts.get(),
+                "memory_rejected_writes": metrics.memory_rejected_writes.get(),
+            },
+            "clients": {
+                "json_connections": metrics.json_connections.get(),
+                "message_pack_connections": metrics.message_pack_connections.get(),
+                "compressed_connections": metrics.compressed_connections.get(),
+                "compression_enabled": engine.compression.load(Ordering::Relaxed),
+                "compression_refused": metrics.compression_refused.get(),
+            },
+            "persistence": {
+                "save_in_progress": engine.snapshots.in_progress(),
+                "last_save_time": last_save.map(|save| save.finished_at),
+                "last_save_duration_ms": last_save.map(|save| save.duration.as_millis() as u64),
+                "last_save_size": last_save.map(|save| save.size),
+                "wal_size": engine.wal.size(),
+                "wal_last_sync_time": engine.wal.last_sync(),
+            },
+            "election": {
+                "enabled": engine.lease.is_enabled(),
+                "primary": engine.lease.is_primary(),
+                "epoch": engine.lease.epoch(),
+            },
+            "memory": {
+                "used_bytes": engine.memory.used(),
+                "reserved_bytes": engine.loads.reserved_bytes(),
+                "max_bytes": engine.memory.limit(),
+                "resume_below_bytes": engine.memory.limit().map(|_| engine.memory.resume_below()),
+                "rejecting_writes": engine.memory.is_rejecting(),
+            },
+            "locks": {
+                "store_write": {
+                    "count": write_waits.count(),
+                    "total_us": write_waits.total().as_micros() as u64,
+                    "buckets": write_wait_buckets,
+                },
+            },
+            "keyspace": {
+                "keys": engine.connection.read().len(),
+            },
+        });
+
+        Ok(NetResponse {
+            action: NetActions::Command,
+            value: Some(info),
+            error: None,
+            ..Default::default()
+        })
+    }
+    .boxed()
+}
+
+#[cfg(test)]
+mod test
+{
+    use clap::Parser;
+
+    use super::*;
+    use crate::cli::Cli;
+
+    #[tokio::test]
+    async fn test_info()
+    {
+        let engine = Arc::new(DbEngine::new(Cli::parse_from(["phoenix-db"])));
+        engine.metrics.keyspace_hits.add(3);
+
+        let response = info_command(CommandArgs::WithArgs(None, vec![]), engine.clone())
+            .await
+            .unwrap();
+        assert_eq!(response.action, NetActions::Command);
+
+        let info = response.value.unwrap();
+        assert_eq!(info["stats"]["keyspace_hits"], json!(3));
+        assert_eq!(info["keyspace"]["keys"], json!(0));
+    }
+}
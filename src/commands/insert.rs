@@ -1,10 +1,16 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
+use std::sync::Arc;
 
 use futures::future::{BoxFuture, FutureExt};
 
-use crate::commands::CommandArgs;
-use crate::protocol::{Database, DbKey, DbValue, NetActions, NetResponse};
+use crate::commands::commit::commit_tagged;
+use crate::commands::mount::{read_only_error, split_mounted_key};
+use crate::commands::reference::check_references;
+use crate::commands::transform::apply_transforms;
+use crate::commands::{progress, CommandArgs};
+use crate::protocol::{DbEngine, DbKey, DbValue, NetActions, NetResponse};
+use crate::wal::WalRecord;
 
 /// Executes an insert command on the database.
 ///
@@ -14,24 +20,67 @@ use crate::protocol::{Database, DbKey, DbValue, NetActions, NetResponse};
 /// # Arguments
 ///
 /// * `args` - The arguments for the command, which could be a single key-value pair or multiple key-value pairs.
-/// * `db` - The database instance used for insertions.
+/// * `engine` - The database engine used for insertions.
 ///
 /// # Returns
 ///
 /// A `BoxFuture` that resolves to a `Result` containing a `NetResponse`. The response indicates the success
 /// or failure of the insertion operation.
-pub fn insert_command(args: CommandArgs, db: Database) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+pub fn insert_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    insert_tagged(args, Vec::new(), engine)
+}
+
+/// Same as [`insert_command`], replacing the tags of the inserted entries by `tags` in the same write.
+///
+/// # Arguments
+///
+/// * `args` - The arguments for the command, which could be a single key-value pair or multiple key-value pairs.
+/// * `tags` - The tags of the inserted entries, which lose their tags if it is empty.
+/// * `engine` - The database engine used for insertions.
+pub fn insert_tagged(
+    args: CommandArgs,
+    tags: Vec<String>,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
 {
     async move {
+        let db = &engine.connection;
         let response = match args {
+            CommandArgs::Single(Some(key), _) if split_mounted_key(&key).is_some() => read_only_error(&key),
             // Handle single key-value insertion
-            CommandArgs::Single(Some(key), Some(value)) => {
-                let mut db_write = db.write().await;
-                db_write.insert(key, value);
+            CommandArgs::Single(Some(key), Some(mut value)) => {
+                apply_transforms(&engine, &key, &mut value.value).await;
+                let warnings = match check_references(&engine, &key, &value.value, &HashSet::new()).await {
+                    Ok(warnings) => warnings,
+                    Err(e) => {
+                        return Ok(NetResponse {
+                            action: NetActions::Error,
+                            value: None,
+                            error: Some(e),
+                            ..Default::default()
+                        })
+                    }
+                };
+
+                let committed = {
+                    let mut db_write = db.write().await;
+                    match commit_tagged(&engine, &mut db_write, vec![WalRecord::Insert(key, value)], Some(&tags)).await {
+                        Ok(committed) => committed,
+                        Err(e) => return Ok(wal_error(e)),
+                    }
+                };
+                committed.refresh(&engine).await;
+
                 NetResponse {
                     action: NetActions::Command,
                     value: Some("OK".to_string().into()),
                     error: None,
+                    warnings,
+                    ..Default::default()
                 }
             }
             // Handle case where no key is provided
@@ -39,26 +88,34 @@ pub fn insert_command(args: CommandArgs, db: Database) -> BoxFuture<'static, Res
                 action: NetActions::Error,
                 value: None,
                 error: Some("No key provided for insert.".to_string()),
+                ..Default::default()
             },
             // Handle case where no value is provided
             CommandArgs::Single(_, None) => NetResponse {
                 action: NetActions::Error,
                 value: None,
                 error: Some("No value provided for insert.".to_string()),
+                ..Default::default()
             },
             // Handle bulk insertions
             CommandArgs::Many(args) => {
                 let mut temp_map: HashMap<DbKey, DbValue> = HashMap::new();
                 let mut insert_errors: Vec<String> = Vec::new();
+                let total = args.len();
 
-                for a in args {
-                    match (a.key, a.value, a.ttl) {
-                        (Some(key), Some(value), ..) => {
+                for (index, a) in args.into_iter().enumerate() {
+                    progress::report(index, total, insert_errors.len());
+                    match (a.key, a.value, a.expires_at) {
+                        (Some(key), ..) if split_mounted_key(&key).is_some() => {
+                            insert_errors.push(format!("Key '{}' belongs to a read-only mounted snapshot", key));
+                        }
+                        (Some(key), Some(mut value), ..) => {
+                            apply_transforms(&engine, &key, &mut value).await;
                             temp_map.insert(
                                 key,
                                 DbValue {
                                     value,
-                                    expires_in: a.ttl,
+                                    expires_at: a.expires_at,
                                 },
                             );
                         }
@@ -74,22 +131,51 @@ pub fn insert_command(args: CommandArgs, db: Database) -> BoxFuture<'static, Res
                     }
                 }
 
+                let mut warnings = Vec::new();
+                let batch: HashSet<&str> = temp_map.keys().map(String::as_str).collect();
+                for (key, value) in &temp_map {
+                    match check_references(&engine, key, &value.value, &batch).await {
+                        Ok(reference_warnings) => warnings.extend(reference_warnings),
+                        Err(e) => insert_errors.push(e),
+                    }
+                }
+
                 if insert_errors.is_empty() {
-                    let mut db_lock = db.write().await;
-                    db_lock.extend(temp_map);
+                    let committed = {
+                        let mut db_lock = db.write().await;
+                        let records: Vec<WalRecord> = temp_map
+                            .into_iter()
+                            .map(|(key, value)| WalRecord::Insert(key, value))
+                            .collect();
+                        match commit_tagged(&engine, &mut db_lock, records, Some(&tags)).await {
+                            Ok(committed) => committed,
+                            Err(e) => return Ok(wal_error(e)),
+                        }
+                    };
+                    committed.refresh(&engine).await;
+
                     NetResponse {
                         action: NetActions::Command,
                         value: Some("OK".to_string().into()),
                         error: None,
+                        warnings,
+                        ..Default::default()
                     }
                 } else {
                     NetResponse {
                         action: NetActions::Error,
                         value: None,
                         error: Some(insert_errors.join(", ")),
+                        ..Default::default()
                     }
                 }
             }
+            CommandArgs::WithArgs(..) => NetResponse {
+                action: NetActions::Error,
+                value: None,
+                error: Some("Invalid arguments for insert.".to_string()),
+                ..Default::default()
+            },
         };
 
         Ok(response)
@@ -97,37 +183,40 @@ pub fn insert_command(args: CommandArgs, db: Database) -> BoxFuture<'static, Res
     .boxed()
 }
 
+/// Builds the error response for a write that could not be recorded in the write-ahead log, and was not applied.
+pub fn wal_error(e: std::io::Error) -> NetResponse
+{
+    NetResponse {
+        action: NetActions::Error,
+        value: None,
+        error: Some(format!("Failed to write to the write-ahead log: {}", e)),
+        ..Default::default()
+    }
+}
+
 #[cfg(test)]
 mod test
 {
-    use std::collections::HashMap;
-    use std::sync::Arc;
-
     use serde_json::json;
-    use tokio::sync::RwLock;
 
     use crate::commands::insert::insert_command;
     use crate::commands::CommandArgs;
-    use crate::protocol::{Database, DbValue, NetActions};
-
-    // Helper function to create a new in-memory database
-    fn create_fake_db() -> Database
-    {
-        Arc::new(RwLock::new(HashMap::new()))
-    }
+    use crate::protocol::{DbValue, NetActions};
+    use crate::testing::create_fake_engine;
 
     #[tokio::test]
     async fn test_single_insert()
     {
-        let db = create_fake_db();
+        let engine = create_fake_engine();
+        let db = &engine.connection;
         let key = "test_key".to_string();
         let data = DbValue {
             value: json!("test_value"),
-            expires_in: None,
+            expires_at: None,
         };
 
         let args = CommandArgs::Single(Some(key.clone()), Some(data.clone()));
-        let response = insert_command(args, db.clone()).await.unwrap();
+        let response = insert_command(args, engine.clone()).await.unwrap();
 
         // Check that the response indicates success
         assert_eq!(response.action, NetActions::Command);
@@ -135,21 +224,21 @@ mod test
         assert!(response.error.is_none());
 
         // Check that the value was inserted correctly
-        let db_read = db.read().await;
+        let db_read = db.read();
         assert_eq!(db_read.get(&key), Some(&data));
     }
 
     #[tokio::test]
     async fn test_single_insert_missing_key()
     {
-        let db = create_fake_db();
+        let engine = create_fake_engine();
         let data = DbValue {
             value: json!("test_value"),
-            expires_in: None,
+            expires_at: None,
         };
 
         let args = CommandArgs::Single(None, Some(data));
-        let response = insert_command(args, db.clone()).await.unwrap();
+        let response = insert_command(args, engine.clone()).await.unwrap();
 
         // Check that the response indicates an error
         assert_eq!(response.action, NetActions::Error);
@@ -160,11 +249,11 @@ mod test
     #[tokio::test]
     async fn test_single_insert_missing_value()
     {
-        let db = create_fake_db();
+        let engine = create_fake_engine();
         let key = "test_key".to_string();
 
         let args = CommandArgs::Single(Some(key), None);
-        let response = insert_command(args, db.clone()).await.unwrap();
+        let response = insert_command(args, engine.clone()).await.unwrap();
 
         // Check that the response indicates an error
         assert_eq!(response.action, NetActions::Error);
@@ -175,32 +264,33 @@ mod test
     #[tokio::test]
     async fn test_bulk_insert()
     {
-        let db = create_fake_db();
+        let engine = create_fake_engine();
+        let db = &engine.connection;
         let key1 = "key1".to_string();
         let key2 = "key2".to_string();
         let data = DbValue {
             value: json!("value1"),
-            expires_in: None,
+            expires_at: None,
         };
         let data2 = DbValue {
             value: json!("value2"),
-            expires_in: None,
+            expires_at: None,
         };
 
         let args = CommandArgs::Many(vec![
             crate::commands::CommandParams {
                 key: Some(key1.clone()),
                 value: Some(data.value.clone()),
-                ttl: None,
+                expires_at: None,
             },
             crate::commands::CommandParams {
                 key: Some(key2.clone()),
                 value: Some(data2.value.clone()),
-                ttl: None,
+                expires_at: None,
             },
         ]);
 
-        let response = insert_command(args, db.clone()).await.unwrap();
+        let response = insert_command(args, engine.clone()).await.unwrap();
 
         // Check that the response indicates success
         assert_eq!(response.action, NetActions::Command);
@@ -208,7 +298,7 @@ mod test
         assert!(response.error.is_none());
 
         // Check that the values were inserted correctly
-        let db_read = db.read().await;
+        let db_read = db.read();
         assert_eq!(db_read.get(&key1), Some(&data));
         assert_eq!(db_read.get(&key2), Some(&data2));
     }
@@ -0,0 +1,325 @@
+use std::error::Error;
+use std::ops::Bound;
+use std::sync::Arc;
+
+use futures::future::{BoxFuture, FutureExt};
+use serde_json::json;
+
+use crate::commands::budget::ResponseBudget;
+use crate::commands::commit::commit;
+use crate::commands::insert::wal_error;
+use crate::commands::CommandArgs;
+use crate::protocol::{DbEngine, DbKey, JsonValue, NetActions, NetResponse};
+use crate::store::KeyOrder;
+use crate::wal::WalRecord;
+
+/// The number of entries `RANGE` returns when no count is given.
+const DEFAULT_RANGE_COUNT: usize = 100;
+
+/// The number of keys `DELRANGE` deletes per call when no count is given.
+const DEFAULT_DELRANGE_COUNT: usize = 1000;
+
+/// Returns the keys between `from` and `to`, both inclusive, in order.
+fn keys_between<'a>(keys: &'a KeyOrder, from: &'a str, to: &'a str) -> impl Iterator<Item = &'a DbKey>
+{
+    // Ranges whose start is after their end are empty, rather than a panic
+    let end = if from <= to {
+        Bound::Included(to)
+    } else {
+        Bound::Excluded(from)
+    };
+    keys.range::<_, str>((Bound::Included(from), end))
+}
+
+/// Reads the `from` key and the `[to, count]` arguments shared by the range commands.
+fn parse_range(args: CommandArgs, name: &str) -> Result<(DbKey, String, Option<usize>), String>
+{
+    match args {
+        CommandArgs::WithArgs(Some(from), params) => {
+            let count = params.get(1).map(JsonValue::as_u64);
+            match (params.first().and_then(JsonValue::as_str), count) {
+                (_, Some(None)) | (_, Some(Some(0))) => Err(format!("Invalid count for {}.", name)),
+                (Some(to), count) => Ok((from, to.to_string(), count.flatten().map(|count| count as usize))),
+                (None, _) => Err(format!("{} requires the last key of the range.", name)),
+            }
+        }
+        _ => Err(format!("No key provided for {}.", name)),
+    }
+}
+
+/// Executes a `RANGE` command, returning the entries whose key is between two keys in lexicographic order.
+///
+/// Only available when the server runs with `--ordered-keys`. Pages are cut short when they reach the requested
+/// count or the maximum response size, in which case the response is `truncated` and the next page starts
+/// right after the last key returned.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` holding the first key and the `[to, count]` arguments, where `to` is
+///   inclusive and `count` optional.
+/// * `engine` - The database engine to read from.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with the `{ key, value }` entries in key
+/// order.
+pub fn range_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let (from, to, count) = match parse_range(args, "RANGE") {
+            Ok((from, to, count)) => (from, to, count.unwrap_or(DEFAULT_RANGE_COUNT)),
+            Err(message) => return Ok(keyrange_error(message)),
+        };
+
+        // The keys are read before the map, see `Store::read_ordered`
+        let Some(keys) = engine.connection.read_ordered() else {
+            return Ok(not_ordered());
+        };
+        let db_read = engine.connection.read();
+        let mut budget = ResponseBudget::new(engine.db_config.max_response_size);
+        let mut results = vec![];
+        let mut truncated = false;
+
+        for (key, data) in keys_between(&keys, &from, &to).filter_map(|key| db_read.get_key_value(key)) {
+            if results.len() == count {
+                break;
+            }
+
+            let entry = json!({ "key": key, "value": data.value });
+            if !budget.try_take(&entry) {
+                truncated = true;
+                break;
+            }
+            results.push(entry);
+        }
+
+        Ok(NetResponse {
+            action: NetActions::Command,
+            value: Some(JsonValue::Array(results)),
+            error: None,
+            truncated,
+            ..Default::default()
+        })
+    }
+    .boxed()
+}
+
+/// Executes a `DELRANGE` command, deleting the keys between two keys in lexicographic order.
+///
+/// Only available when the server runs with `--ordered-keys`. Keys are deleted at most `count` at a time so a
+/// large range does not hold back other writers: the response reports how many keys were deleted and how many
+/// are left in the range, and the command is sent again until none are.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` holding the first key and the `[to, count]` arguments, where `to` is
+///   inclusive and `count` optional.
+/// * `engine` - The database engine to delete from.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with the number of keys `deleted` and
+/// `remaining` in the range.
+pub fn delrange_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let (from, to, count) = match parse_range(args, "DELRANGE") {
+            Ok((from, to, count)) => (from, to, count.unwrap_or(DEFAULT_DELRANGE_COUNT)),
+            Err(message) => return Ok(keyrange_error(message)),
+        };
+
+        let Some(keys) = engine.connection.read_ordered() else {
+            return Ok(not_ordered());
+        };
+        let batch: Vec<DbKey> = keys_between(&keys, &from, &to).take(count).cloned().collect();
+
+        let committed = {
+            let mut db_write = engine.connection.write().await;
+            let deleted: Vec<DbKey> = batch.into_iter().filter(|key| db_write.contains_key(key)).collect();
+            let records = deleted.iter().map(|key| WalRecord::Delete(key.clone())).collect();
+            let committed = match commit(&engine, &mut db_write, records).await {
+                Ok(committed) => committed,
+                Err(e) => return Ok(wal_error(e)),
+            };
+
+            for key in &deleted {
+                engine.access.forget(key);
+            }
+            committed
+        };
+        let deleted = committed.keys().len();
+        committed.refresh(&engine).await;
+
+        let remaining = engine
+            .connection
+            .read_ordered()
+            .map_or(0, |keys| keys_between(&keys, &from, &to).count());
+
+        Ok(NetResponse {
+            action: NetActions::Command,
+            value: Some(json!({ "deleted": deleted, "remaining": remaining })),
+            error: None,
+            ..Default::default()
+        })
+    }
+    .boxed()
+}
+
+/// Executes a `COUNTRANGE` command, counting the keys between two keys in lexicographic order.
+///
+/// Only available when the server runs with `--ordered-keys`.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` holding the first key and the `[to]` argument, which is inclusive.
+/// * `engine` - The database engine to count in.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with the number of keys in the range.
+pub fn countrange_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let (from, to, _) = match parse_range(args, "COUNTRANGE") {
+            Ok(range) => range,
+            Err(message) => return Ok(keyrange_error(message)),
+        };
+
+        let Some(keys) = engine.connection.read_ordered() else {
+            return Ok(not_ordered());
+        };
+
+        Ok(NetResponse {
+            action: NetActions::Command,
+            value: Some(json!(keys_between(&keys, &from, &to).count())),
+            error: None,
+            ..Default::default()
+        })
+    }
+    .boxed()
+}
+
+/// Builds the error response for a range command sent to a server that does not keep its keys in order.
+fn not_ordered() -> NetResponse
+{
+    keyrange_error("Range commands require the server to run with --ordered-keys.".to_string())
+}
+
+/// Builds an error response for the range commands.
+fn keyrange_error(message: String) -> NetResponse
+{
+    NetResponse {
+        action: NetActions::Error,
+        value: None,
+        error: Some(message),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use clap::Parser;
+
+    use super::*;
+    use crate::cli::Cli;
+    use crate::protocol::DbValue;
+
+    #[tokio::test]
+    async fn test_range()
+    {
+        let engine = Arc::new(DbEngine::new(Cli::parse_from(["phoenix-db", "--ordered-keys"])));
+        {
+            let mut db_write = engine.connection.write().await;
+            for day in [
+                "log:2024-06-03",
+                "log:2024-06-01",
+                "log:2024-07-01",
+                "log:2024-06-02",
+                "user:1",
+            ] {
+                db_write.insert(
+                    day.to_string(),
+                    DbValue {
+                        value: json!(day),
+                        expires_at: None,
+                    },
+                );
+            }
+        }
+
+        let args = CommandArgs::WithArgs(Some("log:2024-06-02".to_string()), vec![json!("log:2024-06-99")]);
+        let response = range_command(args, engine.clone()).await.unwrap();
+        let keys: Vec<JsonValue> = response
+            .value
+            .unwrap()
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| e["key"].clone())
+            .collect();
+        assert_eq!(keys, vec![json!("log:2024-06-02"), json!("log:2024-06-03")]);
+
+        let args = CommandArgs::WithArgs(Some("log:".to_string()), vec![json!("log:~"), json!(1)]);
+        let response = range_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.value.unwrap()[0]["key"], json!("log:2024-06-01"));
+
+        let args = CommandArgs::WithArgs(Some("z".to_string()), vec![json!("a")]);
+        let response = range_command(args, engine).await.unwrap();
+        assert_eq!(response.value, Some(json!([])));
+    }
+
+    #[tokio::test]
+    async fn test_delrange_and_countrange()
+    {
+        let engine = Arc::new(DbEngine::new(Cli::parse_from(["phoenix-db", "--ordered-keys"])));
+        {
+            let mut db_write = engine.connection.write().await;
+            for n in 0..5 {
+                db_write.insert(
+                    format!("log:{}", n),
+                    DbValue {
+                        value: json!(n),
+                        expires_at: None,
+                    },
+                );
+            }
+        }
+        let range = |count: Option<u64>| {
+            let mut params = vec![json!("log:3")];
+            params.extend(count.map(|count| json!(count)));
+            CommandArgs::WithArgs(Some("log:0".to_string()), params)
+        };
+
+        let response = countrange_command(range(None), engine.clone()).await.unwrap();
+        assert_eq!(response.value, Some(json!(4)));
+
+        let response = delrange_command(range(Some(3)), engine.clone()).await.unwrap();
+        assert_eq!(response.value, Some(json!({ "deleted": 3, "remaining": 1 })));
+        let response = delrange_command(range(Some(3)), engine.clone()).await.unwrap();
+        assert_eq!(response.value, Some(json!({ "deleted": 1, "remaining": 0 })));
+
+        let db_read = engine.connection.read();
+        assert_eq!(db_read.len(), 1);
+        assert!(db_read.get("log:4").is_some());
+    }
+
+    #[tokio::test]
+    async fn test_range_requires_ordered_keys()
+    {
+        let engine = Arc::new(DbEngine::new(Cli::parse_from(["phoenix-db"])));
+
+        let args = CommandArgs::WithArgs(Some("a".to_string()), vec![json!("z")]);
+        let response = range_command(args, engine).await.unwrap();
+        assert_eq!(response.action, NetActions::Error);
+    }
+}
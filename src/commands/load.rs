@@ -0,0 +1,257 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::future::{BoxFuture, FutureExt};
+use serde_json::json;
+use tokio::time::Instant;
+
+use crate::commands::memory::{estimate_bytes, ENTRY_OVERHEAD};
+use crate::commands::CommandArgs;
+use crate::protocol::{DbEngine, JsonValue, NetActions, NetResponse};
+
+/// How long a load may go without a bulk insert using its token before its reservation is released.
+const LOAD_IDLE_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Bulk loads announced with `PREPARE LOAD`, holding memory for their entries until they are all inserted.
+#[derive(Debug, Default)]
+pub struct Loads
+{
+    /// The token handed out to the next load.
+    next_token: AtomicU64,
+    /// The loads in progress, keyed by their token.
+    active: Mutex<HashMap<u64, Load>>,
+}
+
+/// A bulk load in progress.
+#[derive(Debug)]
+struct Load
+{
+    /// The number of entries announced but not inserted yet.
+    remaining: u64,
+    /// The memory reserved for each entry, in bytes.
+    entry_bytes: usize,
+    /// Whether TTL sweeps and write-ahead log compactions wait for the load to end.
+    pause: bool,
+    /// When the load last inserted entries, used to release abandoned reservations.
+    last_activity: Instant,
+}
+
+/// Entries of a load used up by a bulk insert, given back with [`Loads::give_back`] if the insert fails.
+#[derive(Debug)]
+pub struct Claim
+{
+    /// The token of the load.
+    token: u64,
+    /// The number of entries used up.
+    entries: u64,
+    /// The load, if the claim ended it.
+    ended: Option<Load>,
+}
+
+impl Loads
+{
+    /// Releases the reservations of the loads that have been idle for too long.
+    fn prune(active: &mut HashMap<u64, Load>)
+    {
+        active.retain(|_, load| load.last_activity.elapsed() < LOAD_IDLE_TIMEOUT);
+    }
+
+    /// Returns the memory reserved for the entries of the loads in progress, in bytes.
+    pub fn reserved_bytes(&self) -> usize
+    {
+        let mut active = self.active.lock().unwrap();
+        Self::prune(&mut active);
+        active
+            .values()
+            .map(|load| (load.remaining as usize).saturating_mul(load.entry_bytes))
+            .fold(0, usize::saturating_add)
+    }
+
+    /// Returns whether a load in progress asked for the background jobs rewriting data to wait for it.
+    pub fn pauses_background(&self) -> bool
+    {
+        let mut active = self.active.lock().unwrap();
+        Self::prune(&mut active);
+        active.values().any(|load| load.pause)
+    }
+
+    /// Uses up the reservation of a load for `entries` inserted entries, ending the load once they are all in.
+    ///
+    /// # Returns
+    ///
+    /// The claim on the entries, to give back if they end up not being inserted.
+    pub fn take(&self, token: u64, entries: u64) -> Result<Claim, String>
+    {
+        let mut active = self.active.lock().unwrap();
+        Self::prune(&mut active);
+        let load = active
+            .get_mut(&token)
+            .ok_or_else(|| format!("Load {} does not exist or has expired.", token))?;
+        if entries > load.remaining {
+            return Err(format!(
+                "Load {} has {} entries left, {} were sent.",
+                token, load.remaining, entries
+            ));
+        }
+
+        load.remaining -= entries;
+        load.last_activity = Instant::now();
+        let ended = if load.remaining == 0 { active.remove(&token) } else { None };
+        Ok(Claim { token, entries, ended })
+    }
+
+    /// Gives back the entries of a claim whose insert failed, starting its load again if the claim ended it.
+    pub fn give_back(&self, claim: Claim)
+    {
+        let mut active = self.active.lock().unwrap();
+        if let Some(load) = claim.ended {
+            active.insert(claim.token, load);
+        }
+        if let Some(load) = active.get_mut(&claim.token) {
+            load.remaining += claim.entries;
+        }
+    }
+}
+
+/// Executes a `PREPARE LOAD` command, checking there is room for a bulk load and reserving it.
+///
+/// The load is sized from its number of entries and their average size. It fails if it would not fit under
+/// `--max-memory` along with the loads already in progress. Otherwise the memory is reserved until the entries
+/// are inserted with `INSERT *` commands carrying the returned token in their `args`, which are accepted even
+/// once writes are rejected for memory. A load unused for ten minutes gives its reservation up.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` holding the `[entries, avg_size, pause]` of the load. With `pause` set,
+///   TTL sweeps and write-ahead log compactions wait until the load ends.
+/// * `engine` - The database engine the load is for.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with the `token` of the load, the
+/// `reserved_bytes` and the `headroom_bytes` left under `--max-memory` after the reservation, if it is set.
+pub fn prepare_load_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let params = match args {
+            CommandArgs::WithArgs(_, params) => params,
+            _ => vec![],
+        };
+        let (Some(entries), Some(avg_size)) = (
+            params.first().and_then(JsonValue::as_u64),
+            params.get(1).and_then(JsonValue::as_u64),
+        ) else {
+            return Ok(load_error(
+                "PREPARE LOAD requires the number of entries and their average size.".to_string(),
+            ));
+        };
+        let pause = params.get(2).and_then(JsonValue::as_bool).unwrap_or(false);
+
+        let entry_bytes = avg_size as usize + ENTRY_OVERHEAD;
+        let reserved = (entries as usize).saturating_mul(entry_bytes);
+        let headroom = match engine.memory.limit() {
+            Some(limit) => {
+                let used = estimate_bytes(&engine).saturating_add(engine.loads.reserved_bytes());
+                match limit.checked_sub(used).and_then(|free| free.checked_sub(reserved)) {
+                    Some(headroom) => Some(headroom),
+                    None => {
+                        return Ok(load_error(format!(
+                            "Not enough memory for the load, {} bytes are needed and {} are free.",
+                            reserved,
+                            limit.saturating_sub(used)
+                        )));
+                    }
+                }
+            }
+            None => None,
+        };
+
+        let token = engine.loads.next_token.fetch_add(1, Ordering::Relaxed);
+        if entries > 0 {
+            let load = Load {
+                remaining: entries,
+                entry_bytes,
+                pause,
+                last_activity: Instant::now(),
+            };
+            engine.loads.active.lock().unwrap().insert(token, load);
+        }
+
+        Ok(NetResponse {
+            action: NetActions::Command,
+            value: Some(json!({
+                "token": token,
+                "reserved_bytes": reserved,
+                "headroom_bytes": headroom,
+            })),
+            error: None,
+            ..Default::default()
+        })
+    }
+    .boxed()
+}
+
+fn load_error(message: String) -> NetResponse
+{
+    NetResponse {
+        action: NetActions::Error,
+        value: None,
+        error: Some(message),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use clap::Parser;
+
+    use super::*;
+    use crate::cli::Cli;
+
+    async fn prepare(engine: &Arc<DbEngine>, params: Vec<JsonValue>) -> NetResponse
+    {
+        prepare_load_command(CommandArgs::WithArgs(None, params), engine.clone())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_prepare_load()
+    {
+        let engine = Arc::new(DbEngine::new(Cli::parse_from(["phoenix-db", "--max-memory", "100000"])));
+
+        let response = prepare(&engine, vec![json!(1000), json!(1000)]).await;
+        assert_eq!(response.action, NetActions::Error);
+        assert!(prepare(&engine, vec![json!(10)]).await.error.is_some());
+
+        let response = prepare(&engine, vec![json!(10), json!(100), json!(true)]).await;
+        let value = response.value.unwrap();
+        let token = value["token"].as_u64().unwrap();
+        let reserved = 10 * (100 + ENTRY_OVERHEAD);
+        assert_eq!(value["reserved_bytes"], json!(reserved));
+        assert_eq!(value["headroom_bytes"], json!(100000 - reserved));
+        assert_eq!(engine.loads.reserved_bytes(), reserved);
+        assert!(engine.loads.pauses_background());
+
+        // The reservation shrinks as entries are inserted, and is released once they all are
+        engine.loads.take(token, 4).unwrap();
+        assert_eq!(engine.loads.reserved_bytes(), 6 * (100 + ENTRY_OVERHEAD));
+        assert!(engine.loads.take(token, 7).is_err());
+        let claim = engine.loads.take(token, 6).unwrap();
+        assert_eq!(engine.loads.reserved_bytes(), 0);
+        assert!(!engine.loads.pauses_background());
+        assert!(engine.loads.take(token, 1).is_err());
+
+        // A failed insert gives its entries back, even once it ended the load
+        engine.loads.give_back(claim);
+        assert_eq!(engine.loads.reserved_bytes(), 6 * (100 + ENTRY_OVERHEAD));
+        assert!(engine.loads.pauses_background());
+    }
+}
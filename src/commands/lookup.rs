@@ -1,9 +1,16 @@
 use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
 
 use futures::future::{BoxFuture, FutureExt};
+use tracing::warn;
 
+use crate::commands::budget::ResponseBudget;
+use crate::commands::commit::commit_local;
+use crate::commands::mount::{not_mounted, split_mounted_key};
 use crate::commands::CommandArgs;
-use crate::protocol::{Database, JsonValue, NetActions, NetResponse};
+use crate::protocol::{expiry_after, DbEngine, DbKey, DbValue, JsonValue, NetActions, NetResponse};
+use crate::wal::WalRecord;
 
 /// Executes a lookup command on the database.
 ///
@@ -13,31 +20,57 @@ use crate::protocol::{Database, JsonValue, NetActions, NetResponse};
 /// # Arguments
 ///
 /// * `args` - The arguments for the command, which could be a single key or multiple key-value pairs.
-/// * `db` - The database instance used for lookups.
+/// * `engine` - The database engine used for lookups.
 ///
 /// # Returns
 ///
 /// A `BoxFuture` that resolves to a `Result` containing a `NetResponse`. The response indicates the success
 /// or failure of the lookup operation.
-pub fn lookup_command(args: CommandArgs, db: Database) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+pub fn lookup_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
 {
     async move {
+        let db = &engine.connection;
         // Match on the provided command arguments to determine the appropriate action
         let response = match args {
-            // Handle single key lookup
-            CommandArgs::Single(Some(key), ..) => {
-                let db_read = db.read().await;
-                match db_read.get(&key) {
-                    Some(data) => NetResponse {
-                        action: NetActions::Command,
-                        value: Some(data.value.to_owned()),
-                        error: None,
-                    },
-                    None => NetResponse {
+            // Handle single key lookup in a mounted snapshot
+            CommandArgs::Single(Some(key), ..) if split_mounted_key(&key).is_some() => {
+                let (mount, key) = split_mounted_key(&key).unwrap();
+                match engine.mounts.read().await.get(mount) {
+                    Some(map) => NetResponse {
                         action: NetActions::Command,
-                        value: None,
+                        value: map.get(key).map(|data| data.value.to_owned()),
                         error: None,
+                        ..Default::default()
                     },
+                    None => not_mounted(mount),
+                }
+            }
+            // Handle single key lookup
+            CommandArgs::Single(Some(key), ..) => {
+                let db_read = db.read();
+                match db_read.get(&key) {
+                    Some(data) => {
+                        engine.metrics.keyspace_hits.increment();
+                        engine.access.record(&key);
+                        NetResponse {
+                            action: NetActions::Command,
+                            value: Some(data.value.to_owned()),
+                            error: None,
+                            ..Default::default()
+                        }
+                    }
+                    None => {
+                        engine.metrics.keyspace_misses.increment();
+                        NetResponse {
+                            action: NetActions::Command,
+                            value: read_through(&engine, key).await,
+                            error: None,
+                            ..Default::default()
+                        }
+                    }
                 }
             }
             // Handle case where no key is provided
@@ -45,22 +78,45 @@ pub fn lookup_command(args: CommandArgs, db: Database) -> BoxFuture<'static, Res
                 action: NetActions::Error,
                 value: None,
                 error: Some("No key provided for lookup.".to_string()),
+                ..Default::default()
             },
             // Handle bulk lookup
             CommandArgs::Many(pairs) => {
-                let db_read = db.read().await;
+                let db_read = db.read();
+                let mounts = engine.mounts.read().await;
+                let mut budget = ResponseBudget::new(engine.db_config.max_response_size);
                 let mut results = Vec::new();
 
-                for pair in pairs {
+                for (index, pair) in pairs.into_iter().enumerate() {
                     if let Some(key) = pair.key {
-                        if let Some(data) = db_read.get(&key) {
+                        let data = match split_mounted_key(&key) {
+                            Some((mount, key)) => mounts.get(mount).and_then(|map| map.get(key)),
+                            None => db_read.get(&key),
+                        };
+                        if let Some(data) = data {
+                            if !budget.try_take(&data.value) {
+                                // The client resumes by sending the keys again, starting at the cursor
+                                return Ok(NetResponse {
+                                    action: NetActions::Command,
+                                    value: Some(JsonValue::Array(results)),
+                                    error: None,
+                                    truncated: true,
+                                    cursor: Some(index),
+                                    ..Default::default()
+                                });
+                            }
+                            engine.metrics.keyspace_hits.increment();
+                            engine.access.record(&key);
                             results.push(data.value.to_owned());
+                        } else {
+                            engine.metrics.keyspace_misses.increment();
                         }
                     } else {
                         return Ok(NetResponse {
                             action: NetActions::Error,
                             value: None,
                             error: Some("Missing key in bulk lookup.".to_string()),
+                            ..Default::default()
                         });
                     }
                 }
@@ -69,8 +125,15 @@ pub fn lookup_command(args: CommandArgs, db: Database) -> BoxFuture<'static, Res
                     action: NetActions::Command,
                     value: Some(JsonValue::Array(results)),
                     error: None,
+                    ..Default::default()
                 }
             }
+            CommandArgs::WithArgs(..) => NetResponse {
+                action: NetActions::Error,
+                value: None,
+                error: Some("Invalid arguments for lookup.".to_string()),
+                ..Default::default()
+            },
         };
 
         Ok(response)
@@ -78,32 +141,65 @@ pub fn lookup_command(args: CommandArgs, db: Database) -> BoxFuture<'static, Res
     .boxed()
 }
 
+/// Looks up a key missing from the database on the upstream server, if there is one.
+///
+/// A value found there is kept in the database for `--upstream-ttl` seconds, unless the key was written while it was
+/// being looked up, in which case the local write wins.
+async fn read_through(engine: &DbEngine, key: DbKey) -> Option<JsonValue>
+{
+    let upstream = engine.upstream.as_ref()?;
+
+    match upstream.lookup(&key).await {
+        Ok(Some(value)) => {
+            let data = DbValue {
+                value: value.clone(),
+                expires_at: Some(expiry_after(Duration::from_secs(engine.db_config.upstream_ttl))),
+            };
+            let mut db_write = engine.connection.write().await;
+            if !upstream.finish_lookup(&key) || db_write.contains_key(&key) {
+                return Some(value);
+            }
+            match commit_local(engine, &mut db_write, vec![WalRecord::Insert(key.clone(), data)]).await {
+                Ok(committed) => {
+                    drop(db_write);
+                    committed.refresh(engine).await;
+                }
+                Err(e) => warn!("Failed to keep {} read through upstream {}: {}", key, upstream.addr(), e),
+            }
+            Some(value)
+        }
+        Ok(None) => {
+            upstream.finish_lookup(&key);
+            None
+        }
+        Err(e) => {
+            warn!("Failed to read {} through upstream {}: {}", key, upstream.addr(), e);
+            None
+        }
+    }
+}
+
 #[cfg(test)]
 mod test
 {
-    use std::collections::HashMap;
     use std::sync::Arc;
 
+    use clap::Parser;
     use serde_json::json;
-    use tokio::sync::RwLock;
 
     use super::*;
-    use crate::protocol::DbValue;
-
-    // Helper function to create a new in-memory database
-    fn create_fake_db() -> Database
-    {
-        Arc::new(RwLock::new(HashMap::new()))
-    }
+    use crate::cli::Cli;
+    use crate::testing::create_fake_engine;
 
     #[tokio::test]
     async fn test_single_lookup_existing_key()
     {
-        let db = create_fake_db();
+        let engine = create_fake_engine();
+        let db = &engine.connection;
         let key = "test_key".to_string();
         let data = DbValue {
             value: json!("test_value"),
-            expires_in: None,
+            expires_at: None,
         };
 
         {
@@ -112,7 +208,7 @@ mod test
         }
 
         let args = CommandArgs::Single(Some(key.clone()), None);
-        let response = lookup_command(args, db.clone()).await.unwrap();
+        let response = lookup_command(args, engine.clone()).await.unwrap();
 
         // Check that the response indicates success and returns the correct value
         assert_eq!(response.action, NetActions::Command);
@@ -123,11 +219,11 @@ mod test
     #[tokio::test]
     async fn test_single_lookup_missing_key()
     {
-        let db = create_fake_db();
+        let engine = create_fake_engine();
         let key = "non_existent_key".to_string();
 
         let args = CommandArgs::Single(Some(key), None);
-        let response = lookup_command(args, db.clone()).await.unwrap();
+        let response = lookup_command(args, engine.clone()).await.unwrap();
 
         // Check that the response indicates success but with no value
         assert_eq!(response.action, NetActions::Command);
@@ -138,9 +234,9 @@ mod test
     #[tokio::test]
     async fn test_single_lookup_no_key_provided()
     {
-        let db = create_fake_db();
+        let engine = create_fake_engine();
         let args = CommandArgs::Single(None, None);
-        let response = lookup_command(args, db.clone()).await.unwrap();
+        let response = lookup_command(args, engine.clone()).await.unwrap();
 
         // Check that the response indicates an error due to missing key
         assert_eq!(response.action, NetActions::Error);
@@ -151,17 +247,18 @@ mod test
     #[tokio::test]
     async fn test_bulk_lookup()
     {
-        let db = create_fake_db();
+        let engine = create_fake_engine();
+        let db = &engine.connection;
         let key1 = "key1".to_string();
         let key2 = "key2".to_string();
         let value1 = DbValue {
             value: json!("value1"),
-            expires_in: None,
+            expires_at: None,
         };
 
         let value2 = DbValue {
             value: json!("value2"),
-            expires_in: None,
+            expires_at: None,
         };
 
         {
@@ -174,31 +271,71 @@ mod test
             crate::commands::CommandParams {
                 key: Some(key1.clone()),
                 value: None,
-                ttl: None,
+                expires_at: None,
             },
             crate::commands::CommandParams {
                 key: Some(key2.clone()),
                 value: None,
-                ttl: None,
+                expires_at: None,
             },
         ]);
 
-        let response = lookup_command(args, db.clone()).await.unwrap();
+        let response = lookup_command(args, engine.clone()).await.unwrap();
 
         // Check that the response indicates success and returns the correct values
         assert_eq!(response.action, NetActions::Command);
         assert_eq!(response.value, Some(JsonValue::Array(vec![value1.value, value2.value])));
         assert!(response.error.is_none());
+        assert!(!response.truncated);
+    }
+
+    #[tokio::test]
+    async fn test_bulk_lookup_truncated()
+    {
+        let engine = Arc::new(DbEngine::new(Cli::parse_from(["phoenix-db", "--max-response-size", "10"])));
+        let keys = ["key1", "key2", "key3"];
+
+        {
+            let mut db_write = engine.connection.write().await;
+            for key in keys {
+                db_write.insert(
+                    key.to_string(),
+                    DbValue {
+                        value: json!("value"),
+                        expires_at: None,
+                    },
+                );
+            }
+        }
+
+        let args = CommandArgs::Many(
+            keys.iter()
+                .map(|key| crate::commands::CommandParams {
+                    key: Some(key.to_string()),
+                    value: None,
+                    expires_at: None,
+                })
+                .collect(),
+        );
+
+        let response = lookup_command(args, engine.clone()).await.unwrap();
+
+        // Each value takes 7 bytes, so only the first one fits
+        assert_eq!(response.action, NetActions::Command);
+        assert_eq!(response.value, Some(json!(["value"])));
+        assert!(response.truncated);
+        assert_eq!(response.cursor, Some(1));
     }
 
     #[tokio::test]
     async fn test_bulk_lookup_missing_keys()
     {
-        let db = create_fake_db();
+        let engine = create_fake_engine();
+        let db = &engine.connection;
         let key1 = "key1".to_string();
         let value1 = DbValue {
             value: json!("value1"),
-            expires_in: None,
+            expires_at: None,
         };
 
         {
@@ -210,16 +347,16 @@ mod test
             crate::commands::CommandParams {
                 key: Some(key1.clone()),
                 value: None,
-                ttl: None,
+                expires_at: None,
             },
             crate::commands::CommandParams {
                 key: None,
                 value: None,
-                ttl: None,
+                expires_at: None,
             },
         ]);
 
-        let response = lookup_command(args, db.clone()).await.unwrap();
+        let response = lookup_command(args, engine.clone()).await.unwrap();
 
         // Check that the response indicates an error due to missing key
         assert_eq!(response.action, NetActions::Error);
@@ -232,9 +369,9 @@ mod test
             CommandArgs::Many(vec![crate::commands::CommandParams {
                 key: Some(key1),
                 value: None,
-                ttl: None,
+                expires_at: None,
             }]),
-            db.clone(),
+            engine.clone(),
         )
         .await
         .unwrap();
@@ -0,0 +1,290 @@
+use std::error::Error;
+use std::mem::size_of;
+use std::sync::Arc;
+
+use futures::future::{BoxFuture, FutureExt};
+use rand::Rng;
+use serde_json::json;
+
+use crate::commands::CommandArgs;
+use crate::protocol::{json_size, DbEngine, DbKey, DbValue, JsonValue, NetActions, NetResponse};
+
+/// The number of entries `MEMORY SAMPLE` looks at when no count is given, and that `MEMORY DOCTOR` looks at.
+const DEFAULT_SAMPLE_SIZE: usize = 1000;
+
+/// Values serialized to more bytes than this are reported by `MEMORY DOCTOR`.
+const HUGE_VALUE_BYTES: usize = 1024 * 1024;
+
+/// Values nested deeper than this are reported by `MEMORY DOCTOR`.
+const DEEP_VALUE_DEPTH: usize = 32;
+
+/// The memory used by an entry on top of its key and value bytes: the entry itself and the map node holding it.
+pub const ENTRY_OVERHEAD: usize = size_of::<DbKey>() + size_of::<DbValue>() + 2 * size_of::<usize>();
+
+/// What was learned about the entries looked at by a memory sample.
+struct Sample
+{
+    /// The number of entries sampled.
+    entries: usize,
+    /// The combined size of the sampled keys.
+    key_bytes: usize,
+    /// The combined serialized size of the sampled values.
+    value_bytes: usize,
+    /// The largest sampled value and its size.
+    largest: Option<(DbKey, usize)>,
+    /// The most deeply nested sampled value and its depth.
+    deepest: Option<(DbKey, usize)>,
+    /// Sampled entries whose value is larger than `HUGE_VALUE_BYTES`.
+    huge: Vec<(DbKey, usize)>,
+    /// Sampled entries whose value is nested deeper than `DEEP_VALUE_DEPTH`.
+    deep: Vec<(DbKey, usize)>,
+}
+
+impl Sample
+{
+    /// Estimates the memory used by the whole database from the sampled entries.
+    fn estimated_bytes(&self, total_entries: usize) -> usize
+    {
+        match (self.key_bytes + self.value_bytes).checked_div(self.entries) {
+            Some(per_entry) => (per_entry + ENTRY_OVERHEAD) * total_entries,
+            None => 0,
+        }
+    }
+
+    /// Describes the sample for a response.
+    fn to_json(&self, total_entries: usize) -> JsonValue
+    {
+        let average = |bytes: usize| bytes.checked_div(self.entries).unwrap_or(0);
+
+        json!({
+            "sampled": self.entries,
+            "keys": total_entries,
+            "avg_key_bytes": average(self.key_bytes),
+            "avg_value_bytes": average(self.value_bytes),
+            "entry_overhead_bytes": ENTRY_OVERHEAD,
+            "estimated_bytes": self.estimated_bytes(total_entries),
+            "largest": self.largest.as_ref().map(|(key, bytes)| json!({ "key": key, "bytes": bytes })),
+            "deepest": self.deepest.as_ref().map(|(key, depth)| json!({ "key": key, "depth": depth })),
+        })
+    }
+}
+
+/// Looks at up to `count` entries of the database.
+///
+/// The entries are taken one after the other from a random position. Since the database iterates in hash
+/// order, neighbouring entries are unrelated and this behaves like a random sample.
+fn sample(engine: &DbEngine, count: usize) -> (Sample, usize)
+{
+    let db_read = engine.connection.read();
+    let total_entries = db_read.len();
+    let start = if total_entries > count {
+        rand::thread_rng().gen_range(0..total_entries)
+    } else {
+        0
+    };
+
+    let mut sample = Sample {
+        entries: 0,
+        key_bytes: 0,
+        value_bytes: 0,
+        largest: None,
+        deepest: None,
+        huge: vec![],
+        deep: vec![],
+    };
+
+    for (key, data) in db_read
+        .iter()
+        .skip(start)
+        .chain(db_read.iter())
+        .take(count.min(total_entries))
+    {
+        let bytes = json_size(&data.value);
+        let depth = json_depth(&data.value);
+
+        sample.entries += 1;
+        sample.key_bytes += key.len();
+        sample.value_bytes += bytes;
+
+        if sample.largest.as_ref().is_none_or(|(_, largest)| bytes > *largest) {
+            sample.largest = Some((key.clone(), bytes));
+        }
+        if sample.deepest.as_ref().is_none_or(|(_, deepest)| depth > *deepest) {
+            sample.deepest = Some((key.clone(), depth));
+        }
+        if bytes > HUGE_VALUE_BYTES {
+            sample.huge.push((key.clone(), bytes));
+        }
+        if depth > DEEP_VALUE_DEPTH {
+            sample.deep.push((key.clone(), depth));
+        }
+    }
+
+    (sample, total_entries)
+}
+
+/// Estimates the memory used by the whole database from a sample of entries, as `MEMORY SAMPLE` does.
+pub fn estimate_bytes(engine: &DbEngine) -> usize
+{
+    let (sample, total_entries) = sample(engine, DEFAULT_SAMPLE_SIZE);
+    sample.estimated_bytes(total_entries)
+}
+
+/// Returns how many levels of arrays and objects a value is made of.
+fn json_depth(value: &JsonValue) -> usize
+{
+    match value {
+        JsonValue::Array(items) => 1 + items.iter().map(json_depth).max().unwrap_or(0),
+        JsonValue::Object(fields) => 1 + fields.values().map(json_depth).max().unwrap_or(0),
+        _ => 0,
+    }
+}
+
+/// Executes a `MEMORY SAMPLE` command, estimating memory use from a sample of entries.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` optionally holding the `[count]` of entries to sample.
+/// * `engine` - The database engine to sample.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with the average key and value sizes,
+/// the estimated memory use and the largest and deepest sampled values.
+pub fn memory_sample_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let response = match args {
+            CommandArgs::WithArgs(_, params) => {
+                let count = params
+                    .first()
+                    .and_then(JsonValue::as_u64)
+                    .map(|count| count as usize)
+                    .unwrap_or(DEFAULT_SAMPLE_SIZE);
+                let (sample, total_entries) = sample(&engine, count);
+
+                NetResponse {
+                    action: NetActions::Command,
+                    value: Some(sample.to_json(total_entries)),
+                    error: None,
+                    ..Default::default()
+                }
+            }
+            _ => NetResponse {
+                action: NetActions::Error,
+                value: None,
+                error: Some("Invalid arguments for memory sample.".to_string()),
+                ..Default::default()
+            },
+        };
+
+        Ok(response)
+    }
+    .boxed()
+}
+
+/// Executes a `MEMORY DOCTOR` command, looking for values likely to cause memory trouble.
+///
+/// # Arguments
+///
+/// * `_args` - Unused, `MEMORY DOCTOR` takes no arguments.
+/// * `engine` - The database engine to inspect.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with the sample summary and a list of
+/// human readable findings.
+pub fn memory_doctor_command(
+    _args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let (sample, total_entries) = sample(&engine, DEFAULT_SAMPLE_SIZE);
+        let mut findings = vec![];
+
+        for (key, bytes) in &sample.huge {
+            findings.push(format!("{} holds a huge value of {} bytes.", key, bytes));
+        }
+        for (key, depth) in &sample.deep {
+            findings.push(format!("{} holds a value nested {} levels deep.", key, depth));
+        }
+        if findings.is_empty() {
+            findings.push("No anomalies found in the sampled entries.".to_string());
+        }
+
+        let mut report = sample.to_json(total_entries);
+        report["findings"] = json!(findings);
+
+        Ok(NetResponse {
+            action: NetActions::Command,
+            value: Some(report),
+            error: None,
+            ..Default::default()
+        })
+    }
+    .boxed()
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use crate::testing::create_fake_engine;
+
+    #[test]
+    fn test_json_depth()
+    {
+        assert_eq!(json_depth(&json!(1)), 0);
+        assert_eq!(json_depth(&json!([1, [2, { "a": [] }]])), 4);
+    }
+
+    #[tokio::test]
+    async fn test_memory_sample()
+    {
+        let engine = create_fake_engine();
+        {
+            let mut db_write = engine.connection.write().await;
+            for i in 0..10 {
+                db_write.insert(
+                    format!("key{}", i),
+                    DbValue {
+                        value: json!("value"),
+                        expires_at: None,
+                    },
+                );
+            }
+        }
+
+        let args = CommandArgs::WithArgs(None, vec![json!(5)]);
+        let report = memory_sample_command(args, engine).await.unwrap().value.unwrap();
+
+        assert_eq!(report["sampled"], json!(5));
+        assert_eq!(report["keys"], json!(10));
+        assert_eq!(report["avg_key_bytes"], json!(4));
+        assert_eq!(report["avg_value_bytes"], json!(7));
+    }
+
+    #[tokio::test]
+    async fn test_memory_doctor_reports_deep_values()
+    {
+        let engine = create_fake_engine();
+        let mut value = json!(1);
+        for _ in 0..40 {
+            value = json!([value]);
+        }
+        engine
+            .connection
+            .write()
+            .await
+            .insert("deep".to_string(), DbValue { value, expires_at: None });
+
+        let args = CommandArgs::WithArgs(None, vec![]);
+        let report = memory_doctor_command(args, engine).await.unwrap().value.unwrap();
+
+        assert_eq!(report["findings"], json!(["deep holds a value nested 40 levels deep."]));
+    }
+}
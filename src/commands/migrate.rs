@@ -0,0 +1,228 @@
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::{BoxFuture, FutureExt};
+use tokio::time::{sleep, timeout};
+use tracing::warn;
+
+use crate::client::Client;
+use crate::commands::commit::commit_local;
+use crate::commands::insert::wal_error;
+use crate::commands::CommandArgs;
+use crate::protocol::{DbEngine, DbValue, JsonValue, NetActions, NetCommand, NetResponse};
+use crate::wal::WalRecord;
+
+/// The number of times a migration is attempted before giving up.
+const MIGRATE_ATTEMPTS: u32 = 3;
+
+/// How long to wait between two migration attempts.
+const MIGRATE_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// How long a single attempt can take, from connecting to receiving the acknowledgement.
+const MIGRATE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Executes a `MIGRATE` command, moving an entry to another phoenix-db server.
+///
+/// The value, TTL and tags of the entry are written to the target with an `INSERT`. The entry stays readable
+/// here until the target acknowledges the write, and is then removed unless `COPY` is given. If the entry was
+/// changed in the meantime it is kept, since the target holds an outdated version.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` holding the key and the `[target, "COPY"]` arguments, where `target` is
+///   the `host:port` of the other server and `COPY` is optional.
+/// * `engine` - The database engine holding the entry.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with `true` if the entry was moved or
+/// copied, `false` if it does not exist.
+pub fn migrate_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let (key, target, copy) = match args {
+            CommandArgs::WithArgs(Some(key), params) => match params.first().and_then(JsonValue::as_str) {
+                Some(target) => {
+                    let copy = params
+                        .get(1)
+                        .and_then(JsonValue::as_str)
+                        .is_some_and(|flag| flag.eq_ignore_ascii_case("COPY"));
+                    (key, target.to_string(), copy)
+                }
+                None => {
+                    return Ok(NetResponse {
+                        action: NetActions::Error,
+                        value: None,
+                        error: Some("No target provided for migrate.".to_string()),
+                        ..Default::default()
+                    });
+                }
+            },
+            _ => {
+                return Ok(NetResponse {
+                    action: NetActions::Error,
+                    value: None,
+                    error: Some("Invalid arguments for migrate.".to_string()),
+                    ..Default::default()
+                });
+            }
+        };
+
+        let Some(data) = engine.connection.read().get(&key).cloned() else {
+            return Ok(NetResponse {
+                action: NetActions::Command,
+                value: Some(false.into()),
+                error: None,
+                ..Default::default()
+            });
+        };
+        let tags = engine.tags.read().await.tags(&key);
+
+        let mut attempt = 1;
+        loop {
+            match timeout(MIGRATE_TIMEOUT, send_entry(&target, &key, &data, &tags)).await {
+                Ok(Ok(())) => break,
+                Ok(Err(e)) if attempt >= MIGRATE_ATTEMPTS => {
+                    return Ok(migrate_failed(&target, &e));
+                }
+                Err(_) if attempt >= MIGRATE_ATTEMPTS => {
+                    return Ok(migrate_failed(&target, "timed out"));
+                }
+                Ok(Err(e)) => warn!("Attempt {} to migrate {} to {} failed: {}", attempt, key, target, e),
+                Err(_) => warn!("Attempt {} to migrate {} to {} timed out", attempt, key, target),
+            }
+
+            attempt += 1;
+            sleep(MIGRATE_RETRY_DELAY).await;
+        }
+
+        if !copy {
+            let mut db_write = engine.connection.write().await;
+            if db_write.get(&key) == Some(&data) {
+                let committed = match commit_local(&engine, &mut db_write, vec![WalRecord::Delete(key.clone())]).await {
+                    Ok(committed) => committed,
+                    Err(e) => return Ok(wal_error(e)),
+                };
+                drop(db_write);
+                committed.refresh(&engine).await;
+                engine.access.forget(&key);
+            }
+        }
+
+        Ok(NetResponse {
+            action: NetActions::Command,
+            value: Some(true.into()),
+            error: None,
+            ..Default::default()
+        })
+    }
+    .boxed()
+}
+
+/// Writes an entry to another server and waits for it to acknowledge the write.
+async fn send_entry(target: &str, key: &str, data: &DbValue, tags: &[String]) -> Result<(), String>
+{
+    let command = NetCommand {
+        name: "INSERT",
+        keys: Some(vec![key]),
+        values: Some(vec![data.clone()]),
+        ttls: None,
+        args: None,
+        tags: Some(tags.iter().map(String::as_str).collect()),
+        batch: None,
+        durability: None,
+        fields: None,
+        progress: None,
+        id: None,
+    };
+
+    let response = Client::new(target).request(&command).await?;
+    match response.action {
+        NetActions::Command | NetActions::Event | NetActions::Chunk | NetActions::Progress => Ok(()),
+        NetActions::Error | NetActions::ShuttingDown => Err(response.error.unwrap_or_else(|| "unknown error".to_string())),
+    }
+}
+
+/// Builds the response sent when every attempt to migrate an entry failed.
+fn migrate_failed(target: &str, reason: &str) -> NetResponse
+{
+    NetResponse {
+        action: NetActions::Error,
+        value: None,
+        error: Some(format!("Failed to migrate to {}: {}.", target, reason)),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use serde_json::json;
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::testing::{create_fake_engine, start_server};
+
+    async fn insert(engine: &DbEngine, key: &str)
+    {
+        engine.connection.write().await.insert(
+            key.to_string(),
+            DbValue {
+                value: json!("value"),
+                expires_at: None,
+            },
+        );
+        engine.tags.write().await.set_tags(key, &["tag".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_migrate_moves_entry()
+    {
+        let source = create_fake_engine();
+        let target = create_fake_engine();
+        let addr = start_server(target.clone()).await;
+        insert(&source, "key").await;
+
+        let args = CommandArgs::WithArgs(Some("key".to_string()), vec![json!(addr)]);
+        let response = migrate_command(args, source.clone()).await.unwrap();
+
+        assert_eq!(response.value, Some(json!(true)));
+        assert!(source.connection.read().get("key").is_none());
+        assert_eq!(target.connection.read().get("key").unwrap().value, json!("value"));
+        assert_eq!(target.tags.read().await.keys("tag"), vec!["key".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_migrate_copy_keeps_entry()
+    {
+        let source = create_fake_engine();
+        let target = create_fake_engine();
+        let addr = start_server(target.clone()).await;
+        insert(&source, "key").await;
+
+        let args = CommandArgs::WithArgs(Some("key".to_string()), vec![json!(addr), json!("COPY")]);
+        migrate_command(args, source.clone()).await.unwrap();
+
+        assert!(source.connection.read().get("key").is_some());
+        assert!(target.connection.read().get("key").is_some());
+    }
+
+    #[tokio::test]
+    async fn test_migrate_unreachable_target()
+    {
+        let source = create_fake_engine();
+        insert(&source, "key").await;
+
+        // Bind and drop a listener to get a port nothing listens on
+        let addr = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+        let args = CommandArgs::WithArgs(Some("key".to_string()), vec![json!(addr.to_string())]);
+        let response = migrate_command(args, source.clone()).await.unwrap();
+
+        assert_eq!(response.action, NetActions::Error);
+        assert!(source.connection.read().get("key").is_some());
+    }
+}
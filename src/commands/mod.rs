@@ -1,27 +1,103 @@
 use std::collections::HashMap;
 use std::error::Error;
+use std::future::Future;
 use std::sync::Arc;
 use std::time::Duration;
 
 use futures::future::BoxFuture;
 use once_cell::sync::Lazy;
 use serde_json::Value;
+use tracing::warn;
 
+use crate::cli::Durability;
+use crate::commands::backup::{backup_incremental_command, backup_verify_command};
+use crate::commands::compact::compact_command;
+use crate::commands::config::{config_get_command, config_set_command};
 use crate::commands::delete::delete_command;
-use crate::commands::insert::insert_command;
+use crate::commands::derived::{derive_delete_command, derive_get_command, derive_set_command};
+use crate::commands::dump::{export_command, import_command};
+use crate::commands::hotkeys::hotkeys_command;
+use crate::commands::info::info_command;
+use crate::commands::insert::{insert_command, insert_tagged};
+use crate::commands::keyrange::{countrange_command, delrange_command, range_command};
+use crate::commands::load::prepare_load_command;
 use crate::commands::lookup::lookup_command;
-use crate::protocol::{Database, DbKey, DbValue, NetActions, NetCommand, NetResponse};
+use crate::commands::memory::{memory_doctor_command, memory_sample_command};
+use crate::commands::migrate::migrate_command;
+use crate::commands::mount::{snapshot_mount_command, snapshot_mounts_command, snapshot_unmount_command};
+use crate::commands::namespace::{export_namespace_command, import_namespace_command};
+use crate::commands::ping::ping_command;
+use crate::commands::projection::{project_entries, project_lookup};
+use crate::commands::range::{getrange_command, setrange_command};
+use crate::commands::reference::{reference_delete_command, reference_get_command, reference_set_command};
+use crate::commands::save::{bgsave_command, save_command, savejob_status_command};
+use crate::commands::scan::scan_command;
+use crate::commands::services::{services_command, services_start_command, services_stop_command};
+use crate::commands::sql::sql_command;
+use crate::commands::stats::{stats_prefix_command, stats_ttl_command};
+use crate::commands::stream::{
+    xack_command, xadd_command, xclaim_command, xgroup_create_command, xlen_command, xpending_command, xrange_command,
+    xread_command, xreadgroup_command, xrequeue_command,
+};
+use crate::commands::tags::{delete_bytag_command, invalidate_command, lookup_bytag_command};
+use crate::commands::telemetry::{telemetry_off_command, telemetry_on_command, telemetry_status_command};
+use crate::commands::template::{
+    insert_from_template_command, template_delete_command, template_get_command, template_set_command,
+};
+use crate::commands::timeout::{timeout_error, CommandClass};
+use crate::commands::touch::touch_command;
+use crate::commands::transform::{transform_delete_command, transform_get_command, transform_set_command};
+use crate::commands::upload::{put_abort_command, put_begin_command, put_chunk_command, put_commit_command};
+use crate::features;
+use crate::memory::frees_memory;
+use crate::protocol::{
+    expiry_after, DbEngine, DbKey, DbValue, JsonValue, NetActions, NetCommand, NetResponse, WriteDurability,
+};
 
+pub mod backup;
+pub mod budget;
+pub mod commit;
+pub mod compact;
+pub mod config;
 pub mod delete;
+pub mod derived;
+pub mod dump;
+pub mod hotkeys;
+pub mod info;
 pub mod insert;
+pub mod keyrange;
+pub mod load;
 pub mod lookup;
+pub mod memory;
+pub mod migrate;
+pub mod mount;
+pub mod namespace;
+pub mod ping;
+pub mod progress;
+pub mod projection;
+pub mod range;
+pub mod reference;
+pub mod save;
+pub mod scan;
+pub mod services;
+pub mod sql;
+pub mod stats;
+pub mod stream;
+pub mod tags;
+pub mod telemetry;
+pub mod template;
+pub mod timeout;
+pub mod touch;
+pub mod transform;
+pub mod upload;
 
 /// Represents parameters for commands that require multiple keys and values.
 pub struct CommandParams
 {
     pub key: Option<DbKey>,
     pub value: Option<Value>,
-    pub ttl: Option<Duration>,
+    /// When the entry expires, in milliseconds since the UNIX epoch.
+    pub expires_at: Option<u64>,
 }
 
 /// Represents the arguments that can be passed to a command, either a single key-value pair or multiple pairs.
@@ -29,93 +105,246 @@ pub enum CommandArgs
 {
     Single(Option<DbKey>, Option<DbValue>),
     Many(Vec<CommandParams>),
+    /// A single key followed by positional arguments, for commands that need more than a value.
+    WithArgs(Option<DbKey>, Vec<JsonValue>),
 }
 
 /// Trait that defines the interface for executing commands.
 pub trait CommandExecutor: Send + Sync
 {
-    /// Executes a command with the given arguments and database.
+    /// Executes a command with the given arguments and database engine.
     /// Returns a future that resolves to a `NetResponse`.
-    fn execute(&self, args: CommandArgs, db: Database) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>;
+    fn execute(
+        &self,
+        args: CommandArgs,
+        engine: Arc<DbEngine>,
+    ) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>;
 }
 
 impl<F> CommandExecutor for F
 where
-    F: Fn(CommandArgs, Database) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>> + Send + Sync + 'static,
+    F: Fn(CommandArgs, Arc<DbEngine>) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+        + Send
+        + Sync
+        + 'static,
 {
-    fn execute(&self, args: CommandArgs, db: Database) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+    fn execute(
+        &self,
+        args: CommandArgs,
+        engine: Arc<DbEngine>,
+    ) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
     {
-        self(args, db)
+        self(args, engine)
     }
 }
 
+/// What a command changes, which decides where it is accepted and how its success is acknowledged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Effect
+{
+    /// Changes nothing another node would need to know about.
+    Read,
+    /// Changes the state of the server without writing to the database, such as a template or a stream. Only
+    /// accepted by the primary of an HA pair.
+    State,
+    /// Writes to the database, through the write-ahead log. Only accepted by the primary of an HA pair, and
+    /// acknowledged along with how durable the write is.
+    Write,
+}
+
+/// A command of the registry, along with what it changes.
+pub struct RegisteredCommand
+{
+    pub executor: Arc<dyn CommandExecutor>,
+    pub effect: Effect,
+}
+
+/// Builds the registry entry of a command.
+fn register(executor: impl CommandExecutor + 'static, effect: Effect) -> RegisteredCommand
+{
+    RegisteredCommand {
+        executor: Arc::new(executor),
+        effect,
+    }
+}
+
+/// Returns what a command changes, from the registry. Commands that are not registered change nothing.
+pub fn effect(command_name: &str) -> Effect
+{
+    COMMANDS.get(command_name).map_or(Effect::Read, |command| command.effect)
+}
+
 // Map for storing command executors
-pub static COMMANDS: Lazy<HashMap<&'static str, Arc<dyn CommandExecutor>>> = Lazy::new(|| {
+pub static COMMANDS: Lazy<HashMap<&'static str, RegisteredCommand>> = Lazy::new(|| {
+    let mut map = HashMap::new();
+    map.insert("INSERT", register(insert_command, Effect::Write));
+    map.insert("INSERT *", register(insert_command, Effect::Write));
+    map.insert("LOOKUP", register(lookup_command, Effect::Read));
+    map.insert("LOOKUP *", register(lookup_command, Effect::Read));
+    map.insert("DELETE", register(delete_command, Effect::Write));
+    map.insert("DELETE *", register(delete_command, Effect::Write));
+    map.insert("LOOKUP BYTAG", register(lookup_bytag_command, Effect::Read));
+    map.insert("DELETE BYTAG", register(delete_bytag_command, Effect::Write));
+    map.insert("INVALIDATE", register(invalidate_command, Effect::Write));
+    map.insert("TOUCH", register(touch_command, Effect::Write));
+    map.insert("HOTKEYS", register(hotkeys_command, Effect::Read));
+    map.insert("INFO", register(info_command, Effect::Read));
+    map.insert("SCAN", register(scan_command, Effect::Read));
+    map.insert("RANGE", register(range_command, Effect::Read));
+    map.insert("DELRANGE", register(delrange_command, Effect::Write));
+    map.insert("COUNTRANGE", register(countrange_command, Effect::Read));
+    map.insert("MIGRATE", register(migrate_command, Effect::Write));
+    map.insert("SQL", register(sql_command, Effect::Read));
+    map.insert("STATS PREFIX", register(stats_prefix_command, Effect::Read));
+    map.insert("STATS TTL", register(stats_ttl_command, Effect::Read));
+    map.insert("MEMORY SAMPLE", register(memory_sample_command, Effect::Read));
+    map.insert("MEMORY DOCTOR", register(memory_doctor_command, Effect::Read));
+    map.insert("PREPARE LOAD", register(prepare_load_command, Effect::Read));
+    map.insert("CONFIG SET", register(config_set_command, Effect::Read));
+    map.insert("CONFIG GET", register(config_get_command, Effect::Read));
+    map.insert("XADD", register(xadd_command, Effect::State));
+    map.insert("XRANGE", register(xrange_command, Effect::Read));
+    map.insert("XREAD", register(xread_command, Effect::Read));
+    map.insert("XLEN", register(xlen_command, Effect::Read));
+    map.insert("XGROUP CREATE", register(xgroup_create_command, Effect::State));
+    map.insert("XREADGROUP", register(xreadgroup_command, Effect::State));
+    map.insert("XACK", register(xack_command, Effect::State));
+    map.insert("XPENDING", register(xpending_command, Effect::Read));
+    map.insert("XCLAIM", register(xclaim_command, Effect::State));
+    map.insert("XREQUEUE", register(xrequeue_command, Effect::State));
+    map.insert("SNAPSHOT MOUNT", register(snapshot_mount_command, Effect::Read));
+    map.insert("SNAPSHOT UNMOUNT", register(snapshot_unmount_command, Effect::State));
+    map.insert("SNAPSHOT MOUNTS", register(snapshot_mounts_command, Effect::Read));
+    map.insert("EXPORT", register(export_command, Effect::Read));
+    map.insert("IMPORT", register(import_command, Effect::Write));
+    map.insert("EXPORT NAMESPACE", register(export_namespace_command, Effect::Read));
+    map.insert("IMPORT NAMESPACE", register(import_namespace_command, Effect::Write));
+    map.insert("SAVE", register(save_command, Effect::Read));
+    map.insert("BGSAVE", register(bgsave_command, Effect::Read));
+    map.insert("COMPACT", register(compact_command, Effect::Read));
+    map.insert("PING", register(ping_command, Effect::Read));
+    map.insert("SERVICES", register(services_command, Effect::Read));
+    map.insert("SERVICES STOP", register(services_stop_command, Effect::Read));
+    map.insert("SERVICES START", register(services_start_command, Effect::Read));
+    map.insert("TELEMETRY ON", register(telemetry_on_command, Effect::Read));
+    map.insert("TELEMETRY OFF", register(telemetry_off_command, Effect::Read));
+    map.insert("TELEMETRY STATUS", register(telemetry_status_command, Effect::Read));
+    map.insert("SAVEJOB STATUS", register(savejob_status_command, Effect::Read));
+    map.insert("BACKUP INCREMENTAL", register(backup_incremental_command, Effect::Read));
+    map.insert("BACKUP VERIFY", register(backup_verify_command, Effect::Read));
+    map.insert("GETRANGE", register(getrange_command, Effect::Read));
+    map.insert("SETRANGE", register(setrange_command, Effect::Write));
+    map.insert("PUT BEGIN", register(put_begin_command, Effect::State));
+    map.insert("PUT CHUNK", register(put_chunk_command, Effect::State));
+    map.insert("PUT COMMIT", register(put_commit_command, Effect::Write));
+    map.insert("PUT ABORT", register(put_abort_command, Effect::State));
+    map.insert("TEMPLATE SET", register(template_set_command, Effect::State));
+    map.insert("TEMPLATE GET", register(template_get_command, Effect::Read));
+    map.insert("TEMPLATE DELETE", register(template_delete_command, Effect::State));
+    map.insert("INSERT FROM TEMPLATE", register(insert_from_template_command, Effect::Write));
+    map.insert("DERIVE SET", register(derive_set_command, Effect::Write));
+    map.insert("DERIVE GET", register(derive_get_command, Effect::Read));
+    map.insert("DERIVE DELETE", register(derive_delete_command, Effect::State));
+    map.insert("REFERENCE SET", register(reference_set_command, Effect::State));
+    map.insert("REFERENCE GET", register(reference_get_command, Effect::Read));
+    map.insert("REFERENCE DELETE", register(reference_delete_command, Effect::State));
+    map.insert("TRANSFORM SET", register(transform_set_command, Effect::State));
+    map.insert("TRANSFORM GET", register(transform_get_command, Effect::Read));
+    map.insert("TRANSFORM DELETE", register(transform_delete_command, Effect::State));
+    map
+});
+
+// Map of alternative command names to the command they stand for
+pub static ALIASES: Lazy<HashMap<&'static str, &'static str>> = Lazy::new(|| {
     let mut map = HashMap::new();
-    map.insert("INSERT", Arc::new(insert_command) as Arc<dyn CommandExecutor>);
-    map.insert("INSERT *", Arc::new(insert_command) as Arc<dyn CommandExecutor>);
-    map.insert("LOOKUP", Arc::new(lookup_command) as Arc<dyn CommandExecutor>);
-    map.insert("LOOKUP *", Arc::new(lookup_command) as Arc<dyn CommandExecutor>);
-    map.insert("DELETE", Arc::new(delete_command) as Arc<dyn CommandExecutor>);
-    map.insert("DELETE *", Arc::new(delete_command) as Arc<dyn CommandExecutor>);
+    map.insert("GET", "LOOKUP");
+    map.insert("SET", "INSERT");
+    map.insert("DEL", "DELETE");
+    map.insert("MGET", "LOOKUP *");
+    map.insert("MSET", "INSERT *");
     map
 });
 
+/// Normalizes a command name as sent by a client into the name it is registered under.
+/// Names are case-insensitive, runs of whitespace between words are collapsed and aliases are resolved.
+pub fn normalize_command_name(name: &str) -> String
+{
+    let name = name.split_whitespace().collect::<Vec<_>>().join(" ").to_uppercase();
+
+    match ALIASES.get(name.as_str()) {
+        Some(command_name) => command_name.to_string(),
+        None => name,
+    }
+}
+
 /// Executes the command using the corresponding command executor.
 /// Returns a `NetResponse` indicating the success or failure of the command.
-async fn execute_command(command_name: &str, args: CommandArgs, db: Database) -> NetResponse
+async fn execute_command(command_name: &str, args: CommandArgs, engine: Arc<DbEngine>) -> NetResponse
 {
-    if let Some(command_executor) = COMMANDS.get(command_name) {
-        match command_executor.execute(args, db).await {
-            Ok(res) => res.into(),
-            Err(err_msg) => NetResponse {
-                action: NetActions::Error,
-                value: None,
-                error: Some(err_msg.to_string()),
-            },
-        }
+    if let Some(command) = COMMANDS.get(command_name) {
+        command.executor.execute(args, engine).await.unwrap_or_else(executor_error)
     } else {
         NetResponse {
             action: NetActions::Error,
             value: None,
             error: Some("Error: Unknown command.".to_string()),
+            ..Default::default()
         }
     }
 }
 
+/// Builds the response for a command executor that failed.
+fn executor_error(err: Box<dyn Error + Send>) -> NetResponse
+{
+    NetResponse {
+        action: NetActions::Error,
+        value: None,
+        error: Some(err.to_string()),
+        ..Default::default()
+    }
+}
+
 /// Handles the `INSERT` command. Requires a single key and value.
+/// The entry's tags are replaced by the given `tags`, so inserting without tags clears them.
 /// Returns a `NetResponse` indicating the result of the `INSERT` command.
-async fn handle_insert(keys: Option<Vec<DbKey>>, values: Option<Vec<DbValue>>, db: Database) -> NetResponse
+async fn handle_insert(
+    keys: Option<Vec<DbKey>>,
+    values: Option<Vec<DbValue>>,
+    tags: Vec<String>,
+    engine: Arc<DbEngine>,
+) -> NetResponse
 {
     if let (Some(key), Some(data)) = (
         keys.and_then(|k| k.into_iter().next()),
         values.and_then(|v| v.into_iter().next()),
     ) {
-        execute_command(
-            "INSERT",
-            CommandArgs::Single(
-                Some(key),
-                Some(DbValue {
-                    value: data.value,
-                    expires_in: data.expires_in,
-                }),
-            ),
-            db,
-        )
-        .await
+        let args = CommandArgs::Single(
+            Some(key),
+            Some(DbValue {
+                value: data.value,
+                expires_at: data.expires_at,
+            }),
+        );
+        insert_tagged(args, tags, engine).await.unwrap_or_else(executor_error)
     } else {
         NetResponse {
             action: NetActions::Error,
             value: None,
             error: Some("Error: Missing key or value for INSERT command.".to_string()),
+            ..Default::default()
         }
     }
 }
 
 /// Handles the `INSERT *` command, which supports bulk insertion of key-value pairs.
-/// Requires both keys and values to be provided.
+/// Requires both keys and values to be provided. The given `tags` are applied to every inserted entry.
 /// Returns a `NetResponse` indicating the result of the bulk `INSERT` command.
-async fn handle_insert_bulk(keys: Option<Vec<DbKey>>, values: Option<Vec<DbValue>>, db: Database) -> NetResponse
+async fn handle_insert_bulk(
+    keys: Option<Vec<DbKey>>,
+    values: Option<Vec<DbValue>>,
+    tags: Vec<String>,
+    engine: Arc<DbEngine>,
+) -> NetResponse
 {
     if let (Some(keys), Some(values)) = (keys, values) {
         let params: Vec<CommandParams> = keys
@@ -124,39 +353,46 @@ async fn handle_insert_bulk(keys: Option<Vec<DbKey>>, values: Option<Vec<DbValue
             .map(|(key, value)| CommandParams {
                 key: Some(key),
                 value: Some(value.value),
-                ttl: value.expires_in,
+                expires_at: value.expires_at,
             })
             .collect();
-
-        execute_command("INSERT *", CommandArgs::Many(params), db).await
+        insert_tagged(CommandArgs::Many(params), tags, engine)
+            .await
+            .unwrap_or_else(executor_error)
     } else {
         NetResponse {
             action: NetActions::Error,
             value: None,
             error: Some("Error: Missing keys or values for bulk insert.".to_string()),
+            ..Default::default()
         }
     }
 }
 
-/// Handles the `LOOKUP` command. Requires a single key.
+/// Handles the `LOOKUP` command. Requires a single key, and returns only the given `fields` of the value if any.
 /// Returns a `NetResponse` indicating the result of the `LOOKUP` command.
-async fn handle_lookup(keys: Option<Vec<DbKey>>, db: Database) -> NetResponse
+async fn handle_lookup(keys: Option<Vec<DbKey>>, fields: Option<Vec<String>>, engine: Arc<DbEngine>) -> NetResponse
 {
     if let Some(key) = keys.and_then(|k| k.into_iter().next()) {
-        execute_command("LOOKUP", CommandArgs::Single(Some(key), None), db).await
+        let mut response = execute_command("LOOKUP", CommandArgs::Single(Some(key), None), engine).await;
+        if let Some(fields) = fields {
+            project_lookup(&mut response, &fields, false);
+        }
+        response
     } else {
         NetResponse {
             action: NetActions::Error,
             value: None,
             error: Some("Error: Missing key for LOOKUP command.".to_string()),
+            ..Default::default()
         }
     }
 }
 
 /// Handles the `LOOKUP *` command, which supports bulk lookups of multiple keys.
-/// Requires a list of keys to be provided.
+/// Requires a list of keys to be provided, and returns only the given `fields` of each value if any.
 /// Returns a `NetResponse` indicating the result of the bulk `LOOKUP` command.
-async fn handle_lookup_bulk(keys: Option<Vec<DbKey>>, db: Database) -> NetResponse
+async fn handle_lookup_bulk(keys: Option<Vec<DbKey>>, fields: Option<Vec<String>>, engine: Arc<DbEngine>) -> NetResponse
 {
     if let Some(keys) = keys {
         let params: Vec<CommandParams> = keys
@@ -164,30 +400,36 @@ async fn handle_lookup_bulk(keys: Option<Vec<DbKey>>, db: Database) -> NetRespon
             .map(|key| CommandParams {
                 key: Some(key),
                 value: None,
-                ttl: None,
+                expires_at: None,
             })
             .collect();
-        execute_command("LOOKUP *", CommandArgs::Many(params), db).await
+        let mut response = execute_command("LOOKUP *", CommandArgs::Many(params), engine).await;
+        if let Some(fields) = fields {
+            project_lookup(&mut response, &fields, true);
+        }
+        response
     } else {
         NetResponse {
             action: NetActions::Error,
             value: None,
             error: Some("Error: Missing keys for bulk lookup.".to_string()),
+            ..Default::default()
         }
     }
 }
 
 /// Handles the `DELETE` command. Requires a single key.
 /// Returns a `NetResponse` indicating the result of the `DELETE` command.
-async fn handle_delete(keys: Option<Vec<DbKey>>, db: Database) -> NetResponse
+async fn handle_delete(keys: Option<Vec<DbKey>>, engine: Arc<DbEngine>) -> NetResponse
 {
     if let Some(key) = keys.and_then(|k| k.into_iter().next()) {
-        execute_command("DELETE", CommandArgs::Single(Some(key), None), db).await
+        execute_command("DELETE", CommandArgs::Single(Some(key), None), engine).await
     } else {
         NetResponse {
             action: NetActions::Error,
             value: None,
             error: Some("Error: Missing key for DELETE command.".to_string()),
+            ..Default::default()
         }
     }
 }
@@ -195,7 +437,7 @@ async fn handle_delete(keys: Option<Vec<DbKey>>, db: Database) -> NetResponse
 /// Handles the `DELETE *` command, which supports bulk deletion of multiple keys.
 /// Requires a list of keys to be provided.
 /// Returns a `NetResponse` indicating the result of the bulk `DELETE` command.
-async fn handle_delete_bulk(keys: Option<Vec<DbKey>>, db: Database) -> NetResponse
+async fn handle_delete_bulk(keys: Option<Vec<DbKey>>, engine: Arc<DbEngine>) -> NetResponse
 {
     if let Some(keys) = keys {
         let params: Vec<CommandParams> = keys
@@ -203,53 +445,601 @@ async fn handle_delete_bulk(keys: Option<Vec<DbKey>>, db: Database) -> NetRespon
             .map(|key| CommandParams {
                 key: Some(key),
                 value: None,
-                ttl: None,
+                expires_at: None,
             })
             .collect();
-        execute_command("DELETE *", CommandArgs::Many(params), db).await
+        execute_command("DELETE *", CommandArgs::Many(params), engine).await
     } else {
         NetResponse {
             action: NetActions::Error,
             value: None,
             error: Some("Error: Missing keys for bulk delete.".to_string()),
+            ..Default::default()
+        }
+    }
+}
+
+/// Handles the `LOOKUP BYTAG`, `DELETE BYTAG` and `INVALIDATE` commands. Requires a single tag passed as the key.
+/// Returns a `NetResponse` indicating the result of the tag operation.
+async fn handle_tag_operation(command_name: &str, keys: Option<Vec<DbKey>>, engine: Arc<DbEngine>) -> NetResponse
+{
+    if let Some(tag) = keys.and_then(|k| k.into_iter().next()) {
+        execute_command(command_name, CommandArgs::Single(Some(tag), None), engine).await
+    } else {
+        NetResponse {
+            action: NetActions::Error,
+            value: None,
+            error: Some(format!("Error: Missing tag for {} command.", command_name)),
+            ..Default::default()
+        }
+    }
+}
+
+/// Handles the `TOUCH` command. Requires a list of keys and optionally takes a new TTL applied to all of them.
+/// Returns a `NetResponse` indicating the result of the `TOUCH` command.
+async fn handle_touch(keys: Option<Vec<DbKey>>, ttl: Option<Duration>, engine: Arc<DbEngine>) -> NetResponse
+{
+    if let Some(keys) = keys {
+        let params: Vec<CommandParams> = keys
+            .into_iter()
+            .map(|key| CommandParams {
+                key: Some(key),
+                value: None,
+                expires_at: ttl.map(expiry_after),
+            })
+            .collect();
+        execute_command("TOUCH", CommandArgs::Many(params), engine).await
+    } else {
+        NetResponse {
+            action: NetActions::Error,
+            value: None,
+            error: Some("Error: Missing keys for TOUCH command.".to_string()),
+            ..Default::default()
+        }
+    }
+}
+
+/// Handles the `GETRANGE` command. Requires a single key and the `[start, end]` arguments.
+/// Returns a `NetResponse` indicating the result of the `GETRANGE` command.
+async fn handle_getrange(keys: Option<Vec<DbKey>>, args: Option<Vec<JsonValue>>, engine: Arc<DbEngine>) -> NetResponse
+{
+    if let Some(key) = keys.and_then(|k| k.into_iter().next()) {
+        execute_command("GETRANGE", CommandArgs::WithArgs(Some(key), args.unwrap_or_default()), engine).await
+    } else {
+        NetResponse {
+            action: NetActions::Error,
+            value: None,
+            error: Some("Error: Missing key for GETRANGE command.".to_string()),
+            ..Default::default()
+        }
+    }
+}
+
+/// Handles the `SETRANGE` command. Requires a single key and the `[offset, data]` arguments.
+/// Returns a `NetResponse` indicating the result of the `SETRANGE` command.
+async fn handle_setrange(keys: Option<Vec<DbKey>>, args: Option<Vec<JsonValue>>, engine: Arc<DbEngine>) -> NetResponse
+{
+    if let Some(key) = keys.and_then(|k| k.into_iter().next()) {
+        execute_command("SETRANGE", CommandArgs::WithArgs(Some(key), args.unwrap_or_default()), engine).await
+    } else {
+        NetResponse {
+            action: NetActions::Error,
+            value: None,
+            error: Some("Error: Missing key for SETRANGE command.".to_string()),
+            ..Default::default()
+        }
+    }
+}
+
+/// Handles commands that take an optional key followed by positional arguments, such as the `PUT` and
+/// `TEMPLATE` families. The key is optional since some commands identify their target through the arguments.
+/// Returns a `NetResponse` indicating the result of the command.
+async fn handle_with_args(
+    command_name: &str,
+    keys: Option<Vec<DbKey>>,
+    args: Option<Vec<JsonValue>>,
+    engine: Arc<DbEngine>,
+) -> NetResponse
+{
+    let key = keys.and_then(|k| k.into_iter().next());
+    execute_command(command_name, CommandArgs::WithArgs(key, args.unwrap_or_default()), engine).await
+}
+
+/// Handles the `SCAN` command, returning only the given `fields` of each value if any.
+/// Returns a `NetResponse` indicating the result of the `SCAN` command.
+async fn handle_scan(
+    keys: Option<Vec<DbKey>>,
+    args: Option<Vec<JsonValue>>,
+    fields: Option<Vec<String>>,
+    engine: Arc<DbEngine>,
+) -> NetResponse
+{
+    let mut response = handle_with_args("SCAN", keys, args, engine).await;
+    if let Some(fields) = fields {
+        project_entries(&mut response, &fields);
+    }
+    response
+}
+
+/// Returns how durable a write applied by the current command is, syncing the write-ahead log first if the
+/// client asked for `fsync`.
+///
+/// Writes always reach the log when it is enabled, and are synced along with it with the `always` durability.
+/// If the sync fails the write stays acknowledged as recorded to the log, with a warning.
+async fn acknowledge_write(engine: &Arc<DbEngine>, requested: Option<WriteDurability>) -> (WriteDurability, Option<String>)
+{
+    match engine.wal.durability() {
+        None => (WriteDurability::Memory, None),
+        Some(Durability::Always) => (WriteDurability::Fsync, None),
+        Some(_) if requested == Some(WriteDurability::Fsync) => {
+            let wal_engine = engine.clone();
+            match tokio::task::spawn_blocking(move || wal_engine.wal.sync_now()).await {
+                Ok(Ok(())) => (WriteDurability::Fsync, None),
+                Ok(Err(e)) => (
+                    WriteDurability::Wal,
+                    Some(format!("Failed to sync the write-ahead log: {}", e)),
+                ),
+                Err(e) => (
+                    WriteDurability::Wal,
+                    Some(format!("Failed to sync the write-ahead log: {}", e)),
+                ),
+            }
         }
+        Some(_) => (WriteDurability::Wal, None),
+    }
+}
+
+/// Runs the commands of a `BATCH` in order and returns their responses in a single response.
+///
+/// The batch is not atomic: each command runs on its own, and one failing does not stop the ones after it.
+/// Batches cannot be nested.
+async fn handle_batch(commands: Vec<NetCommand<'_>>, engine: Arc<DbEngine>) -> NetResponse
+{
+    let mut responses = Vec::with_capacity(commands.len());
+    for command in commands {
+        let response = if normalize_command_name(command.name) == "BATCH" {
+            NetResponse {
+                action: NetActions::Error,
+                value: None,
+                error: Some("Batches cannot be nested.".to_string()),
+                ..Default::default()
+            }
+        } else {
+            Box::pin(handler(command, engine.clone())).await
+        };
+        responses.push(response);
+    }
+
+    NetResponse {
+        action: NetActions::Command,
+        value: None,
+        error: None,
+        responses,
+        ..Default::default()
     }
 }
 
 /// Main handler for processing commands.
 /// Matches the command name and delegates to the appropriate handler function.
 /// Returns a `NetResponse` based on the execution result of the command.
-pub async fn handler(command: NetCommand<'_>, db: Database) -> NetResponse
+pub async fn handler(command: NetCommand<'_>, engine: Arc<DbEngine>) -> NetResponse
 {
-    let command_name = command.name.to_uppercase();
+    engine.metrics.commands_processed.increment();
+
+    let command_name = normalize_command_name(command.name);
+
+    // A batch runs its commands in order and replies with all of their responses at once
+    if command_name == "BATCH" {
+        return handle_batch(command.batch.unwrap_or_default(), engine).await;
+    }
     let keys: Option<Vec<DbKey>> = command.keys.map(|k_list| k_list.into_iter().map(|k| k.to_string()).collect());
+    let tags: Vec<String> = command.tags.unwrap_or_default().into_iter().map(|t| t.to_string()).collect();
+    let fields: Option<Vec<String>> = command
+        .fields
+        .map(|f_list| f_list.into_iter().map(|f| f.to_string()).collect());
+    let ttl: Option<Duration> = command.ttls.as_ref().and_then(|t| t.first().copied());
+    let effect = effect(&command_name);
 
-    // Map values to DbValue with optional TTL
-    let values: Option<Vec<DbValue>> = if let Some(vals) = command.values {
-        Some(
-            vals.into_iter()
-                .zip(command.ttls.unwrap_or(Vec::new()))  // Handle TTLs
-                .map(|(val, ttl)| DbValue {
-                    value: val.value,
-                    expires_in: Option::from(ttl),  // This now works as expires_in expects Option<Duration>
-                })
-                .collect(),
-        )
-    } else {
-        None
+    // Bulk inserts carrying the token of a `PREPARE LOAD` use up its reservation, given back if they fail
+    let token = command
+        .args
+        .as_ref()
+        .and_then(|args| args.first())
+        .and_then(JsonValue::as_u64);
+    let claim = match (command_name.as_str(), token) {
+        ("INSERT *", Some(token)) => {
+            let entries = keys.as_ref().map_or(0, Vec::len) as u64;
+            match engine.loads.take(token, entries) {
+                Ok(claim) => Some(claim),
+                Err(error) => {
+                    return NetResponse {
+                        action: NetActions::Error,
+                        value: None,
+                        error: Some(error),
+                        ..Default::default()
+                    };
+                }
+            }
+        }
+        _ => None,
+    };
+    let mut warnings = match admit(&command_name, effect, &engine, claim.is_some()) {
+        Ok(warnings) => warnings,
+        Err(response) => {
+            if let Some(claim) = claim {
+                engine.loads.give_back(claim);
+            }
+            return *response;
+        }
+    };
+
+    // Values carry their own TTL. The deprecated `ttls` list still overrides it, matched by position.
+    let values: Option<Vec<DbValue>> = command.values.map(|vals| {
+        let ttls = command.ttls.unwrap_or_default();
+        if !ttls.is_empty() {
+            if let Ok(Some(warning)) = features::check("ttls", &engine.db_config) {
+                warn!("Client used a deprecated field: {}", warning);
+                warnings.push(warning);
+            }
+        }
+
+        vals.into_iter()
+            .enumerate()
+            .map(|(index, val)| DbValue {
+                value: val.value,
+                expires_at: ttls.get(index).copied().map(expiry_after).or(val.expires_at),
+            })
+            .collect()
+    });
+
+    let durability = command.durability;
+    let name = command_name.clone();
+    let run_engine = engine.clone();
+    let claim_engine = engine.clone();
+
+    let dispatch = async move {
+        let response = match command_name.as_str() {
+            "INSERT" => handle_insert(keys, values, tags, engine).await,
+            "LOOKUP" => handle_lookup(keys, fields, engine).await,
+            "DELETE" => handle_delete(keys, engine).await,
+            "INSERT *" => handle_insert_bulk(keys, values, tags, engine).await,
+            "LOOKUP *" => handle_lookup_bulk(keys, fields, engine).await,
+            "DELETE *" => handle_delete_bulk(keys, engine).await,
+            "LOOKUP BYTAG" => handle_tag_operation("LOOKUP BYTAG", keys, engine).await,
+            "DELETE BYTAG" => handle_tag_operation("DELETE BYTAG", keys, engine).await,
+            "INVALIDATE" => handle_tag_operation("INVALIDATE", keys, engine).await,
+            "TOUCH" => handle_touch(keys, ttl, engine).await,
+            "HOTKEYS" => handle_with_args("HOTKEYS", keys, command.args, engine).await,
+            "INFO" => handle_with_args("INFO", keys, command.args, engine).await,
+            "SCAN" => handle_scan(keys, command.args, fields, engine).await,
+            "GETRANGE" => handle_getrange(keys, command.args, engine).await,
+            "SETRANGE" => handle_setrange(keys, command.args, engine).await,
+            "PUT BEGIN"
+            | "PUT CHUNK"
+            | "PUT COMMIT"
+            | "PUT ABORT"
+            | "TEMPLATE SET"
+            | "TEMPLATE GET"
+            | "TEMPLATE DELETE"
+            | "INSERT FROM TEMPLATE"
+            | "TRANSFORM SET"
+            | "DERIVE SET"
+            | "DERIVE GET"
+            | "DERIVE DELETE"
+            | "REFERENCE SET"
+            | "REFERENCE GET"
+            | "REFERENCE DELETE"
+            | "TRANSFORM GET"
+            | "TRANSFORM DELETE"
+            | "MIGRATE"
+            | "SQL"
+            | "STATS PREFIX"
+            | "STATS TTL"
+            | "MEMORY SAMPLE"
+            | "MEMORY DOCTOR"
+            | "PREPARE LOAD"
+            | "CONFIG SET"
+            | "CONFIG GET"
+            | "XADD"
+            | "XRANGE"
+            | "RANGE"
+            | "DELRANGE"
+            | "COUNTRANGE"
+            | "XREAD"
+            | "XLEN"
+            | "XGROUP CREATE"
+            | "XREADGROUP"
+            | "XACK"
+            | "XPENDING"
+            | "XCLAIM"
+            | "XREQUEUE"
+            | "SNAPSHOT MOUNT"
+            | "SNAPSHOT UNMOUNT"
+            | "SNAPSHOT MOUNTS"
+            | "EXPORT"
+            | "IMPORT"
+            | "EXPORT NAMESPACE"
+            | "IMPORT NAMESPACE"
+            | "SAVE"
+            | "BGSAVE"
+            | "COMPACT"
+            | "SAVEJOB STATUS"
+            | "TELEMETRY ON"
+            | "TELEMETRY OFF"
+            | "TELEMETRY STATUS"
+            | "PING"
+            | "SERVICES"
+            | "SERVICES STOP"
+            | "SERVICES START"
+            | "BACKUP INCREMENTAL"
+            | "BACKUP VERIFY" => handle_with_args(&command_name, keys, command.args, engine).await,
+            _ => NetResponse {
+                action: NetActions::Error,
+                value: None,
+                error: Some("Error: Unknown command.".to_string()),
+                ..Default::default()
+            },
+        };
+        if let Some(claim) = claim.filter(|_| response.action == NetActions::Error) {
+            claim_engine.loads.give_back(claim);
+        }
+        response
     };
 
-    match command_name.as_str() {
-        "INSERT" => handle_insert(keys, values, db).await,
-        "LOOKUP" => handle_lookup(keys, db).await,
-        "DELETE" => handle_delete(keys, db).await,
-        "INSERT *" => handle_insert_bulk(keys, values, db).await,
-        "LOOKUP *" => handle_lookup_bulk(keys, db).await,
-        "DELETE *" => handle_delete_bulk(keys, db).await,
-        _ => NetResponse {
+    run(&name, effect, durability, warnings, &run_engine, dispatch).await
+}
+
+/// Checks that a command may run: that it is enabled, that this node accepts it, and that it fits within
+/// `--max-memory` if it writes, unless memory was `reserved` for it.
+///
+/// # Returns
+///
+/// The warnings to send along with the response of the command, or the response refusing it.
+pub fn admit(command_name: &str, effect: Effect, engine: &DbEngine, reserved: bool)
+    -> Result<Vec<String>, Box<NetResponse>>
+{
+    let mut warnings = vec![];
+
+    match features::check(command_name, &engine.db_config) {
+        Ok(warning) => warnings.extend(warning),
+        Err(error) => {
+            return Err(Box::new(NetResponse {
+                action: NetActions::Error,
+                value: Some(serde_json::json!({ "reason": "disabled" })),
+                error: Some(error),
+                ..Default::default()
+            }));
+        }
+    }
+
+    // Only the primary of an HA pair accepts writes, so a former primary cannot diverge from its replacement
+    if effect != Effect::Read && !engine.lease.is_primary() {
+        return Err(Box::new(NetResponse {
             action: NetActions::Error,
-            value: None,
-            error: Some("Error: Unknown command.".to_string()),
+            value: Some(serde_json::json!({ "reason": "not_primary" })),
+            error: Some("This node is not the primary, writes are rejected.".to_string()),
+            ..Default::default()
+        }));
+    }
+
+    // Past `--max-memory` writes fail rather than evict entries, except those that can only free memory and the
+    // loads memory was reserved for
+    if effect != Effect::Read && !frees_memory(command_name) && !reserved && engine.memory.is_rejecting() {
+        engine.metrics.memory_rejected_writes.increment();
+        return Err(Box::new(NetResponse {
+            action: NetActions::Error,
+            value: Some(serde_json::json!({ "reason": "out_of_memory" })),
+            error: Some(format!(
+                "Out of memory, writes are rejected until memory use falls below {} bytes.",
+                engine.memory.resume_below()
+            )),
+            ..Default::default()
+        }));
+    }
+
+    Ok(warnings)
+}
+
+/// Runs a command admitted by [`admit`] within the timeout of its class, and acknowledges it once as durable as
+/// `durability` if it wrote to the database. The `warnings` are added to the response.
+pub async fn run<F>(
+    command_name: &str,
+    effect: Effect,
+    durability: Option<WriteDurability>,
+    mut warnings: Vec<String>,
+    engine: &Arc<DbEngine>,
+    dispatch: F,
+) -> NetResponse
+where
+    F: Future<Output = NetResponse> + Send + 'static,
+{
+    // Commands are limited by the timeout of their class, if one is configured
+    let class = CommandClass::of(command_name);
+    match class {
+        CommandClass::Read => engine.metrics.read_commands.increment(),
+        CommandClass::Write => engine.metrics.write_commands.increment(),
+        CommandClass::Bulk => engine.metrics.bulk_commands.increment(),
+        CommandClass::Admin => engine.metrics.admin_commands.increment(),
+    }
+    let timeout = class.timeout(&engine.db_config);
+
+    let mut response = match timeout {
+        Some(timeout) if effect == Effect::Read => match tokio::time::timeout(timeout, dispatch).await {
+            Ok(response) => response,
+            Err(_) => timeout_error(class, timeout, &engine.metrics),
         },
+        // Commands changing something run on their own task, so one timing out is never dropped half applied
+        Some(timeout) => {
+            let mut task = tokio::spawn(dispatch);
+            match tokio::time::timeout(timeout, &mut task).await {
+                Ok(Ok(response)) => response,
+                Ok(Err(e)) => NetResponse {
+                    action: NetActions::Error,
+                    value: None,
+                    error: Some(format!("Command failed: {}", e)),
+                    ..Default::default()
+                },
+                Err(_) => {
+                    warnings.push("The command keeps running and its changes still apply.".to_string());
+                    timeout_error(class, timeout, &engine.metrics)
+                }
+            }
+        }
+        None => dispatch.await,
+    };
+
+    // Successful writes to the database report how durable they are, once as durable as the client asked for
+    if effect == Effect::Write && response.action == NetActions::Command {
+        let (reached, warning) = acknowledge_write(engine, durability).await;
+        response.durability = Some(reached);
+        warnings.extend(warning);
+    }
+
+    response.warnings.extend(warnings);
+    response
+}
+
+#[cfg(test)]
+mod test
+{
+    use clap::Parser;
+
+    use super::*;
+    use crate::cli::Cli;
+
+    #[test]
+    fn test_normalize_command_name()
+    {
+        assert_eq!(normalize_command_name("lookup"), "LOOKUP");
+        assert_eq!(normalize_command_name("  insert   *  "), "INSERT *");
+        assert_eq!(normalize_command_name("Lookup\tByTag"), "LOOKUP BYTAG");
+    }
+
+    #[test]
+    fn test_aliases_resolve_to_registered_commands()
+    {
+        assert_eq!(normalize_command_name("get"), "LOOKUP");
+        assert_eq!(normalize_command_name("SET"), "INSERT");
+        assert_eq!(normalize_command_name("Del"), "DELETE");
+
+        for command_name in ALIASES.values() {
+            assert!(COMMANDS.contains_key(command_name));
+        }
+    }
+
+    #[test]
+    fn test_effects()
+    {
+        assert_eq!(effect("TOUCH"), Effect::Write);
+        assert_eq!(effect("TEMPLATE SET"), Effect::State);
+        // Reading through a consumer group moves its cursor and adds to its pending entries
+        assert_eq!(effect("XREADGROUP"), Effect::State);
+        assert_eq!(effect("LOOKUP"), Effect::Read);
+        assert_eq!(effect("BATCH"), Effect::Read);
+
+        // Commands limited by the write timeout always change something
+        for (command_name, command) in COMMANDS.iter() {
+            if CommandClass::of(command_name) == CommandClass::Write {
+                assert_ne!(command.effect, Effect::Read, "{}", command_name);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_batch()
+    {
+        let engine = Arc::new(DbEngine::new(Cli::parse_from(["phoenix-db"])));
+        let command: NetCommand = serde_json::from_str(
+            r#"{"name": "BATCH", "batch": [
+                {"name": "INSERT", "keys": ["a"], "values": [{"value": 1, "expires_in": null}]},
+                {"name": "DELETE", "keys": ["missing"]},
+                {"name": "BATCH"},
+                {"name": "LOOKUP", "keys": ["a"]}
+            ]}"#,
+        )
+        .unwrap();
+
+        let response = handler(command, engine).await;
+        let actions: Vec<&NetActions> = response.responses.iter().map(|response| &response.action).collect();
+        assert_eq!(
+            actions,
+            [
+                &NetActions::Command,
+                &NetActions::Error,
+                &NetActions::Error,
+                &NetActions::Command
+            ]
+        );
+        assert_eq!(response.responses[3].value, Some(serde_json::json!(1)));
+    }
+
+    #[tokio::test]
+    async fn test_write_durability()
+    {
+        let insert =
+            r#"{"name": "INSERT", "keys": ["a"], "values": [{"value": 1, "expires_in": null}], "durability": "fsync"}"#;
+
+        // Without a write-ahead log, writes only ever reach memory
+        let engine = Arc::new(DbEngine::new(Cli::parse_from(["phoenix-db"])));
+        let response = handler(serde_json::from_str(insert).unwrap(), engine).await;
+        assert_eq!(response.durability, Some(WriteDurability::Memory));
+
+        let path = std::env::temp_dir().join(format!("phoenix-db-durability-{}.wal", std::process::id()));
+        let mut engine = DbEngine::new(Cli::parse_from(["phoenix-db", "--durability", "no"]));
+        engine.wal = crate::wal::Wal::open(&path, Durability::No, None).unwrap();
+        let engine = Arc::new(engine);
+
+        let response = handler(serde_json::from_str(insert).unwrap(), engine.clone()).await;
+        assert_eq!(response.durability, Some(WriteDurability::Fsync));
+        let response = handler(serde_json::from_str(&insert.replace("fsync", "wal")).unwrap(), engine.clone()).await;
+        assert_eq!(response.durability, Some(WriteDurability::Wal));
+        let response = handler(serde_json::from_str(r#"{"name": "LOOKUP", "keys": ["a"]}"#).unwrap(), engine).await;
+        assert_eq!(response.durability, None);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_timed_out_writes_still_apply()
+    {
+        let engine = Arc::new(DbEngine::new(Cli::parse_from(["phoenix-db", "--write-timeout-ms", "10"])));
+        let insert = r#"{"name": "INSERT", "keys": ["a"], "values": [{"value": 1, "expires_in": null}]}"#;
+
+        // The insert times out waiting for the writer lock, then applies once it is released
+        let db_write = engine.connection.write().await;
+        let response = handler(serde_json::from_str(insert).unwrap(), engine.clone()).await;
+        assert_eq!(response.action, NetActions::Error);
+        assert_eq!(response.warnings.len(), 1);
+        drop(db_write);
+
+        for _ in 0..100 {
+            if engine.connection.read().contains_key("a") {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("the write that timed out was not applied");
+    }
+
+    #[tokio::test]
+    async fn test_failed_bulk_inserts_keep_their_load()
+    {
+        let engine = Arc::new(DbEngine::new(Cli::parse_from(["phoenix-db"])));
+        let prepare = r#"{"name": "PREPARE LOAD", "args": [2, 10]}"#;
+        let response = handler(serde_json::from_str(prepare).unwrap(), engine.clone()).await;
+        let token = response.value.unwrap()["token"].as_u64().unwrap();
+
+        // An insert that fails leaves the entries of the load to the next one
+        let insert = format!(r#"{{"name": "INSERT *", "keys": ["a", "b"], "args": [{}]}}"#, token);
+        let response = handler(serde_json::from_str(&insert).unwrap(), engine.clone()).await;
+        assert_eq!(response.action, NetActions::Error);
+
+        let insert = insert.replace(
+            r#""args""#,
+            r#""values": [{"value": 1, "expires_in": null}, {"value": 2, "expires_in": null}], "args""#,
+        );
+        let response = handler(serde_json::from_str(&insert).unwrap(), engine.clone()).await;
+        assert_eq!(response.action, NetActions::Command);
+        let response = handler(serde_json::from_str(&insert).unwrap(), engine).await;
+        assert_eq!(response.action, NetActions::Error);
     }
 }
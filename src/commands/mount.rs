@@ -0,0 +1,227 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Arc;
+
+use futures::future::{BoxFuture, FutureExt};
+use serde_json::json;
+use tokio::sync::RwLock;
+
+use crate::commands::CommandArgs;
+use crate::protocol::{DbEngine, JsonValue, NetActions, NetResponse};
+use crate::snapshot;
+use crate::store::DbMap;
+
+/// Snapshots mounted read-only with `SNAPSHOT MOUNT`, keyed by their mount name.
+pub type Mounts = RwLock<HashMap<String, Arc<DbMap>>>;
+
+/// Splits a key such as `@2024-06-01:user:1` into the name of a mounted snapshot and the key inside of it.
+///
+/// # Returns
+///
+/// `None` if the key does not start with `@`, meaning it belongs to the live database.
+pub fn split_mounted_key(key: &str) -> Option<(&str, &str)>
+{
+    key.strip_prefix('@')?.split_once(':')
+}
+
+/// Builds the error response for a write to a key of a mounted snapshot.
+pub fn read_only_error(key: &str) -> NetResponse
+{
+    mount_error(format!("Key '{}' belongs to a mounted snapshot, which is read-only.", key))
+}
+
+/// Executes a `SNAPSHOT MOUNT` command, loading a snapshot so its keys can be read under `@<name>:`.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` holding the mount name and the `[path]` argument, which defaults to the
+///   snapshot written by `BGSAVE`.
+/// * `engine` - The database engine holding the mounted snapshots.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with the number of keys in the snapshot.
+pub fn snapshot_mount_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let (name, path) = match args {
+            CommandArgs::WithArgs(Some(name), params) if !name.contains(':') => {
+                let path = match params.first().and_then(JsonValue::as_str) {
+                    Some(path) => match engine.db_config.data_path(path) {
+                        Ok(path) => path,
+                        Err(message) => return Ok(mount_error(message)),
+                    },
+                    None => engine.db_config.snapshot_path.clone(),
+                };
+                (name, path)
+            }
+            CommandArgs::WithArgs(Some(_), _) => {
+                return Ok(mount_error("Mount names cannot contain ':'.".to_string()));
+            }
+            _ => return Ok(mount_error("No name provided for mount.".to_string())),
+        };
+
+        let cipher = engine.cipher.clone();
+        let response = match tokio::task::spawn_blocking(move || snapshot::read(&path, cipher.as_deref())).await {
+            Ok(Ok(map)) => {
+                let keys = map.len();
+                engine.mounts.write().await.insert(name, Arc::new(map));
+                NetResponse {
+                    action: NetActions::Command,
+                    value: Some(json!({ "keys": keys })),
+                    error: None,
+                    ..Default::default()
+                }
+            }
+            Ok(Err(e)) => mount_error(format!("Failed to mount snapshot: {}", e)),
+            Err(e) => mount_error(format!("Failed to mount snapshot: {}", e)),
+        };
+
+        Ok(response)
+    }
+    .boxed()
+}
+
+/// Executes a `SNAPSHOT UNMOUNT` command, releasing a mounted snapshot.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` holding the mount name.
+/// * `engine` - The database engine holding the mounted snapshots.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` indicating the result of the command.
+pub fn snapshot_unmount_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let response = match args {
+            CommandArgs::WithArgs(Some(name), _) => {
+                if engine.mounts.write().await.remove(&name).is_some() {
+                    NetResponse {
+                        action: NetActions::Command,
+                        value: Some("OK".to_string().into()),
+                        error: None,
+                        ..Default::default()
+                    }
+                } else {
+                    not_mounted(&name)
+                }
+            }
+            _ => mount_error("No name provided for mount.".to_string()),
+        };
+
+        Ok(response)
+    }
+    .boxed()
+}
+
+/// Executes a `SNAPSHOT MOUNTS` command, listing the mounted snapshots.
+///
+/// # Arguments
+///
+/// * `_args` - Unused.
+/// * `engine` - The database engine holding the mounted snapshots.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with the `{ name, keys }` of every mount.
+pub fn snapshot_mounts_command(
+    _args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let mounts = engine.mounts.read().await;
+        let mut list: Vec<JsonValue> = mounts
+            .iter()
+            .map(|(name, map)| json!({ "name": name, "keys": map.len() }))
+            .collect();
+        list.sort_by(|a, b| a["name"].as_str().cmp(&b["name"].as_str()));
+
+        Ok(NetResponse {
+            action: NetActions::Command,
+            value: Some(JsonValue::Array(list)),
+            error: None,
+            ..Default::default()
+        })
+    }
+    .boxed()
+}
+
+/// Builds the error response for a mount name that is not mounted.
+pub fn not_mounted(name: &str) -> NetResponse
+{
+    mount_error(format!("Snapshot '{}' is not mounted.", name))
+}
+
+/// Builds an error response for the mount commands.
+fn mount_error(message: String) -> NetResponse
+{
+    NetResponse {
+        action: NetActions::Error,
+        value: None,
+        error: Some(message),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use clap::Parser;
+
+    use super::*;
+    use crate::cli::Cli;
+    use crate::commands::insert::insert_command;
+    use crate::commands::lookup::lookup_command;
+    use crate::protocol::DbValue;
+
+    #[tokio::test]
+    async fn test_mounted_snapshot_reads()
+    {
+        let path = std::env::temp_dir().join(format!("phoenix-db-test-{}-mount.snapshot", std::process::id()));
+        let mut map = DbMap::new();
+        map.insert(
+            "user:1".to_string(),
+            DbValue {
+                value: json!("old"),
+                expires_at: None,
+            },
+        );
+        snapshot::write(&map, &path, None).unwrap();
+
+        let engine = Arc::new(DbEngine::new(Cli::parse_from([
+            "phoenix-db",
+            "--snapshot-path",
+            path.to_str().unwrap(),
+        ])));
+        let args = CommandArgs::WithArgs(Some("2024-06-01".to_string()), vec![]);
+        let response = snapshot_mount_command(args, engine.clone()).await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(response.value, Some(json!({ "keys": 1 })));
+
+        let args = CommandArgs::Single(Some("@2024-06-01:user:1".to_string()), None);
+        let response = lookup_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.value, Some(json!("old")));
+
+        let args = CommandArgs::Single(Some("@2024-06-02:user:1".to_string()), None);
+        let response = lookup_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.action, NetActions::Error);
+
+        // Mounted snapshots are read-only
+        let value = DbValue {
+            value: json!("new"),
+            expires_at: None,
+        };
+        let args = CommandArgs::Single(Some("@2024-06-01:user:1".to_string()), Some(value));
+        let response = insert_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.action, NetActions::Error);
+    }
+}
@@ -0,0 +1,297 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use futures::future::{BoxFuture, FutureExt};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::commands::commit::commit;
+use crate::commands::dump::{parse_line, write_line};
+use crate::commands::insert::wal_error;
+use crate::commands::reference::{check_references, reference_error};
+use crate::commands::transform::apply_transforms;
+use crate::commands::CommandArgs;
+use crate::crypto::Cipher;
+use crate::protocol::{DbEngine, DbKey, DbValue, JsonValue, NetActions, NetResponse};
+use crate::store::DbMap;
+use crate::wal::WalRecord;
+
+/// Separates the namespace of a key from the rest of it, like in `user:1`.
+const NAMESPACE_SEPARATOR: char = ':';
+
+/// The first line of an export, naming the namespace it holds.
+#[derive(Serialize, Deserialize)]
+struct ExportHeader
+{
+    namespace: String,
+}
+
+/// A line of an export, holding a key relative to its namespace, or the full key in a dump written by `EXPORT`.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct ExportEntry
+{
+    pub(crate) key: DbKey,
+    #[serde(flatten)]
+    pub(crate) data: DbValue,
+}
+
+/// Writes the keys of `namespace` to `path`, one JSON document per line after the header, each sealed with
+/// `cipher` if given.
+///
+/// # Returns
+///
+/// The number of keys written along with the size of the export in bytes.
+fn write_export(map: &DbMap, namespace: &str, path: &Path, cipher: Option<&Cipher>) -> io::Result<(usize, u64)>
+{
+    let prefix = format!("{}{}", namespace, NAMESPACE_SEPARATOR);
+    let temp_path = path.with_extension("tmp");
+
+    let mut writer = BufWriter::new(File::create(&temp_path)?);
+    let header = ExportHeader {
+        namespace: namespace.to_string(),
+    };
+    write_line(&mut writer, &header, cipher)?;
+
+    let mut keys = 0;
+    for (key, data) in map.iter() {
+        if let Some(key) = key.strip_prefix(&prefix) {
+            let entry = ExportEntry {
+                key: key.to_string(),
+                data: data.clone(),
+            };
+            write_line(&mut writer, &entry, cipher)?;
+            keys += 1;
+        }
+    }
+    writer.flush()?;
+
+    let file = writer.into_inner().map_err(|e| e.into_error())?;
+    file.sync_all()?;
+    let size = file.metadata()?.len();
+
+    fs::rename(&temp_path, path)?;
+    Ok((keys, size))
+}
+
+/// Reads an export of `namespace` from `path`, opening its lines with `cipher`.
+fn read_export(namespace: &str, path: &Path, cipher: Option<&Cipher>) -> io::Result<Vec<ExportEntry>>
+{
+    let mut lines = BufReader::new(File::open(path)?).lines();
+
+    let header: ExportHeader = match lines.next() {
+        Some(line) => parse_line(&line?, cipher)?,
+        None => return Err(io::Error::new(io::ErrorKind::InvalidData, "the export is empty")),
+    };
+    if header.namespace != namespace {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("the export holds namespace '{}'", header.namespace),
+        ));
+    }
+
+    lines
+        .filter(|line| !line.as_ref().is_ok_and(|line| line.is_empty()))
+        .map(|line| parse_line(&line?, cipher))
+        .collect()
+}
+
+/// Executes an `EXPORT NAMESPACE` command, writing every key of a namespace to a file.
+///
+/// A namespace is the part of a key before the first `:`, so exporting `user` writes `user:1` and `user:2`. Keys
+/// are written relative to their namespace so the export can be imported under another one.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` holding the namespace and the `[file]` argument, a path on the server.
+/// * `engine` - The database engine to export from.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with the number of keys exported and the
+/// size of the file.
+pub fn export_namespace_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let (namespace, path) = match args {
+            CommandArgs::WithArgs(Some(namespace), params) => match params.first().and_then(JsonValue::as_str) {
+                Some(path) => match engine.db_config.data_path(path) {
+                    Ok(path) => (namespace, path),
+                    Err(message) => return Ok(namespace_error(message)),
+                },
+                None => return Ok(namespace_error("EXPORT NAMESPACE requires a file.".to_string())),
+            },
+            _ => return Ok(namespace_error("No namespace provided for export.".to_string())),
+        };
+
+        let map = engine.connection.read();
+        let cipher = engine.cipher.clone();
+        let result = tokio::task::spawn_blocking(move || write_export(&map, &namespace, &path, cipher.as_deref())).await;
+
+        let response = match result {
+            Ok(Ok((keys, size))) => NetResponse {
+                action: NetActions::Command,
+                value: Some(json!({ "keys": keys, "size": size })),
+                error: None,
+                ..Default::default()
+            },
+            Ok(Err(e)) => namespace_error(format!("Failed to export namespace: {}", e)),
+            Err(e) => namespace_error(format!("Failed to export namespace: {}", e)),
+        };
+
+        Ok(response)
+    }
+    .boxed()
+}
+
+/// Executes an `IMPORT NAMESPACE` command, loading a file written by `EXPORT NAMESPACE`.
+///
+/// Keys are imported under the namespace they were exported from, or under another one when it is given, which
+/// allows cloning `prod` into `staging`. Existing keys are overwritten.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` holding the exported namespace and the `[file, as]` arguments, where `as`
+///   is optional.
+/// * `engine` - The database engine to import into.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with the number of keys imported.
+pub fn import_namespace_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let (namespace, path, target) = match args {
+            CommandArgs::WithArgs(Some(namespace), params) => match params.first().and_then(JsonValue::as_str) {
+                Some(path) => {
+                    let path = match engine.db_config.data_path(path) {
+                        Ok(path) => path,
+                        Err(message) => return Ok(namespace_error(message)),
+                    };
+                    let target = params
+                        .get(1)
+                        .and_then(JsonValue::as_str)
+                        .map(str::to_string)
+                        .unwrap_or_else(|| namespace.clone());
+                    (namespace, path, target)
+                }
+                None => return Ok(namespace_error("IMPORT NAMESPACE requires a file.".to_string())),
+            },
+            _ => return Ok(namespace_error("No namespace provided for import.".to_string())),
+        };
+
+        let cipher = engine.cipher.clone();
+        let mut entries = match tokio::task::spawn_blocking(move || read_export(&namespace, &path, cipher.as_deref())).await
+        {
+            Ok(Ok(entries)) => entries,
+            Ok(Err(e)) => return Ok(namespace_error(format!("Failed to import namespace: {}", e))),
+            Err(e) => return Ok(namespace_error(format!("Failed to import namespace: {}", e))),
+        };
+
+        let keys: Vec<DbKey> = entries
+            .iter()
+            .map(|entry| format!("{}{}{}", target, NAMESPACE_SEPARATOR, entry.key))
+            .collect();
+        for (key, entry) in keys.iter().zip(&mut entries) {
+            apply_transforms(&engine, key, &mut entry.data.value).await;
+        }
+        let imported: HashSet<&str> = keys.iter().map(String::as_str).collect();
+        let mut warnings = Vec::new();
+        for (key, entry) in keys.iter().zip(&entries) {
+            match check_references(&engine, key, &entry.data.value, &imported).await {
+                Ok(entry_warnings) => warnings.extend(entry_warnings),
+                Err(e) => return Ok(reference_error(e)),
+            }
+        }
+        let committed = {
+            let mut db_write = engine.connection.write().await;
+            let records = keys
+                .iter()
+                .zip(entries)
+                .map(|(key, entry)| WalRecord::Insert(key.clone(), entry.data))
+                .collect();
+            match commit(&engine, &mut db_write, records).await {
+                Ok(committed) => committed,
+                Err(e) => return Ok(wal_error(e)),
+            }
+        };
+        committed.refresh(&engine).await;
+
+        Ok(NetResponse {
+            action: NetActions::Command,
+            value: Some(json!({ "keys": keys.len() })),
+            error: None,
+            warnings,
+            ..Default::default()
+        })
+    }
+    .boxed()
+}
+
+/// Builds an error response for the namespace commands.
+fn namespace_error(message: String) -> NetResponse
+{
+    NetResponse {
+        action: NetActions::Error,
+        value: None,
+        error: Some(message),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use crate::testing::create_engine_in_temp_dir;
+
+    #[tokio::test]
+    async fn test_export_and_import_namespace()
+    {
+        let engine = create_engine_in_temp_dir();
+        let name = format!("phoenix-db-test-{}.export", std::process::id());
+        let path = std::env::temp_dir().join(&name);
+        let file = || json!(name);
+
+        {
+            let mut db_write = engine.connection.write().await;
+            for (key, value) in [("prod:1", json!("a")), ("prod:2", json!("b")), ("production:1", json!("c"))] {
+                db_write.insert(key.to_string(), DbValue { value, expires_at: None });
+            }
+        }
+
+        let args = CommandArgs::WithArgs(Some("prod".to_string()), vec![file()]);
+        let response = export_namespace_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.value.unwrap()["keys"], json!(2));
+
+        let args = CommandArgs::WithArgs(Some("prod".to_string()), vec![file(), json!("staging")]);
+        let response = import_namespace_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.value, Some(json!({ "keys": 2 })));
+
+        // Importing under the wrong namespace is refused
+        let args = CommandArgs::WithArgs(Some("production".to_string()), vec![file()]);
+        let response = import_namespace_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.action, NetActions::Error);
+
+        // So are files outside of the data directory
+        let args = CommandArgs::WithArgs(Some("prod".to_string()), vec![json!(path.to_str().unwrap())]);
+        let response = export_namespace_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.action, NetActions::Error);
+
+        fs::remove_file(&path).unwrap();
+
+        let db_read = engine.connection.read();
+        assert_eq!(db_read.get("staging:1").unwrap().value, json!("a"));
+        assert_eq!(db_read.get("staging:2").unwrap().value, json!("b"));
+        assert!(db_read.get("staging:production:1").is_none());
+    }
+}
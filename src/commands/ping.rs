@@ -0,0 +1,35 @@
+use std::error::Error;
+use std::sync::Arc;
+
+use futures::future::{BoxFuture, FutureExt};
+use serde_json::json;
+
+use crate::commands::CommandArgs;
+use crate::protocol::{unix_millis, DbEngine, NetActions, NetResponse};
+
+/// Executes a `PING` command, letting clients check the server answers and keep idle connections open.
+///
+/// # Arguments
+///
+/// * `_args` - Unused, `PING` takes no arguments.
+/// * `_engine` - Unused, the reply does not depend on the database.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with the `reply` `PONG` and the `time` of
+/// the server, in milliseconds since the UNIX epoch.
+pub fn ping_command(
+    _args: CommandArgs,
+    _engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        Ok(NetResponse {
+            action: NetActions::Command,
+            value: Some(json!({ "reply": "PONG", "time": unix_millis() })),
+            error: None,
+            ..Default::default()
+        })
+    }
+    .boxed()
+}
@@ -0,0 +1,94 @@
+use std::future::Future;
+
+use serde_json::json;
+use tokio::sync::mpsc;
+
+use crate::protocol::{NetActions, NetResponse};
+
+tokio::task_local! {
+    /// The progress reporter of the command being handled, set when the client asked for progress.
+    static PROGRESS: Progress;
+}
+
+/// Reports the progress of bulk commands to the client that asked for it with the `progress` field of a command.
+#[derive(Clone)]
+pub struct Progress
+{
+    /// How many items are processed between two reports.
+    every: usize,
+    /// The progress responses, sent to the client ahead of the final response.
+    frames: mpsc::UnboundedSender<NetResponse>,
+}
+
+impl Progress
+{
+    /// Creates a reporter sending a progress response every `every` items.
+    ///
+    /// # Returns
+    ///
+    /// The reporter and the receiver of its progress responses.
+    pub fn new(every: usize) -> (Self, mpsc::UnboundedReceiver<NetResponse>)
+    {
+        let (frames, receiver) = mpsc::unbounded_channel();
+        (
+            Self {
+                every: every.max(1),
+                frames,
+            },
+            receiver,
+        )
+    }
+
+    /// Runs `future`, the handling of a command, reporting the progress of the bulk commands it runs.
+    pub async fn scope<F: Future>(self, future: F) -> F::Output
+    {
+        PROGRESS.scope(self, future).await
+    }
+}
+
+/// Reports that `processed` of the `total` items of a bulk command are done, with `errors` so far.
+///
+/// Does nothing unless the client asked for progress, and only reports once every so many items.
+pub fn report(processed: usize, total: usize, errors: usize)
+{
+    let _ = PROGRESS.try_with(|progress| {
+        if processed > 0 && processed.is_multiple_of(progress.every) {
+            // The connection stopped listening if sending fails, the final response is lost as well then
+            let _ = progress.frames.send(NetResponse {
+                action: NetActions::Progress,
+                value: Some(json!({ "processed": processed, "total": total, "errors": errors })),
+                error: None,
+                ..Default::default()
+            });
+        }
+    });
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[tokio::test]
+    async fn test_report()
+    {
+        // Outside of a scope nothing is reported
+        report(1, 1, 0);
+
+        let (progress, mut frames) = Progress::new(2);
+        progress
+            .scope(async {
+                for processed in 1..=5 {
+                    report(processed, 5, 1);
+                }
+            })
+            .await;
+
+        let mut reported = vec![];
+        while let Ok(frame) = frames.try_recv() {
+            assert_eq!(frame.action, NetActions::Progress);
+            reported.push(frame.value.unwrap()["processed"].clone());
+        }
+        assert_eq!(reported, vec![json!(2), json!(4)]);
+    }
+}
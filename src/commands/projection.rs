@@ -0,0 +1,88 @@
+use serde_json::Map;
+
+use crate::protocol::{JsonValue, NetResponse};
+
+/// Keeps only the given fields of a document, each a field name or a path such as `profile.email`.
+///
+/// The fields are returned in a document of the same shape, leaving out the ones the document does not have.
+/// Values that are not objects have no fields to select and are returned whole.
+pub fn project(value: &JsonValue, fields: &[String]) -> JsonValue
+{
+    if !value.is_object() {
+        return value.clone();
+    }
+
+    let mut projected = JsonValue::Object(Map::new());
+    for field in fields {
+        if let Some(selected) = field.split('.').try_fold(value, |value, name| value.get(name)) {
+            insert_path(&mut projected, field, selected.clone());
+        }
+    }
+    projected
+}
+
+/// Projects the value of a `LOOKUP` response, or each of the values of a `LOOKUP *` response when `many` is set.
+pub fn project_lookup(response: &mut NetResponse, fields: &[String], many: bool)
+{
+    match &mut response.value {
+        Some(JsonValue::Array(values)) if many => {
+            for value in values {
+                *value = project(value, fields);
+            }
+        }
+        Some(value) if !many => *value = project(value, fields),
+        _ => {}
+    }
+}
+
+/// Projects the value of each `{ key, value }` entry of a `SCAN` response.
+pub fn project_entries(response: &mut NetResponse, fields: &[String])
+{
+    if let Some(JsonValue::Array(entries)) = &mut response.value {
+        for value in entries.iter_mut().filter_map(|entry| entry.get_mut("value")) {
+            *value = project(value, fields);
+        }
+    }
+}
+
+/// Inserts `selected` at `path` in `target`, creating the objects along the way.
+fn insert_path(target: &mut JsonValue, path: &str, selected: JsonValue)
+{
+    let mut target = target;
+    let mut names = path.split('.').peekable();
+    while let Some(name) = names.next() {
+        let Some(object) = target.as_object_mut() else {
+            return;
+        };
+        if names.peek().is_none() {
+            object.insert(name.to_string(), selected);
+            return;
+        }
+        target = object.entry(name).or_insert_with(|| JsonValue::Object(Map::new()));
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_project()
+    {
+        let user = json!({
+            "name": "jamal",
+            "profile": { "email": "jamal@example.com", "bio": "..." },
+            "posts": [1, 2, 3],
+        });
+        let fields = ["name", "profile.email", "missing", "name.first"].map(String::from);
+
+        assert_eq!(
+            project(&user, &fields),
+            json!({ "name": "jamal", "profile": { "email": "jamal@example.com" } })
+        );
+        assert_eq!(project(&json!("text"), &fields), json!("text"));
+    }
+}
@@ -0,0 +1,374 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::sync::Arc;
+
+use futures::future::{BoxFuture, FutureExt};
+
+use crate::commands::commit::commit;
+use crate::commands::insert::wal_error;
+use crate::commands::reference::{check_references, reference_error};
+use crate::commands::transform::apply_transforms;
+use crate::commands::CommandArgs;
+use crate::protocol::{DbEngine, DbValue, JsonValue, NetActions, NetResponse};
+use crate::wal::WalRecord;
+
+/// The largest offset `SETRANGE` accepts, preventing a single command from allocating unbounded memory.
+const MAX_SETRANGE_OFFSET: u64 = 512 * 1024 * 1024;
+
+/// Executes a `GETRANGE` command on the database.
+///
+/// Returns the bytes between `start` and `end` (both inclusive) of a string value. Negative offsets count
+/// from the end of the value, so `-1` is the last byte. Ranges reaching outside the value are clamped. A range
+/// splitting a multi-byte character is an error.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` holding the key and the `[start, end]` offsets.
+/// * `engine` - The database engine used for the lookup.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with the selected range.
+pub fn getrange_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let db = &engine.connection;
+        let response = match args {
+            CommandArgs::WithArgs(Some(key), params) => {
+                match (
+                    params.first().and_then(JsonValue::as_i64),
+                    params.get(1).and_then(JsonValue::as_i64),
+                ) {
+                    (Some(start), Some(end)) => {
+                        let db_read = db.read();
+                        match db_read.get(&key).map(|data| &data.value) {
+                            Some(JsonValue::String(text)) => {
+                                let slice = match resolve_range(start, end, text.len()) {
+                                    Some((from, to)) => text.get(from..=to),
+                                    None => Some(""),
+                                };
+                                match slice {
+                                    Some(slice) => NetResponse {
+                                        action: NetActions::Command,
+                                        value: Some(slice.into()),
+                                        error: None,
+                                        ..Default::default()
+                                    },
+                                    None => NetResponse {
+                                        action: NetActions::Error,
+                                        value: None,
+                                        error: Some("GETRANGE would split a multi-byte character.".to_string()),
+                                        ..Default::default()
+                                    },
+                                }
+                            }
+                            Some(_) => NetResponse {
+                                action: NetActions::Error,
+                                value: None,
+                                error: Some(format!("Value at key '{}' is not a string.", key)),
+                                ..Default::default()
+                            },
+                            None => NetResponse {
+                                action: NetActions::Command,
+                                value: None,
+                                error: None,
+                                ..Default::default()
+                            },
+                        }
+                    }
+                    _ => NetResponse {
+                        action: NetActions::Error,
+                        value: None,
+                        error: Some("GETRANGE requires a start and end offset.".to_string()),
+                        ..Default::default()
+                    },
+                }
+            }
+            CommandArgs::WithArgs(None, ..) => NetResponse {
+                action: NetActions::Error,
+                value: None,
+                error: Some("No key provided for getrange.".to_string()),
+                ..Default::default()
+            },
+            _ => NetResponse {
+                action: NetActions::Error,
+                value: None,
+                error: Some("Invalid arguments for getrange.".to_string()),
+                ..Default::default()
+            },
+        };
+
+        Ok(response)
+    }
+    .boxed()
+}
+
+/// Executes a `SETRANGE` command on the database.
+///
+/// Overwrites part of a string value starting at the given byte offset. Missing keys are created, and values
+/// shorter than the offset are padded with zero bytes. The result must still be valid UTF-8.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` holding the key and the `[offset, data]` arguments.
+/// * `engine` - The database engine used for the update.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with the new length of the value.
+pub fn setrange_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let db = &engine.connection;
+        let response = match args {
+            CommandArgs::WithArgs(Some(key), params) => {
+                match (
+                    params.first().and_then(JsonValue::as_u64),
+                    params.get(1).and_then(JsonValue::as_str),
+                ) {
+                    (Some(offset), Some(_)) if offset > MAX_SETRANGE_OFFSET => NetResponse {
+                        action: NetActions::Error,
+                        value: None,
+                        error: Some(format!("Offset is out of range, the maximum is {}.", MAX_SETRANGE_OFFSET)),
+                        ..Default::default()
+                    },
+                    (Some(offset), Some(data)) => {
+                        let mut db_write = db.write().await;
+                        let current = match db_write.get(&key).map(|entry| &entry.value) {
+                            Some(JsonValue::String(text)) => Some(text.as_bytes().to_vec()),
+                            Some(_) => None,
+                            None => Some(Vec::new()),
+                        };
+
+                        match current {
+                            Some(mut bytes) => {
+                                let offset = offset as usize;
+                                let end = offset + data.len();
+                                if bytes.len() < end {
+                                    bytes.resize(end, 0);
+                                }
+                                bytes[offset..end].copy_from_slice(data.as_bytes());
+
+                                match String::from_utf8(bytes) {
+                                    Ok(text) => {
+                                        let mut value = JsonValue::String(text);
+                                        apply_transforms(&engine, &key, &mut value).await;
+                                        let length = value.as_str().map_or(0, str::len);
+                                        let warnings = match check_references(&engine, &key, &value, &HashSet::new()).await {
+                                            Ok(warnings) => warnings,
+                                            Err(e) => return Ok(reference_error(e)),
+                                        };
+                                        let expires_at = db_write.get(&key).and_then(|entry| entry.expires_at);
+                                        let data = DbValue { value, expires_at };
+                                        let committed =
+                                            match commit(&engine, &mut db_write, vec![WalRecord::Insert(key, data)]).await {
+                                                Ok(committed) => committed,
+                                                Err(e) => return Ok(wal_error(e)),
+                                            };
+                                        drop(db_write);
+                                        committed.refresh(&engine).await;
+                                        NetResponse {
+                                            action: NetActions::Command,
+                                            value: Some(length.into()),
+                                            error: None,
+                                            warnings,
+                                            ..Default::default()
+                                        }
+                                    }
+                                    Err(_) => NetResponse {
+                                        action: NetActions::Error,
+                                        value: None,
+                                        error: Some("SETRANGE would produce an invalid UTF-8 string.".to_string()),
+                                        ..Default::default()
+                                    },
+                                }
+                            }
+                            None => NetResponse {
+                                action: NetActions::Error,
+                                value: None,
+                                error: Some(format!("Value at key '{}' is not a string.", key)),
+                                ..Default::default()
+                            },
+                        }
+                    }
+                    _ => NetResponse {
+                        action: NetActions::Error,
+                        value: None,
+                        error: Some("SETRANGE requires an offset and a string.".to_string()),
+                        ..Default::default()
+                    },
+                }
+            }
+            CommandArgs::WithArgs(None, ..) => NetResponse {
+                action: NetActions::Error,
+                value: None,
+                error: Some("No key provided for setrange.".to_string()),
+                ..Default::default()
+            },
+            _ => NetResponse {
+                action: NetActions::Error,
+                value: None,
+                error: Some("Invalid arguments for setrange.".to_string()),
+                ..Default::default()
+            },
+        };
+
+        Ok(response)
+    }
+    .boxed()
+}
+
+/// Converts a possibly negative, inclusive `start..=end` range into valid indexes for a value of `len` bytes.
+/// Returns `None` if the range selects nothing.
+fn resolve_range(start: i64, end: i64, len: usize) -> Option<(usize, usize)>
+{
+    let len = len as i64;
+    let start = if start < 0 { (len + start).max(0) } else { start };
+    let end = if end < 0 { len + end } else { end.min(len - 1) };
+
+    if len == 0 || start > end || start >= len {
+        None
+    } else {
+        Some((start as usize, end as usize))
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use serde_json::json;
+
+    use super::*;
+    use crate::commands::derived::derive_set_command;
+    use crate::protocol::Database;
+    use crate::testing::create_fake_engine;
+
+    async fn insert_string(db: &Database, key: &str, value: &str)
+    {
+        let mut db_write = db.write().await;
+        db_write.insert(
+            key.to_string(),
+            DbValue {
+                value: json!(value),
+                expires_at: None,
+            },
+        );
+    }
+
+    #[tokio::test]
+    async fn test_getrange()
+    {
+        let engine = create_fake_engine();
+        let db = &engine.connection;
+        insert_string(db, "key", "Hello World").await;
+
+        let args = CommandArgs::WithArgs(Some("key".to_string()), vec![json!(0), json!(4)]);
+        let response = getrange_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.action, NetActions::Command);
+        assert_eq!(response.value, Some(json!("Hello")));
+
+        // Negative offsets count from the end of the value
+        let args = CommandArgs::WithArgs(Some("key".to_string()), vec![json!(-5), json!(-1)]);
+        let response = getrange_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.value, Some(json!("World")));
+
+        // Out of range offsets are clamped
+        let args = CommandArgs::WithArgs(Some("key".to_string()), vec![json!(6), json!(100)]);
+        let response = getrange_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.value, Some(json!("World")));
+    }
+
+    #[tokio::test]
+    async fn test_getrange_multibyte()
+    {
+        let engine = create_fake_engine();
+        insert_string(&engine.connection, "key", "héllo").await;
+
+        let args = CommandArgs::WithArgs(Some("key".to_string()), vec![json!(0), json!(2)]);
+        let response = getrange_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.value, Some(json!("hé")));
+
+        // The second byte of `é` is not the end of a character
+        let args = CommandArgs::WithArgs(Some("key".to_string()), vec![json!(0), json!(1)]);
+        let response = getrange_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.action, NetActions::Error);
+        assert_eq!(
+            response.error,
+            Some("GETRANGE would split a multi-byte character.".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_getrange_not_a_string()
+    {
+        let engine = create_fake_engine();
+        let db = &engine.connection;
+        {
+            let mut db_write = db.write().await;
+            db_write.insert(
+                "key".to_string(),
+                DbValue {
+                    value: json!({ "a": 1 }),
+                    expires_at: None,
+                },
+            );
+        }
+
+        let args = CommandArgs::WithArgs(Some("key".to_string()), vec![json!(0), json!(1)]);
+        let response = getrange_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.action, NetActions::Error);
+        assert_eq!(response.error, Some("Value at key 'key' is not a string.".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_setrange()
+    {
+        let engine = create_fake_engine();
+        let db = &engine.connection;
+        insert_string(db, "key", "Hello World").await;
+
+        let args = CommandArgs::WithArgs(Some("key".to_string()), vec![json!(6), json!("Redis")]);
+        let response = setrange_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.action, NetActions::Command);
+        assert_eq!(response.value, Some(json!(11)));
+
+        let db_read = db.read();
+        assert_eq!(db_read.get("key").unwrap().value, json!("Hello Redis"));
+    }
+
+    #[tokio::test]
+    async fn test_setrange_pads_missing_key()
+    {
+        let engine = create_fake_engine();
+        let db = &engine.connection;
+
+        let args = CommandArgs::WithArgs(Some("key".to_string()), vec![json!(2), json!("ab")]);
+        let response = setrange_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.value, Some(json!(4)));
+
+        let db_read = db.read();
+        assert_eq!(db_read.get("key").unwrap().value, json!("\0\0ab"));
+    }
+
+    #[tokio::test]
+    async fn test_setrange_refreshes_derived_keys()
+    {
+        let engine = create_fake_engine();
+
+        let definition = json!({ "op": "count", "sources": ["key"] });
+        let args = CommandArgs::WithArgs(Some("count".to_string()), vec![definition]);
+        derive_set_command(args, engine.clone()).await.unwrap();
+
+        let args = CommandArgs::WithArgs(Some("key".to_string()), vec![json!(0), json!("ab")]);
+        setrange_command(args, engine.clone()).await.unwrap();
+
+        let db_read = engine.connection.read();
+        assert_eq!(db_read.get("count").unwrap().value, json!(1));
+    }
+}
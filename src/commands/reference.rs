@@ -0,0 +1,318 @@
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::ops::Bound;
+use std::sync::Arc;
+
+use futures::future::{BoxFuture, FutureExt};
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+use crate::commands::CommandArgs;
+use crate::protocol::{DbEngine, DbKey, JsonValue, NetActions, NetResponse};
+
+/// The references declared with `REFERENCE SET`, keyed by the key prefix of the values holding them.
+pub type References = RwLock<BTreeMap<String, Vec<Reference>>>;
+
+/// A field of a value naming another key that is expected to exist.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Reference
+{
+    /// The `.` separated path of the field holding the reference.
+    field: String,
+    /// The referenced key, where `{}` is replaced by the value of the field, such as `user:{}`.
+    target: String,
+    /// What happens when the referenced key does not exist.
+    #[serde(default)]
+    mode: ReferenceMode,
+}
+
+/// What happens to a write holding a dangling reference.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ReferenceMode
+{
+    /// The write is rejected.
+    #[default]
+    Enforce,
+    /// The write goes through with a warning.
+    Warn,
+}
+
+impl Reference
+{
+    /// Returns the key referenced by `value`, if it holds the field.
+    fn target_key(&self, value: &JsonValue) -> Option<DbKey>
+    {
+        let field = self.field.split('.').try_fold(value, |value, name| value.get(name))?;
+        let id = match field {
+            JsonValue::String(id) => id.clone(),
+            JsonValue::Number(id) => id.to_string(),
+            _ => return None,
+        };
+
+        Some(self.target.replace("{}", &id))
+    }
+}
+
+/// Checks the references held by a value about to be stored under `key`.
+///
+/// # Arguments
+///
+/// * `engine` - The database engine holding the references.
+/// * `key` - The key the value is about to be stored under.
+/// * `value` - The value to check.
+/// * `batch` - The other values written along with this one, which count as existing.
+///
+/// # Returns
+///
+/// The warnings for the dangling references in `warn` mode, or an error for the first dangling reference in
+/// `enforce` mode.
+pub async fn check_references(
+    engine: &DbEngine,
+    key: &str,
+    value: &JsonValue,
+    batch: &HashSet<&str>,
+) -> Result<Vec<String>, String>
+{
+    let references = engine.references.read().await;
+    let db_read = engine.connection.read();
+    let mut warnings = Vec::new();
+
+    // Prefixes of a key sort before it
+    for (prefix, references) in references.range::<str, _>((Bound::Unbounded, Bound::Included(key))) {
+        if !key.starts_with(prefix.as_str()) {
+            continue;
+        }
+
+        for reference in references {
+            let Some(target) = reference.target_key(value) else {
+                continue;
+            };
+            if db_read.contains_key(&target) || batch.contains(target.as_str()) {
+                continue;
+            }
+
+            let message = format!(
+                "Field '{}' of '{}' references missing key '{}'.",
+                reference.field, key, target
+            );
+            match reference.mode {
+                ReferenceMode::Enforce => return Err(message),
+                ReferenceMode::Warn => warnings.push(message),
+            }
+        }
+    }
+
+    Ok(warnings)
+}
+
+/// Executes a `REFERENCE SET` command, declaring the references held by the values written under a key prefix.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` holding the prefix and the `[references]` argument, a list such as
+///   `{ "field": "user_id", "target": "user:{}", "mode": "warn" }`. Replaces the references previously declared
+///   for the prefix.
+/// * `engine` - The database engine storing the references.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` indicating the result of the command.
+pub fn reference_set_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let response = match args {
+            CommandArgs::WithArgs(Some(prefix), mut params) if !params.is_empty() => {
+                match serde_json::from_value::<Vec<Reference>>(params.swap_remove(0)) {
+                    Ok(references) => {
+                        engine.references.write().await.insert(prefix, references);
+                        NetResponse {
+                            action: NetActions::Command,
+                            value: Some("OK".to_string().into()),
+                            error: None,
+                            ..Default::default()
+                        }
+                    }
+                    Err(e) => reference_error(format!("Invalid reference: {}", e)),
+                }
+            }
+            CommandArgs::WithArgs(Some(_), _) => reference_error("REFERENCE SET requires a list of references.".to_string()),
+            _ => reference_error("No prefix provided for reference.".to_string()),
+        };
+
+        Ok(response)
+    }
+    .boxed()
+}
+
+/// Executes a `REFERENCE GET` command, returning the references declared for a key prefix.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` holding the prefix.
+/// * `engine` - The database engine storing the references.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with the references, if there are any.
+pub fn reference_get_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let response = match args {
+            CommandArgs::WithArgs(Some(prefix), _) => NetResponse {
+                action: NetActions::Command,
+                value: engine
+                    .references
+                    .read()
+                    .await
+                    .get(&prefix)
+                    .and_then(|references| serde_json::to_value(references).ok()),
+                error: None,
+                ..Default::default()
+            },
+            _ => reference_error("No prefix provided for reference.".to_string()),
+        };
+
+        Ok(response)
+    }
+    .boxed()
+}
+
+/// Executes a `REFERENCE DELETE` command, removing the references declared for a key prefix.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` holding the prefix.
+/// * `engine` - The database engine storing the references.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` indicating the result of the command.
+pub fn reference_delete_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let response = match args {
+            CommandArgs::WithArgs(Some(prefix), _) => {
+                if engine.references.write().await.remove(&prefix).is_some() {
+                    NetResponse {
+                        action: NetActions::Command,
+                        value: Some("OK".to_string().into()),
+                        error: None,
+                        ..Default::default()
+                    }
+                } else {
+                    reference_error(format!("No reference declared for '{}'.", prefix))
+                }
+            }
+            _ => reference_error("No prefix provided for reference.".to_string()),
+        };
+
+        Ok(response)
+    }
+    .boxed()
+}
+
+/// Builds an error response for the reference commands, and for the writes holding a dangling reference.
+pub fn reference_error(message: String) -> NetResponse
+{
+    NetResponse {
+        action: NetActions::Error,
+        value: None,
+        error: Some(message),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use serde_json::json;
+
+    use super::*;
+    use crate::commands::insert::insert_command;
+    use crate::commands::template::{insert_from_template_command, template_set_command};
+    use crate::commands::CommandParams;
+    use crate::protocol::DbValue;
+    use crate::testing::create_fake_engine;
+
+    fn insert_args(key: &str, value: JsonValue) -> CommandArgs
+    {
+        CommandArgs::Single(Some(key.to_string()), Some(DbValue { value, expires_at: None }))
+    }
+
+    #[tokio::test]
+    async fn test_enforced_references()
+    {
+        let engine = create_fake_engine();
+        let references = json!([{ "field": "user_id", "target": "user:{}" }]);
+        let args = CommandArgs::WithArgs(Some("order:".to_string()), vec![references]);
+        reference_set_command(args, engine.clone()).await.unwrap();
+
+        let response = insert_command(insert_args("order:1", json!({ "user_id": 7 })), engine.clone())
+            .await
+            .unwrap();
+        assert_eq!(response.action, NetActions::Error);
+        assert_eq!(
+            response.error,
+            Some("Field 'user_id' of 'order:1' references missing key 'user:7'.".to_string())
+        );
+        assert!(engine.connection.read().get("order:1").is_none());
+
+        // The referenced key can be written in the same batch
+        let args = CommandArgs::Many(vec![
+            CommandParams {
+                key: Some("order:1".to_string()),
+                value: Some(json!({ "user_id": 7 })),
+                expires_at: None,
+            },
+            CommandParams {
+                key: Some("user:7".to_string()),
+                value: Some(json!({ "name": "jamal" })),
+                expires_at: None,
+            },
+        ]);
+        let response = insert_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.action, NetActions::Command);
+    }
+
+    #[tokio::test]
+    async fn test_references_enforced_on_templates()
+    {
+        let engine = create_fake_engine();
+        let references = json!([{ "field": "user_id", "target": "user:{}" }]);
+        let args = CommandArgs::WithArgs(Some("order:".to_string()), vec![references]);
+        reference_set_command(args, engine.clone()).await.unwrap();
+
+        let args = CommandArgs::WithArgs(Some("order".to_string()), vec![json!({ "user_id": 7 })]);
+        template_set_command(args, engine.clone()).await.unwrap();
+        let args = CommandArgs::WithArgs(Some("order:1".to_string()), vec![json!("order")]);
+        let response = insert_from_template_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.action, NetActions::Error);
+        assert!(engine.connection.read().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_warned_references()
+    {
+        let engine = create_fake_engine();
+        let references = json!([{ "field": "user_id", "target": "user:{}", "mode": "warn" }]);
+        let args = CommandArgs::WithArgs(Some("order:".to_string()), vec![references]);
+        reference_set_command(args, engine.clone()).await.unwrap();
+
+        let response = insert_command(insert_args("order:1", json!({ "user_id": "7" })), engine.clone())
+            .await
+            .unwrap();
+        assert_eq!(response.action, NetActions::Command);
+        assert_eq!(response.warnings.len(), 1);
+        assert!(engine.connection.read().get("order:1").is_some());
+    }
+}
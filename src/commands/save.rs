@@ -0,0 +1,423 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use futures::future::{BoxFuture, FutureExt};
+use serde::Serialize;
+use serde_json::json;
+use tokio::time::Instant;
+use tracing::{error, info};
+
+use crate::commands::CommandArgs;
+use crate::diagnostics::{DiagnosticKind, DiagnosticLevel};
+use crate::protocol::{DbEngine, JsonValue, NetActions, NetResponse};
+use crate::snapshot;
+use crate::store::DbMap;
+
+/// The number of finished save jobs kept around for `SAVEJOB STATUS`.
+const MAX_SAVE_JOBS: usize = 16;
+
+/// Snapshots started with `BGSAVE` and the outcome of the last one.
+#[derive(Debug, Default)]
+pub struct Snapshots
+{
+    /// The id handed out to the next save job.
+    next_id: AtomicU64,
+    /// The most recent save jobs, keyed by their id.
+    jobs: Mutex<BTreeMap<u64, SaveJobStatus>>,
+    /// The last snapshot that was saved successfully.
+    last_save: Mutex<Option<SaveReport>>,
+}
+
+/// The progress of a save job.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum SaveJobStatus
+{
+    /// The snapshot is still being written.
+    Running,
+    /// The snapshot was written to disk.
+    Completed
+    {
+        /// The size of the snapshot in bytes.
+        size: u64,
+        /// How long saving the snapshot took, in milliseconds.
+        duration_ms: u64,
+    },
+    /// The snapshot could not be written.
+    Failed
+    {
+        /// Why the snapshot could not be written.
+        error: String,
+    },
+}
+
+/// Details about a saved snapshot, reported by `INFO`.
+#[derive(Debug, Clone, Copy)]
+pub struct SaveReport
+{
+    /// When the snapshot finished saving, in seconds since the UNIX epoch.
+    pub finished_at: u64,
+    /// How long saving the snapshot took.
+    pub duration: Duration,
+    /// The size of the snapshot in bytes.
+    pub size: u64,
+}
+
+impl Snapshots
+{
+    /// Registers a new save job, unless one is already running.
+    fn start(&self) -> Option<u64>
+    {
+        let mut jobs = self.jobs.lock().unwrap();
+        if jobs.values().any(|job| *job == SaveJobStatus::Running) {
+            return None;
+        }
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        jobs.insert(id, SaveJobStatus::Running);
+        while jobs.len() > MAX_SAVE_JOBS {
+            jobs.pop_first();
+        }
+        Some(id)
+    }
+
+    /// Records the outcome of a save job.
+    fn finish(&self, id: u64, result: &std::io::Result<u64>, duration: Duration)
+    {
+        let status = match result {
+            Ok(size) => {
+                let size = *size;
+                let finished_at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+                *self.last_save.lock().unwrap() = Some(SaveReport {
+                    finished_at,
+                    duration,
+                    size,
+                });
+                SaveJobStatus::Completed {
+                    size,
+                    duration_ms: duration.as_millis() as u64,
+                }
+            }
+            Err(e) => SaveJobStatus::Failed { error: e.to_string() },
+        };
+
+        self.jobs.lock().unwrap().insert(id, status);
+    }
+
+    /// Returns the status of a save job, if it is still known.
+    pub fn status(&self, id: u64) -> Option<SaveJobStatus>
+    {
+        self.jobs.lock().unwrap().get(&id).cloned()
+    }
+
+    /// Returns whether a save job is running.
+    pub fn in_progress(&self) -> bool
+    {
+        self.jobs.lock().unwrap().values().any(|job| *job == SaveJobStatus::Running)
+    }
+
+    /// Returns the last snapshot that was saved successfully.
+    pub fn last_save(&self) -> Option<SaveReport>
+    {
+        *self.last_save.lock().unwrap()
+    }
+}
+
+/// Executes a `BGSAVE` command, saving a snapshot of the database in the background.
+///
+/// The snapshot is taken when the command runs, writes made while it is saved are not part of it.
+///
+/// # Arguments
+///
+/// * `_args` - Unused, `BGSAVE` takes no arguments.
+/// * `engine` - The database engine to save.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with the id of the save job, to be
+/// passed to `SAVEJOB STATUS`.
+pub fn bgsave_command(
+    _args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let Some(id) = engine.snapshots.start() else {
+            return Ok(NetResponse {
+                action: NetActions::Error,
+                value: None,
+                error: Some("A snapshot is already being saved.".to_string()),
+                ..Default::default()
+            });
+        };
+
+        let (map, checkpoint) = checkpoint(&engine).await;
+        let started = Instant::now();
+        let write_engine = engine.clone();
+        let write = tokio::task::spawn_blocking(move || write_snapshot(&write_engine, &map, checkpoint));
+
+        // The job is finished from the outside of the write, so it fails rather than stays running if the write
+        // panics
+        let job_engine = engine.clone();
+        tokio::spawn(async move {
+            let result = match write.await {
+                Ok(result) => result,
+                Err(e) => {
+                    if checkpoint {
+                        end_checkpoint(&job_engine, false);
+                    }
+                    Err(std::io::Error::other(e))
+                }
+            };
+
+            report_save(&job_engine, &result);
+            job_engine.snapshots.finish(id, &result, started.elapsed());
+        });
+
+        Ok(NetResponse {
+            action: NetActions::Command,
+            value: Some(json!({ "job": id })),
+            error: None,
+            ..Default::default()
+        })
+    }
+    .boxed()
+}
+
+/// Saves a snapshot of the database and waits for it to be written, recording it as a save job.
+///
+/// # Arguments
+///
+/// * `engine` - The database engine to save.
+///
+/// # Returns
+///
+/// The size of the snapshot and how long it took to save, or `None` if a snapshot is already being saved.
+pub async fn save(engine: &Arc<DbEngine>) -> Option<std::io::Result<(u64, Duration)>>
+{
+    let id = engine.snapshots.start()?;
+
+    let (map, checkpoint) = checkpoint(engine).await;
+    let started = Instant::now();
+    let write_engine = engine.clone();
+    let result = match tokio::task::spawn_blocking(move || write_snapshot(&write_engine, &map, checkpoint)).await {
+        Ok(result) => result,
+        Err(e) => {
+            if checkpoint {
+                end_checkpoint(engine, false);
+            }
+            Err(std::io::Error::other(e))
+        }
+    };
+    let duration = started.elapsed();
+
+    report_save(engine, &result);
+    engine.snapshots.finish(id, &result, duration);
+    Some(result.map(|size| (size, duration)))
+}
+
+/// Takes the snapshot of the database to save, starting a checkpoint of the write-ahead log at the same point.
+///
+/// # Returns
+///
+/// The snapshot, and whether the checkpoint started.
+async fn checkpoint(engine: &DbEngine) -> (Arc<DbMap>, bool)
+{
+    // Holding the write lock keeps writers from appending between starting the checkpoint and taking the snapshot
+    let _db_write = engine.connection.write().await;
+    let checkpoint = engine.wal.begin_checkpoint();
+    (engine.connection.read(), checkpoint)
+}
+
+/// Writes `map` to the snapshot file, then ends the checkpoint of the write-ahead log if one was started with it.
+/// Blocks on the file system, so it runs on the blocking thread pool.
+fn write_snapshot(engine: &DbEngine, map: &DbMap, checkpoint: bool) -> std::io::Result<u64>
+{
+    let result = snapshot::write(map, &engine.db_config.snapshot_path, engine.cipher.as_deref());
+    if checkpoint {
+        end_checkpoint(engine, result.is_ok());
+    }
+    result
+}
+
+/// Ends the checkpoint of the write-ahead log started along with a save, cutting the log back if `saved`.
+fn end_checkpoint(engine: &DbEngine, saved: bool)
+{
+    if let Err(e) = engine.wal.end_checkpoint(saved) {
+        error!("Failed to cut back the write-ahead log after saving a snapshot: {}", e);
+    }
+}
+
+/// Logs the outcome of a save and sends it to the clients subscribed to diagnostics.
+fn report_save(engine: &DbEngine, result: &std::io::Result<u64>)
+{
+    let path = engine.db_config.snapshot_path.display();
+    match result {
+        Ok(size) => {
+            let message = format!("Saved snapshot of {} bytes to {}", size, path);
+            info!("{}", message);
+            engine
+                .diagnostics
+                .emit(DiagnosticLevel::Info, DiagnosticKind::Snapshot, message);
+        }
+        Err(e) => {
+            let message = format!("Failed to save snapshot to {}: {}", path, e);
+            error!("{}", message);
+            engine
+                .diagnostics
+                .emit(DiagnosticLevel::Error, DiagnosticKind::Snapshot, message);
+        }
+    }
+}
+
+/// Executes a `SAVE` command, saving a snapshot of the database and waiting for it to be written.
+///
+/// Other commands keep being served while the snapshot is written, only the client sending `SAVE` waits.
+///
+/// # Arguments
+///
+/// * `_args` - Unused, `SAVE` takes no arguments.
+/// * `engine` - The database engine to save.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with the size of the snapshot and how
+/// long it took to save, in milliseconds.
+pub fn save_command(
+    _args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let response = match save(&engine).await {
+            None => NetResponse {
+                action: NetActions::Error,
+                value: None,
+                error: Some("A snapshot is already being saved.".to_string()),
+                ..Default::default()
+            },
+            Some(Ok((size, duration))) => NetResponse {
+                action: NetActions::Command,
+                value: Some(json!({ "size": size, "duration_ms": duration.as_millis() as u64 })),
+                error: None,
+                ..Default::default()
+            },
+            Some(Err(e)) => NetResponse {
+                action: NetActions::Error,
+                value: None,
+                error: Some(format!("Failed to save snapshot: {}", e)),
+                ..Default::default()
+            },
+        };
+
+        Ok(response)
+    }
+    .boxed()
+}
+
+/// Executes a `SAVEJOB STATUS` command, reporting the progress of a save job.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` holding the `[id]` of the job returned by `BGSAVE`.
+/// * `engine` - The database engine tracking the job.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with the status of the job.
+pub fn savejob_status_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let response = match args {
+            CommandArgs::WithArgs(_, params) => match params.first().and_then(JsonValue::as_u64) {
+                Some(id) => match engine.snapshots.status(id) {
+                    Some(status) => NetResponse {
+                        action: NetActions::Command,
+                        value: Some(json!(status)),
+                        error: None,
+                        ..Default::default()
+                    },
+                    None => NetResponse {
+                        action: NetActions::Error,
+                        value: None,
+                        error: Some(format!("Unknown save job {}.", id)),
+                        ..Default::default()
+                    },
+                },
+                None => NetResponse {
+                    action: NetActions::Error,
+                    value: None,
+                    error: Some("No save job id provided.".to_string()),
+                    ..Default::default()
+                },
+            },
+            _ => NetResponse {
+                action: NetActions::Error,
+                value: None,
+                error: Some("Invalid arguments for savejob status.".to_string()),
+                ..Default::default()
+            },
+        };
+
+        Ok(response)
+    }
+    .boxed()
+}
+
+#[cfg(test)]
+mod test
+{
+    use clap::Parser;
+
+    use super::*;
+    use crate::cli::Cli;
+
+    #[tokio::test]
+    async fn test_bgsave()
+    {
+        let path = std::env::temp_dir().join(format!("phoenix-db-bgsave-{}.snapshot", std::process::id()));
+        let engine = Arc::new(DbEngine::new(Cli::parse_from([
+            "phoenix-db",
+            "--snapshot-path",
+            path.to_str().unwrap(),
+        ])));
+
+        let response = bgsave_command(CommandArgs::WithArgs(None, vec![]), engine.clone())
+            .await
+            .unwrap();
+        assert_eq!(response.action, NetActions::Command);
+        let id = response.value.unwrap()["job"].clone();
+
+        let status = loop {
+            let response = savejob_status_command(CommandArgs::WithArgs(None, vec![id.clone()]), engine.clone())
+                .await
+                .unwrap();
+            let status = response.value.unwrap();
+            if status["status"] != "running" {
+                break status;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+
+        assert_eq!(status["status"], "completed");
+        assert!(engine.snapshots.last_save().is_some());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_savejob_status_unknown_job()
+    {
+        let engine = Arc::new(DbEngine::new(Cli::parse_from(["phoenix-db"])));
+
+        let response = savejob_status_command(CommandArgs::WithArgs(None, vec![json!(42)]), engine)
+            .await
+            .unwrap();
+        assert_eq!(response.action, NetActions::Error);
+    }
+}
@@ -0,0 +1,210 @@
+use std::error::Error;
+use std::ops::Bound;
+use std::sync::Arc;
+
+use futures::future::{BoxFuture, FutureExt};
+use serde_json::json;
+
+use crate::commands::budget::ResponseBudget;
+use crate::commands::CommandArgs;
+use crate::protocol::{DbEngine, DbKey, DbValue, JsonValue, NetActions, NetResponse};
+
+/// The number of entries `SCAN` returns when no count is given.
+const DEFAULT_SCAN_COUNT: usize = 100;
+
+/// Executes a `SCAN` command, iterating over the keyspace a page at a time.
+///
+/// The cursor is a position in the database's iteration order. Pages are cut short when they reach the
+/// requested count or the maximum response size, in which case the response carries the cursor of the next
+/// page. Entries written or deleted while a scan is in progress can shift positions, so a scan may return an
+/// entry twice or miss one.
+///
+/// When the server runs with `--ordered-keys` entries are returned in key order, and a scan with a prefix only
+/// goes through the keys starting with it.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` holding an optional key prefix and the `[cursor, count]` arguments,
+///   both optional.
+/// * `engine` - The database engine to scan.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with the `{ key, value }` entries of the
+/// page.
+pub fn scan_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let (prefix, cursor, count) = match args {
+            CommandArgs::WithArgs(prefix, params) => {
+                let cursor = params.first().map(JsonValue::as_u64);
+                let count = params.get(1).map(JsonValue::as_u64);
+
+                match (cursor, count) {
+                    (Some(None), _) | (_, Some(None)) | (_, Some(Some(0))) => {
+                        return Ok(NetResponse {
+                            action: NetActions::Error,
+                            value: None,
+                            error: Some("Invalid cursor or count for scan.".to_string()),
+                            ..Default::default()
+                        });
+                    }
+                    (cursor, count) => (
+                        prefix,
+                        cursor.flatten().unwrap_or(0) as usize,
+                        count.flatten().map(|count| count as usize).unwrap_or(DEFAULT_SCAN_COUNT),
+                    ),
+                }
+            }
+            _ => {
+                return Ok(NetResponse {
+                    action: NetActions::Error,
+                    value: None,
+                    error: Some("Invalid arguments for scan.".to_string()),
+                    ..Default::default()
+                });
+            }
+        };
+
+        // The keys are read before the map, see `Store::read_ordered`
+        let ordered = engine.connection.read_ordered();
+        let db_read = engine.connection.read();
+        let entries: Box<dyn Iterator<Item = (&DbKey, &DbValue)>> = match (&ordered, &prefix) {
+            (Some(keys), Some(prefix)) => Box::new(
+                keys.range::<_, str>((Bound::Included(prefix.as_str()), Bound::Unbounded))
+                    .take_while(|key| key.starts_with(prefix.as_str()))
+                    .filter_map(|key| db_read.get_key_value(key)),
+            ),
+            (Some(keys), None) => Box::new(keys.iter().filter_map(|key| db_read.get_key_value(key))),
+            (None, _) => Box::new(db_read.iter()),
+        };
+        let mut budget = ResponseBudget::new(engine.db_config.max_response_size);
+        let mut results = vec![];
+        let mut next_cursor = None;
+        let mut truncated = false;
+
+        for (position, (key, data)) in entries.enumerate().skip(cursor) {
+            if results.len() == count {
+                next_cursor = Some(position);
+                break;
+            }
+            if prefix.as_ref().is_some_and(|prefix| !key.starts_with(prefix.as_str())) {
+                continue;
+            }
+
+            let entry = json!({ "key": key, "value": data.value });
+            if !budget.try_take(&entry) {
+                next_cursor = Some(position);
+                truncated = true;
+                break;
+            }
+            results.push(entry);
+        }
+
+        Ok(NetResponse {
+            action: NetActions::Command,
+            value: Some(JsonValue::Array(results)),
+            error: None,
+            truncated,
+            cursor: next_cursor,
+            ..Default::default()
+        })
+    }
+    .boxed()
+}
+
+#[cfg(test)]
+mod test
+{
+    use clap::Parser;
+
+    use super::*;
+    use crate::cli::Cli;
+    use crate::protocol::DbValue;
+
+    fn create_fake_engine(max_response_size: usize) -> Arc<DbEngine>
+    {
+        Arc::new(DbEngine::new(Cli::parse_from([
+            "phoenix-db",
+            "--max-response-size",
+            &max_response_size.to_string(),
+        ])))
+    }
+
+    async fn insert_keys(engine: &DbEngine, count: usize)
+    {
+        let mut db_write = engine.connection.write().await;
+        for i in 0..count {
+            db_write.insert(
+                format!("key:{}", i),
+                DbValue {
+                    value: json!(i),
+                    expires_at: None,
+                },
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_scan_pages_through_every_key()
+    {
+        let engine = create_fake_engine(1024 * 1024);
+        insert_keys(&engine, 25).await;
+
+        let mut seen = vec![];
+        let mut cursor = 0;
+        loop {
+            let response = scan_command(CommandArgs::WithArgs(None, vec![json!(cursor), json!(10)]), engine.clone())
+                .await
+                .unwrap();
+            assert!(!response.truncated);
+
+            seen.extend(response.value.unwrap().as_array().unwrap().clone());
+            match response.cursor {
+                Some(next) => cursor = next,
+                None => break,
+            }
+        }
+
+        assert_eq!(seen.len(), 25);
+    }
+
+    #[tokio::test]
+    async fn test_scan_truncates_large_pages()
+    {
+        let engine = create_fake_engine(64);
+        insert_keys(&engine, 25).await;
+
+        let response = scan_command(CommandArgs::WithArgs(None, vec![]), engine.clone())
+            .await
+            .unwrap();
+
+        assert!(response.truncated);
+        assert!(response.cursor.is_some());
+        assert!(response.value.unwrap().as_array().unwrap().len() < 25);
+    }
+
+    #[tokio::test]
+    async fn test_scan_with_prefix()
+    {
+        let engine = create_fake_engine(1024 * 1024);
+        insert_keys(&engine, 5).await;
+        engine.connection.write().await.insert(
+            "other".to_string(),
+            DbValue {
+                value: json!(true),
+                expires_at: None,
+            },
+        );
+
+        let response = scan_command(CommandArgs::WithArgs(Some("key:".to_string()), vec![]), engine.clone())
+            .await
+            .unwrap();
+
+        assert_eq!(response.value.unwrap().as_array().unwrap().len(), 5);
+        assert_eq!(response.cursor, None);
+    }
+}
@@ -0,0 +1,97 @@
+use std::error::Error;
+use std::sync::Arc;
+
+use futures::future::{BoxFuture, FutureExt};
+use serde_json::json;
+
+use crate::commands::CommandArgs;
+use crate::protocol::{DbEngine, NetActions, NetResponse};
+
+/// Executes a `SERVICES` command, listing the background services of the server.
+///
+/// # Arguments
+///
+/// * `_args` - Unused, `SERVICES` takes no arguments.
+/// * `engine` - The database engine owning the services.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with the `name`, `state`, whether it is
+/// `busy` and the number of `runs` of each service.
+pub fn services_command(
+    _args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        Ok(NetResponse {
+            action: NetActions::Command,
+            value: Some(json!(engine.services.status())),
+            error: None,
+            ..Default::default()
+        })
+    }
+    .boxed()
+}
+
+/// Executes a `SERVICES STOP` command, stopping a background service once its run in progress is done.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` holding the name of the service.
+/// * `engine` - The database engine owning the services.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with `OK`, or an error if there is no such
+/// service.
+pub fn services_stop_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move { Ok(set_enabled(args, &engine, false)) }.boxed()
+}
+
+/// Executes a `SERVICES START` command, starting a stopped background service again.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` holding the name of the service.
+/// * `engine` - The database engine owning the services.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with `OK`, or an error if there is no such
+/// service.
+pub fn services_start_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move { Ok(set_enabled(args, &engine, true)) }.boxed()
+}
+
+/// Starts or stops the service named by `args`, replying with `OK` or why it cannot be.
+fn set_enabled(args: CommandArgs, engine: &DbEngine, enabled: bool) -> NetResponse
+{
+    let result = match args {
+        CommandArgs::WithArgs(Some(name), _) => engine.services.set_enabled(&name, enabled),
+        _ => Err("No service name provided.".to_string()),
+    };
+
+    match result {
+        Ok(()) => NetResponse {
+            action: NetActions::Command,
+            value: Some("OK".to_string().into()),
+            error: None,
+            ..Default::default()
+        },
+        Err(e) => NetResponse {
+            action: NetActions::Error,
+            value: None,
+            error: Some(e),
+            ..Default::default()
+        },
+    }
+}
@@ -0,0 +1,556 @@
+use std::error::Error;
+use std::sync::Arc;
+
+use futures::future::{BoxFuture, FutureExt};
+use serde_json::json;
+
+use crate::commands::budget::ResponseBudget;
+use crate::commands::CommandArgs;
+use crate::protocol::{DbEngine, JsonValue, NetActions, NetResponse};
+
+/// Executes an `SQL` command, running a read-only `SELECT` over the keyspace.
+///
+/// Only a small subset of SQL is understood, enough for reporting tools to query entries:
+///
+/// ```sql
+/// SELECT key, value->'address'->>'city' FROM keys WHERE key LIKE 'user:%' AND value->>'active' = 'true' LIMIT 50
+/// ```
+///
+/// The only table is `keys`, with the `key` and `value` columns. `->` extracts a field or array element of a
+/// JSON value and `->>` does the same but returns it as text. Conditions compare a column to a literal with
+/// `=`, `!=` or `LIKE` and can be combined with `AND`. The whole keyspace is scanned for every query.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` holding the `[query]`.
+/// * `engine` - The database engine to query.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with the `columns` and `rows` of the
+/// result.
+pub fn sql_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let query = match args {
+            CommandArgs::WithArgs(_, params) => match params.first().and_then(JsonValue::as_str) {
+                Some(query) => Query::parse(query),
+                None => Err("No query provided.".to_string()),
+            },
+            _ => Err("Invalid arguments for sql.".to_string()),
+        };
+
+        let query = match query {
+            Ok(query) => query,
+            Err(e) => {
+                return Ok(NetResponse {
+                    action: NetActions::Error,
+                    value: None,
+                    error: Some(e),
+                    ..Default::default()
+                });
+            }
+        };
+
+        let mut budget = ResponseBudget::new(engine.db_config.max_response_size);
+        let mut rows = vec![];
+        let mut truncated = false;
+
+        for (key, data) in engine.connection.read().iter() {
+            if query.limit.is_some_and(|limit| rows.len() >= limit) {
+                break;
+            }
+            if !query.conditions.iter().all(|condition| condition.matches(key, &data.value)) {
+                continue;
+            }
+
+            let row = JsonValue::Array(query.columns.iter().map(|column| column.eval(key, &data.value)).collect());
+            if !budget.try_take(&row) {
+                truncated = true;
+                break;
+            }
+            rows.push(row);
+        }
+
+        let columns: Vec<String> = query.columns.iter().map(Column::to_string).collect();
+        Ok(NetResponse {
+            action: NetActions::Command,
+            value: Some(json!({ "columns": columns, "rows": rows })),
+            error: None,
+            truncated,
+            ..Default::default()
+        })
+    }
+    .boxed()
+}
+
+/// A parsed `SELECT` statement.
+#[derive(Debug, PartialEq)]
+struct Query
+{
+    columns: Vec<Column>,
+    conditions: Vec<Condition>,
+    limit: Option<usize>,
+}
+
+/// A column of the `keys` table, possibly narrowed down to part of the value.
+#[derive(Debug, PartialEq)]
+enum Column
+{
+    Key,
+    Value
+    {
+        /// The fields and array elements to follow into the value.
+        path: Vec<JsonValue>,
+        /// Whether the result is converted to text, as done by `->>`.
+        as_text: bool,
+    },
+}
+
+/// A `WHERE` condition comparing a column to a literal.
+#[derive(Debug, PartialEq)]
+struct Condition
+{
+    column: Column,
+    operator: Operator,
+    literal: JsonValue,
+}
+
+#[derive(Debug, PartialEq)]
+enum Operator
+{
+    Eq,
+    NotEq,
+    Like,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token
+{
+    Word(String),
+    Literal(JsonValue),
+    Comma,
+    Star,
+    Arrow,
+    TextArrow,
+    Eq,
+    NotEq,
+}
+
+impl Query
+{
+    /// Parses a `SELECT` statement.
+    fn parse(query: &str) -> Result<Self, String>
+    {
+        let mut parser = Parser {
+            tokens: tokenize(query)?,
+            position: 0,
+        };
+
+        parser.expect_keyword("SELECT")?;
+        let mut columns = vec![];
+        loop {
+            if parser.next_if(&Token::Star) {
+                columns.push(Column::Key);
+                columns.push(Column::Value {
+                    path: vec![],
+                    as_text: false,
+                });
+            } else {
+                columns.push(parser.column()?);
+            }
+            if !parser.next_if(&Token::Comma) {
+                break;
+            }
+        }
+
+        parser.expect_keyword("FROM")?;
+        parser.expect_keyword("KEYS")?;
+
+        let mut conditions = vec![];
+        if parser.next_if_keyword("WHERE") {
+            loop {
+                conditions.push(parser.condition()?);
+                if !parser.next_if_keyword("AND") {
+                    break;
+                }
+            }
+        }
+
+        let mut limit = None;
+        if parser.next_if_keyword("LIMIT") {
+            match parser.next() {
+                Some(Token::Literal(JsonValue::Number(n))) if n.is_u64() => limit = n.as_u64().map(|n| n as usize),
+                _ => return Err("LIMIT expects a positive number.".to_string()),
+            }
+        }
+
+        match parser.next() {
+            None => Ok(Query {
+                columns,
+                conditions,
+                limit,
+            }),
+            Some(token) => Err(format!("Unexpected {:?} at the end of the query.", token)),
+        }
+    }
+}
+
+impl Column
+{
+    /// Returns the value of the column for an entry.
+    fn eval(&self, key: &str, value: &JsonValue) -> JsonValue
+    {
+        match self {
+            Column::Key => JsonValue::String(key.to_string()),
+            Column::Value { path, as_text } => {
+                let mut current = Some(value);
+                for step in path {
+                    current = current.and_then(|current| match step {
+                        JsonValue::String(field) => current.get(field),
+                        JsonValue::Number(index) => current.get(index.as_u64()? as usize),
+                        _ => None,
+                    });
+                }
+
+                match current {
+                    None | Some(JsonValue::Null) => JsonValue::Null,
+                    Some(JsonValue::String(text)) if *as_text => JsonValue::String(text.clone()),
+                    Some(other) if *as_text => JsonValue::String(other.to_string()),
+                    Some(other) => other.clone(),
+                }
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Column
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+    {
+        match self {
+            Column::Key => write!(f, "key"),
+            Column::Value { path, as_text } => {
+                write!(f, "value")?;
+                for (i, step) in path.iter().enumerate() {
+                    let arrow = if *as_text && i == path.len() - 1 { "->>" } else { "->" };
+                    match step {
+                        JsonValue::String(field) => write!(f, "{}'{}'", arrow, field)?,
+                        other => write!(f, "{}{}", arrow, other)?,
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Condition
+{
+    /// Returns whether an entry satisfies the condition.
+    fn matches(&self, key: &str, value: &JsonValue) -> bool
+    {
+        let actual = self.column.eval(key, value);
+
+        match self.operator {
+            Operator::Eq => actual == self.literal,
+            Operator::NotEq => actual != self.literal,
+            Operator::Like => match (actual.as_str(), self.literal.as_str()) {
+                (Some(text), Some(pattern)) => like(text, pattern),
+                _ => false,
+            },
+        }
+    }
+}
+
+/// Matches text against a `LIKE` pattern, where `%` matches any run of characters and `_` any one character.
+fn like(text: &str, pattern: &str) -> bool
+{
+    let text: Vec<char> = text.chars().collect();
+    let pattern: Vec<char> = pattern.chars().collect();
+
+    // Greedy matching, backtracking to the last `%` on mismatch
+    let (mut t, mut p) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '_' || pattern[p] == text[t]) {
+            t += 1;
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == '%' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            t = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '%')
+}
+
+/// Splits a query into tokens.
+fn tokenize(query: &str) -> Result<Vec<Token>, String>
+{
+    let mut tokens = vec![];
+    let mut chars = query.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            ',' => {
+                chars.next();
+                tokens.push(Token::Comma);
+            }
+            '*' => {
+                chars.next();
+                tokens.push(Token::Star);
+            }
+            '=' => {
+                chars.next();
+                tokens.push(Token::Eq);
+            }
+            '!' | '<' => {
+                chars.next();
+                match chars.next() {
+                    Some('=') if c == '!' => tokens.push(Token::NotEq),
+                    Some('>') if c == '<' => tokens.push(Token::NotEq),
+                    _ => return Err(format!("Unexpected character '{}'.", c)),
+                }
+            }
+            '-' => {
+                chars.next();
+                if chars.next_if_eq(&'>').is_some() {
+                    if chars.next_if_eq(&'>').is_some() {
+                        tokens.push(Token::TextArrow);
+                    } else {
+                        tokens.push(Token::Arrow);
+                    }
+                } else {
+                    let number = read_while(&mut chars, |c| c.is_ascii_digit() || c == '.');
+                    tokens.push(Token::Literal(parse_number(&format!("-{}", number))?));
+                }
+            }
+            '\'' => {
+                chars.next();
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        // A doubled quote stands for a quote inside the string
+                        Some('\'') if chars.next_if_eq(&'\'').is_some() => text.push('\''),
+                        Some('\'') => break,
+                        Some(c) => text.push(c),
+                        None => return Err("Unterminated string in query.".to_string()),
+                    }
+                }
+                tokens.push(Token::Literal(JsonValue::String(text)));
+            }
+            c if c.is_ascii_digit() => {
+                let number = read_while(&mut chars, |c| c.is_ascii_digit() || c == '.');
+                tokens.push(Token::Literal(parse_number(&number)?));
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let word = read_while(&mut chars, |c| c.is_alphanumeric() || c == '_');
+                tokens.push(Token::Word(word));
+            }
+            c => return Err(format!("Unexpected character '{}'.", c)),
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn read_while(chars: &mut std::iter::Peekable<std::str::Chars>, accept: impl Fn(char) -> bool) -> String
+{
+    let mut text = String::new();
+    while let Some(c) = chars.next_if(|c| accept(*c)) {
+        text.push(c);
+    }
+    text
+}
+
+fn parse_number(text: &str) -> Result<JsonValue, String>
+{
+    serde_json::from_str::<serde_json::Number>(text)
+        .map(JsonValue::Number)
+        .map_err(|_| format!("Invalid number '{}'.", text))
+}
+
+/// Walks through the tokens of a query.
+struct Parser
+{
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser
+{
+    fn next(&mut self) -> Option<Token>
+    {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn next_if(&mut self, expected: &Token) -> bool
+    {
+        if self.tokens.get(self.position) == Some(expected) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn next_if_keyword(&mut self, keyword: &str) -> bool
+    {
+        match self.tokens.get(self.position) {
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword) => {
+                self.position += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), String>
+    {
+        if self.next_if_keyword(keyword) {
+            Ok(())
+        } else {
+            Err(format!("Expected {}.", keyword))
+        }
+    }
+
+    fn column(&mut self) -> Result<Column, String>
+    {
+        if self.next_if_keyword("KEY") {
+            return Ok(Column::Key);
+        }
+        if !self.next_if_keyword("VALUE") {
+            return Err("Expected a column, either key or value.".to_string());
+        }
+
+        let mut path = vec![];
+        let mut as_text = false;
+        while !as_text {
+            if self.next_if(&Token::TextArrow) {
+                as_text = true;
+            } else if !self.next_if(&Token::Arrow) {
+                break;
+            }
+
+            match self.next() {
+                Some(Token::Literal(step)) => path.push(step),
+                _ => return Err("Expected a field name or index after an arrow.".to_string()),
+            }
+        }
+
+        Ok(Column::Value { path, as_text })
+    }
+
+    fn condition(&mut self) -> Result<Condition, String>
+    {
+        let column = self.column()?;
+        let operator = match self.next() {
+            Some(Token::Eq) => Operator::Eq,
+            Some(Token::NotEq) => Operator::NotEq,
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case("LIKE") => Operator::Like,
+            _ => return Err("Expected =, != or LIKE.".to_string()),
+        };
+
+        match self.next() {
+            Some(Token::Literal(literal)) => Ok(Condition {
+                column,
+                operator,
+                literal,
+            }),
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case("NULL") => Ok(Condition {
+                column,
+                operator,
+                literal: JsonValue::Null,
+            }),
+            _ => Err("Expected a literal to compare with.".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use clap::Parser;
+
+    use super::*;
+    use crate::cli::Cli;
+    use crate::protocol::DbValue;
+
+    #[test]
+    fn test_like()
+    {
+        assert!(like("user:42", "user:%"));
+        assert!(like("user:42", "%:4_"));
+        assert!(like("abcabd", "%abd"));
+        assert!(!like("session:1", "user:%"));
+        assert!(!like("user", "user_"));
+    }
+
+    #[test]
+    fn test_parse_query()
+    {
+        let query = Query::parse("select key, value->'address'->>'city' from keys where key like 'user:%' limit 5");
+
+        assert_eq!(
+            query,
+            Ok(Query {
+                columns: vec![
+                    Column::Key,
+                    Column::Value {
+                        path: vec![json!("address"), json!("city")],
+                        as_text: true
+                    }
+                ],
+                conditions: vec![Condition {
+                    column: Column::Key,
+                    operator: Operator::Like,
+                    literal: json!("user:%")
+                }],
+                limit: Some(5),
+            })
+        );
+        assert!(Query::parse("SELECT key FROM users").is_err());
+        assert!(Query::parse("DELETE FROM keys").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_sql_command()
+    {
+        let engine = Arc::new(DbEngine::new(Cli::parse_from(["phoenix-db"])));
+        {
+            let mut db_write = engine.connection.write().await;
+            for (key, value) in [
+                ("user:1", json!({ "name": "Ada", "age": 36 })),
+                ("user:2", json!({ "name": "Alan", "age": 41 })),
+                ("session:1", json!({ "name": "ignored" })),
+            ] {
+                db_write.insert(key.to_string(), DbValue { value, expires_at: None });
+            }
+        }
+
+        let query = "SELECT value->>'name' FROM keys WHERE key LIKE 'user:%' AND value->'age' = 41";
+        let args = CommandArgs::WithArgs(None, vec![json!(query)]);
+        let response = sql_command(args, engine.clone()).await.unwrap();
+
+        assert_eq!(
+            response.value,
+            Some(json!({ "columns": ["value->>'name'"], "rows": [["Alan"]] }))
+        );
+    }
+}
@@ -0,0 +1,297 @@
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::{BoxFuture, FutureExt};
+use serde_json::json;
+
+use crate::commands::CommandArgs;
+use crate::protocol::{DbEngine, JsonValue, NetActions, NetResponse};
+
+/// The prefix depth `STATS PREFIX` reports when none is given.
+const DEFAULT_PREFIX_DEPTH: usize = 1;
+
+/// The number of sweep intervals `STATS TTL` forecasts when no count is given.
+const DEFAULT_FORECAST_INTERVALS: usize = 10;
+
+/// The largest number of sweep intervals `STATS TTL` forecasts.
+const MAX_FORECAST_INTERVALS: u64 = 1024;
+
+/// The upper bounds of the buckets of the `STATS TTL` histogram, with their label.
+const TTL_BUCKETS: [(Duration, &str); 6] = [
+    (Duration::from_secs(60), "<1m"),
+    (Duration::from_secs(5 * 60), "<5m"),
+    (Duration::from_secs(15 * 60), "<15m"),
+    (Duration::from_secs(60 * 60), "<1h"),
+    (Duration::from_secs(6 * 60 * 60), "<6h"),
+    (Duration::from_secs(24 * 60 * 60), "<1d"),
+];
+
+/// Executes a `STATS PREFIX` command, reporting how many keys and bytes each key family holds.
+///
+/// Keys are grouped by the segments separated by `:`, so `user:42:profile` counts towards `user` and
+/// `user:42`. The totals come from statistics maintained as entries are written, so no scan of the database is
+/// needed.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` optionally holding the `[depth]` of the prefixes to report, either as a
+///   number or as `"depth=N"`.
+/// * `engine` - The database engine to report on.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with the `{ prefix, keys, bytes }` of
+/// every prefix, largest first.
+pub fn stats_prefix_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let response = match args {
+            CommandArgs::WithArgs(_, params) => match parse_depth(params.first()) {
+                Some(depth) => {
+                    let stats = engine
+                        .connection
+                        .prefix_stats(depth)
+                        .into_iter()
+                        .map(|stat| json!({ "prefix": stat.prefix, "keys": stat.keys, "bytes": stat.bytes }))
+                        .collect();
+
+                    NetResponse {
+                        action: NetActions::Command,
+                        value: Some(JsonValue::Array(stats)),
+                        error: None,
+                        ..Default::default()
+                    }
+                }
+                None => NetResponse {
+                    action: NetActions::Error,
+                    value: None,
+                    error: Some("Invalid depth for stats prefix.".to_string()),
+                    ..Default::default()
+                },
+            },
+            _ => NetResponse {
+                action: NetActions::Error,
+                value: None,
+                error: Some("Invalid arguments for stats prefix.".to_string()),
+                ..Default::default()
+            },
+        };
+
+        Ok(response)
+    }
+    .boxed()
+}
+
+/// Executes a `STATS TTL` command, describing when entries are going to expire.
+///
+/// Reports a histogram of the remaining TTL of every entry and a forecast of how many entries are going to expire
+/// in each of the upcoming `--ttl-sweep-max` intervals, so that expiry storms can be anticipated.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` optionally holding the number of `[intervals]` to forecast, up to
+///   `MAX_FORECAST_INTERVALS`.
+/// * `engine` - The database engine to report on.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with the `histogram`, the number of
+/// `persistent` entries without a TTL and the `forecast` of expirations per interval.
+pub fn stats_ttl_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let intervals = match args {
+            CommandArgs::WithArgs(_, params) => match params.first() {
+                None => DEFAULT_FORECAST_INTERVALS,
+                Some(intervals) => match intervals.as_u64() {
+                    Some(intervals) if intervals > MAX_FORECAST_INTERVALS => {
+                        return Ok(NetResponse {
+                            action: NetActions::Error,
+                            value: None,
+                            error: Some(format!(
+                                "Too many intervals for stats ttl, the maximum is {}.",
+                                MAX_FORECAST_INTERVALS
+                            )),
+                            ..Default::default()
+                        });
+                    }
+                    Some(intervals) => intervals as usize,
+                    None => {
+                        return Ok(NetResponse {
+                            action: NetActions::Error,
+                            value: None,
+                            error: Some("Invalid number of intervals for stats ttl.".to_string()),
+                            ..Default::default()
+                        });
+                    }
+                },
+            },
+            _ => {
+                return Ok(NetResponse {
+                    action: NetActions::Error,
+                    value: None,
+                    error: Some("Invalid arguments for stats ttl.".to_string()),
+                    ..Default::default()
+                });
+            }
+        };
+
+        let sweep_interval = engine.db_config.ttl_sweep_max.max(1);
+        let mut histogram = [0u64; TTL_BUCKETS.len() + 1];
+        let mut forecast = vec![0u64; intervals];
+        let mut persistent = 0u64;
+
+        for data in engine.connection.read().values() {
+            let Some(remaining) = data.ttl() else {
+                persistent += 1;
+                continue;
+            };
+
+            let bucket = TTL_BUCKETS
+                .iter()
+                .position(|(bound, _)| remaining < *bound)
+                .unwrap_or(TTL_BUCKETS.len());
+            histogram[bucket] += 1;
+
+            let interval = (remaining.as_secs() / sweep_interval) as usize;
+            if let Some(expiring) = forecast.get_mut(interval) {
+                *expiring += 1;
+            }
+        }
+
+        let mut buckets = serde_json::Map::new();
+        for ((_, label), count) in TTL_BUCKETS.iter().zip(histogram) {
+            buckets.insert(label.to_string(), json!(count));
+        }
+        buckets.insert(">=1d".to_string(), json!(histogram[TTL_BUCKETS.len()]));
+
+        let forecast: Vec<JsonValue> = forecast
+            .into_iter()
+            .enumerate()
+            .map(|(interval, expiring)| {
+                json!({ "within_secs": (interval as u64 + 1) * sweep_interval, "expiring": expiring })
+            })
+            .collect();
+
+        Ok(NetResponse {
+            action: NetActions::Command,
+            value: Some(json!({ "histogram": buckets, "persistent": persistent, "forecast": forecast })),
+            error: None,
+            ..Default::default()
+        })
+    }
+    .boxed()
+}
+
+/// Reads the depth argument of `STATS PREFIX`, given as `2` or `"depth=2"`.
+fn parse_depth(arg: Option<&JsonValue>) -> Option<usize>
+{
+    match arg {
+        None => Some(DEFAULT_PREFIX_DEPTH),
+        Some(JsonValue::Number(depth)) => depth.as_u64().map(|depth| depth as usize),
+        Some(JsonValue::String(arg)) => arg.strip_prefix("depth=")?.parse().ok(),
+        Some(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use clap::Parser;
+
+    use super::*;
+    use crate::cli::Cli;
+    use crate::protocol::{expiry_after, DbValue};
+
+    #[tokio::test]
+    async fn test_stats_prefix()
+    {
+        let engine = Arc::new(DbEngine::new(Cli::parse_from(["phoenix-db"])));
+        {
+            let mut db_write = engine.connection.write().await;
+            for key in ["user:1:profile", "user:2:profile", "session:1"] {
+                db_write.insert(
+                    key.to_string(),
+                    DbValue {
+                        value: json!(1),
+                        expires_at: None,
+                    },
+                );
+            }
+        }
+
+        let args = CommandArgs::WithArgs(None, vec![json!("depth=1")]);
+        let response = stats_prefix_command(args, engine.clone()).await.unwrap();
+        assert_eq!(
+            response.value,
+            Some(json!([
+                { "prefix": "user", "keys": 2, "bytes": 30 },
+                { "prefix": "session", "keys": 1, "bytes": 10 },
+            ]))
+        );
+
+        let args = CommandArgs::WithArgs(None, vec![json!(2)]);
+        let response = stats_prefix_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.value.unwrap().as_array().unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_stats_ttl()
+    {
+        let engine = Arc::new(DbEngine::new(Cli::parse_from(["phoenix-db"])));
+        {
+            let mut db_write = engine.connection.write().await;
+            for (key, ttl) in [("a", Some(30)), ("b", Some(90)), ("c", Some(7200)), ("d", None)] {
+                db_write.insert(
+                    key.to_string(),
+                    DbValue {
+                        value: json!(1),
+                        expires_at: ttl.map(|ttl| expiry_after(Duration::from_secs(ttl))),
+                    },
+                );
+            }
+        }
+
+        let args = CommandArgs::WithArgs(None, vec![json!(2)]);
+        let report = stats_ttl_command(args, engine).await.unwrap().value.unwrap();
+
+        assert_eq!(report["persistent"], json!(1));
+        assert_eq!(report["histogram"]["<1m"], json!(1));
+        assert_eq!(report["histogram"]["<5m"], json!(1));
+        assert_eq!(report["histogram"]["<6h"], json!(1));
+        assert_eq!(
+            report["forecast"],
+            json!([
+                { "within_secs": 60, "expiring": 1 },
+                { "within_secs": 120, "expiring": 1 },
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stats_prefix_invalid_depth()
+    {
+        let engine = Arc::new(DbEngine::new(Cli::parse_from(["phoenix-db"])));
+
+        let args = CommandArgs::WithArgs(None, vec![json!("deep")]);
+        let response = stats_prefix_command(args, engine).await.unwrap();
+        assert_eq!(response.action, NetActions::Error);
+    }
+
+    #[tokio::test]
+    async fn test_stats_ttl_too_many_intervals()
+    {
+        let engine = Arc::new(DbEngine::new(Cli::parse_from(["phoenix-db"])));
+
+        let args = CommandArgs::WithArgs(None, vec![json!(u64::MAX)]);
+        let response = stats_ttl_command(args, engine).await.unwrap();
+        assert_eq!(response.action, NetActions::Error);
+    }
+}
@@ -0,0 +1,1169 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use futures::future::{BoxFuture, FutureExt};
+use serde_json::json;
+use tokio::sync::{Notify, RwLock};
+use tokio::time::{timeout, Instant};
+
+use crate::commands::CommandArgs;
+use crate::protocol::{DbEngine, DbKey, JsonValue, NetActions, NetResponse};
+
+/// The most entries a stream keeps when `XADD` is not given a length.
+const DEFAULT_STREAM_MAX_LEN: usize = 10_000;
+
+/// The most entries `XRANGE` and `XREAD` return when no count is given.
+const DEFAULT_STREAM_COUNT: usize = 100;
+
+/// The longest `XREAD` can block waiting for new entries.
+const MAX_STREAM_BLOCK: Duration = Duration::from_secs(60);
+
+/// Append-only event logs written with `XADD`, kept apart from the regular keyspace.
+#[derive(Debug, Default)]
+pub struct Streams
+{
+    /// The streams, keyed by name.
+    streams: RwLock<HashMap<DbKey, Stream>>,
+    /// Wakes up blocked `XREAD` commands when an entry is added to any stream.
+    appended: Notify,
+}
+
+impl Streams
+{
+    /// Appends `values` to the stream `name` in order, creating it if needed, and wakes up the blocked readers.
+    ///
+    /// Used by the server itself to publish events, the stream is capped to the default length of `XADD`.
+    pub async fn publish(&self, name: &str, values: Vec<JsonValue>)
+    {
+        {
+            let mut streams = self.streams.write().await;
+            let stream = streams.entry(name.to_string()).or_default();
+            for value in values {
+                stream.append(value);
+            }
+            stream.trim(DEFAULT_STREAM_MAX_LEN, None);
+        }
+        self.appended.notify_waiters();
+    }
+}
+
+/// A capped sequence of entries ordered by id.
+#[derive(Debug, Default)]
+struct Stream
+{
+    entries: VecDeque<StreamEntry>,
+    /// The id of the last entry ever added, which can be trimmed already.
+    last_id: StreamId,
+    /// The consumer groups reading the stream, keyed by name.
+    groups: HashMap<String, ConsumerGroup>,
+}
+
+/// Consumers sharing the entries of a stream, each entry being delivered to a single consumer of the group.
+#[derive(Debug, Default)]
+struct ConsumerGroup
+{
+    /// The id of the last entry delivered to a consumer of the group.
+    last_delivered: StreamId,
+    /// The entries delivered to a consumer but not acknowledged with `XACK` yet.
+    pending: BTreeMap<StreamId, PendingEntry>,
+    /// How many times an entry can be delivered before `XCLAIM` moves it to the dead-letter stream instead.
+    max_deliveries: Option<u64>,
+    /// The stream entries delivered too many times are moved to.
+    dead_letter: DbKey,
+}
+
+#[derive(Debug)]
+struct PendingEntry
+{
+    /// The consumer the entry was last delivered to.
+    consumer: String,
+    /// When the entry was last delivered.
+    delivered_at: Instant,
+    /// How many times the entry was delivered.
+    deliveries: u64,
+}
+
+#[derive(Debug, Clone)]
+struct StreamEntry
+{
+    id: StreamId,
+    value: JsonValue,
+    /// When the entry was added, used to trim entries older than the retention of the stream.
+    added_at: Instant,
+}
+
+/// The outcome of an `XCLAIM`.
+#[derive(Debug, Default)]
+struct Claimed
+{
+    /// The `{ id, value }` entries handed over to the consumer.
+    entries: Vec<JsonValue>,
+    /// The entries delivered too many times, to be moved to `dead_letter`.
+    dead: Vec<JsonValue>,
+    dead_letter: DbKey,
+}
+
+/// The id of a stream entry: the time it was added in milliseconds since the UNIX epoch and a sequence number
+/// telling apart entries added in the same millisecond. Written as `<ms>-<seq>`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct StreamId
+{
+    ms: u64,
+    seq: u64,
+}
+
+impl StreamId
+{
+    const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+    const MAX: StreamId = StreamId {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+
+    /// Returns the id following `last` for an entry added now.
+    fn next(last: StreamId) -> StreamId
+    {
+        let ms = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+
+        if ms > last.ms {
+            StreamId { ms, seq: 0 }
+        } else {
+            StreamId {
+                ms: last.ms,
+                seq: last.seq + 1,
+            }
+        }
+    }
+}
+
+impl fmt::Display for StreamId
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+impl FromStr for StreamId
+{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>
+    {
+        let invalid = || format!("Invalid stream id '{}'.", s);
+
+        match s.split_once('-') {
+            Some((ms, seq)) => Ok(StreamId {
+                ms: ms.parse().map_err(|_| invalid())?,
+                seq: seq.parse().map_err(|_| invalid())?,
+            }),
+            None => Ok(StreamId {
+                ms: s.parse().map_err(|_| invalid())?,
+                seq: 0,
+            }),
+        }
+    }
+}
+
+impl Stream
+{
+    /// Appends an entry to the stream, returning its id.
+    fn append(&mut self, value: JsonValue) -> StreamId
+    {
+        let id = StreamId::next(self.last_id);
+        self.last_id = id;
+        self.entries.push_back(StreamEntry {
+            id,
+            value,
+            added_at: Instant::now(),
+        });
+        id
+    }
+
+    /// Removes the entries past the length and age limits.
+    fn trim(&mut self, max_len: usize, max_age: Option<Duration>)
+    {
+        while self.entries.len() > max_len {
+            self.entries.pop_front();
+        }
+
+        if let Some(max_age) = max_age {
+            while self.entries.front().is_some_and(|entry| entry.added_at.elapsed() > max_age) {
+                self.entries.pop_front();
+            }
+        }
+    }
+
+    /// Returns up to `count` entries with an id between `start` and `end`, both inclusive.
+    fn range(&self, start: StreamId, end: StreamId, count: usize) -> Vec<JsonValue>
+    {
+        // Entries are sorted by id, so the first one in range can be found with a binary search
+        let first = self.entries.partition_point(|entry| entry.id < start);
+
+        self.entries
+            .range(first..)
+            .take_while(|entry| entry.id <= end)
+            .take(count)
+            .map(|entry| json!({ "id": entry.id.to_string(), "value": entry.value }))
+            .collect()
+    }
+
+    /// Delivers up to `count` entries the group has not seen yet to `consumer`, adding them to the pending entries
+    /// of the group. Returns `None` if the group does not exist.
+    fn read_group(&mut self, group: &str, consumer: &str, count: usize) -> Option<Vec<JsonValue>>
+    {
+        let group = self.groups.get_mut(group)?;
+        let first = self.entries.partition_point(|entry| entry.id <= group.last_delivered);
+
+        let delivered: Vec<&StreamEntry> = self.entries.range(first..).take(count).collect();
+        for entry in &delivered {
+            group.last_delivered = entry.id;
+            group.pending.insert(
+                entry.id,
+                PendingEntry {
+                    consumer: consumer.to_string(),
+                    delivered_at: Instant::now(),
+                    deliveries: 1,
+                },
+            );
+        }
+
+        Some(
+            delivered
+                .into_iter()
+                .map(|entry| json!({ "id": entry.id.to_string(), "value": entry.value }))
+                .collect(),
+        )
+    }
+
+    /// Hands up to `count` pending entries of the group that were delivered at least `min_idle` ago over to
+    /// `consumer`, so entries of a consumer that went away are not lost. Entries trimmed from the stream in the
+    /// meantime are dropped from the pending entries.
+    ///
+    /// Entries already delivered as many times as the group allows are removed from the pending entries instead,
+    /// and returned next to the claimed ones for the caller to move to the dead-letter stream of the group.
+    /// Returns `None` if the group does not exist.
+    fn claim(&mut self, group: &str, consumer: &str, min_idle: Duration, count: usize) -> Option<Claimed>
+    {
+        let group = self.groups.get_mut(group)?;
+        let entries = &self.entries;
+        let value_of = |id: &StreamId| {
+            entries
+                .binary_search_by(|entry| entry.id.cmp(id))
+                .ok()
+                .map(|index| entries[index].value.clone())
+        };
+
+        group.pending.retain(|id, _| value_of(id).is_some());
+
+        let mut claimed = Claimed::default();
+        for (id, pending) in group.pending.iter_mut() {
+            if claimed.entries.len() >= count {
+                break;
+            }
+            if pending.delivered_at.elapsed() < min_idle {
+                continue;
+            }
+
+            if group.max_deliveries.is_some_and(|max| pending.deliveries >= max) {
+                claimed.dead.push(json!({
+                    "id": id.to_string(),
+                    "consumer": pending.consumer,
+                    "deliveries": pending.deliveries,
+                    "value": value_of(id),
+                }));
+                continue;
+            }
+
+            pending.consumer = consumer.to_string();
+            pending.delivered_at = Instant::now();
+            pending.deliveries += 1;
+            claimed.entries.push(json!({ "id": id.to_string(), "value": value_of(id) }));
+        }
+
+        for dead in &claimed.dead {
+            if let Some(id) = dead["id"].as_str().and_then(|id| id.parse().ok()) {
+                group.pending.remove(&id);
+            }
+        }
+        claimed.dead_letter = group.dead_letter.clone();
+
+        Some(claimed)
+    }
+}
+
+/// Executes an `XADD` command, appending an entry to a stream.
+///
+/// The stream is created on its first entry. Once it holds more than `max_len` entries the oldest ones are
+/// dropped, and when `max_age` is given entries older than that many seconds are dropped as well.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` holding the stream name and the `[entry, max_len, max_age]` arguments,
+///   where only `entry` is required.
+/// * `engine` - The database engine holding the streams.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with the id of the new entry.
+pub fn xadd_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let response = match args {
+            CommandArgs::WithArgs(Some(name), mut params) if !params.is_empty() => {
+                let max_len = params
+                    .get(1)
+                    .and_then(JsonValue::as_u64)
+                    .map(|max_len| max_len as usize)
+                    .unwrap_or(DEFAULT_STREAM_MAX_LEN);
+                let max_age = params.get(2).and_then(JsonValue::as_u64).map(Duration::from_secs);
+                let value = params.swap_remove(0);
+
+                let id = {
+                    let mut streams = engine.streams.streams.write().await;
+                    let stream = streams.entry(name).or_default();
+
+                    let id = stream.append(value);
+                    stream.trim(max_len, max_age);
+                    id
+                };
+                engine.streams.appended.notify_waiters();
+
+                NetResponse {
+                    action: NetActions::Command,
+                    value: Some(json!(id.to_string())),
+                    error: None,
+                    ..Default::default()
+                }
+            }
+            CommandArgs::WithArgs(Some(_), _) => stream_error("XADD requires an entry."),
+            _ => stream_error("No stream provided for xadd."),
+        };
+
+        Ok(response)
+    }
+    .boxed()
+}
+
+/// Executes an `XRANGE` command, returning the entries of a stream between two ids.
+///
+/// `-` and `+` stand for the first and last possible ids. An id given without a sequence number covers every
+/// entry of that millisecond.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` holding the stream name and the optional `[start, end, count]`
+///   arguments.
+/// * `engine` - The database engine holding the streams.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with the `{ id, value }` entries, oldest
+/// first.
+pub fn xrange_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let response = match args {
+            CommandArgs::WithArgs(Some(name), params) => {
+                let bound =
+                    |index: usize, default: StreamId, open_end: bool| match params.get(index).and_then(JsonValue::as_str) {
+                        None | Some("-") | Some("+") => Ok(default),
+                        Some(id) if open_end && !id.contains('-') => {
+                            id.parse::<StreamId>().map(|id| StreamId { seq: u64::MAX, ..id })
+                        }
+                        Some(id) => id.parse(),
+                    };
+                let count = params
+                    .get(2)
+                    .and_then(JsonValue::as_u64)
+                    .map(|count| count as usize)
+                    .unwrap_or(DEFAULT_STREAM_COUNT);
+
+                match (bound(0, StreamId::MIN, false), bound(1, StreamId::MAX, true)) {
+                    (Ok(start), Ok(end)) => {
+                        let streams = engine.streams.streams.read().await;
+                        let entries = streams
+                            .get(&name)
+                            .map(|stream| stream.range(start, end, count))
+                            .unwrap_or_default();
+
+                        NetResponse {
+                            action: NetActions::Command,
+                            value: Some(JsonValue::Array(entries)),
+                            error: None,
+                            ..Default::default()
+                        }
+                    }
+                    (Err(e), _) | (_, Err(e)) => stream_error(&e),
+                }
+            }
+            _ => stream_error("No stream provided for xrange."),
+        };
+
+        Ok(response)
+    }
+    .boxed()
+}
+
+/// Executes an `XREAD` command, returning the entries of a stream added after a given id.
+///
+/// `$` stands for the last entry of the stream, so only entries added from now on are returned. When there
+/// is nothing to return and `block_ms` is given, the command waits up to that long for new entries.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` holding the stream name and the `[after, count, block_ms]` arguments,
+///   where only `after` is required.
+/// * `engine` - The database engine holding the streams.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with the `{ id, value }` entries, oldest
+/// first.
+pub fn xread_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let (name, after, count, block) = match args {
+            CommandArgs::WithArgs(Some(name), params) => {
+                let after = match params.first().and_then(JsonValue::as_str) {
+                    Some("$") => {
+                        let streams = engine.streams.streams.read().await;
+                        Ok(streams.get(&name).map(|stream| stream.last_id).unwrap_or_default())
+                    }
+                    Some(id) => id.parse::<StreamId>(),
+                    None => Err("XREAD requires the id to read after.".to_string()),
+                };
+                let count = params
+                    .get(1)
+                    .and_then(JsonValue::as_u64)
+                    .map(|count| count as usize)
+                    .unwrap_or(DEFAULT_STREAM_COUNT);
+                let block = params
+                    .get(2)
+                    .and_then(JsonValue::as_u64)
+                    .map(|ms| Duration::from_millis(ms).min(MAX_STREAM_BLOCK));
+
+                match after {
+                    Ok(after) => (name, after, count, block),
+                    Err(e) => return Ok(stream_error(&e)),
+                }
+            }
+            _ => return Ok(stream_error("No stream provided for xread.")),
+        };
+
+        // Entries strictly after `after`
+        let start = StreamId {
+            ms: after.ms,
+            seq: after.seq.saturating_add(1),
+        };
+        let deadline = block.map(|block| Instant::now() + block);
+
+        let entries = loop {
+            // Listen for new entries before checking, so none can be added unnoticed in between
+            let appended = engine.streams.appended.notified();
+            tokio::pin!(appended);
+            appended.as_mut().enable();
+
+            let entries = {
+                let streams = engine.streams.streams.read().await;
+                streams
+                    .get(&name)
+                    .map(|stream| stream.range(start, StreamId::MAX, count))
+                    .unwrap_or_default()
+            };
+
+            match deadline {
+                Some(deadline) if entries.is_empty() => {
+                    if timeout(deadline.saturating_duration_since(Instant::now()), appended)
+                        .await
+                        .is_err()
+                    {
+                        break entries;
+                    }
+                }
+                _ => break entries,
+            }
+        };
+
+        Ok(NetResponse {
+            action: NetActions::Command,
+            value: Some(JsonValue::Array(entries)),
+            error: None,
+            ..Default::default()
+        })
+    }
+    .boxed()
+}
+
+/// Executes an `XLEN` command, returning the number of entries in a stream.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` holding the stream name.
+/// * `engine` - The database engine holding the streams.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with the number of entries, `0` if the
+/// stream does not exist.
+pub fn xlen_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let response = match args {
+            CommandArgs::WithArgs(Some(name), _) => {
+                let streams = engine.streams.streams.read().await;
+                let len = streams.get(&name).map(|stream| stream.entries.len()).unwrap_or(0);
+
+                NetResponse {
+                    action: NetActions::Command,
+                    value: Some(len.into()),
+                    error: None,
+                    ..Default::default()
+                }
+            }
+            _ => stream_error("No stream provided for xlen."),
+        };
+
+        Ok(response)
+    }
+    .boxed()
+}
+
+/// Executes an `XGROUP CREATE` command, adding a consumer group to a stream.
+///
+/// `$` makes the group start with the entries added from now on, and `0` with every entry of the stream. The
+/// stream is created if it does not exist yet.
+///
+/// When `max_deliveries` is given, entries delivered that many times without being acknowledged are moved to the
+/// dead-letter stream of the group by `XCLAIM`, instead of being delivered again. The dead-letter stream is
+/// `<stream>:<group>:dead-letter` unless another name is given.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` holding the stream name and the `[group, start, max_deliveries,
+///   dead_letter]` arguments, where only `group` is required and `start` defaults to `$`.
+/// * `engine` - The database engine holding the streams.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with `OK`, or an error if the group
+/// already exists.
+pub fn xgroup_create_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let response = match args {
+            CommandArgs::WithArgs(Some(name), params) => match params.first().and_then(JsonValue::as_str) {
+                Some(group) => {
+                    let max_deliveries = params.get(2).and_then(JsonValue::as_u64);
+                    let dead_letter = params
+                        .get(3)
+                        .and_then(JsonValue::as_str)
+                        .map(str::to_string)
+                        .unwrap_or_else(|| format!("{}:{}:dead-letter", name, group));
+
+                    let mut streams = engine.streams.streams.write().await;
+                    let stream = streams.entry(name).or_default();
+
+                    let start = match params.get(1).and_then(JsonValue::as_str) {
+                        None | Some("$") => Ok(stream.last_id),
+                        Some(id) => id.parse::<StreamId>(),
+                    };
+
+                    match start {
+                        Ok(_) if stream.groups.contains_key(group) => {
+                            stream_error(&format!("Consumer group '{}' already exists.", group))
+                        }
+                        Ok(start) => {
+                            stream.groups.insert(
+                                group.to_string(),
+                                ConsumerGroup {
+                                    last_delivered: start,
+                                    pending: BTreeMap::new(),
+                                    max_deliveries,
+                                    dead_letter,
+                                },
+                            );
+
+                            NetResponse {
+                                action: NetActions::Command,
+                                value: Some("OK".into()),
+                                error: None,
+                                ..Default::default()
+                            }
+                        }
+                        Err(e) => stream_error(&e),
+                    }
+                }
+                None => stream_error("XGROUP CREATE requires a group name."),
+            },
+            _ => stream_error("No stream provided for xgroup create."),
+        };
+
+        Ok(response)
+    }
+    .boxed()
+}
+
+/// Executes an `XREADGROUP` command, delivering entries no consumer of the group has seen yet to a consumer.
+///
+/// Delivered entries stay pending until they are acknowledged with `XACK`. When there is nothing to deliver and
+/// `block_ms` is given, the command waits up to that long for new entries.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` holding the stream name and the `[group, consumer, count, block_ms]`
+///   arguments, where `group` and `consumer` are required.
+/// * `engine` - The database engine holding the streams.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with the delivered `{ id, value }`
+/// entries, oldest first.
+pub fn xreadgroup_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let (name, params) = match args {
+            CommandArgs::WithArgs(Some(name), params) => (name, params),
+            _ => return Ok(stream_error("No stream provided for xreadgroup.")),
+        };
+        let (group, consumer) = match (
+            params.first().and_then(JsonValue::as_str),
+            params.get(1).and_then(JsonValue::as_str),
+        ) {
+            (Some(group), Some(consumer)) => (group, consumer),
+            _ => return Ok(stream_error("XREADGROUP requires a group and a consumer.")),
+        };
+        let count = params
+            .get(2)
+            .and_then(JsonValue::as_u64)
+            .map(|count| count as usize)
+            .unwrap_or(DEFAULT_STREAM_COUNT);
+        let deadline = params
+            .get(3)
+            .and_then(JsonValue::as_u64)
+            .map(|ms| Instant::now() + Duration::from_millis(ms).min(MAX_STREAM_BLOCK));
+
+        let entries = loop {
+            // Listen for new entries before checking, so none can be added unnoticed in between
+            let appended = engine.streams.appended.notified();
+            tokio::pin!(appended);
+            appended.as_mut().enable();
+
+            let entries = {
+                let mut streams = engine.streams.streams.write().await;
+                match streams
+                    .get_mut(&name)
+                    .and_then(|stream| stream.read_group(group, consumer, count))
+                {
+                    Some(entries) => entries,
+                    None => return Ok(stream_error(&format!("Consumer group '{}' does not exist.", group))),
+                }
+            };
+
+            match deadline {
+                Some(deadline) if entries.is_empty() => {
+                    if timeout(deadline.saturating_duration_since(Instant::now()), appended)
+                        .await
+                        .is_err()
+                    {
+                        break entries;
+                    }
+                }
+                _ => break entries,
+            }
+        };
+
+        Ok(NetResponse {
+            action: NetActions::Command,
+            value: Some(JsonValue::Array(entries)),
+            error: None,
+            ..Default::default()
+        })
+    }
+    .boxed()
+}
+
+/// Executes an `XACK` command, removing entries from the pending entries of a consumer group.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` holding the stream name and the `[group, id, ...]` arguments.
+/// * `engine` - The database engine holding the streams.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with the number of entries acknowledged,
+/// which leaves out the ids that were not pending.
+pub fn xack_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let response = match args {
+            CommandArgs::WithArgs(Some(name), params) => match params.split_first() {
+                Some((JsonValue::String(group), ids)) => {
+                    let ids: Result<Vec<StreamId>, String> = ids
+                        .iter()
+                        .map(|id| id.as_str().ok_or_else(|| format!("Invalid stream id '{}'.", id))?.parse())
+                        .collect();
+
+                    match ids {
+                        Ok(ids) => {
+                            let mut streams = engine.streams.streams.write().await;
+                            match streams.get_mut(&name).and_then(|stream| stream.groups.get_mut(group)) {
+                                Some(group) => {
+                                    let acknowledged = ids.iter().filter(|id| group.pending.remove(id).is_some()).count();
+
+                                    NetResponse {
+                                        action: NetActions::Command,
+                                        value: Some(acknowledged.into()),
+                                        error: None,
+                                        ..Default::default()
+                                    }
+                                }
+                                None => stream_error(&format!("Consumer group '{}' does not exist.", group)),
+                            }
+                        }
+                        Err(e) => stream_error(&e),
+                    }
+                }
+                _ => stream_error("XACK requires a group name."),
+            },
+            _ => stream_error("No stream provided for xack."),
+        };
+
+        Ok(response)
+    }
+    .boxed()
+}
+
+/// Executes an `XPENDING` command, listing the entries delivered to the consumers of a group but not
+/// acknowledged yet.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` holding the stream name and the `[group]` argument.
+/// * `engine` - The database engine holding the streams.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with the `{ id, consumer, idle_ms,
+/// deliveries }` pending entries, oldest first.
+pub fn xpending_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let response = match args {
+            CommandArgs::WithArgs(Some(name), params) => match params.first().and_then(JsonValue::as_str) {
+                Some(group) => {
+                    let streams = engine.streams.streams.read().await;
+                    match streams.get(&name).and_then(|stream| stream.groups.get(group)) {
+                        Some(group) => {
+                            let pending = group
+                                .pending
+                                .iter()
+                                .map(|(id, pending)| {
+                                    json!({
+                                        "id": id.to_string(),
+                                        "consumer": pending.consumer,
+                                        "idle_ms": pending.delivered_at.elapsed().as_millis() as u64,
+                                        "deliveries": pending.deliveries,
+                                    })
+                                })
+                                .collect();
+
+                            NetResponse {
+                                action: NetActions::Command,
+                                value: Some(JsonValue::Array(pending)),
+                                error: None,
+                                ..Default::default()
+                            }
+                        }
+                        None => stream_error(&format!("Consumer group '{}' does not exist.", group)),
+                    }
+                }
+                None => stream_error("XPENDING requires a group name."),
+            },
+            _ => stream_error("No stream provided for xpending."),
+        };
+
+        Ok(response)
+    }
+    .boxed()
+}
+
+/// Executes an `XCLAIM` command, handing pending entries idle for long enough over to another consumer of the
+/// group.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` holding the stream name and the `[group, consumer, min_idle_ms, count]`
+///   arguments, where only `count` is optional.
+/// * `engine` - The database engine holding the streams.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with the claimed `{ id, value }` entries,
+/// oldest first. Entries moved to the dead-letter stream are left out.
+pub fn xclaim_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let response = match args {
+            CommandArgs::WithArgs(Some(name), params) => match (
+                params.first().and_then(JsonValue::as_str),
+                params.get(1).and_then(JsonValue::as_str),
+                params.get(2).and_then(JsonValue::as_u64),
+            ) {
+                (Some(group), Some(consumer), Some(min_idle)) => {
+                    let count = params
+                        .get(3)
+                        .and_then(JsonValue::as_u64)
+                        .map(|count| count as usize)
+                        .unwrap_or(DEFAULT_STREAM_COUNT);
+
+                    let mut streams = engine.streams.streams.write().await;
+                    match streams
+                        .get_mut(&name)
+                        .and_then(|stream| stream.claim(group, consumer, Duration::from_millis(min_idle), count))
+                    {
+                        Some(claimed) => {
+                            if !claimed.dead.is_empty() {
+                                let dead_letter = streams.entry(claimed.dead_letter).or_default();
+                                for dead in claimed.dead {
+                                    dead_letter.append(dead);
+                                }
+                                dead_letter.trim(DEFAULT_STREAM_MAX_LEN, None);
+                                engine.streams.appended.notify_waiters();
+                            }
+
+                            NetResponse {
+                                action: NetActions::Command,
+                                value: Some(JsonValue::Array(claimed.entries)),
+                                error: None,
+                                ..Default::default()
+                            }
+                        }
+                        None => stream_error(&format!("Consumer group '{}' does not exist.", group)),
+                    }
+                }
+                _ => stream_error("XCLAIM requires a group, a consumer and a minimum idle time."),
+            },
+            _ => stream_error("No stream provided for xclaim."),
+        };
+
+        Ok(response)
+    }
+    .boxed()
+}
+
+/// Executes an `XREQUEUE` command, moving entries from the dead-letter stream of a consumer group back to the
+/// stream, where they are added again as new entries.
+///
+/// The dead-letter stream itself can be inspected with `XRANGE` and `XLEN`.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` holding the stream name and the `[group, count]` arguments, where `count`
+///   defaults to every dead entry.
+/// * `engine` - The database engine holding the streams.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with the number of entries requeued.
+pub fn xrequeue_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let response = match args {
+            CommandArgs::WithArgs(Some(name), params) => match params.first().and_then(JsonValue::as_str) {
+                Some(group) => {
+                    let count = params
+                        .get(1)
+                        .and_then(JsonValue::as_u64)
+                        .map(|count| count as usize)
+                        .unwrap_or(usize::MAX);
+
+                    let mut streams = engine.streams.streams.write().await;
+                    match streams
+                        .get(&name)
+                        .and_then(|stream| stream.groups.get(group))
+                        .map(|group| group.dead_letter.clone())
+                    {
+                        Some(dead_letter) => {
+                            let dead: Vec<JsonValue> = match streams.get_mut(&dead_letter) {
+                                Some(dead_letter) => {
+                                    let count = count.min(dead_letter.entries.len());
+                                    dead_letter.entries.drain(..count).map(|entry| entry.value).collect()
+                                }
+                                None => Vec::new(),
+                            };
+
+                            let requeued = dead.len();
+                            if let Some(stream) = streams.get_mut(&name) {
+                                for mut dead in dead {
+                                    stream.append(dead["value"].take());
+                                }
+                            }
+                            engine.streams.appended.notify_waiters();
+
+                            NetResponse {
+                                action: NetActions::Command,
+                                value: Some(requeued.into()),
+                                error: None,
+                                ..Default::default()
+                            }
+                        }
+                        None => stream_error(&format!("Consumer group '{}' does not exist.", group)),
+                    }
+                }
+                None => stream_error("XREQUEUE requires a group name."),
+            },
+            _ => stream_error("No stream provided for xrequeue."),
+        };
+
+        Ok(response)
+    }
+    .boxed()
+}
+
+/// Builds an error response for the stream commands.
+fn stream_error(message: &str) -> NetResponse
+{
+    NetResponse {
+        action: NetActions::Error,
+        value: None,
+        error: Some(message.to_string()),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use crate::testing::create_fake_engine;
+
+    async fn xadd(engine: &Arc<DbEngine>, stream: &str, params: Vec<JsonValue>) -> String
+    {
+        let args = CommandArgs::WithArgs(Some(stream.to_string()), params);
+        let response = xadd_command(args, engine.clone()).await.unwrap();
+        response.value.unwrap().as_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_stream_id()
+    {
+        assert_eq!("12-3".parse(), Ok(StreamId { ms: 12, seq: 3 }));
+        assert_eq!("12".parse(), Ok(StreamId { ms: 12, seq: 0 }));
+        assert!("abc".parse::<StreamId>().is_err());
+
+        let last = StreamId {
+            ms: u64::MAX - 1,
+            seq: 7,
+        };
+        assert_eq!(
+            StreamId::next(last),
+            StreamId {
+                ms: u64::MAX - 1,
+                seq: 8
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_xadd_and_xrange()
+    {
+        let engine = create_fake_engine();
+        let first = xadd(&engine, "events", vec![json!({ "n": 1 })]).await;
+        let second = xadd(&engine, "events", vec![json!({ "n": 2 })]).await;
+        assert!(first.parse::<StreamId>().unwrap() < second.parse::<StreamId>().unwrap());
+
+        let args = CommandArgs::WithArgs(Some("events".to_string()), vec![]);
+        let response = xrange_command(args, engine.clone()).await.unwrap();
+        assert_eq!(
+            response.value,
+            Some(json!([
+                { "id": first, "value": { "n": 1 } },
+                { "id": second, "value": { "n": 2 } },
+            ]))
+        );
+
+        let args = CommandArgs::WithArgs(Some("events".to_string()), vec![json!(second), json!("+")]);
+        let response = xrange_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.value.unwrap().as_array().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_xadd_caps_length()
+    {
+        let engine = create_fake_engine();
+        for n in 0..5 {
+            xadd(&engine, "events", vec![json!(n), json!(3)]).await;
+        }
+
+        let args = CommandArgs::WithArgs(Some("events".to_string()), vec![]);
+        let response = xlen_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.value, Some(json!(3)));
+
+        let args = CommandArgs::WithArgs(Some("events".to_string()), vec![]);
+        let response = xrange_command(args, engine.clone()).await.unwrap();
+        let values: Vec<JsonValue> = response
+            .value
+            .unwrap()
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| e["value"].clone())
+            .collect();
+        assert_eq!(values, vec![json!(2), json!(3), json!(4)]);
+    }
+
+    #[tokio::test]
+    async fn test_xread_blocks_for_new_entries()
+    {
+        let engine = create_fake_engine();
+        xadd(&engine, "events", vec![json!("old")]).await;
+
+        let reader = {
+            let engine = engine.clone();
+            tokio::spawn(async move {
+                let args = CommandArgs::WithArgs(Some("events".to_string()), vec![json!("$"), json!(10), json!(5000)]);
+                xread_command(args, engine).await.unwrap()
+            })
+        };
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        xadd(&engine, "events", vec![json!("new")]).await;
+
+        let response = reader.await.unwrap();
+        let entries = response.value.unwrap();
+        assert_eq!(entries.as_array().unwrap().len(), 1);
+        assert_eq!(entries[0]["value"], json!("new"));
+    }
+
+    #[tokio::test]
+    async fn test_xread_times_out()
+    {
+        let engine = create_fake_engine();
+
+        let args = CommandArgs::WithArgs(Some("events".to_string()), vec![json!("0"), json!(10), json!(20)]);
+        let response = xread_command(args, engine).await.unwrap();
+        assert_eq!(response.value, Some(json!([])));
+    }
+
+    #[tokio::test]
+    async fn test_consumer_groups()
+    {
+        let engine = create_fake_engine();
+        let stream = || Some("jobs".to_string());
+
+        let args = CommandArgs::WithArgs(stream(), vec![json!("workers"), json!("0")]);
+        let response = xgroup_create_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.action, NetActions::Command);
+
+        let args = CommandArgs::WithArgs(stream(), vec![json!("workers")]);
+        let response = xgroup_create_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.action, NetActions::Error);
+
+        let first = xadd(&engine, "jobs", vec![json!("a")]).await;
+        let second = xadd(&engine, "jobs", vec![json!("b")]).await;
+
+        // Each entry is delivered to a single consumer of the group
+        let args = CommandArgs::WithArgs(stream(), vec![json!("workers"), json!("alice"), json!(1)]);
+        let response = xreadgroup_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.value, Some(json!([{ "id": first, "value": "a" }])));
+
+        let args = CommandArgs::WithArgs(stream(), vec![json!("workers"), json!("bob")]);
+        let response = xreadgroup_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.value, Some(json!([{ "id": second, "value": "b" }])));
+
+        let args = CommandArgs::WithArgs(stream(), vec![json!("workers")]);
+        let response = xpending_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.value.unwrap().as_array().unwrap().len(), 2);
+
+        let args = CommandArgs::WithArgs(stream(), vec![json!("workers"), json!(first), json!(first)]);
+        let response = xack_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.value, Some(json!(1)));
+
+        // Bob's entry is still pending and can be claimed by alice
+        let args = CommandArgs::WithArgs(stream(), vec![json!("workers"), json!("alice"), json!(0)]);
+        let response = xclaim_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.value, Some(json!([{ "id": second, "value": "b" }])));
+
+        let args = CommandArgs::WithArgs(stream(), vec![json!("workers")]);
+        let response = xpending_command(args, engine.clone()).await.unwrap();
+        let pending = response.value.unwrap();
+        assert_eq!(pending[0]["consumer"], json!("alice"));
+        assert_eq!(pending[0]["deliveries"], json!(2));
+    }
+
+    #[tokio::test]
+    async fn test_dead_letter()
+    {
+        let engine = create_fake_engine();
+        let stream = || Some("jobs".to_string());
+
+        let args = CommandArgs::WithArgs(stream(), vec![json!("workers"), json!("$"), json!(2)]);
+        xgroup_create_command(args, engine.clone()).await.unwrap();
+        xadd(&engine, "jobs", vec![json!("poison")]).await;
+
+        let args = CommandArgs::WithArgs(stream(), vec![json!("workers"), json!("alice")]);
+        xreadgroup_command(args, engine.clone()).await.unwrap();
+
+        // The second delivery is allowed, the third one moves the entry to the dead-letter stream
+        let claim = || CommandArgs::WithArgs(stream(), vec![json!("workers"), json!("bob"), json!(0)]);
+        let response = xclaim_command(claim(), engine.clone()).await.unwrap();
+        assert_eq!(response.value.unwrap().as_array().unwrap().len(), 1);
+        let response = xclaim_command(claim(), engine.clone()).await.unwrap();
+        assert_eq!(response.value, Some(json!([])));
+
+        let args = CommandArgs::WithArgs(Some("jobs:workers:dead-letter".to_string()), vec![]);
+        let response = xrange_command(args, engine.clone()).await.unwrap();
+        let dead = response.value.unwrap();
+        assert_eq!(dead[0]["value"]["value"], json!("poison"));
+        assert_eq!(dead[0]["value"]["deliveries"], json!(2));
+
+        let args = CommandArgs::WithArgs(stream(), vec![json!("workers")]);
+        let response = xpending_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.value, Some(json!([])));
+
+        // Requeued entries are delivered to the group again
+        let args = CommandArgs::WithArgs(stream(), vec![json!("workers")]);
+        let response = xrequeue_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.value, Some(json!(1)));
+
+        let args = CommandArgs::WithArgs(stream(), vec![json!("workers"), json!("alice")]);
+        let response = xreadgroup_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.value.unwrap()[0]["value"], json!("poison"));
+    }
+}
@@ -0,0 +1,390 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::sync::Arc;
+
+use futures::future::{BoxFuture, FutureExt};
+use serde_json::Map;
+
+use crate::commands::commit::commit;
+use crate::commands::insert::wal_error;
+use crate::commands::CommandArgs;
+use crate::protocol::{DbEngine, DbKey, JsonValue, NetActions, NetResponse};
+use crate::wal::WalRecord;
+
+/// The number of entries `INVALIDATE` removes before releasing the write lock to let other commands run.
+const INVALIDATE_BATCH_SIZE: usize = 256;
+
+/// Index of the tags attached to entries with `INSERT ... TAGS`.
+///
+/// The index is updated along with the database, under the same write lock, by every write replacing or deleting
+/// an entry. Keys missing from the database anyway are pruned the next time one of their tags is queried.
+#[derive(Debug, Default)]
+pub struct TagIndex
+{
+    /// The keys carrying each tag.
+    by_tag: HashMap<String, HashSet<DbKey>>,
+    /// The tags carried by each key.
+    by_key: HashMap<DbKey, HashSet<String>>,
+}
+
+impl TagIndex
+{
+    /// Replaces the tags of a key. An empty list removes the key from the index.
+    pub fn set_tags(&mut self, key: &str, tags: &[String])
+    {
+        self.remove_key(key);
+
+        if tags.is_empty() {
+            return;
+        }
+
+        for tag in tags {
+            self.by_tag.entry(tag.clone()).or_default().insert(key.to_string());
+        }
+        self.by_key.insert(key.to_string(), tags.iter().cloned().collect());
+    }
+
+    /// Removes a key and all of its tags from the index.
+    pub fn remove_key(&mut self, key: &str)
+    {
+        if let Some(tags) = self.by_key.remove(key) {
+            for tag in tags {
+                if let Some(keys) = self.by_tag.get_mut(&tag) {
+                    keys.remove(key);
+                    if keys.is_empty() {
+                        self.by_tag.remove(&tag);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the tags carried by a key.
+    pub fn tags(&self, key: &str) -> Vec<String>
+    {
+        self.by_key
+            .get(key)
+            .map(|tags| tags.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Returns the keys carrying a tag.
+    pub fn keys(&self, tag: &str) -> Vec<DbKey>
+    {
+        self.by_tag
+            .get(tag)
+            .map(|keys| keys.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+/// Executes a `LOOKUP BYTAG` command, returning every entry carrying a tag.
+///
+/// # Arguments
+///
+/// * `args` - The arguments for the command, holding the tag as a single key.
+/// * `engine` - The database engine used for the lookup.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with an object mapping keys to values.
+pub fn lookup_bytag_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let response = match args {
+            CommandArgs::Single(Some(tag), ..) => {
+                let mut tags = engine.tags.write().await;
+                let db_read = engine.connection.read();
+                let mut results = Map::new();
+
+                for key in tags.keys(&tag) {
+                    match db_read.get(&key) {
+                        Some(data) => {
+                            results.insert(key, data.value.to_owned());
+                        }
+                        // The entry is gone (e.g. expired), so drop it from the index
+                        None => tags.remove_key(&key),
+                    }
+                }
+
+                NetResponse {
+                    action: NetActions::Command,
+                    value: Some(JsonValue::Object(results)),
+                    error: None,
+                    ..Default::default()
+                }
+            }
+            _ => NetResponse {
+                action: NetActions::Error,
+                value: None,
+                error: Some("No tag provided for lookup.".to_string()),
+                ..Default::default()
+            },
+        };
+
+        Ok(response)
+    }
+    .boxed()
+}
+
+/// Executes a `DELETE BYTAG` command, removing every entry carrying a tag.
+///
+/// # Arguments
+///
+/// * `args` - The arguments for the command, holding the tag as a single key.
+/// * `engine` - The database engine used for the deletion.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with the deleted keys.
+pub fn delete_bytag_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let response = match args {
+            CommandArgs::Single(Some(tag), ..) => {
+                let mut db_write = engine.connection.write().await;
+                let keys = engine.tags.read().await.keys(&tag);
+                let deleted: Vec<DbKey> = keys.iter().filter(|key| db_write.contains_key(*key)).cloned().collect();
+                let records = deleted.iter().map(|key| WalRecord::Delete(key.clone())).collect();
+                let committed = match commit(&engine, &mut db_write, records).await {
+                    Ok(committed) => committed,
+                    Err(e) => return Ok(wal_error(e)),
+                };
+                // Keys that expired since they were tagged are not deleted, but still carry the tag
+                let mut tags = engine.tags.write().await;
+                for key in &keys {
+                    tags.remove_key(key);
+                }
+                drop(tags);
+                drop(db_write);
+                committed.refresh(&engine).await;
+                let results = deleted.into_iter().map(JsonValue::String).collect();
+
+                NetResponse {
+                    action: NetActions::Command,
+                    value: Some(JsonValue::Array(results)),
+                    error: None,
+                    ..Default::default()
+                }
+            }
+            _ => NetResponse {
+                action: NetActions::Error,
+                value: None,
+                error: Some("No tag provided for delete.".to_string()),
+                ..Default::default()
+            },
+        };
+
+        Ok(response)
+    }
+    .boxed()
+}
+
+/// Executes an `INVALIDATE` command, removing every entry associated with a surrogate key.
+///
+/// Surrogate keys are regular tags. Unlike `DELETE BYTAG`, the entries are removed in batches of
+/// `INVALIDATE_BATCH_SIZE`, releasing the write lock between batches so invalidating a large group does not
+/// stall other clients.
+///
+/// # Arguments
+///
+/// * `args` - The arguments for the command, holding the surrogate key as a single key.
+/// * `engine` - The database engine used for the invalidation.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with the number of invalidated entries.
+pub fn invalidate_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let response = match args {
+            CommandArgs::Single(Some(surrogate), ..) => {
+                let keys = {
+                    let mut tags = engine.tags.write().await;
+                    let keys = tags.keys(&surrogate);
+                    for key in &keys {
+                        tags.remove_key(key);
+                    }
+                    keys
+                };
+
+                let mut invalidated = 0;
+                for batch in keys.chunks(INVALIDATE_BATCH_SIZE) {
+                    let committed = {
+                        let mut db_write = engine.connection.write().await;
+                        let records: Vec<WalRecord> = batch
+                            .iter()
+                            .filter(|key| db_write.contains_key(*key))
+                            .map(|key| WalRecord::Delete(key.clone()))
+                            .collect();
+                        invalidated += records.len();
+                        match commit(&engine, &mut db_write, records).await {
+                            Ok(committed) => committed,
+                            Err(e) => return Ok(wal_error(e)),
+                        }
+                    };
+                    committed.refresh(&engine).await;
+                    tokio::task::yield_now().await;
+                }
+
+                NetResponse {
+                    action: NetActions::Command,
+                    value: Some(invalidated.into()),
+                    error: None,
+                    ..Default::default()
+                }
+            }
+            _ => NetResponse {
+                action: NetActions::Error,
+                value: None,
+                error: Some("No surrogate key provided for invalidate.".to_string()),
+                ..Default::default()
+            },
+        };
+
+        Ok(response)
+    }
+    .boxed()
+}
+
+#[cfg(test)]
+mod test
+{
+    use clap::Parser;
+    use serde_json::json;
+
+    use super::*;
+    use crate::cli::Cli;
+    use crate::commands::insert::insert_command;
+    use crate::commands::range::setrange_command;
+    use crate::protocol::DbValue;
+    use crate::testing::create_fake_engine;
+
+    // Helper function to create a new in-memory database engine with tagged entries
+    async fn create_tagged_engine() -> Arc<DbEngine>
+    {
+        let engine = create_fake_engine();
+        {
+            let mut db_write = engine.connection.write().await;
+            let mut tags = engine.tags.write().await;
+            for (key, key_tags) in [
+                ("a", vec!["tenant:a", "cache"]),
+                ("b", vec!["tenant:a"]),
+                ("c", vec!["cache"]),
+            ] {
+                db_write.insert(
+                    key.to_string(),
+                    DbValue {
+                        value: json!(key),
+                        expires_at: None,
+                    },
+                );
+                tags.set_tags(key, &key_tags.into_iter().map(String::from).collect::<Vec<_>>());
+            }
+        }
+        engine
+    }
+
+    #[tokio::test]
+    async fn test_lookup_bytag()
+    {
+        let engine = create_tagged_engine().await;
+
+        let args = CommandArgs::Single(Some("tenant:a".to_string()), None);
+        let response = lookup_bytag_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.action, NetActions::Command);
+        assert_eq!(response.value, Some(json!({ "a": "a", "b": "b" })));
+    }
+
+    #[tokio::test]
+    async fn test_lookup_bytag_prunes_missing_keys()
+    {
+        let engine = create_tagged_engine().await;
+        engine.connection.write().await.remove("c");
+
+        let args = CommandArgs::Single(Some("cache".to_string()), None);
+        let response = lookup_bytag_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.value, Some(json!({ "a": "a" })));
+        assert_eq!(engine.tags.read().await.keys("cache"), vec!["a".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_delete_bytag()
+    {
+        let engine = create_tagged_engine().await;
+
+        let args = CommandArgs::Single(Some("cache".to_string()), None);
+        let response = delete_bytag_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.action, NetActions::Command);
+        assert_eq!(response.value.unwrap().as_array().unwrap().len(), 2);
+
+        let db_read = engine.connection.read();
+        assert!(db_read.get("a").is_none());
+        assert!(db_read.get("c").is_none());
+        assert!(db_read.get("b").is_some());
+
+        // Deleted keys no longer carry their other tags
+        assert_eq!(engine.tags.read().await.keys("tenant:a"), vec!["b".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_overwritten_keys_lose_their_tags()
+    {
+        let engine = create_tagged_engine().await;
+
+        let args = CommandArgs::Single(
+            Some("a".to_string()),
+            Some(DbValue {
+                value: json!("new"),
+                expires_at: None,
+            }),
+        );
+        insert_command(args, engine.clone()).await.unwrap();
+        let args = CommandArgs::WithArgs(Some("c".to_string()), vec![json!(0), json!("d")]);
+        setrange_command(args, engine.clone()).await.unwrap();
+
+        let args = CommandArgs::Single(Some("cache".to_string()), None);
+        let response = delete_bytag_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.value, Some(json!([])));
+        assert_eq!(engine.connection.read().get("a").unwrap().value, json!("new"));
+        assert_eq!(engine.tags.read().await.keys("tenant:a"), vec!["b".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_invalidate_in_batches()
+    {
+        let engine = Arc::new(DbEngine::new(Cli::parse_from(["phoenix-db"])));
+        let count = INVALIDATE_BATCH_SIZE * 2 + 1;
+        {
+            let mut db_write = engine.connection.write().await;
+            let mut tags = engine.tags.write().await;
+            for i in 0..count {
+                let key = format!("page:{}", i);
+                db_write.insert(
+                    key.clone(),
+                    DbValue {
+                        value: json!(i),
+                        expires_at: None,
+                    },
+                );
+                tags.set_tags(&key, &["product:1".to_string()]);
+            }
+        }
+
+        let args = CommandArgs::Single(Some("product:1".to_string()), None);
+        let response = invalidate_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.action, NetActions::Command);
+        assert_eq!(response.value, Some(json!(count)));
+        assert!(engine.connection.read().is_empty());
+        assert!(engine.tags.read().await.keys("product:1").is_empty());
+    }
+}
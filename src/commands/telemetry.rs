@@ -0,0 +1,96 @@
+use std::error::Error;
+use std::sync::Arc;
+
+use futures::future::{BoxFuture, FutureExt};
+use serde_json::json;
+
+use crate::commands::CommandArgs;
+use crate::protocol::{DbEngine, NetActions, NetResponse};
+use crate::services::telemetry;
+
+/// Executes a `TELEMETRY ON` command, starting to send anonymous usage statistics.
+///
+/// # Arguments
+///
+/// * `_args` - Unused, `TELEMETRY ON` takes no arguments.
+/// * `engine` - The database engine holding the telemetry settings.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with `OK`, or an error if the server has no
+/// telemetry endpoint.
+pub fn telemetry_on_command(
+    _args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move { Ok(set_enabled(&engine, true)) }.boxed()
+}
+
+/// Executes a `TELEMETRY OFF` command, stopping anonymous usage statistics from being sent.
+///
+/// # Arguments
+///
+/// * `_args` - Unused, `TELEMETRY OFF` takes no arguments.
+/// * `engine` - The database engine holding the telemetry settings.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with `OK`.
+pub fn telemetry_off_command(
+    _args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move { Ok(set_enabled(&engine, false)) }.boxed()
+}
+
+/// Executes a `TELEMETRY STATUS` command, showing whether telemetry is enabled and the report it would send.
+///
+/// # Arguments
+///
+/// * `_args` - Unused, `TELEMETRY STATUS` takes no arguments.
+/// * `engine` - The database engine holding the telemetry settings.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with whether telemetry is `enabled`, its
+/// `endpoint` and the `report` sent next.
+pub fn telemetry_status_command(
+    _args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        Ok(NetResponse {
+            action: NetActions::Command,
+            value: Some(json!({
+                "enabled": engine.telemetry.is_enabled(),
+                "endpoint": engine.telemetry.endpoint(),
+                "report": telemetry::report(&engine),
+            })),
+            error: None,
+            ..Default::default()
+        })
+    }
+    .boxed()
+}
+
+/// Turns telemetry on or off, replying with `OK` or why it cannot be turned on.
+fn set_enabled(engine: &DbEngine, enabled: bool) -> NetResponse
+{
+    match engine.telemetry.set_enabled(enabled) {
+        Ok(()) => NetResponse {
+            action: NetActions::Command,
+            value: Some("OK".to_string().into()),
+            error: None,
+            ..Default::default()
+        },
+        Err(error) => NetResponse {
+            action: NetActions::Error,
+            value: None,
+            error: Some(error),
+            ..Default::default()
+        },
+    }
+}
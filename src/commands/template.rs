@@ -0,0 +1,314 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::sync::Arc;
+
+use futures::future::{BoxFuture, FutureExt};
+use tokio::sync::RwLock;
+
+use crate::commands::commit::commit;
+use crate::commands::insert::wal_error;
+use crate::commands::reference::{check_references, reference_error};
+use crate::commands::transform::apply_transforms;
+use crate::commands::CommandArgs;
+use crate::protocol::{DbEngine, DbValue, JsonValue, NetActions, NetResponse};
+use crate::wal::WalRecord;
+
+/// Named JSON documents registered with `TEMPLATE SET`, used as the base of `INSERT FROM TEMPLATE`.
+pub type Templates = RwLock<HashMap<String, JsonValue>>;
+
+/// Executes a `TEMPLATE SET` command, registering or replacing a named template.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` holding the template name and the `[template]` document.
+/// * `engine` - The database engine storing the templates.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` indicating the result of the command.
+pub fn template_set_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let response = match args {
+            CommandArgs::WithArgs(Some(name), mut params) if !params.is_empty() => {
+                let template = params.swap_remove(0);
+                engine.templates.write().await.insert(name, template);
+                NetResponse {
+                    action: NetActions::Command,
+                    value: Some("OK".to_string().into()),
+                    error: None,
+                    ..Default::default()
+                }
+            }
+            CommandArgs::WithArgs(Some(_), _) => NetResponse {
+                action: NetActions::Error,
+                value: None,
+                error: Some("TEMPLATE SET requires a template document.".to_string()),
+                ..Default::default()
+            },
+            _ => NetResponse {
+                action: NetActions::Error,
+                value: None,
+                error: Some("No name provided for template.".to_string()),
+                ..Default::default()
+            },
+        };
+
+        Ok(response)
+    }
+    .boxed()
+}
+
+/// Executes a `TEMPLATE GET` command, returning a registered template.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` holding the template name.
+/// * `engine` - The database engine storing the templates.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with the template, if it exists.
+pub fn template_get_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let response = match args {
+            CommandArgs::WithArgs(Some(name), _) => NetResponse {
+                action: NetActions::Command,
+                value: engine.templates.read().await.get(&name).cloned(),
+                error: None,
+                ..Default::default()
+            },
+            _ => NetResponse {
+                action: NetActions::Error,
+                value: None,
+                error: Some("No name provided for template.".to_string()),
+                ..Default::default()
+            },
+        };
+
+        Ok(response)
+    }
+    .boxed()
+}
+
+/// Executes a `TEMPLATE DELETE` command, removing a registered template.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` holding the template name.
+/// * `engine` - The database engine storing the templates.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` indicating the result of the command.
+pub fn template_delete_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let response = match args {
+            CommandArgs::WithArgs(Some(name), _) => {
+                if engine.templates.write().await.remove(&name).is_some() {
+                    NetResponse {
+                        action: NetActions::Command,
+                        value: Some("OK".to_string().into()),
+                        error: None,
+                        ..Default::default()
+                    }
+                } else {
+                    unknown_template(&name)
+                }
+            }
+            _ => NetResponse {
+                action: NetActions::Error,
+                value: None,
+                error: Some("No name provided for template.".to_string()),
+                ..Default::default()
+            },
+        };
+
+        Ok(response)
+    }
+    .boxed()
+}
+
+/// Executes an `INSERT FROM TEMPLATE` command.
+///
+/// Builds a document from a registered template and optional overrides, then stores it under the given key.
+/// Overrides are applied as a JSON merge patch: objects are merged recursively, `null` removes a field and any
+/// other value replaces the one from the template.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` holding the key and the `[template name, overrides]` arguments.
+/// * `engine` - The database engine used for the insertion.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` indicating the result of the insertion.
+pub fn insert_from_template_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let response = match args {
+            CommandArgs::WithArgs(Some(key), params) => match params.first().and_then(JsonValue::as_str) {
+                Some(name) => {
+                    let templates = engine.templates.read().await;
+                    match templates.get(name) {
+                        Some(template) => {
+                            let mut value = template.clone();
+                            if let Some(overrides) = params.get(1) {
+                                merge_patch(&mut value, overrides);
+                            }
+                            apply_transforms(&engine, &key, &mut value).await;
+                            let warnings = match check_references(&engine, &key, &value, &HashSet::new()).await {
+                                Ok(warnings) => warnings,
+                                Err(e) => return Ok(reference_error(e)),
+                            };
+
+                            let committed = {
+                                let mut db_write = engine.connection.write().await;
+                                let records = vec![WalRecord::Insert(key, DbValue { value, expires_at: None })];
+                                match commit(&engine, &mut db_write, records).await {
+                                    Ok(committed) => committed,
+                                    Err(e) => return Ok(wal_error(e)),
+                                }
+                            };
+                            committed.refresh(&engine).await;
+
+                            NetResponse {
+                                action: NetActions::Command,
+                                value: Some("OK".to_string().into()),
+                                error: None,
+                                warnings,
+                                ..Default::default()
+                            }
+                        }
+                        None => unknown_template(name),
+                    }
+                }
+                None => NetResponse {
+                    action: NetActions::Error,
+                    value: None,
+                    error: Some("INSERT FROM TEMPLATE requires a template name.".to_string()),
+                    ..Default::default()
+                },
+            },
+            _ => NetResponse {
+                action: NetActions::Error,
+                value: None,
+                error: Some("No key provided for insert.".to_string()),
+                ..Default::default()
+            },
+        };
+
+        Ok(response)
+    }
+    .boxed()
+}
+
+/// Applies `patch` to `target` following the JSON merge patch rules (RFC 7386).
+fn merge_patch(target: &mut JsonValue, patch: &JsonValue)
+{
+    match patch {
+        JsonValue::Object(fields) => {
+            if !target.is_object() {
+                *target = JsonValue::Object(Default::default());
+            }
+            let target_fields = target.as_object_mut().unwrap();
+            for (name, value) in fields {
+                if value.is_null() {
+                    target_fields.remove(name);
+                } else {
+                    merge_patch(target_fields.entry(name.clone()).or_insert(JsonValue::Null), value);
+                }
+            }
+        }
+        _ => *target = patch.clone(),
+    }
+}
+
+/// Builds the error response for a template name that is not registered.
+fn unknown_template(name: &str) -> NetResponse
+{
+    NetResponse {
+        action: NetActions::Error,
+        value: None,
+        error: Some(format!("Template '{}' not found.", name)),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::create_fake_engine;
+
+    #[tokio::test]
+    async fn test_insert_from_template()
+    {
+        let engine = create_fake_engine();
+        let template = json!({ "role": "user", "settings": { "theme": "dark", "beta": false }, "legacy": true });
+
+        let args = CommandArgs::WithArgs(Some("user".to_string()), vec![template]);
+        let response = template_set_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.action, NetActions::Command);
+
+        let overrides = json!({ "name": "jamal", "settings": { "beta": true }, "legacy": null });
+        let args = CommandArgs::WithArgs(Some("user:1".to_string()), vec![json!("user"), overrides]);
+        let response = insert_from_template_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.action, NetActions::Command);
+
+        let db_read = engine.connection.read();
+        assert_eq!(
+            db_read.get("user:1").unwrap().value,
+            json!({ "role": "user", "name": "jamal", "settings": { "theme": "dark", "beta": true } })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_insert_from_missing_template()
+    {
+        let engine = create_fake_engine();
+
+        let args = CommandArgs::WithArgs(Some("user:1".to_string()), vec![json!("user")]);
+        let response = insert_from_template_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.action, NetActions::Error);
+        assert_eq!(response.error, Some("Template 'user' not found.".to_string()));
+        assert!(engine.connection.read().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_template_get_and_delete()
+    {
+        let engine = create_fake_engine();
+
+        let args = CommandArgs::WithArgs(Some("user".to_string()), vec![json!({ "role": "user" })]);
+        template_set_command(args, engine.clone()).await.unwrap();
+
+        let args = CommandArgs::WithArgs(Some("user".to_string()), vec![]);
+        let response = template_get_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.value, Some(json!({ "role": "user" })));
+
+        let args = CommandArgs::WithArgs(Some("user".to_string()), vec![]);
+        let response = template_delete_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.action, NetActions::Command);
+
+        let args = CommandArgs::WithArgs(Some("user".to_string()), vec![]);
+        let response = template_get_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.value, None);
+    }
+}
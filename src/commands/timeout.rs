@@ -0,0 +1,121 @@
+use std::time::Duration;
+
+use serde::Serialize;
+use serde_json::json;
+
+use crate::cli::Cli;
+use crate::metrics::Metrics;
+use crate::protocol::{NetActions, NetResponse};
+
+/// The kind of work a command does, which decides how long it may run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CommandClass
+{
+    /// Reads a single entry or a small amount of data, limited by `--read-timeout-ms`.
+    Read,
+    /// Writes a single entry or a small amount of data, limited by `--write-timeout-ms`.
+    Write,
+    /// Reads or writes many entries at once, limited by `--bulk-timeout-ms`.
+    Bulk,
+    /// Blocks on purpose or works on the whole database, such as `XREAD` or `SAVE`, and never times out.
+    Admin,
+}
+
+impl CommandClass
+{
+    /// Returns the class of a command from its normalized name. Commands not listed are reads.
+    pub fn of(command_name: &str) -> Self
+    {
+        match command_name {
+            "INSERT"
+            | "DELETE"
+            | "TOUCH"
+            | "SETRANGE"
+            | "PUT BEGIN"
+            | "PUT CHUNK"
+            | "PUT COMMIT"
+            | "PUT ABORT"
+            | "TEMPLATE SET"
+            | "TEMPLATE DELETE"
+            | "INSERT FROM TEMPLATE"
+            | "TRANSFORM SET"
+            | "TRANSFORM DELETE"
+            | "DERIVE SET"
+            | "DERIVE DELETE"
+            | "REFERENCE SET"
+            | "REFERENCE DELETE"
+            | "XADD"
+            | "XGROUP CREATE"
+            | "XACK"
+            | "XCLAIM"
+            | "XREQUEUE"
+            | "SNAPSHOT UNMOUNT" => CommandClass::Write,
+            "INSERT *" | "LOOKUP *" | "DELETE *" | "LOOKUP BYTAG" | "DELETE BYTAG" | "INVALIDATE" | "SCAN" | "SQL"
+            | "RANGE" | "DELRANGE" | "COUNTRANGE" | "XRANGE" | "STATS PREFIX" | "STATS TTL" | "MEMORY SAMPLE" => {
+                CommandClass::Bulk
+            }
+            "XREAD" | "XREADGROUP" | "MIGRATE" | "SNAPSHOT MOUNT" | "EXPORT" | "IMPORT" | "RESTORE STREAM"
+            | "EXPORT NAMESPACE" | "IMPORT NAMESPACE" | "SAVE" | "BGSAVE" | "BACKUP INCREMENTAL" | "BACKUP VERIFY"
+            | "COMPACT" | "PREPARE LOAD" | "CONFIG SET" | "SERVICES STOP" | "SERVICES START" => CommandClass::Admin,
+            _ => CommandClass::Read,
+        }
+    }
+
+    /// Returns how long commands of this class may run, or `None` if they are not limited.
+    pub fn timeout(self, config: &Cli) -> Option<Duration>
+    {
+        let timeout_ms = match self {
+            CommandClass::Read => config.read_timeout_ms,
+            CommandClass::Write => config.write_timeout_ms,
+            CommandClass::Bulk => config.bulk_timeout_ms,
+            CommandClass::Admin => None,
+        };
+        timeout_ms.map(Duration::from_millis)
+    }
+}
+
+/// Builds the error response for a command that ran out of time, and counts the timeout in the metrics.
+///
+/// The response `value` holds the `class` of the command and its `timeout_ms`, so clients can tell timeouts
+/// apart from other errors without parsing the message.
+pub fn timeout_error(class: CommandClass, timeout: Duration, metrics: &Metrics) -> NetResponse
+{
+    match class {
+        CommandClass::Read => metrics.read_timeouts.increment(),
+        CommandClass::Write => metrics.write_timeouts.increment(),
+        CommandClass::Bulk => metrics.bulk_timeouts.increment(),
+        CommandClass::Admin => {}
+    }
+
+    let timeout_ms = timeout.as_millis() as u64;
+    NetResponse {
+        action: NetActions::Error,
+        value: Some(json!({ "class": class, "timeout_ms": timeout_ms })),
+        error: Some(format!("Command timed out after {} ms.", timeout_ms)),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use clap::Parser;
+
+    use super::*;
+
+    #[test]
+    fn test_command_class_timeout()
+    {
+        let config = Cli::parse_from(["phoenix-db", "--write-timeout-ms", "50"]);
+
+        assert_eq!(CommandClass::of("INSERT"), CommandClass::Write);
+        assert_eq!(CommandClass::of("LOOKUP *"), CommandClass::Bulk);
+        assert_eq!(CommandClass::of("XREAD"), CommandClass::Admin);
+        assert_eq!(CommandClass::of("LOOKUP"), CommandClass::Read);
+
+        assert_eq!(CommandClass::Write.timeout(&config), Some(Duration::from_millis(50)));
+        assert_eq!(CommandClass::Read.timeout(&config), None);
+        assert_eq!(CommandClass::Admin.timeout(&config), None);
+    }
+}
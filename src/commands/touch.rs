@@ -0,0 +1,174 @@
+use std::error::Error;
+use std::sync::Arc;
+
+use futures::future::{BoxFuture, FutureExt};
+
+use crate::commands::commit::commit_tagged;
+use crate::commands::insert::wal_error;
+use crate::commands::CommandArgs;
+use crate::protocol::{unix_millis, DbEngine, DbValue, NetActions, NetResponse};
+use crate::wal::WalRecord;
+
+/// Executes a `TOUCH` command on the database.
+///
+/// Resets the TTL of every given key to the provided one without rewriting its value. Without a TTL, nothing is
+/// written and `TOUCH` only counts the keys that exist, like its Redis counterpart. Keys that do not exist or
+/// have already expired are ignored, so touching them does not bring them back.
+///
+/// # Arguments
+///
+/// * `args` - The arguments for the command, a `CommandArgs::Many` holding each key and the new TTL.
+/// * `engine` - The database engine used for the update.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with the number of touched keys.
+pub fn touch_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let response = match args {
+            CommandArgs::Many(params) => {
+                let mut db_write = engine.connection.write().await;
+                let now = unix_millis();
+                let mut touched = 0;
+                let mut records = vec![];
+
+                for param in params {
+                    let Some(data) = param.key.as_ref().and_then(|key| db_write.get(key)) else {
+                        continue;
+                    };
+                    if data.is_expired(now) {
+                        continue;
+                    }
+                    if param.expires_at.is_some() {
+                        let data = DbValue {
+                            value: data.value.clone(),
+                            expires_at: param.expires_at,
+                        };
+                        records.push(WalRecord::Insert(param.key.unwrap(), data));
+                    }
+                    touched += 1;
+                }
+                // Only the expiration time changes, so the entries keep their tags
+                let committed = match commit_tagged(&engine, &mut db_write, records, None).await {
+                    Ok(committed) => committed,
+                    Err(e) => return Ok(wal_error(e)),
+                };
+                drop(db_write);
+                committed.refresh(&engine).await;
+
+                NetResponse {
+                    action: NetActions::Command,
+                    value: Some(touched.into()),
+                    error: None,
+                    ..Default::default()
+                }
+            }
+            _ => NetResponse {
+                action: NetActions::Error,
+                value: None,
+                error: Some("Invalid arguments for touch.".to_string()),
+                ..Default::default()
+            },
+        };
+
+        Ok(response)
+    }
+    .boxed()
+}
+
+#[cfg(test)]
+mod test
+{
+    use std::time::Duration;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::commands::CommandParams;
+    use crate::protocol::expiry_after;
+    use crate::testing::create_fake_engine;
+
+    #[tokio::test]
+    async fn test_touch_with_new_ttl()
+    {
+        let engine = create_fake_engine();
+        {
+            let mut db_write = engine.connection.write().await;
+            for key in ["session:1", "session:2"] {
+                db_write.insert(
+                    key.to_string(),
+                    DbValue {
+                        value: json!(key),
+                        expires_at: Some(expiry_after(Duration::from_secs(10))),
+                    },
+                );
+            }
+        }
+
+        let expires_at = Some(expiry_after(Duration::from_secs(60)));
+        let args = CommandArgs::Many(
+            ["session:1", "session:2", "session:3"]
+                .into_iter()
+                .map(|key| CommandParams {
+                    key: Some(key.to_string()),
+                    value: None,
+                    expires_at,
+                })
+                .collect(),
+        );
+        let response = touch_command(args, engine.clone()).await.unwrap();
+
+        // Only existing keys are touched
+        assert_eq!(response.action, NetActions::Command);
+        assert_eq!(response.value, Some(json!(2)));
+
+        let db_read = engine.connection.read();
+        assert_eq!(db_read.get("session:1").unwrap().expires_at, expires_at);
+        assert_eq!(db_read.get("session:2").unwrap().expires_at, expires_at);
+        assert_eq!(db_read.get("session:1").unwrap().value, json!("session:1"));
+    }
+
+    #[tokio::test]
+    async fn test_touch_without_ttl_only_counts_keys()
+    {
+        let engine = create_fake_engine();
+        let expires_at = Some(expiry_after(Duration::from_secs(10)));
+        {
+            let mut db_write = engine.connection.write().await;
+            db_write.insert(
+                "session:1".to_string(),
+                DbValue {
+                    value: json!(1),
+                    expires_at,
+                },
+            );
+            db_write.insert(
+                "session:2".to_string(),
+                DbValue {
+                    value: json!(2),
+                    expires_at: Some(1),
+                },
+            );
+        }
+
+        let args = CommandArgs::Many(
+            ["session:1", "session:2"]
+                .into_iter()
+                .map(|key| CommandParams {
+                    key: Some(key.to_string()),
+                    value: None,
+                    expires_at: None,
+                })
+                .collect(),
+        );
+        let response = touch_command(args, engine.clone()).await.unwrap();
+
+        // The expired key is not counted, and the other one keeps its TTL
+        assert_eq!(response.value, Some(json!(1)));
+        assert_eq!(engine.connection.read().get("session:1").unwrap().expires_at, expires_at);
+    }
+}
@@ -0,0 +1,315 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::ops::Bound;
+use std::sync::Arc;
+
+use futures::future::{BoxFuture, FutureExt};
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+use crate::commands::CommandArgs;
+use crate::protocol::{DbEngine, JsonValue, NetActions, NetResponse};
+
+/// The transforms registered with `TRANSFORM SET`, keyed by the key prefix they apply to.
+pub type Transforms = RwLock<BTreeMap<String, Vec<Transform>>>;
+
+/// A step applied to a value before it is stored.
+///
+/// `field` is a `.` separated path into the value, the value itself when it is left out. Steps targeting a field
+/// that does not exist or that has another type are skipped.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Transform
+{
+    /// Removes the leading and trailing whitespace of a string.
+    Trim
+    {
+        /// The string to trim.
+        field: Option<String>,
+    },
+    /// Lowercases a string.
+    Lowercase
+    {
+        /// The string to lowercase.
+        field: Option<String>,
+    },
+    /// Removes a field from an object.
+    Strip
+    {
+        /// The field to remove.
+        field: String,
+    },
+    /// Drops the elements of an array past the first `len`.
+    MaxLen
+    {
+        /// The array to cap.
+        field: Option<String>,
+        /// The most elements the array keeps.
+        len: usize,
+    },
+}
+
+impl Transform
+{
+    /// Applies the step to `value`.
+    fn apply(&self, value: &mut JsonValue)
+    {
+        match self {
+            Transform::Trim { field } => {
+                if let Some(JsonValue::String(s)) = field_mut(value, field.as_deref()) {
+                    *s = s.trim().to_string();
+                }
+            }
+            Transform::Lowercase { field } => {
+                if let Some(JsonValue::String(s)) = field_mut(value, field.as_deref()) {
+                    *s = s.to_lowercase();
+                }
+            }
+            Transform::Strip { field } => {
+                let (parent, name) = match field.rsplit_once('.') {
+                    Some((parent, name)) => (Some(parent), name),
+                    None => (None, field.as_str()),
+                };
+                if let Some(JsonValue::Object(fields)) = field_mut(value, parent) {
+                    fields.remove(name);
+                }
+            }
+            Transform::MaxLen { field, len } => {
+                if let Some(JsonValue::Array(items)) = field_mut(value, field.as_deref()) {
+                    items.truncate(*len);
+                }
+            }
+        }
+    }
+}
+
+/// Returns the field of `value` at the `.` separated `path`, or `value` itself when there is no path.
+fn field_mut<'a>(value: &'a mut JsonValue, path: Option<&str>) -> Option<&'a mut JsonValue>
+{
+    match path {
+        Some(path) => path.split('.').try_fold(value, |value, name| value.get_mut(name)),
+        None => Some(value),
+    }
+}
+
+/// Applies the transforms registered for every prefix of `key` to `value`, shortest prefix first.
+///
+/// # Arguments
+///
+/// * `engine` - The database engine holding the transforms.
+/// * `key` - The key the value is about to be stored under.
+/// * `value` - The value to transform in place.
+pub async fn apply_transforms(engine: &DbEngine, key: &str, value: &mut JsonValue)
+{
+    let transforms = engine.transforms.read().await;
+
+    // Prefixes of a key sort before it, shortest first
+    for (prefix, steps) in transforms.range::<str, _>((Bound::Unbounded, Bound::Included(key))) {
+        if key.starts_with(prefix.as_str()) {
+            for step in steps {
+                step.apply(value);
+            }
+        }
+    }
+}
+
+/// Executes a `TRANSFORM SET` command, registering the transforms applied to values written under a key prefix.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` holding the prefix and the `[steps]` argument, a list of steps such as
+///   `{ "op": "lowercase", "field": "email" }`. Replaces the steps previously registered for the prefix.
+/// * `engine` - The database engine storing the transforms.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` indicating the result of the command.
+pub fn transform_set_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let response = match args {
+            CommandArgs::WithArgs(Some(prefix), mut params) if !params.is_empty() => {
+                match serde_json::from_value::<Vec<Transform>>(params.swap_remove(0)) {
+                    Ok(steps) => {
+                        engine.transforms.write().await.insert(prefix, steps);
+                        NetResponse {
+                            action: NetActions::Command,
+                            value: Some("OK".to_string().into()),
+                            error: None,
+                            ..Default::default()
+                        }
+                    }
+                    Err(e) => transform_error(format!("Invalid transform: {}", e)),
+                }
+            }
+            CommandArgs::WithArgs(Some(_), _) => transform_error("TRANSFORM SET requires a list of steps.".to_string()),
+            _ => transform_error("No prefix provided for transform.".to_string()),
+        };
+
+        Ok(response)
+    }
+    .boxed()
+}
+
+/// Executes a `TRANSFORM GET` command, returning the transforms registered for a key prefix.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` holding the prefix.
+/// * `engine` - The database engine storing the transforms.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with the steps, if there are any.
+pub fn transform_get_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let response = match args {
+            CommandArgs::WithArgs(Some(prefix), _) => NetResponse {
+                action: NetActions::Command,
+                value: engine
+                    .transforms
+                    .read()
+                    .await
+                    .get(&prefix)
+                    .and_then(|steps| serde_json::to_value(steps).ok()),
+                error: None,
+                ..Default::default()
+            },
+            _ => transform_error("No prefix provided for transform.".to_string()),
+        };
+
+        Ok(response)
+    }
+    .boxed()
+}
+
+/// Executes a `TRANSFORM DELETE` command, removing the transforms registered for a key prefix.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` holding the prefix.
+/// * `engine` - The database engine storing the transforms.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` indicating the result of the command.
+pub fn transform_delete_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let response = match args {
+            CommandArgs::WithArgs(Some(prefix), _) => {
+                if engine.transforms.write().await.remove(&prefix).is_some() {
+                    NetResponse {
+                        action: NetActions::Command,
+                        value: Some("OK".to_string().into()),
+                        error: None,
+                        ..Default::default()
+                    }
+                } else {
+                    transform_error(format!("No transform registered for '{}'.", prefix))
+                }
+            }
+            _ => transform_error("No prefix provided for transform.".to_string()),
+        };
+
+        Ok(response)
+    }
+    .boxed()
+}
+
+/// Builds an error response for the transform commands.
+fn transform_error(message: String) -> NetResponse
+{
+    NetResponse {
+        action: NetActions::Error,
+        value: None,
+        error: Some(message),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use serde_json::json;
+
+    use super::*;
+    use crate::commands::insert::insert_command;
+    use crate::commands::range::setrange_command;
+    use crate::protocol::DbValue;
+    use crate::testing::create_fake_engine;
+
+    #[tokio::test]
+    async fn test_transforms_on_insert()
+    {
+        let engine = create_fake_engine();
+        let steps = json!([
+            { "op": "trim", "field": "email" },
+            { "op": "lowercase", "field": "email" },
+            { "op": "strip", "field": "profile.password" },
+            { "op": "max_len", "field": "tags", "len": 2 },
+        ]);
+
+        let args = CommandArgs::WithArgs(Some("user:".to_string()), vec![steps]);
+        let response = transform_set_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.action, NetActions::Command);
+
+        let value = json!({
+            "email": "  Jamal@Example.COM ",
+            "profile": { "name": "jamal", "password": "hunter2" },
+            "tags": ["a", "b", "c"],
+        });
+        for key in ["user:1", "team:1"] {
+            let args = CommandArgs::Single(
+                Some(key.to_string()),
+                Some(DbValue {
+                    value: value.clone(),
+                    expires_at: None,
+                }),
+            );
+            insert_command(args, engine.clone()).await.unwrap();
+        }
+
+        let db_read = engine.connection.read();
+        assert_eq!(
+            db_read.get("user:1").unwrap().value,
+            json!({ "email": "jamal@example.com", "profile": { "name": "jamal" }, "tags": ["a", "b"] })
+        );
+        // Keys outside of the prefix are stored as is
+        assert_eq!(db_read.get("team:1").unwrap().value, value);
+    }
+
+    #[tokio::test]
+    async fn test_transforms_on_setrange()
+    {
+        let engine = create_fake_engine();
+        let args = CommandArgs::WithArgs(Some("name:".to_string()), vec![json!([{ "op": "lowercase" }])]);
+        transform_set_command(args, engine.clone()).await.unwrap();
+
+        let args = CommandArgs::WithArgs(Some("name:1".to_string()), vec![json!(0), json!("JAMAL")]);
+        setrange_command(args, engine.clone()).await.unwrap();
+
+        let db_read = engine.connection.read();
+        assert_eq!(db_read.get("name:1").unwrap().value, json!("jamal"));
+    }
+
+    #[tokio::test]
+    async fn test_transform_set_rejects_unknown_steps()
+    {
+        let engine = create_fake_engine();
+
+        let args = CommandArgs::WithArgs(Some("user:".to_string()), vec![json!([{ "op": "uppercase" }])]);
+        let response = transform_set_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.action, NetActions::Error);
+    }
+}
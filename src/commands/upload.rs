@@ -0,0 +1,465 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::{BoxFuture, FutureExt};
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+use crate::commands::commit::commit;
+use crate::commands::insert::wal_error;
+use crate::commands::reference::{check_references, reference_error};
+use crate::commands::transform::apply_transforms;
+use crate::commands::CommandArgs;
+use crate::protocol::{DbEngine, DbKey, DbValue, JsonValue, NetActions, NetResponse};
+use crate::wal::WalRecord;
+
+/// The largest value that can be assembled with a chunked upload.
+const MAX_UPLOAD_SIZE: u64 = 512 * 1024 * 1024;
+
+/// The most uploads that can be in progress at once.
+const MAX_PENDING_UPLOADS: usize = 1024;
+
+/// The most bytes the uploads in progress can announce in total, so they cannot hold more memory than this.
+const MAX_PENDING_UPLOAD_BYTES: usize = 1024 * 1024 * 1024;
+
+/// Chunked uploads started with `PUT BEGIN` that have not been committed or aborted yet.
+#[derive(Debug, Default)]
+pub struct Uploads
+{
+    /// The id handed out to the next upload.
+    next_id: AtomicU64,
+    /// The uploads in progress, keyed by their id.
+    pending: Mutex<HashMap<u64, PendingUpload>>,
+}
+
+/// A value being assembled from chunks.
+#[derive(Debug)]
+struct PendingUpload
+{
+    /// The key the value is stored under once committed.
+    key: DbKey,
+    /// The number of bytes announced by `PUT BEGIN`.
+    size: usize,
+    /// The bytes received so far.
+    data: Vec<u8>,
+    /// When the upload last received a command, used to discard abandoned uploads.
+    last_activity: Instant,
+}
+
+impl Uploads
+{
+    /// Discards uploads that have been idle for longer than `idle_timeout`.
+    /// Returns the number of uploads removed.
+    pub async fn remove_stale(&self, idle_timeout: Duration) -> usize
+    {
+        let mut pending = self.pending.lock().await;
+        let before = pending.len();
+        pending.retain(|_, upload| upload.last_activity.elapsed() < idle_timeout);
+        before - pending.len()
+    }
+}
+
+/// Executes a `PUT BEGIN` command, starting a chunked upload for a key.
+///
+/// Uploads are refused once too many are in progress, or once the sizes they announced add up to more than
+/// `MAX_PENDING_UPLOAD_BYTES`.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` holding the key and the `[size]` of the value in bytes.
+/// * `engine` - The database engine tracking the upload.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with the id of the new upload.
+pub fn put_begin_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let response = match args {
+            CommandArgs::WithArgs(Some(key), params) => match params.first().and_then(JsonValue::as_u64) {
+                Some(size) if size > MAX_UPLOAD_SIZE => NetResponse {
+                    action: NetActions::Error,
+                    value: None,
+                    error: Some(format!("Upload size is too large, the maximum is {}.", MAX_UPLOAD_SIZE)),
+                    ..Default::default()
+                },
+                Some(size) => {
+                    // The data grows as chunks arrive, and the announced sizes are capped so clients cannot
+                    // reserve memory they never send
+                    let mut pending = engine.uploads.pending.lock().await;
+                    let announced: usize = pending.values().map(|upload| upload.size).sum();
+                    if pending.len() >= MAX_PENDING_UPLOADS || announced + size as usize > MAX_PENDING_UPLOAD_BYTES {
+                        return Ok(NetResponse {
+                            action: NetActions::Error,
+                            value: None,
+                            error: Some("Too many uploads in progress, commit or abort some first.".to_string()),
+                            ..Default::default()
+                        });
+                    }
+
+                    let id = engine.uploads.next_id.fetch_add(1, Ordering::Relaxed);
+                    let upload = PendingUpload {
+                        key,
+                        size: size as usize,
+                        data: Vec::new(),
+                        last_activity: Instant::now(),
+                    };
+                    pending.insert(id, upload);
+                    NetResponse {
+                        action: NetActions::Command,
+                        value: Some(id.into()),
+                        error: None,
+                        ..Default::default()
+                    }
+                }
+                None => NetResponse {
+                    action: NetActions::Error,
+                    value: None,
+                    error: Some("PUT BEGIN requires the size of the value.".to_string()),
+                    ..Default::default()
+                },
+            },
+            CommandArgs::WithArgs(None, ..) => NetResponse {
+                action: NetActions::Error,
+                value: None,
+                error: Some("No key provided for upload.".to_string()),
+                ..Default::default()
+            },
+            _ => NetResponse {
+                action: NetActions::Error,
+                value: None,
+                error: Some("Invalid arguments for upload.".to_string()),
+                ..Default::default()
+            },
+        };
+
+        Ok(response)
+    }
+    .boxed()
+}
+
+/// Executes a `PUT CHUNK` command, appending data to an upload.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` holding the `[id, data]` of the chunk.
+/// * `engine` - The database engine tracking the upload.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` with the number of bytes received so far.
+pub fn put_chunk_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let response = match args {
+            CommandArgs::WithArgs(_, params) => match (
+                params.first().and_then(JsonValue::as_u64),
+                params.get(1).and_then(JsonValue::as_str),
+            ) {
+                (Some(id), Some(chunk)) => {
+                    let mut pending = engine.uploads.pending.lock().await;
+                    match pending.get_mut(&id) {
+                        Some(upload) if upload.data.len() + chunk.len() > upload.size => NetResponse {
+                            action: NetActions::Error,
+                            value: None,
+                            error: Some(format!("Chunk exceeds the announced size of upload {}.", id)),
+                            ..Default::default()
+                        },
+                        Some(upload) => {
+                            upload.data.extend_from_slice(chunk.as_bytes());
+                            upload.last_activity = Instant::now();
+                            NetResponse {
+                                action: NetActions::Command,
+                                value: Some(upload.data.len().into()),
+                                error: None,
+                                ..Default::default()
+                            }
+                        }
+                        None => unknown_upload(id),
+                    }
+                }
+                _ => NetResponse {
+                    action: NetActions::Error,
+                    value: None,
+                    error: Some("PUT CHUNK requires an upload id and a string chunk.".to_string()),
+                    ..Default::default()
+                },
+            },
+            _ => NetResponse {
+                action: NetActions::Error,
+                value: None,
+                error: Some("Invalid arguments for upload.".to_string()),
+                ..Default::default()
+            },
+        };
+
+        Ok(response)
+    }
+    .boxed()
+}
+
+/// Executes a `PUT COMMIT` command, storing the assembled value.
+///
+/// The received bytes must add up to the size announced by `PUT BEGIN` and form a valid JSON document.
+/// The upload is discarded whether or not the commit succeeds.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` holding the `[id]` of the upload.
+/// * `engine` - The database engine tracking the upload.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` indicating the result of the commit.
+pub fn put_commit_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let response = match args {
+            CommandArgs::WithArgs(_, params) => match params.first().and_then(JsonValue::as_u64) {
+                Some(id) => {
+                    let upload = engine.uploads.pending.lock().await.remove(&id);
+                    match upload {
+                        Some(upload) if upload.data.len() != upload.size => NetResponse {
+                            action: NetActions::Error,
+                            value: None,
+                            error: Some(format!(
+                                "Upload {} is incomplete, received {} of {} bytes.",
+                                id,
+                                upload.data.len(),
+                                upload.size
+                            )),
+                            ..Default::default()
+                        },
+                        Some(upload) => match serde_json::from_slice::<JsonValue>(&upload.data) {
+                            Ok(mut value) => {
+                                apply_transforms(&engine, &upload.key, &mut value).await;
+                                let warnings = match check_references(&engine, &upload.key, &value, &HashSet::new()).await {
+                                    Ok(warnings) => warnings,
+                                    Err(e) => return Ok(reference_error(e)),
+                                };
+                                let mut db_write = engine.connection.write().await;
+                                let records = vec![WalRecord::Insert(upload.key, DbValue { value, expires_at: None })];
+                                let committed = match commit(&engine, &mut db_write, records).await {
+                                    Ok(committed) => committed,
+                                    Err(e) => return Ok(wal_error(e)),
+                                };
+                                drop(db_write);
+                                committed.refresh(&engine).await;
+                                NetResponse {
+                                    action: NetActions::Command,
+                                    value: Some("OK".to_string().into()),
+                                    error: None,
+                                    warnings,
+                                    ..Default::default()
+                                }
+                            }
+                            Err(e) => NetResponse {
+                                action: NetActions::Error,
+                                value: None,
+                                error: Some(format!("Upload {} is not valid JSON: {}", id, e)),
+                                ..Default::default()
+                            },
+                        },
+                        None => unknown_upload(id),
+                    }
+                }
+                None => NetResponse {
+                    action: NetActions::Error,
+                    value: None,
+                    error: Some("PUT COMMIT requires an upload id.".to_string()),
+                    ..Default::default()
+                },
+            },
+            _ => NetResponse {
+                action: NetActions::Error,
+                value: None,
+                error: Some("Invalid arguments for upload.".to_string()),
+                ..Default::default()
+            },
+        };
+
+        Ok(response)
+    }
+    .boxed()
+}
+
+/// Executes a `PUT ABORT` command, discarding an upload and the data received so far.
+///
+/// # Arguments
+///
+/// * `args` - A `CommandArgs::WithArgs` holding the `[id]` of the upload.
+/// * `engine` - The database engine tracking the upload.
+///
+/// # Returns
+///
+/// A `BoxFuture` that resolves to a `Result` containing a `NetResponse` indicating the result of the abort.
+pub fn put_abort_command(
+    args: CommandArgs,
+    engine: Arc<DbEngine>,
+) -> BoxFuture<'static, Result<NetResponse, Box<dyn Error + Send>>>
+{
+    async move {
+        let response = match args {
+            CommandArgs::WithArgs(_, params) => match params.first().and_then(JsonValue::as_u64) {
+                Some(id) => match engine.uploads.pending.lock().await.remove(&id) {
+                    Some(_) => NetResponse {
+                        action: NetActions::Command,
+                        value: Some("OK".to_string().into()),
+                        error: None,
+                        ..Default::default()
+                    },
+                    None => unknown_upload(id),
+                },
+                None => NetResponse {
+                    action: NetActions::Error,
+                    value: None,
+                    error: Some("PUT ABORT requires an upload id.".to_string()),
+                    ..Default::default()
+                },
+            },
+            _ => NetResponse {
+                action: NetActions::Error,
+                value: None,
+                error: Some("Invalid arguments for upload.".to_string()),
+                ..Default::default()
+            },
+        };
+
+        Ok(response)
+    }
+    .boxed()
+}
+
+/// Builds the error response for an upload id that does not exist, was committed, or was discarded.
+fn unknown_upload(id: u64) -> NetResponse
+{
+    NetResponse {
+        action: NetActions::Error,
+        value: None,
+        error: Some(format!("Upload {} not found.", id)),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::create_fake_engine;
+
+    async fn begin(engine: &Arc<DbEngine>, key: &str, size: usize) -> u64
+    {
+        let args = CommandArgs::WithArgs(Some(key.to_string()), vec![json!(size)]);
+        let response = put_begin_command(args, engine.clone()).await.unwrap();
+        response.value.and_then(|id| id.as_u64()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_chunked_upload()
+    {
+        let engine = create_fake_engine();
+        let payload = json!({ "name": "phoenix", "tags": ["a", "b"] }).to_string();
+        let (first, second) = payload.split_at(10);
+
+        let id = begin(&engine, "key", payload.len()).await;
+
+        for chunk in [first, second] {
+            let args = CommandArgs::WithArgs(None, vec![json!(id), json!(chunk)]);
+            let response = put_chunk_command(args, engine.clone()).await.unwrap();
+            assert_eq!(response.action, NetActions::Command);
+        }
+
+        let args = CommandArgs::WithArgs(None, vec![json!(id)]);
+        let response = put_commit_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.action, NetActions::Command);
+        assert_eq!(response.value, Some("OK".to_string().into()));
+
+        let db_read = engine.connection.read();
+        assert_eq!(
+            db_read.get("key").unwrap().value,
+            json!({ "name": "phoenix", "tags": ["a", "b"] })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_chunk_exceeding_size()
+    {
+        let engine = create_fake_engine();
+        let id = begin(&engine, "key", 2).await;
+
+        let args = CommandArgs::WithArgs(None, vec![json!(id), json!("\"abc\"")]);
+        let response = put_chunk_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.action, NetActions::Error);
+        assert_eq!(
+            response.error,
+            Some(format!("Chunk exceeds the announced size of upload {}.", id))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pending_uploads_are_capped()
+    {
+        let engine = create_fake_engine();
+        let id = begin(&engine, "key", MAX_UPLOAD_SIZE as usize).await;
+        assert_eq!(engine.uploads.pending.lock().await[&id].data.capacity(), 0);
+        begin(&engine, "key", MAX_UPLOAD_SIZE as usize).await;
+
+        // The two uploads announced as much memory as all uploads may hold
+        let args = CommandArgs::WithArgs(Some("key".to_string()), vec![json!(1)]);
+        let response = put_begin_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.action, NetActions::Error);
+
+        let args = CommandArgs::WithArgs(None, vec![json!(id)]);
+        put_abort_command(args, engine.clone()).await.unwrap();
+        begin(&engine, "key", 1).await;
+    }
+
+    #[tokio::test]
+    async fn test_commit_incomplete_upload()
+    {
+        let engine = create_fake_engine();
+        let id = begin(&engine, "key", 10).await;
+
+        let args = CommandArgs::WithArgs(None, vec![json!(id)]);
+        let response = put_commit_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.action, NetActions::Error);
+        assert_eq!(
+            response.error,
+            Some(format!("Upload {} is incomplete, received 0 of 10 bytes.", id))
+        );
+        assert!(engine.connection.read().get("key").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_abort_and_remove_stale()
+    {
+        let engine = create_fake_engine();
+        let aborted = begin(&engine, "a", 4).await;
+        begin(&engine, "b", 4).await;
+
+        let args = CommandArgs::WithArgs(None, vec![json!(aborted)]);
+        let response = put_abort_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.action, NetActions::Command);
+
+        // Committing an aborted upload fails
+        let args = CommandArgs::WithArgs(None, vec![json!(aborted)]);
+        let response = put_commit_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.error, Some(format!("Upload {} not found.", aborted)));
+
+        assert_eq!(engine.uploads.remove_stale(Duration::ZERO).await, 1);
+    }
+}
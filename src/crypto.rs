@@ -0,0 +1,146 @@
+use std::{fmt, fs, io};
+
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+
+use crate::cli::Cli;
+
+/// The environment variable the encryption key is read from when `--encryption-key-file` is not given.
+pub const KEY_VAR: &str = "PHOENIX_DB_ENCRYPTION_KEY";
+
+/// The length of the random nonce written in front of every encrypted record.
+const NONCE_LEN: usize = 12;
+
+/// Encrypts the records of snapshots and write-ahead logs with AES-256-GCM, so they cannot be read without the key.
+pub struct Cipher
+{
+    aead: Aes256Gcm,
+}
+
+impl fmt::Debug for Cipher
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        // The key is never printed
+        f.write_str("Cipher")
+    }
+}
+
+impl Cipher
+{
+    /// Creates a cipher from a 256-bit key.
+    pub fn new(key: &[u8; 32]) -> Self
+    {
+        Self {
+            aead: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key)),
+        }
+    }
+
+    /// Loads the key from the `--encryption-key-file` or else the `PHOENIX_DB_ENCRYPTION_KEY` environment variable,
+    /// both holding the 32 bytes of the key as 64 hex digits.
+    ///
+    /// # Returns
+    ///
+    /// The cipher, or `None` if no key is configured and files are written in the clear.
+    pub fn load(args: &Cli) -> io::Result<Option<Self>>
+    {
+        let hex = match &args.encryption_key_file {
+            Some(path) => fs::read_to_string(path)?,
+            None => match std::env::var(KEY_VAR) {
+                Ok(hex) => hex,
+                Err(_) => return Ok(None),
+            },
+        };
+        parse_key(hex.trim()).map(|key| Some(Self::new(&key)))
+    }
+
+    /// Encrypts `plaintext` under a random nonce.
+    ///
+    /// # Returns
+    ///
+    /// The nonce followed by the ciphertext and its authentication tag.
+    pub fn seal(&self, plaintext: &[u8]) -> io::Result<Vec<u8>>
+    {
+        let nonce: [u8; NONCE_LEN] = rand::random();
+        let ciphertext = self
+            .aead
+            .encrypt(Nonce::from_slice(&nonce), plaintext)
+            .map_err(|_| io::Error::other("failed to encrypt a record"))?;
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        Ok(sealed)
+    }
+
+    /// Decrypts a record written by [`Cipher::seal`], failing if it was encrypted with another key or altered.
+    pub fn open(&self, sealed: &[u8]) -> io::Result<Vec<u8>>
+    {
+        if sealed.len() < NONCE_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "encrypted record is too short"));
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        self.aead.decrypt(Nonce::from_slice(nonce), ciphertext).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "failed to decrypt a record, the encryption key is wrong or the file was altered",
+            )
+        })
+    }
+}
+
+/// Parses a key written as 64 hex digits.
+fn parse_key(hex: &str) -> io::Result<[u8; 32]>
+{
+    let invalid = || io::Error::new(io::ErrorKind::InvalidInput, "the encryption key must be 64 hex digits");
+    if hex.len() != 64 {
+        return Err(invalid());
+    }
+    from_hex(hex).map_err(|_| invalid())?.try_into().map_err(|_| invalid())
+}
+
+/// Writes `bytes` as hex digits, two per byte.
+pub fn to_hex(bytes: &[u8]) -> String
+{
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Parses bytes written as hex digits, two per byte.
+pub fn from_hex(hex: &str) -> io::Result<Vec<u8>>
+{
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid hex digits");
+    if hex.len() & 1 == 1 || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+
+    hex.as_bytes()
+        .chunks(2)
+        .map(|digits| {
+            let digits = std::str::from_utf8(digits).map_err(|_| invalid())?;
+            u8::from_str_radix(digits, 16).map_err(|_| invalid())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn test_seal_and_open()
+    {
+        let cipher = Cipher::new(&parse_key(&"2a".repeat(32)).unwrap());
+        let sealed = cipher.seal(b"secret").unwrap();
+        assert!(!sealed.windows(6).any(|window| window == b"secret"));
+        assert_eq!(cipher.open(&sealed).unwrap(), b"secret");
+
+        // Another key, or an altered record, is refused
+        let other = Cipher::new(&[7; 32]);
+        assert_eq!(other.open(&sealed).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        let mut altered = sealed.clone();
+        *altered.last_mut().unwrap() ^= 1;
+        assert!(cipher.open(&altered).is_err());
+
+        assert!(parse_key("2a").is_err());
+    }
+}
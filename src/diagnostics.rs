@@ -0,0 +1,115 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+/// The number of events kept for each subscriber, a subscriber falling further behind misses the oldest ones.
+const DIAGNOSTICS_BUFFER: usize = 256;
+
+/// How important a diagnostic event is.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DiagnosticLevel
+{
+    Info,
+    Warn,
+    Error,
+}
+
+/// What a diagnostic event is about.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiagnosticKind
+{
+    /// A snapshot finished saving, or failed to.
+    Snapshot,
+    /// The write-ahead log was compacted, or failed to be.
+    Compaction,
+    /// A write could not be mirrored to the upstream server.
+    Upstream,
+    /// The server is shutting down.
+    Shutdown,
+    /// This node became the primary of its HA pair, or stopped being it.
+    Election,
+    /// Writes started or stopped being rejected because of memory use.
+    Memory,
+}
+
+/// A server event pushed to the clients subscribed with `DIAGNOSTICS SUBSCRIBE`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DiagnosticEvent
+{
+    /// How important the event is.
+    pub level: DiagnosticLevel,
+    /// What the event is about.
+    pub kind: DiagnosticKind,
+    /// A description of the event, as written to the server logs.
+    pub message: String,
+    /// When the event happened, in seconds since the UNIX epoch.
+    pub timestamp: u64,
+}
+
+/// Broadcasts important server events to the subscribed admin clients.
+///
+/// Events are only kept while there are subscribers, emitting an event nobody listens to is free.
+#[derive(Debug)]
+pub struct Diagnostics
+{
+    sender: broadcast::Sender<DiagnosticEvent>,
+}
+
+impl Default for Diagnostics
+{
+    fn default() -> Self
+    {
+        Self {
+            sender: broadcast::channel(DIAGNOSTICS_BUFFER).0,
+        }
+    }
+}
+
+impl Diagnostics
+{
+    /// Sends an event to every subscriber.
+    pub fn emit(&self, level: DiagnosticLevel, kind: DiagnosticKind, message: String)
+    {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+
+        // Sending only fails when there are no subscribers
+        let _ = self.sender.send(DiagnosticEvent {
+            level,
+            kind,
+            message,
+            timestamp,
+        });
+    }
+
+    /// Subscribes to the events emitted from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<DiagnosticEvent>
+    {
+        self.sender.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[tokio::test]
+    async fn test_subscribe()
+    {
+        let diagnostics = Diagnostics::default();
+
+        // Events emitted before subscribing are not received
+        diagnostics.emit(DiagnosticLevel::Info, DiagnosticKind::Snapshot, "first".to_string());
+
+        let mut events = diagnostics.subscribe();
+        diagnostics.emit(DiagnosticLevel::Error, DiagnosticKind::Upstream, "second".to_string());
+
+        let event = events.recv().await.unwrap();
+        assert_eq!(event.kind, DiagnosticKind::Upstream);
+        assert_eq!(event.message, "second");
+        assert!(events.try_recv().is_err());
+    }
+}
@@ -0,0 +1,200 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::client::Client;
+use crate::crypto::Cipher;
+use crate::protocol::{DbKey, JsonValue, NetActions, NetCommand};
+use crate::snapshot;
+
+/// The number of entries requested per `SCAN` page when reading a keyspace from a server.
+const DIFF_SCAN_COUNT: u64 = 1000;
+
+/// How a key differs between the source and the target.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum Change
+{
+    /// The key only exists in the target.
+    Added,
+    /// The key only exists in the source.
+    Removed,
+    /// The key exists in both with different values.
+    Changed,
+}
+
+impl Change
+{
+    fn symbol(self) -> char
+    {
+        match self {
+            Change::Added => '+',
+            Change::Removed => '-',
+            Change::Changed => '~',
+        }
+    }
+}
+
+/// The keys of a keyspace along with a hash of their value.
+type KeyHashes = BTreeMap<DbKey, u64>;
+
+fn hash_value(value: &JsonValue) -> u64
+{
+    let mut hasher = DefaultHasher::new();
+    // Serializing a `JsonValue` never fails
+    serde_json::to_vec(value).unwrap_or_default().hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Reads the keys of a keyspace and hashes their values.
+///
+/// `location` is the path of a snapshot if such a file exists, otherwise the `host:port` of a server, which must
+/// have `SCAN` enabled. Encrypted snapshots are decrypted with `cipher`.
+async fn read_keyspace(location: &str, prefix: Option<&str>, cipher: Option<Arc<Cipher>>) -> Result<KeyHashes, String>
+{
+    let keep = |key: &str| prefix.is_none_or(|prefix| key.starts_with(prefix));
+
+    if Path::new(location).is_file() {
+        let path = Path::new(location).to_path_buf();
+        let map = tokio::task::spawn_blocking(move || snapshot::read(&path, cipher.as_deref()))
+            .await
+            .map_err(|e| e.to_string())?
+            .map_err(|e| format!("failed to read snapshot {}: {}", location, e))?;
+
+        return Ok(map
+            .iter()
+            .filter(|(key, _)| keep(key))
+            .map(|(key, data)| (key.clone(), hash_value(&data.value)))
+            .collect());
+    }
+
+    let mut client = Client::new(location);
+    let mut hashes = KeyHashes::new();
+    let mut cursor = 0;
+
+    loop {
+        let command = NetCommand {
+            name: "SCAN",
+            keys: prefix.map(|prefix| vec![prefix]),
+            values: None,
+            ttls: None,
+            args: Some(vec![cursor.into(), DIFF_SCAN_COUNT.into()]),
+            tags: None,
+            batch: None,
+            durability: None,
+            fields: None,
+            progress: None,
+            id: None,
+        };
+        let response = client
+            .request(&command)
+            .await
+            .map_err(|e| format!("failed to scan {}: {}", location, e))?;
+        if response.action == NetActions::Error {
+            return Err(format!("failed to scan {}: {}", location, response.error.unwrap_or_default()));
+        }
+
+        for entry in response.value.as_ref().and_then(JsonValue::as_array).into_iter().flatten() {
+            if let Some(key) = entry["key"].as_str() {
+                hashes.insert(key.to_string(), hash_value(&entry["value"]));
+            }
+        }
+
+        match response.cursor {
+            Some(next) => cursor = next,
+            None => return Ok(hashes),
+        }
+    }
+}
+
+/// Compares two keyspaces, returning the keys that differ in key order.
+fn compare(source: &KeyHashes, target: &KeyHashes) -> Vec<(DbKey, Change)>
+{
+    let mut changes: Vec<(DbKey, Change)> = source
+        .iter()
+        .filter_map(|(key, hash)| match target.get(key) {
+            None => Some((key.clone(), Change::Removed)),
+            Some(other) if other != hash => Some((key.clone(), Change::Changed)),
+            Some(_) => None,
+        })
+        .chain(
+            target
+                .keys()
+                .filter(|key| !source.contains_key(*key))
+                .map(|key| (key.clone(), Change::Added)),
+        )
+        .collect();
+
+    changes.sort();
+    changes
+}
+
+/// Runs `phoenix-db diff`, printing the keys that differ between two servers or snapshots.
+///
+/// Each differing key is printed on its own line, prefixed by `+` when it was added in the target, `-` when it
+/// was removed and `~` when its value changed, followed by a summary.
+///
+/// # Arguments
+///
+/// * `source` - The snapshot path or `host:port` of the server to compare from.
+/// * `target` - The snapshot path or `host:port` of the server to compare to.
+/// * `prefix` - Only compare the keys starting with this prefix, if any.
+/// * `cipher` - Decrypts the snapshots, if they are encrypted.
+///
+/// # Returns
+///
+/// Whether the keyspaces are identical.
+pub async fn execute(source: &str, target: &str, prefix: Option<&str>, cipher: Option<Arc<Cipher>>) -> Result<bool, String>
+{
+    let (source, target) = tokio::try_join!(
+        read_keyspace(source, prefix, cipher.clone()),
+        read_keyspace(target, prefix, cipher)
+    )?;
+    let changes = compare(&source, &target);
+
+    for (key, change) in &changes {
+        println!("{} {}", change.symbol(), key);
+    }
+
+    let count = |kind: Change| changes.iter().filter(|(_, change)| *change == kind).count();
+    println!(
+        "{} added, {} removed, {} changed, {} unchanged",
+        count(Change::Added),
+        count(Change::Removed),
+        count(Change::Changed),
+        source.len() - count(Change::Removed) - count(Change::Changed),
+    );
+
+    Ok(changes.is_empty())
+}
+
+#[cfg(test)]
+mod test
+{
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_compare()
+    {
+        let source: KeyHashes = [("a", json!(1)), ("b", json!(2)), ("c", json!(3))]
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), hash_value(&value)))
+            .collect();
+        let target: KeyHashes = [("b", json!(2)), ("c", json!(4)), ("d", json!(5))]
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), hash_value(&value)))
+            .collect();
+
+        assert_eq!(
+            compare(&source, &target),
+            vec![
+                ("a".to_string(), Change::Removed),
+                ("c".to_string(), Change::Changed),
+                ("d".to_string(), Change::Added),
+            ]
+        );
+    }
+}
@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+
+use once_cell::sync::Lazy;
+
+use crate::cli::Cli;
+
+/// How settled a command or protocol field is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stability
+{
+    /// Supported and enabled for everyone.
+    Stable,
+    /// Still likely to change, only enabled when listed in `--experimental`.
+    Experimental,
+    /// Still supported but scheduled for removal. Holds what clients should use instead.
+    Deprecated(&'static str),
+}
+
+// Map of the commands and protocol fields that are not stable. Anything missing is stable.
+pub static FEATURES: Lazy<HashMap<&'static str, Stability>> = Lazy::new(|| {
+    let mut map = HashMap::new();
+    map.insert("SCAN", Stability::Experimental);
+    map.insert("SQL", Stability::Experimental);
+    map.insert(
+        "ttls",
+        Stability::Deprecated("set `expires_at` on each of the `values` instead"),
+    );
+    map
+});
+
+/// Returns the stability of a command or protocol field.
+pub fn stability(name: &str) -> Stability
+{
+    FEATURES.get(name).copied().unwrap_or(Stability::Stable)
+}
+
+/// Checks whether a command or protocol field can be used with the given configuration.
+///
+/// # Arguments
+///
+/// * `name` - The name of the command, in its normalized form, or of the protocol field.
+/// * `config` - The configuration the server was started with.
+///
+/// # Returns
+///
+/// An error message if the feature is experimental and was not enabled, otherwise a warning to pass on to the
+/// client if the feature is deprecated.
+pub fn check(name: &str, config: &Cli) -> Result<Option<String>, String>
+{
+    match stability(name) {
+        Stability::Stable => Ok(None),
+        Stability::Experimental => {
+            if config.experimental.iter().any(|enabled| enabled.eq_ignore_ascii_case(name)) {
+                Ok(None)
+            } else {
+                Err(format!(
+                    "Error: {} is experimental, start the server with `--experimental {}` to enable it.",
+                    name, name
+                ))
+            }
+        }
+        Stability::Deprecated(replacement) => Ok(Some(format!("{} is deprecated, {}.", name, replacement))),
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use clap::Parser;
+
+    use super::*;
+
+    #[test]
+    fn test_check()
+    {
+        let config = Cli::parse_from(["phoenix-db"]);
+
+        assert_eq!(check("LOOKUP", &config), Ok(None));
+        assert!(check("SCAN", &config).is_err());
+        assert!(check("ttls", &config).unwrap().is_some());
+
+        let config = Cli::parse_from(["phoenix-db", "--experimental", "scan"]);
+        assert_eq!(check("SCAN", &config), Ok(None));
+    }
+}
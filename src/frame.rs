@@ -0,0 +1,118 @@
+use std::io;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// The largest frame that can be sent, and the largest accepted unless the server is given `--max-message-size`.
+pub const MAX_FRAME_LEN: usize = 64 * 1024 * 1024;
+
+/// Reads the next frame of the native protocol: a big-endian `u32` length followed by that many bytes of JSON.
+///
+/// Frames longer than `max_len` bytes are refused before their payload is read, so a bogus length cannot exhaust
+/// memory.
+///
+/// # Returns
+///
+/// The payload of the frame, or `None` if the stream ended cleanly before a new frame.
+pub async fn read_frame(reader: &mut (impl AsyncRead + Unpin), max_len: usize) -> io::Result<Option<Vec<u8>>>
+{
+    let mut len = [0; 4];
+    match reader.read_exact(&mut len).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    let len = u32::from_be_bytes(len) as usize;
+    if len > max_len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("frame of {} bytes is larger than the limit of {} bytes", len, max_len),
+        ));
+    }
+
+    let mut payload = vec![0; len];
+    reader.read_exact(&mut payload).await?;
+    Ok(Some(payload))
+}
+
+/// Returns whether `buffer` starts with a whole frame, which can then be read without waiting for more data.
+pub fn has_frame(buffer: &[u8]) -> bool
+{
+    match buffer.first_chunk::<4>() {
+        Some(len) => buffer.len() - 4 >= u32::from_be_bytes(*len) as usize,
+        None => false,
+    }
+}
+
+/// Appends `payload` to `buffer` as a single frame of the native protocol, to be written along with other frames.
+pub fn push_frame(buffer: &mut Vec<u8>, payload: &[u8]) -> io::Result<()>
+{
+    if payload.len() > MAX_FRAME_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "frame of {} bytes is larger than the limit of {} bytes",
+                payload.len(),
+                MAX_FRAME_LEN
+            ),
+        ));
+    }
+
+    buffer.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    buffer.extend_from_slice(payload);
+    Ok(())
+}
+
+/// Writes `payload` as a single frame of the native protocol.
+pub async fn write_frame(writer: &mut (impl AsyncWrite + Unpin), payload: &[u8]) -> io::Result<()>
+{
+    let mut frame = Vec::with_capacity(payload.len() + 4);
+    push_frame(&mut frame, payload)?;
+    writer.write_all(&frame).await?;
+
+    // TLS streams can hold on to the end of a write until they are flushed
+    writer.flush().await
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[tokio::test]
+    async fn test_frames()
+    {
+        let mut buffer = vec![];
+        write_frame(&mut buffer, b"{\"name\":\"INFO\"}").await.unwrap();
+        write_frame(&mut buffer, &vec![b'x'; 5000]).await.unwrap();
+
+        // Coalesced frames are split apart, and frames larger than a single read are read whole
+        let mut reader = buffer.as_slice();
+        assert_eq!(
+            read_frame(&mut reader, MAX_FRAME_LEN).await.unwrap().unwrap(),
+            b"{\"name\":\"INFO\"}"
+        );
+        assert_eq!(read_frame(&mut reader, MAX_FRAME_LEN).await.unwrap().unwrap().len(), 5000);
+        assert!(read_frame(&mut reader, MAX_FRAME_LEN).await.unwrap().is_none());
+
+        // A frame cut short is an error rather than the end of the stream
+        let mut reader = &buffer[..25];
+        read_frame(&mut reader, MAX_FRAME_LEN).await.unwrap();
+        assert!(read_frame(&mut reader, MAX_FRAME_LEN).await.is_err());
+
+        assert!(has_frame(&buffer));
+        assert!(!has_frame(&buffer[19..25]));
+        assert!(!has_frame(&buffer[..3]));
+
+        let mut reader: &[u8] = &u32::MAX.to_be_bytes();
+        assert_eq!(
+            read_frame(&mut reader, MAX_FRAME_LEN).await.unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        // The limit can be lowered
+        let mut reader = buffer.as_slice();
+        read_frame(&mut reader, 100).await.unwrap();
+        assert!(read_frame(&mut reader, 100).await.is_err());
+    }
+}
@@ -0,0 +1,260 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+use crate::protocol::unix_millis;
+
+/// The primary lease as stored in the lease file shared by the nodes of an HA pair.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct LeaseRecord
+{
+    /// The `--node-id` of the node holding the lease.
+    holder: String,
+    /// Incremented every time the lease changes hands, so a former primary can tell it was replaced.
+    epoch: u64,
+    /// When the lease expires unless renewed, in milliseconds since the UNIX epoch.
+    expires_at: u64,
+}
+
+/// The lease held by this node.
+#[derive(Debug, Clone, Copy)]
+struct HeldLease
+{
+    /// The epoch the lease was acquired in.
+    epoch: u64,
+    /// Until when this node accepts writes, unless the lease is renewed.
+    valid_until: Instant,
+}
+
+/// Decides which node of an HA pair accepts writes, through a lease stored in a file both nodes can reach.
+///
+/// The node holding the lease is the primary and renews it periodically. The standby takes the lease over once
+/// it expires, incrementing its epoch. The primary stops accepting writes halfway through its lease, so it is
+/// fenced well before the standby may take over even if the clocks of the two nodes drift apart a little.
+///
+/// Disabled unless the server is started with `--lease-file`, in which case the node is always the primary.
+#[derive(Debug, Default)]
+pub struct Lease
+{
+    /// The lease file, if election is enabled.
+    path: Option<PathBuf>,
+    /// The id this node holds the lease under.
+    node_id: String,
+    /// How long the lease lasts unless renewed.
+    ttl: Duration,
+    /// The lease held by this node, if any.
+    held: Mutex<Option<HeldLease>>,
+}
+
+impl Lease
+{
+    /// Creates the lease of this node, enabling election if `path` is set.
+    pub fn new(path: Option<PathBuf>, node_id: String, ttl: Duration) -> Self
+    {
+        Self {
+            path,
+            node_id,
+            ttl,
+            held: Mutex::new(None),
+        }
+    }
+
+    /// Returns whether election is enabled.
+    pub fn is_enabled(&self) -> bool
+    {
+        self.path.is_some()
+    }
+
+    /// Returns how long the lease lasts unless renewed.
+    pub fn ttl(&self) -> Duration
+    {
+        self.ttl
+    }
+
+    /// Returns whether this node accepts writes: it holds a lease that has not lapsed, or election is disabled.
+    pub fn is_primary(&self) -> bool
+    {
+        if !self.is_enabled() {
+            return true;
+        }
+        self.held
+            .lock()
+            .unwrap()
+            .is_some_and(|held| Instant::now() < held.valid_until)
+    }
+
+    /// Returns the epoch of the lease held by this node, if it is the primary.
+    pub fn epoch(&self) -> Option<u64>
+    {
+        match *self.held.lock().unwrap() {
+            Some(held) if Instant::now() < held.valid_until => Some(held.epoch),
+            _ => None,
+        }
+    }
+
+    /// Acquires the lease if it is free or expired, or renews it if this node holds it.
+    ///
+    /// The lease file is read and written while holding an exclusive lock on the file next to it, so two nodes
+    /// finding the lease expired at once never both take it over. Blocks on file I/O. If the lease file cannot be
+    /// reached, the lease held by this node is left to lapse.
+    ///
+    /// # Returns
+    ///
+    /// Whether this node holds the lease.
+    pub fn renew(&self) -> io::Result<bool>
+    {
+        let Some(path) = &self.path else {
+            return Ok(true);
+        };
+
+        let _lock = FileLock::acquire(&path.with_extension("lock"))?;
+        let now = unix_millis();
+        let epoch = match read_record(path)? {
+            Some(record) if record.holder == self.node_id => record.epoch,
+            Some(record) if record.expires_at > now => {
+                *self.held.lock().unwrap() = None;
+                return Ok(false);
+            }
+            Some(record) => record.epoch + 1,
+            None => 1,
+        };
+
+        let started = Instant::now();
+        let record = LeaseRecord {
+            holder: self.node_id.clone(),
+            epoch,
+            expires_at: now + self.ttl.as_millis() as u64,
+        };
+        write_record(path, &record)?;
+
+        *self.held.lock().unwrap() = Some(HeldLease {
+            epoch,
+            valid_until: started + self.ttl / 2,
+        });
+        Ok(true)
+    }
+}
+
+/// An exclusive lock on a file, released when dropped.
+struct FileLock(File);
+
+impl FileLock
+{
+    /// Waits for the lock on the file at `path`, creating it if needed.
+    fn acquire(path: &Path) -> io::Result<Self>
+    {
+        let file = OpenOptions::new().create(true).truncate(false).write(true).open(path)?;
+        // SAFETY: the descriptor belongs to `file`, which stays open as long as the lock is held
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self(file))
+    }
+}
+
+impl Drop for FileLock
+{
+    fn drop(&mut self)
+    {
+        // SAFETY: the descriptor belongs to the file held by the lock, which is still open
+        unsafe { libc::flock(self.0.as_raw_fd(), libc::LOCK_UN) };
+    }
+}
+
+/// Reads the lease file, returning `None` if no node has taken the lease yet.
+fn read_record(path: &Path) -> io::Result<Option<LeaseRecord>>
+{
+    match fs::read(path) {
+        Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Replaces the lease file at once, so the other node never reads it half written.
+fn write_record(path: &Path, record: &LeaseRecord) -> io::Result<()>
+{
+    let temp_path = path.with_extension(format!("{}.tmp", std::process::id()));
+
+    let mut writer = BufWriter::new(File::create(&temp_path)?);
+    serde_json::to_writer(&mut writer, record)?;
+    writer.flush()?;
+    writer.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+
+    fs::rename(&temp_path, path)
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn test_lease_election()
+    {
+        let path = std::env::temp_dir().join(format!("phoenix-db-lease-{}.json", std::process::id()));
+        let ttl = Duration::from_millis(100);
+        let a = Lease::new(Some(path.clone()), "a".to_string(), ttl);
+        let b = Lease::new(Some(path.clone()), "b".to_string(), ttl);
+
+        assert!(a.renew().unwrap());
+        assert!(!b.renew().unwrap());
+        assert!(a.is_primary() && !b.is_primary());
+        assert_eq!(a.epoch(), Some(1));
+
+        // Once the primary stops renewing, it is fenced and the standby takes over in a new epoch
+        std::thread::sleep(ttl + Duration::from_millis(20));
+        assert!(!a.is_primary());
+        assert!(b.renew().unwrap());
+        assert_eq!(b.epoch(), Some(2));
+        assert!(!a.renew().unwrap());
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(path.with_extension("lock")).unwrap();
+        assert!(Lease::default().is_primary());
+    }
+
+    #[test]
+    fn test_only_one_node_takes_over_an_expired_lease()
+    {
+        let path = std::env::temp_dir().join(format!("phoenix-db-lease-race-{}.json", std::process::id()));
+        let ttl = Duration::from_secs(60);
+
+        for round in 0..20 {
+            let expired = LeaseRecord {
+                holder: "former".to_string(),
+                epoch: round,
+                expires_at: 0,
+            };
+            write_record(&path, &expired).unwrap();
+
+            let barrier = std::sync::Barrier::new(2);
+            let acquired: Vec<bool> = std::thread::scope(|scope| {
+                let nodes: Vec<_> = ["a", "b"]
+                    .into_iter()
+                    .map(|node_id| {
+                        let (path, barrier) = (&path, &barrier);
+                        scope.spawn(move || {
+                            let lease = Lease::new(Some(path.clone()), node_id.to_string(), ttl);
+                            barrier.wait();
+                            lease.renew().unwrap()
+                        })
+                    })
+                    .collect();
+                nodes.into_iter().map(|node| node.join().unwrap()).collect()
+            });
+
+            assert_eq!(acquired.iter().filter(|acquired| **acquired).count(), 1);
+            assert_eq!(read_record(&path).unwrap().unwrap().epoch, round + 1);
+        }
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(path.with_extension("lock")).unwrap();
+    }
+}
@@ -0,0 +1,88 @@
+use once_cell::sync::OnceCell;
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::level_filters::LevelFilter;
+use tracing::{error, info};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, Registry};
+
+/// The levels `SIGUSR1` cycles through, from the quietest to the most verbose.
+const LEVELS: [LevelFilter; 5] = [
+    LevelFilter::ERROR,
+    LevelFilter::WARN,
+    LevelFilter::INFO,
+    LevelFilter::DEBUG,
+    LevelFilter::TRACE,
+];
+
+/// Changes the level of the installed subscriber, set once logging is initialized.
+static RELOAD: OnceCell<reload::Handle<LevelFilter, Registry>> = OnceCell::new();
+
+/// Parses a log level name, such as `debug`, regardless of its case.
+pub fn parse_level(name: &str) -> Option<LevelFilter>
+{
+    LEVELS.into_iter().find(|level| level.to_string().eq_ignore_ascii_case(name))
+}
+
+/// Installs the global subscriber writing the logs, at `level` or `info` if it is not a valid level.
+pub fn init(level: &str)
+{
+    let (filter, handle) = reload::Layer::new(parse_level(level).unwrap_or(LevelFilter::INFO));
+    tracing_subscriber::registry().with(filter).with(fmt::layer()).init();
+    let _ = RELOAD.set(handle);
+}
+
+/// Returns the current log level.
+pub fn level() -> Option<LevelFilter>
+{
+    RELOAD.get().and_then(|handle| handle.clone_current())
+}
+
+/// Changes the log level while the server runs.
+pub fn set_level(level: LevelFilter) -> Result<(), String>
+{
+    let handle = RELOAD.get().ok_or_else(|| "Logging is not initialized.".to_string())?;
+    handle.reload(level).map_err(|e| e.to_string())
+}
+
+/// Moves the log level one step towards `trace` every time the process receives `SIGUSR1`, going back to `error`
+/// after `trace`.
+pub async fn cycle_on_signal()
+{
+    let mut signals = match signal(SignalKind::user_defined1()) {
+        Ok(signals) => signals,
+        Err(e) => {
+            error!("Failed to listen for SIGUSR1: {}", e);
+            return;
+        }
+    };
+
+    while signals.recv().await.is_some() {
+        let Some(current) = level() else {
+            continue;
+        };
+        let next = LEVELS
+            .iter()
+            .position(|level| *level == current)
+            .map_or(LevelFilter::INFO, |index| LEVELS[(index + 1) % LEVELS.len()]);
+        match set_level(next) {
+            Ok(()) => info!("Received SIGUSR1, log level is now {}", next),
+            Err(e) => error!("Failed to change the log level: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn test_parse_level()
+    {
+        assert_eq!(parse_level("debug"), Some(LevelFilter::DEBUG));
+        assert_eq!(parse_level("WARN"), Some(LevelFilter::WARN));
+        assert_eq!(parse_level("off"), None);
+        assert_eq!(parse_level("verbose"), None);
+    }
+}
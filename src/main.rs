@@ -1,21 +1,44 @@
+mod access;
 mod cli;
+mod client;
 mod commands;
+mod crypto;
+mod diagnostics;
+mod diff;
+mod features;
+mod frame;
+mod lease;
+mod logging;
+mod memory;
+mod metrics;
+mod prefix;
 mod protocol;
 
 mod services;
+mod store;
+mod upgrade;
+mod upstream;
+mod wal;
 
 mod server;
+mod snapshot;
+mod tls;
+
+#[cfg(test)]
+mod testing;
 
-use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 
 use clap::Parser;
 use protocol::DbEngine;
-use tokio::sync::RwLock;
-use tracing::Level;
-use tracing_subscriber::FmtSubscriber;
+use tracing::{error, info};
+
+use crate::cli::{Cli, Tool};
+use crate::crypto::Cipher;
 
-use crate::cli::Cli;
+/// How long the shutdown waits for a background save still running before it gives up on the final save.
+const BACKGROUND_SAVE_TIMEOUT: Duration = Duration::from_secs(300);
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>>
@@ -23,27 +46,75 @@ async fn main() -> Result<(), Box<dyn std::error::Error>>
     // Parse CLI arguments
     let args = Cli::parse();
 
-    // Convert log level string to `tracing::Level`
-    let log_level = match args.log_level.to_lowercase().as_str() {
-        "error" => Level::ERROR,
-        "warn" => Level::WARN,
-        "info" => Level::INFO,
-        "debug" => Level::DEBUG,
-        "trace" => Level::TRACE,
-        _ => Level::INFO, // Default to INFO if the input is invalid
+    // The level can be changed later with `CONFIG SET log-level` or `SIGUSR1`
+    logging::init(&args.log_level);
+
+    let cipher = match Cipher::load(&args) {
+        Ok(cipher) => cipher.map(Arc::new),
+        Err(e) => {
+            error!("Failed to load the encryption key: {}", e);
+            return Err(e.into());
+        }
     };
 
-    let subscriber = FmtSubscriber::builder().with_max_level(log_level).finish();
+    if let Some(Tool::Diff { source, target, prefix }) = &args.tool {
+        let identical = diff::execute(source, target, prefix.as_deref(), cipher).await?;
+        std::process::exit(if identical { 0 } else { 1 });
+    }
+
+    if let Err(e) = upgrade::upgrade_files(&args) {
+        error!("Failed to upgrade the files of an older version: {}", e);
+        return Err(e.into());
+    }
+
+    let mut engine = DbEngine::new(args.clone());
+    engine.cipher = cipher;
 
-    tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
+    match snapshot::restore(&engine, &args.snapshot_path).await {
+        Ok(Some(entries)) => info!("Loaded {} entries from {}", entries, args.snapshot_path.display()),
+        Ok(None) => {}
+        Err(e) => {
+            error!("Failed to load snapshot {}: {}", args.snapshot_path.display(), e);
+            return Err(e.into());
+        }
+    }
 
-    let engine = Arc::new(DbEngine {
-        connection: Arc::new(RwLock::new(HashMap::new())),
-        db_config: args.clone(),
-    });
+    if let Some(wal_path) = &args.wal_path {
+        match wal::recover(&mut engine, wal_path).await {
+            Ok(records) => info!("Replayed {} records from {}", records, wal_path.display()),
+            Err(e) => {
+                error!("Failed to recover from {}: {}", wal_path.display(), e);
+                return Err(e.into());
+            }
+        }
+    }
+
+    let engine = Arc::new(engine);
+    tokio::spawn(logging::cycle_on_signal());
 
     services::execute(engine.clone()).await?;
     server::execute(&args, &engine).await?;
 
+    // Let the background services finish what they are doing, so none of them runs during the final save
+    engine.services.shutdown().await;
+
+    // Save a final snapshot, loaded back on the next start, once the background saves still running are done
+    let background_save = async {
+        while engine.snapshots.in_progress() {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    };
+    if tokio::time::timeout(BACKGROUND_SAVE_TIMEOUT, background_save).await.is_err() {
+        error!(
+            "Failed to save snapshot, the background save is still running after {} seconds",
+            BACKGROUND_SAVE_TIMEOUT.as_secs()
+        );
+        return Ok(());
+    }
+    // The outcome is logged by `save`
+    if commands::save::save(&engine).await.is_none() {
+        error!("Failed to save snapshot, another save is still running");
+    }
+
     Ok(())
 }
@@ -0,0 +1,131 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// Rejects writes while the database uses more memory than `--max-memory`, rather than evicting entries.
+///
+/// Writes are rejected once the estimated memory use reaches the limit, and accepted again only once it falls
+/// below `--memory-resume-percent` of the limit, so a database hovering around the limit does not flip between
+/// the two on every check. Reads and the commands freeing memory, such as deletes, are always accepted.
+///
+/// Disabled unless the server is started with `--max-memory`.
+#[derive(Debug, Default)]
+pub struct MemoryGuard
+{
+    /// The memory use in bytes at which writes are rejected, if any.
+    limit: Option<usize>,
+    /// The memory use in bytes below which writes are accepted again.
+    resume_below: usize,
+    /// The memory use in bytes found by the last check.
+    used: AtomicUsize,
+    /// Whether writes are currently rejected.
+    rejecting: AtomicBool,
+}
+
+impl MemoryGuard
+{
+    /// Creates the guard of a database limited to `limit` bytes, resuming writes below `resume_percent` of it.
+    pub fn new(limit: Option<usize>, resume_percent: u8) -> Self
+    {
+        let resume_below = limit.map_or(0, |limit| limit / 100 * resume_percent.min(100) as usize);
+        Self {
+            limit,
+            resume_below,
+            used: AtomicUsize::new(0),
+            rejecting: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns whether memory use is limited.
+    pub fn is_enabled(&self) -> bool
+    {
+        self.limit.is_some()
+    }
+
+    /// Returns the memory use in bytes at which writes are rejected, if any.
+    pub fn limit(&self) -> Option<usize>
+    {
+        self.limit
+    }
+
+    /// Returns the memory use in bytes below which writes are accepted again.
+    pub fn resume_below(&self) -> usize
+    {
+        self.resume_below
+    }
+
+    /// Returns the memory use in bytes found by the last check.
+    pub fn used(&self) -> usize
+    {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    /// Returns whether writes are currently rejected.
+    pub fn is_rejecting(&self) -> bool
+    {
+        self.rejecting.load(Ordering::Relaxed)
+    }
+
+    /// Records the current memory use, starting or stopping to reject writes as it crosses the thresholds.
+    ///
+    /// # Returns
+    ///
+    /// Whether writes started or stopped being rejected.
+    pub fn update(&self, used: usize) -> bool
+    {
+        self.used.store(used, Ordering::Relaxed);
+        let Some(limit) = self.limit else {
+            return false;
+        };
+
+        let rejecting = self.is_rejecting();
+        let reject = if rejecting { used >= self.resume_below } else { used >= limit };
+        self.rejecting.store(reject, Ordering::Relaxed);
+        reject != rejecting
+    }
+}
+
+/// Returns whether a write command can only free memory, so it is accepted even while writes are rejected.
+pub fn frees_memory(command_name: &str) -> bool
+{
+    matches!(
+        command_name,
+        "DELETE"
+            | "DELETE *"
+            | "DELETE BYTAG"
+            | "DELRANGE"
+            | "INVALIDATE"
+            | "TOUCH"
+            | "PUT ABORT"
+            | "TEMPLATE DELETE"
+            | "TRANSFORM DELETE"
+            | "DERIVE DELETE"
+            | "REFERENCE DELETE"
+            | "XACK"
+            | "SNAPSHOT UNMOUNT"
+    )
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn test_hysteresis()
+    {
+        let guard = MemoryGuard::new(Some(1000), 90);
+        assert!(!guard.update(999));
+        assert!(!guard.is_rejecting());
+
+        // Writes are rejected at the limit, and stay rejected until memory use falls below the resume threshold
+        assert!(guard.update(1000));
+        assert!(guard.is_rejecting());
+        assert!(!guard.update(950));
+        assert!(guard.is_rejecting());
+        assert!(guard.update(899));
+        assert!(!guard.is_rejecting());
+
+        let disabled = MemoryGuard::new(None, 90);
+        assert!(!disabled.update(usize::MAX));
+        assert!(!disabled.is_rejecting());
+    }
+}
@@ -0,0 +1,225 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// The number of shards each counter is split into.
+const COUNTER_SHARDS: usize = 16;
+
+/// Hands out shard indexes to threads in a round-robin fashion.
+static NEXT_SHARD: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// The shard used by counters incremented from the current thread.
+    static THREAD_SHARD: usize = NEXT_SHARD.fetch_add(1, Ordering::Relaxed) % COUNTER_SHARDS;
+}
+
+/// A single counter slot, padded to its own cache line so neighbouring shards don't contend.
+#[derive(Debug, Default)]
+#[repr(align(64))]
+struct PaddedCounter(AtomicU64);
+
+/// A counter split across several atomics to avoid contention on hot paths.
+///
+/// Each thread increments its own shard with relaxed ordering and the shards are only summed up when the
+/// counter is read, which makes reads slightly more expensive and only eventually consistent.
+#[derive(Debug, Default)]
+pub struct ShardedCounter
+{
+    shards: [PaddedCounter; COUNTER_SHARDS],
+}
+
+impl ShardedCounter
+{
+    /// Adds `n` to the counter.
+    pub fn add(&self, n: u64)
+    {
+        THREAD_SHARD.with(|shard| self.shards[*shard].0.fetch_add(n, Ordering::Relaxed));
+    }
+
+    /// Adds one to the counter.
+    pub fn increment(&self)
+    {
+        self.add(1);
+    }
+
+    /// Returns the current value of the counter.
+    pub fn get(&self) -> u64
+    {
+        self.shards.iter().map(|shard| shard.0.load(Ordering::Relaxed)).sum()
+    }
+}
+
+/// A value going up and down, such as a number of open connections.
+#[derive(Debug, Default)]
+pub struct Gauge(AtomicU64);
+
+impl Gauge
+{
+    /// Adds one to the gauge.
+    pub fn increment(&self)
+    {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Removes one from the gauge.
+    pub fn decrement(&self)
+    {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Returns the current value of the gauge.
+    pub fn get(&self) -> u64
+    {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// The upper bounds in microseconds of the buckets of a `LatencyHistogram`, with their label.
+const LATENCY_BUCKETS: [(u64, &str); 7] = [
+    (1, "<1us"),
+    (10, "<10us"),
+    (100, "<100us"),
+    (1_000, "<1ms"),
+    (10_000, "<10ms"),
+    (100_000, "<100ms"),
+    (1_000_000, "<1s"),
+];
+
+/// A histogram of durations, such as the time spent waiting for a lock, in buckets growing tenfold.
+#[derive(Debug, Default)]
+pub struct LatencyHistogram
+{
+    /// The number of durations recorded in each bucket, the last one holding those of a second or more.
+    buckets: [AtomicU64; LATENCY_BUCKETS.len() + 1],
+    /// The sum of every duration recorded, in microseconds.
+    total_us: AtomicU64,
+}
+
+impl LatencyHistogram
+{
+    /// Records a duration.
+    pub fn record(&self, duration: Duration)
+    {
+        let micros = duration.as_micros() as u64;
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|(bound, _)| micros < *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.total_us.fetch_add(micros, Ordering::Relaxed);
+    }
+
+    /// Returns the number of durations recorded.
+    pub fn count(&self) -> u64
+    {
+        self.buckets.iter().map(|bucket| bucket.load(Ordering::Relaxed)).sum()
+    }
+
+    /// Returns the sum of every duration recorded.
+    pub fn total(&self) -> Duration
+    {
+        Duration::from_micros(self.total_us.load(Ordering::Relaxed))
+    }
+
+    /// Returns the number of durations recorded in each bucket, along with its label.
+    pub fn buckets(&self) -> Vec<(&'static str, u64)>
+    {
+        LATENCY_BUCKETS
+            .iter()
+            .map(|(_, label)| *label)
+            .chain([">=1s"])
+            .zip(self.buckets.iter().map(|bucket| bucket.load(Ordering::Relaxed)))
+            .collect()
+    }
+}
+
+/// Server-wide counters used for diagnostics.
+#[derive(Debug, Default)]
+pub struct Metrics
+{
+    /// Number of times accepting a new client connection failed.
+    pub accept_failures: ShardedCounter,
+    /// Number of commands processed.
+    pub commands_processed: ShardedCounter,
+    /// Number of keys found by lookups.
+    pub keyspace_hits: ShardedCounter,
+    /// Number of keys missing during lookups.
+    pub keyspace_misses: ShardedCounter,
+    /// Number of read commands processed.
+    pub read_commands: ShardedCounter,
+    /// Number of write commands processed.
+    pub write_commands: ShardedCounter,
+    /// Number of bulk commands processed.
+    pub bulk_commands: ShardedCounter,
+    /// Number of admin commands processed.
+    pub admin_commands: ShardedCounter,
+    /// Number of bytes received from clients.
+    pub bytes_read: ShardedCounter,
+    /// Number of bytes sent to clients.
+    pub bytes_written: ShardedCounter,
+    /// Number of read commands that timed out.
+    pub read_timeouts: ShardedCounter,
+    /// Number of write commands that timed out.
+    pub write_timeouts: ShardedCounter,
+    /// Number of bulk commands that timed out.
+    pub bulk_timeouts: ShardedCounter,
+    /// Number of writes rejected because memory use was past `--max-memory`.
+    pub memory_rejected_writes: ShardedCounter,
+    /// Number of open connections encoding their messages as JSON.
+    pub json_connections: Gauge,
+    /// Number of open connections encoding their messages as MessagePack.
+    pub message_pack_connections: Gauge,
+    /// Number of open connections compressed with zstd, whatever their encoding.
+    pub compressed_connections: Gauge,
+    /// Number of connections that asked for compression while it was turned off.
+    pub compression_refused: ShardedCounter,
+}
+
+#[cfg(test)]
+mod test
+{
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn test_sharded_counter_across_threads()
+    {
+        let counter = Arc::new(ShardedCounter::default());
+
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let counter = counter.clone();
+                thread::spawn(move || {
+                    for _ in 0..1000 {
+                        counter.increment();
+                    }
+                    counter.add(10);
+                })
+            })
+            .collect();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(counter.get(), 8 * 1010);
+    }
+
+    #[test]
+    fn test_latency_histogram()
+    {
+        let histogram = LatencyHistogram::default();
+        histogram.record(Duration::from_nanos(500));
+        histogram.record(Duration::from_micros(50));
+        histogram.record(Duration::from_micros(70));
+        histogram.record(Duration::from_secs(3));
+
+        let buckets = histogram.buckets();
+        assert_eq!(buckets[0], ("<1us", 1));
+        assert_eq!(buckets[2], ("<100us", 2));
+        assert_eq!(buckets[7], (">=1s", 1));
+        assert_eq!(histogram.count(), 4);
+        assert_eq!(histogram.total(), Duration::from_micros(3_000_120));
+    }
+}
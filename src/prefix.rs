@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+
+/// The character separating the segments of a key, as in `user:42:profile`.
+pub const PREFIX_SEPARATOR: char = ':';
+
+/// The deepest level of prefixes the tree keeps track of.
+const MAX_PREFIX_DEPTH: usize = 4;
+
+/// The most children a prefix can have. Keys under further children are only counted in the parent.
+const MAX_PREFIX_CHILDREN: usize = 1024;
+
+/// Key counts and sizes aggregated by key prefix, kept up to date as entries are written.
+///
+/// A key is counted in the node of every prefix made of its leading segments, except the last segment which
+/// identifies the key itself. The tree is bounded in depth and width to keep its memory use in check, which
+/// makes it approximate for keyspaces with many distinct prefixes: keys that do not fit are counted in the
+/// closest prefix that does.
+#[derive(Debug, Default)]
+pub struct PrefixTree
+{
+    root: PrefixNode,
+}
+
+/// The totals of every key sharing a prefix.
+#[derive(Debug, Default)]
+struct PrefixNode
+{
+    /// The number of keys under this prefix.
+    keys: u64,
+    /// The combined size in bytes of the keys under this prefix and their values.
+    bytes: u64,
+    /// The longer prefixes, keyed by their last segment.
+    children: HashMap<String, PrefixNode>,
+}
+
+/// The totals of one prefix, as reported by `STATS PREFIX`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrefixStat
+{
+    pub prefix: String,
+    pub keys: u64,
+    pub bytes: u64,
+}
+
+impl PrefixTree
+{
+    /// Counts a new entry of `bytes` bytes.
+    pub fn add(&mut self, key: &str, bytes: u64)
+    {
+        let mut node = &mut self.root;
+        node.keys += 1;
+        node.bytes += bytes;
+
+        for segment in prefixes(key) {
+            if !node.children.contains_key(segment) && node.children.len() >= MAX_PREFIX_CHILDREN {
+                break;
+            }
+
+            node = node.children.entry(segment.to_string()).or_default();
+            node.keys += 1;
+            node.bytes += bytes;
+        }
+    }
+
+    /// Stops counting an entry previously added with the same size.
+    pub fn remove(&mut self, key: &str, bytes: u64)
+    {
+        fn remove_from(node: &mut PrefixNode, mut segments: std::slice::Iter<&str>, bytes: u64)
+        {
+            node.keys = node.keys.saturating_sub(1);
+            node.bytes = node.bytes.saturating_sub(bytes);
+
+            if let Some(segment) = segments.next() {
+                if let Some(child) = node.children.get_mut(*segment) {
+                    remove_from(child, segments, bytes);
+                    if child.keys == 0 {
+                        node.children.remove(*segment);
+                    }
+                }
+            }
+        }
+
+        let segments: Vec<&str> = prefixes(key).collect();
+        remove_from(&mut self.root, segments.iter(), bytes);
+    }
+
+    /// Returns the totals of every prefix up to `depth` segments long, largest first.
+    pub fn stats(&self, depth: usize) -> Vec<PrefixStat>
+    {
+        fn collect(node: &PrefixNode, prefix: &str, depth: usize, stats: &mut Vec<PrefixStat>)
+        {
+            if depth == 0 {
+                return;
+            }
+
+            for (segment, child) in &node.children {
+                let prefix = if prefix.is_empty() {
+                    segment.clone()
+                } else {
+                    format!("{}{}{}", prefix, PREFIX_SEPARATOR, segment)
+                };
+
+                stats.push(PrefixStat {
+                    prefix: prefix.clone(),
+                    keys: child.keys,
+                    bytes: child.bytes,
+                });
+                collect(child, &prefix, depth - 1, stats);
+            }
+        }
+
+        let mut stats = vec![];
+        collect(&self.root, "", depth, &mut stats);
+        stats.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.prefix.cmp(&b.prefix)));
+        stats
+    }
+}
+
+/// Returns the segments of a key that make up its prefixes, leaving out the last one.
+fn prefixes(key: &str) -> impl Iterator<Item = &str>
+{
+    let segments = key.split(PREFIX_SEPARATOR).count() - 1;
+    key.split(PREFIX_SEPARATOR).take(segments.min(MAX_PREFIX_DEPTH))
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn test_prefix_stats()
+    {
+        let mut tree = PrefixTree::default();
+        tree.add("user:1:profile", 10);
+        tree.add("user:2:profile", 20);
+        tree.add("session:1", 5);
+        tree.add("counter", 1);
+
+        assert_eq!(
+            tree.stats(1),
+            vec![
+                PrefixStat {
+                    prefix: "user".to_string(),
+                    keys: 2,
+                    bytes: 30
+                },
+                PrefixStat {
+                    prefix: "session".to_string(),
+                    keys: 1,
+                    bytes: 5
+                },
+            ]
+        );
+        assert_eq!(tree.stats(2).len(), 4);
+    }
+
+    #[test]
+    fn test_removing_prunes_empty_prefixes()
+    {
+        let mut tree = PrefixTree::default();
+        tree.add("user:1:profile", 10);
+        tree.add("user:2:profile", 20);
+
+        tree.remove("user:1:profile", 10);
+        assert_eq!(tree.stats(2)[0].bytes, 20);
+        assert_eq!(tree.stats(2).len(), 2);
+
+        tree.remove("user:2:profile", 20);
+        assert!(tree.stats(2).is_empty());
+    }
+}
@@ -1,14 +1,36 @@
-use std::collections::HashMap;
 use std::fmt::Debug;
+use std::io::{self, Write};
+use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use tokio::sync::RwLock;
-use tokio::time::Instant;
+use tokio::sync::{watch, RwLock};
 
+use crate::access::AccessTracker;
 use crate::cli::Cli;
+use crate::commands::backup::Backups;
+use crate::commands::derived::DerivedKeys;
+use crate::commands::load::Loads;
+use crate::commands::mount::Mounts;
+use crate::commands::reference::References;
+use crate::commands::save::Snapshots;
+use crate::commands::stream::Streams;
+use crate::commands::tags::TagIndex;
+use crate::commands::template::Templates;
+use crate::commands::transform::Transforms;
+use crate::commands::upload::Uploads;
+use crate::crypto::Cipher;
+use crate::diagnostics::Diagnostics;
+use crate::lease::Lease;
+use crate::memory::MemoryGuard;
+use crate::metrics::Metrics;
+use crate::services::manager::ServiceManager;
+use crate::services::telemetry::Telemetry;
+use crate::store::Store;
+use crate::upstream::Upstream;
+use crate::wal::Wal;
 
 /// Represents the database engine, managing the connection and metadata.
 #[derive(Debug)]
@@ -19,9 +41,100 @@ pub struct DbEngine
     /// The database configuration created on start up.
     #[allow(dead_code)]
     pub db_config: Cli,
+    /// Counters describing the health of the server.
+    pub metrics: Metrics,
+    /// Important server events, pushed to the clients subscribed with `DIAGNOSTICS SUBSCRIBE`.
+    pub diagnostics: Diagnostics,
+    /// Chunked uploads that are still being received.
+    pub uploads: Uploads,
+    /// Bulk loads announced with `PREPARE LOAD`, holding memory for their entries.
+    pub loads: Loads,
+    /// Named documents used as the base of `INSERT FROM TEMPLATE`.
+    pub templates: Templates,
+    /// The transforms applied to values before they are stored, keyed by key prefix.
+    pub transforms: Transforms,
+    /// Keys whose value is computed from other keys, recomputed when those are written.
+    pub derived: DerivedKeys,
+    /// The references to other keys checked when values are inserted, keyed by key prefix.
+    pub references: References,
+    /// The tags attached to entries, used by the `BYTAG` commands.
+    pub tags: RwLock<TagIndex>,
+    /// Approximate recency of reads, used to find hot keys.
+    pub access: AccessTracker,
+    /// Append-only event streams written with `XADD`.
+    pub streams: Streams,
+    /// Snapshots saved in the background with `BGSAVE`.
+    pub snapshots: Snapshots,
+    /// The incremental backups taken with `BACKUP INCREMENTAL`.
+    pub backups: Backups,
+    /// Older snapshots mounted read-only, whose keys are read under `@<name>:`.
+    pub mounts: Mounts,
+    /// The server reads fall through to and writes are mirrored to, if any.
+    pub upstream: Option<Upstream>,
+    /// The write-ahead log recording inserts and deletes, disabled unless `--wal-path` is given.
+    pub wal: Wal,
+    /// Encrypts snapshots and the write-ahead log, unless no encryption key is configured.
+    pub cipher: Option<Arc<Cipher>>,
+    /// The primary lease deciding whether this node accepts writes, always held unless `--lease-file` is given.
+    pub lease: Lease,
+    /// Rejects writes while the database uses too much memory, disabled unless `--max-memory` is given.
+    pub memory: MemoryGuard,
+    /// Anonymous usage statistics, only reported when enabled.
+    pub telemetry: Telemetry,
+    /// The background services, such as the TTL sweep, listed and controlled with `SERVICES`.
+    pub services: ServiceManager,
+    /// Set once the server shuts down, telling open connections to close after their commands in progress.
+    pub shutdown: watch::Sender<bool>,
+    /// Whether new connections may ask for compression, turned off with `CONFIG SET` when it costs too much CPU.
+    pub compression: AtomicBool,
 }
-/// Type alias for the database, using an `Arc<RwLock<HashMap<DbKey, DbValue>>>` to provide concurrent read/write access.
-pub type Database = Arc<RwLock<HashMap<DbKey, DbValue>>>;
+impl DbEngine
+{
+    /// Creates an engine with an empty database for the given configuration.
+    pub fn new(db_config: Cli) -> Self
+    {
+        let upstream = db_config.upstream.clone().map(Upstream::new);
+        let telemetry = Telemetry::new(&db_config);
+        let lease = Lease::new(
+            db_config.lease_file.clone(),
+            db_config.node_id.clone().unwrap_or_default(),
+            Duration::from_secs(db_config.lease_ttl.max(1)),
+        );
+
+        let memory = MemoryGuard::new(db_config.max_memory, db_config.memory_resume_percent);
+
+        Self {
+            connection: Arc::new(Store::new(db_config.ordered_keys, db_config.incremental_backups)),
+            db_config,
+            metrics: Metrics::default(),
+            diagnostics: Diagnostics::default(),
+            uploads: Uploads::default(),
+            loads: Loads::default(),
+            templates: Templates::default(),
+            transforms: Transforms::default(),
+            derived: DerivedKeys::default(),
+            references: References::default(),
+            tags: RwLock::new(TagIndex::default()),
+            access: AccessTracker::default(),
+            streams: Streams::default(),
+            snapshots: Snapshots::default(),
+            backups: Backups::default(),
+            mounts: Mounts::default(),
+            upstream,
+            wal: Wal::default(),
+            cipher: None,
+            lease,
+            memory,
+            telemetry,
+            services: ServiceManager::default(),
+            shutdown: watch::Sender::new(false),
+            compression: AtomicBool::new(true),
+        }
+    }
+}
+
+/// Type alias for the database, using an `Arc<Store>` so readers work on snapshots while writers are serialized.
+pub type Database = Arc<Store>;
 
 /// Type alias for the keys in the database, represented as strings.
 pub type DbKey = String;
@@ -31,20 +144,88 @@ pub type JsonValue = Value;
 
 /// A value stored in the database
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(from = "DbValueFields")]
 pub struct DbValue
 {
     /// Any data type that supports json
     pub value: JsonValue,
-    /// When this data expires. If none, the data will need manual deletion.
-    pub expires_in: Option<Duration>,
+    /// When this data expires, in milliseconds since the UNIX epoch. If none, the data will need manual deletion.
+    pub expires_at: Option<u64>,
+}
+
+/// The fields a `DbValue` is read from, which also accept the TTL relative to now that values used to carry.
+#[derive(Deserialize)]
+struct DbValueFields
+{
+    value: JsonValue,
+    #[serde(default)]
+    expires_at: Option<u64>,
+    #[serde(default)]
+    expires_in: Option<Duration>,
+}
+
+impl From<DbValueFields> for DbValue
+{
+    fn from(fields: DbValueFields) -> Self
+    {
+        Self {
+            value: fields.value,
+            expires_at: fields.expires_at.or(fields.expires_in.map(expiry_after)),
+        }
+    }
 }
 
 impl DbValue
 {
-    /// Serde cant deserialize Instants, so we use this to convert the duration to instant at runtime.
-    pub fn expires_at(&self) -> Option<Instant>
+    /// Returns whether the value has expired by `now`, in milliseconds since the UNIX epoch.
+    pub fn is_expired(&self, now: u64) -> bool
+    {
+        self.expires_at.is_some_and(|expires_at| now >= expires_at)
+    }
+
+    /// Returns how long is left until the value expires, or `None` if it never does.
+    pub fn ttl(&self) -> Option<Duration>
+    {
+        self.expires_at
+            .map(|expires_at| Duration::from_millis(expires_at.saturating_sub(unix_millis())))
+    }
+}
+
+/// Returns the current time in milliseconds since the UNIX epoch.
+pub fn unix_millis() -> u64
+{
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+/// Returns when a TTL starting now runs out, in milliseconds since the UNIX epoch.
+pub fn expiry_after(ttl: Duration) -> u64
+{
+    unix_millis().saturating_add(ttl.as_millis() as u64)
+}
+
+/// Returns the size of a value once serialized, without allocating the serialized form.
+pub fn json_size(value: &JsonValue) -> usize
+{
+    let mut counter = ByteCounter(0);
+    // Writing to a counter never fails and values are always serializable
+    let _ = serde_json::to_writer(&mut counter, value);
+    counter.0
+}
+
+/// A writer that discards its input and only counts the bytes written to it.
+struct ByteCounter(usize);
+
+impl Write for ByteCounter
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize>
+    {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()>
     {
-        self.expires_in.map(|duration| Instant::now() + duration)
+        Ok(())
     }
 }
 
@@ -58,12 +239,45 @@ pub struct NetCommand<'a>
     pub keys: Option<Vec<&'a str>>,
     /// Optional list of values associated with the command.
     pub values: Option<Vec<DbValue>>,
-    /// Optional list of data explorations
+    /// Optional list of data explorations. Deprecated for writes, where each value carries its own `expires_at`.
     pub ttls: Option<Vec<Duration>>,
+    /// Optional list of extra arguments, such as the offsets used by `GETRANGE`.
+    pub args: Option<Vec<JsonValue>>,
+    /// Optional list of tags attached to the entries written by `INSERT` and `INSERT *`.
+    pub tags: Option<Vec<&'a str>>,
+    /// Optional list of commands run in order by `BATCH`, each getting its own response.
+    #[serde(default, borrow, skip_serializing_if = "Option::is_none")]
+    pub batch: Option<Vec<NetCommand<'a>>>,
+    /// Optional durability a write waits for before it is acknowledged, `memory` if not given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub durability: Option<WriteDurability>,
+    /// Optional list of the fields `LOOKUP`, `LOOKUP *` and `SCAN` return of each document, all of them if not given.
+    #[serde(default, borrow, skip_serializing_if = "Option::is_none")]
+    pub fields: Option<Vec<&'a str>>,
+    /// Optional number of items `INSERT *` and `DELETE *` process between two progress responses, none if not given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub progress: Option<usize>,
+    /// Optional identifier echoed back in the response. Commands with an `id` run concurrently with the following
+    /// ones and their responses may come back out of order.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+}
+
+/// How durable a write is once acknowledged.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum WriteDurability
+{
+    /// Applied in memory only, lost if the server crashes.
+    Memory,
+    /// Recorded to the write-ahead log, lost only if the machine crashes before the log is synced.
+    Wal,
+    /// Recorded to the write-ahead log and synced to disk.
+    Fsync,
 }
 
 /// Represents the response sent back to a client after processing a command.
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Default)]
 pub struct NetResponse
 {
     /// The action performed, indicating whether the command was successful or if there was an error.
@@ -72,14 +286,212 @@ pub struct NetResponse
     pub value: Option<JsonValue>,
     /// Optional error message, if an error occurred during command processing.
     pub error: Option<String>,
+    /// Set when the result was cut short because it exceeded the maximum response size.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+    /// Where to resume from to fetch the rest of a partial result, if there is more to fetch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<usize>,
+    /// Warnings about deprecated commands or fields used by the request.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+    /// The responses to the commands of a `BATCH`, in the order they were sent.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub responses: Vec<NetResponse>,
+    /// How durable a successful write is, which may exceed the durability it asked for.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub durability: Option<WriteDurability>,
+    /// The `id` of the command the response is for, if it had one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
 }
 
 /// Enum representing possible network actions in response to commands.
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Default)]
 pub enum NetActions
 {
     /// Indicates that a command was processed successfully.
+    #[default]
     Command,
     /// Indicates that an error occurred while processing a command.
     Error,
+    /// Indicates a server event pushed to a client subscribed with `DIAGNOSTICS SUBSCRIBE`, or a batch of writes
+    /// pushed to a replica following the server with `SYNC`.
+    Event,
+    /// Indicates one part of a streamed response, such as `BACKUP STREAM`. More parts may follow, and the stream
+    /// ends with a `Command` or `Error` response.
+    Chunk,
+    /// Indicates the progress of a bulk command, sent ahead of its final response when the command asked for it.
+    Progress,
+    /// Indicates the server is shutting down and closes the connection. Sent instead of waiting for the next
+    /// command once the commands already received are done.
+    ShuttingDown,
+}
+
+/// Encodes the commands and responses of a connection. Clients choose an encoding by sending its handshake byte
+/// before their first command, and connections that skip the handshake use JSON.
+pub trait WireCodec: Send + Sync
+{
+    /// The byte a client sends when it connects to choose this encoding. It is never the first byte of a frame,
+    /// whose length of at most 64 MiB starts with a byte below 4.
+    fn handshake(&self) -> u8;
+
+    /// Decodes a command received from the client.
+    fn decode<'a>(&self, bytes: &'a [u8]) -> Result<NetCommand<'a>, String>;
+
+    /// Encodes a response sent to the client.
+    fn encode(&self, response: &NetResponse) -> Result<Vec<u8>, String>;
+
+    /// Restores a frame received from the client before it is decoded, refusing it if it would grow past `max_len`
+    /// bytes. Frames are left as they are unless the connection is compressed.
+    fn unpack(&self, frame: Vec<u8>, _max_len: usize) -> Result<Vec<u8>, String>
+    {
+        Ok(frame)
+    }
+}
+
+/// Encodes messages as JSON, the default.
+pub struct JsonCodec;
+
+impl WireCodec for JsonCodec
+{
+    fn handshake(&self) -> u8
+    {
+        b'J'
+    }
+
+    fn decode<'a>(&self, bytes: &'a [u8]) -> Result<NetCommand<'a>, String>
+    {
+        serde_json::from_slice(bytes).map_err(|e| e.to_string())
+    }
+
+    fn encode(&self, response: &NetResponse) -> Result<Vec<u8>, String>
+    {
+        serde_json::to_vec(response).map_err(|e| e.to_string())
+    }
+}
+
+/// Encodes messages as MessagePack, which is more compact than JSON. Structs are encoded as maps keyed by their
+/// field names, as in JSON.
+pub struct MessagePackCodec;
+
+impl WireCodec for MessagePackCodec
+{
+    fn handshake(&self) -> u8
+    {
+        b'M'
+    }
+
+    fn decode<'a>(&self, bytes: &'a [u8]) -> Result<NetCommand<'a>, String>
+    {
+        rmp_serde::from_slice(bytes).map_err(|e| e.to_string())
+    }
+
+    fn encode(&self, response: &NetResponse) -> Result<Vec<u8>, String>
+    {
+        rmp_serde::to_vec_named(response).map_err(|e| e.to_string())
+    }
+}
+
+/// The byte a client sends when it connects, before the handshake of its encoding if any, to compress every frame
+/// of the connection with zstd. The server replies with the same byte to accept.
+pub const COMPRESSION_HANDSHAKE: u8 = b'Z';
+
+/// The byte the server replies to [`COMPRESSION_HANDSHAKE`] with while compression is turned off. The connection
+/// then goes on uncompressed.
+pub const COMPRESSION_REFUSED: u8 = b'N';
+
+/// Compresses the frames of another encoding with zstd, in both directions.
+pub struct ZstdCodec(&'static dyn WireCodec);
+
+impl WireCodec for ZstdCodec
+{
+    fn handshake(&self) -> u8
+    {
+        COMPRESSION_HANDSHAKE
+    }
+
+    fn decode<'a>(&self, bytes: &'a [u8]) -> Result<NetCommand<'a>, String>
+    {
+        self.0.decode(bytes)
+    }
+
+    fn encode(&self, response: &NetResponse) -> Result<Vec<u8>, String>
+    {
+        let encoded = self.0.encode(response)?;
+        zstd::bulk::compress(&encoded, 0).map_err(|e| e.to_string())
+    }
+
+    fn unpack(&self, frame: Vec<u8>, max_len: usize) -> Result<Vec<u8>, String>
+    {
+        zstd::bulk::decompress(&frame, max_len).map_err(|e| format!("Failed to decompress frame: {}", e))
+    }
+}
+
+const ZSTD_JSON: ZstdCodec = ZstdCodec(&JsonCodec);
+const ZSTD_MESSAGE_PACK: ZstdCodec = ZstdCodec(&MessagePackCodec);
+
+/// Returns the compressed version of an encoding.
+pub fn compressed(codec: &'static dyn WireCodec) -> &'static dyn WireCodec
+{
+    match codec.handshake() {
+        b'M' => &ZSTD_MESSAGE_PACK,
+        _ => &ZSTD_JSON,
+    }
+}
+
+/// The encodings clients can choose from.
+pub const WIRE_CODECS: [&dyn WireCodec; 2] = [&JsonCodec, &MessagePackCodec];
+
+/// Returns the encoding chosen by a handshake byte, if it is one.
+pub fn wire_codec(handshake: u8) -> Option<&'static dyn WireCodec>
+{
+    WIRE_CODECS.into_iter().find(|codec| codec.handshake() == handshake)
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn test_wire_codecs()
+    {
+        let command = NetCommand {
+            name: "INSERT",
+            keys: Some(vec!["name"]),
+            values: Some(vec![DbValue {
+                value: serde_json::json!({ "first": "jamal" }),
+                expires_at: Some(1_700_000_000_000),
+            }]),
+            ttls: None,
+            args: None,
+            tags: None,
+            batch: None,
+            durability: None,
+            fields: None,
+            progress: None,
+            id: Some(7),
+        };
+        let response = NetResponse {
+            action: NetActions::Command,
+            value: Some(serde_json::json!([1, "two", null])),
+            error: None,
+            warnings: vec!["deprecated".to_string()],
+            ..Default::default()
+        };
+
+        let packed = rmp_serde::to_vec_named(&command).unwrap();
+        assert_eq!(wire_codec(b'M').unwrap().decode(&packed).unwrap(), command);
+
+        for codec in WIRE_CODECS {
+            let encoded = codec.encode(&response).unwrap();
+            let decoded: NetResponse = match codec.handshake() {
+                b'J' => serde_json::from_slice(&encoded).unwrap(),
+                _ => rmp_serde::from_slice(&encoded).unwrap(),
+            };
+            assert_eq!(decoded, response);
+        }
+        assert!(wire_codec(0).is_none());
+    }
 }
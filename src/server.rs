@@ -1,34 +1,252 @@
+use std::io;
 use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
 
-use tokio::net::{TcpListener, TcpStream};
+use tokio::net::{TcpListener, TcpSocket, TcpStream};
+use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::mpsc;
 use tokio::sync::mpsc::{Receiver, Sender};
-use tracing::{debug, info};
+use tokio::task::JoinSet;
+use tokio::time::{timeout, timeout_at, Instant};
+use tracing::{debug, error, info, warn};
 
 use crate::cli::Cli;
-use crate::protocol::{Database, DbEngine};
-use crate::services::tcp;
+use crate::diagnostics::{DiagnosticKind, DiagnosticLevel};
+use crate::protocol::DbEngine;
+use crate::services::{grpc, http, resp, tcp};
+use crate::tls;
 
-pub async fn execute(args: &Cli, engine: &DbEngine) -> Result<(), Box<dyn std::error::Error>>
+/// A freshly accepted client connection waiting to be handed to the TCP service.
+type PendingConnection = (TcpStream, Arc<DbEngine>);
+
+/// The first delay used when the listener runs out of resources (e.g. `EMFILE`).
+const ACCEPT_BACKOFF_MIN: Duration = Duration::from_millis(10);
+
+/// The longest delay between two accept attempts while the listener is out of resources.
+const ACCEPT_BACKOFF_MAX: Duration = Duration::from_secs(1);
+
+/// The number of pending connections the listener queues before refusing new ones.
+const LISTEN_BACKLOG: u32 = 1024;
+
+/// How long a client has to complete the TLS handshake before its connection is closed.
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Runs the server until it receives `SIGINT` or `SIGTERM`.
+///
+/// On shutdown the listener is closed straight away, so another process bound to the same port with
+/// `--reuse-port` takes over new connections, while the connections already open are given up to
+/// `--drain-timeout` seconds to finish. Each of them is sent a `ShuttingDown` response and closed once the commands
+/// it already sent are answered, and the ones still open past the deadline, Redis protocol ones included, are
+/// closed right away.
+pub async fn execute(args: &Cli, engine: &Arc<DbEngine>) -> Result<(), Box<dyn std::error::Error>>
 {
     let socket = SocketAddr::new(args.addr.parse().unwrap(), args.port);
-    let listener = TcpListener::bind(socket).await?;
+    let listener = bind(socket, args.reuse_port)?;
+
+    // Connections are wrapped in TLS before any command is read, if a certificate is configured
+    let acceptor = match (&args.tls_cert, &args.tls_key) {
+        (Some(cert), Some(key)) => Some(tls::acceptor(cert, key)?),
+        _ => None,
+    };
 
-    let (tx, mut rx): (Sender<(TcpStream, Database)>, Receiver<(TcpStream, Database)>) = mpsc::channel(1024);
+    let (tx, mut rx): (Sender<PendingConnection>, Receiver<PendingConnection>) = mpsc::channel(1024);
 
-    // Spawn task to handle streams
-    tokio::spawn(async move {
+    // Spawn task to handle streams, ending once the listener is closed and every connection has ended. Aborting it
+    // drops the connections still open along with their set.
+    let service = tokio::spawn(async move {
         debug!("Starting TCP Service");
-        while let Some((stream, db)) = rx.recv().await {
-            tokio::spawn(tcp::execute(stream, db));
+        let mut connections = JoinSet::new();
+        loop {
+            let (stream, engine) = tokio::select! {
+                pending = rx.recv() => match pending {
+                    Some(pending) => pending,
+                    None => break,
+                },
+                Some(_) = connections.join_next() => continue,
+            };
+            let acceptor = acceptor.clone();
+            connections.spawn(async move {
+                match acceptor {
+                    Some(acceptor) => match timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                        Ok(Ok(stream)) => {
+                            let _ = tcp::execute(stream, engine).await;
+                        }
+                        Ok(Err(e)) => debug!("TLS handshake failed: {}", e),
+                        Err(_) => debug!("TLS handshake timed out after {:?}", TLS_HANDSHAKE_TIMEOUT),
+                    },
+                    None => {
+                        let _ = tcp::execute(stream, engine).await;
+                    }
+                }
+            });
         }
+        while connections.join_next().await.is_some() {}
     });
 
-    info!("Listening on {}", socket.to_string());
+    match args.tls_cert {
+        Some(_) => info!("Listening with TLS on {}", socket),
+        None => info!("Listening on {}", socket.to_string()),
+    }
+
+    let resp = match args.resp_port {
+        Some(port) => {
+            let socket = SocketAddr::new(socket.ip(), port);
+            let listener = bind(socket, args.reuse_port)?;
+            info!("Listening for the Redis protocol on {}", socket);
+            Some(tokio::spawn(resp::listen(listener, engine.clone())))
+        }
+        None => None,
+    };
+
+    let http = match args.http_port {
+        Some(port) => {
+            let socket = SocketAddr::new(socket.ip(), port);
+            let listener = bind(socket, args.reuse_port)?;
+            info!("Listening for HTTP on {}", socket);
+            let engine = engine.clone();
+            Some(tokio::spawn(async move {
+                if let Err(e) = http::serve(listener, engine).await {
+                    error!("The HTTP API stopped: {}", e);
+                }
+            }))
+        }
+        None => None,
+    };
+
+    let grpc = match args.grpc_port {
+        Some(port) => {
+            let socket = SocketAddr::new(socket.ip(), port);
+            let listener = bind(socket, args.reuse_port)?;
+            info!("Listening for gRPC on {}", socket);
+            let engine = engine.clone();
+            Some(tokio::spawn(async move {
+                if let Err(e) = grpc::serve(listener, engine).await {
+                    error!("The gRPC service stopped: {}", e);
+                }
+            }))
+        }
+        None => None,
+    };
+
+    let mut backoff = ACCEPT_BACKOFF_MIN;
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
 
     // Main loop to accept connections and send to channel
     loop {
-        let (stream, _) = listener.accept().await?;
-        tx.send((stream, engine.connection.clone())).await?;
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = &mut shutdown => break,
+        };
+
+        match accepted {
+            Ok((stream, _)) => {
+                backoff = ACCEPT_BACKOFF_MIN;
+                tx.send((stream, engine.clone())).await?;
+            }
+            Err(e) => {
+                engine.metrics.accept_failures.increment();
+
+                if is_resource_exhausted(&e) {
+                    // Retrying straight away would spin on the same error until a descriptor is freed.
+                    error!("Failed to accept connection: {}. Retrying in {:?}", e, backoff);
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(ACCEPT_BACKOFF_MAX);
+                } else {
+                    // Errors such as `ECONNABORTED` only affect the connection being accepted.
+                    warn!("Failed to accept connection: {}", e);
+                }
+            }
+        }
+    }
+
+    // Stop accepting, then wait for the open connections and HTTP and gRPC requests to end
+    drop(listener);
+    drop(tx);
+    engine.shutdown.send_replace(true);
+    info!("Stopped accepting connections, draining open connections");
+    engine.diagnostics.emit(
+        DiagnosticLevel::Warn,
+        DiagnosticKind::Shutdown,
+        "Stopped accepting connections, draining open connections".to_string(),
+    );
+
+    let drain_timeout = Duration::from_secs(args.drain_timeout);
+    let deadline = Instant::now() + drain_timeout;
+    let mut open = false;
+    for mut connections in [Some(service), resp, http, grpc].into_iter().flatten() {
+        if timeout_at(deadline, &mut connections).await.is_err() {
+            connections.abort();
+            open = true;
+        }
+    }
+    if open {
+        warn!("Connections still open after {:?}, closing them", drain_timeout);
+    }
+
+    Ok(())
+}
+
+/// Binds a listener to `addr`.
+/// With `reuse_port`, several processes can listen on the same port and the kernel spreads connections
+/// between them, which lets a new server start before the old one stops.
+fn bind(addr: SocketAddr, reuse_port: bool) -> io::Result<TcpListener>
+{
+    let socket = if addr.is_ipv4() {
+        TcpSocket::new_v4()?
+    } else {
+        TcpSocket::new_v6()?
+    };
+
+    socket.set_reuseaddr(true)?;
+    if reuse_port {
+        socket.set_reuseport(true)?;
+    }
+    socket.bind(addr)?;
+    socket.listen(LISTEN_BACKLOG)
+}
+
+/// Resolves once the process is asked to stop with `SIGINT` or `SIGTERM`.
+async fn shutdown_signal()
+{
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(terminate) => terminate,
+        Err(e) => {
+            error!("Failed to listen for SIGTERM: {}", e);
+            let _ = tokio::signal::ctrl_c().await;
+            return;
+        }
+    };
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => info!("Received SIGINT, shutting down"),
+        _ = terminate.recv() => info!("Received SIGTERM, shutting down"),
+    }
+}
+
+/// Returns `true` if the accept error was caused by the process or system running out of resources.
+fn is_resource_exhausted(e: &io::Error) -> bool
+{
+    matches!(
+        e.raw_os_error(),
+        Some(libc::EMFILE) | Some(libc::ENFILE) | Some(libc::ENOBUFS) | Some(libc::ENOMEM)
+    )
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[tokio::test]
+    async fn test_bind_with_reuse_port()
+    {
+        let first = bind("127.0.0.1:0".parse().unwrap(), true).unwrap();
+        let addr = first.local_addr().unwrap();
+
+        // A second server can take over the port while the first one is still listening
+        assert!(bind(addr, true).is_ok());
+        assert!(bind(addr, false).is_err());
     }
 }
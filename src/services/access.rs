@@ -0,0 +1,18 @@
+use std::sync::Arc;
+
+use crate::protocol::DbEngine;
+use crate::services::scheduler::Reschedule;
+
+/// Ages the access tracker, run periodically by the scheduler.
+///
+/// Each run decrements the clock of every tracked key, so a key that is no longer read drops out of the
+/// tracker after `u8::MAX` runs.
+///
+/// # Arguments
+///
+/// * `engine` - The database engine holding the access tracker.
+pub async fn age(engine: Arc<DbEngine>) -> Reschedule
+{
+    engine.access.age();
+    Reschedule::Interval
+}
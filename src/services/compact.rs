@@ -0,0 +1,56 @@
+use std::sync::Arc;
+
+use futures::future::{BoxFuture, FutureExt};
+
+use crate::protocol::DbEngine;
+use crate::services::scheduler::{Job, Reschedule};
+use crate::wal;
+
+/// A job that compacts the write-ahead log once it has grown too large.
+///
+/// The log is compacted when it reaches `--wal-compact-size` bytes and has at least doubled since it was last
+/// compacted, so a keyspace larger than the threshold is not rewritten on every check. Stops if the log is
+/// disabled.
+pub struct WalCompaction
+{
+    /// The database engine holding the write-ahead log.
+    engine: Arc<DbEngine>,
+    /// The size of the log after it was last compacted.
+    compacted_size: u64,
+}
+
+impl WalCompaction
+{
+    /// Creates the compaction job of the log of `engine`.
+    pub fn new(engine: Arc<DbEngine>) -> Self
+    {
+        Self {
+            engine,
+            compacted_size: 0,
+        }
+    }
+}
+
+impl Job for WalCompaction
+{
+    fn run(&mut self) -> BoxFuture<'_, Reschedule>
+    {
+        async move {
+            let Some(size) = self.engine.wal.size() else {
+                return Reschedule::Stop;
+            };
+            if size < self.engine.db_config.wal_compact_size.max(self.compacted_size * 2)
+                || self.engine.loads.pauses_background()
+            {
+                return Reschedule::Interval;
+            }
+
+            // The outcome is logged by `compact`
+            if let Ok(report) = wal::compact(self.engine.clone()).await {
+                self.compacted_size = report.size_after;
+            }
+            Reschedule::Interval
+        }
+        .boxed()
+    }
+}
@@ -0,0 +1,30 @@
+use std::sync::Arc;
+
+use tracing::error;
+
+use crate::cli::Durability;
+use crate::protocol::DbEngine;
+use crate::services::scheduler::Reschedule;
+
+/// Syncs the write-ahead log to disk, run periodically by the scheduler.
+///
+/// Only needed with the `everysec` durability, the log syncs itself on every write with `always` and is left to
+/// the operating system with `no`. Stops with any other durability.
+///
+/// # Arguments
+///
+/// * `engine` - The database engine holding the write-ahead log.
+pub async fn sync(engine: Arc<DbEngine>) -> Reschedule
+{
+    if engine.wal.durability() != Some(Durability::Everysec) {
+        return Reschedule::Stop;
+    }
+
+    let wal_engine = engine.clone();
+    match tokio::task::spawn_blocking(move || wal_engine.wal.sync()).await {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => error!("Failed to sync the write-ahead log: {}", e),
+        Err(e) => error!("Failed to sync the write-ahead log: {}", e),
+    }
+    Reschedule::Interval
+}
@@ -0,0 +1,329 @@
+use std::pin::Pin;
+use std::sync::Arc;
+
+use futures::stream::{self, Stream, StreamExt};
+use proto::phoenix_server::{Phoenix, PhoenixServer};
+use proto::{
+    BulkInsertRequest, BulkInsertResponse, DeleteRequest, DeleteResponse, Entry, InsertRequest, InsertResponse,
+    LookupRequest, LookupResponse, ScanRequest,
+};
+use serde_json::json;
+use tokio::net::TcpListener;
+use tonic::transport::server::TcpIncoming;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+
+use crate::protocol::{unix_millis, DbEngine, DbValue, JsonValue};
+use crate::services::{command, run, Failure};
+
+/// The number of entries a `Scan` call reads through each `SCAN` command it runs.
+const SCAN_PAGE_SIZE: u64 = 1000;
+
+/// The messages and service generated from `proto/phoenix.proto`.
+#[allow(clippy::all)]
+pub mod proto
+{
+    tonic::include_proto!("phoenix.v1");
+}
+
+/// Serves the gRPC service defined in `proto/phoenix.proto` on `listener`, until the server shuts down. Calls in
+/// progress then still complete.
+///
+/// Every call runs through the regular command handler, so it is applied, logged and limited as if it was sent
+/// over the TCP protocol. `Scan` runs one `SCAN` command per page of entries it streams.
+pub async fn serve(listener: TcpListener, engine: Arc<DbEngine>) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+{
+    let incoming = TcpIncoming::from_listener(listener, true, None)?;
+    let mut shutdown = engine.shutdown.subscribe();
+    Server::builder()
+        .add_service(PhoenixServer::new(PhoenixService { engine }))
+        .serve_with_incoming_shutdown(incoming, async move { drop(shutdown.wait_for(|shutdown| *shutdown).await) })
+        .await?;
+    Ok(())
+}
+
+/// Implements the gRPC service on top of the command handler.
+struct PhoenixService
+{
+    engine: Arc<DbEngine>,
+}
+
+#[tonic::async_trait]
+impl Phoenix for PhoenixService
+{
+    type ScanStream = Pin<Box<dyn Stream<Item = Result<Entry, Status>> + Send>>;
+
+    async fn insert(&self, request: Request<InsertRequest>) -> Result<Response<InsertResponse>, Status>
+    {
+        let entry = request
+            .into_inner()
+            .entry
+            .ok_or_else(|| Status::invalid_argument("An entry is required."))?;
+        let value = parse_value(&entry).map_err(Status::invalid_argument)?;
+
+        let mut insert = command("INSERT", vec![&entry.key]);
+        insert.values = Some(vec![value]);
+        run(&self.engine, insert).await.map_err(status)?;
+        Ok(Response::new(InsertResponse {}))
+    }
+
+    async fn lookup(&self, request: Request<LookupRequest>) -> Result<Response<LookupResponse>, Status>
+    {
+        let key = request.into_inner().key;
+        let value = run(&self.engine, command("LOOKUP", vec![&key])).await.map_err(status)?.value;
+
+        Ok(Response::new(LookupResponse {
+            value: value.filter(|value| !value.is_null()).map(|value| value.to_string()),
+        }))
+    }
+
+    async fn delete(&self, request: Request<DeleteRequest>) -> Result<Response<DeleteResponse>, Status>
+    {
+        // `DELETE *` lists the keys it deleted rather than failing on missing ones
+        let key = request.into_inner().key;
+        let deleted = run(&self.engine, command("DELETE *", vec![&key]))
+            .await
+            .map_err(status)?
+            .value;
+
+        Ok(Response::new(DeleteResponse {
+            deleted: matches!(deleted, Some(JsonValue::Array(keys)) if !keys.is_empty()),
+        }))
+    }
+
+    async fn bulk_insert(&self, request: Request<BulkInsertRequest>) -> Result<Response<BulkInsertResponse>, Status>
+    {
+        let entries = request.into_inner().entries;
+        let values = entries
+            .iter()
+            .map(parse_value)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(Status::invalid_argument)?;
+
+        let mut insert = command("INSERT *", entries.iter().map(|entry| entry.key.as_str()).collect());
+        insert.values = Some(values);
+        run(&self.engine, insert).await.map_err(status)?;
+        Ok(Response::new(BulkInsertResponse {
+            inserted: entries.len() as u64,
+        }))
+    }
+
+    async fn scan(&self, request: Request<ScanRequest>) -> Result<Response<Self::ScanStream>, Status>
+    {
+        // The first page is read before replying, so a call the handler rejects fails as a whole. The following
+        // ones are read as the client consumes the stream, so only a page is held in memory at a time.
+        let prefix = request.into_inner().prefix;
+        let (entries, cursor) = scan_page(&self.engine, &prefix, 0).await.map_err(status)?;
+
+        let engine = self.engine.clone();
+        let pages = stream::unfold(cursor, move |cursor| {
+            let engine = engine.clone();
+            let prefix = prefix.clone();
+            async move {
+                match scan_page(&engine, &prefix, cursor?).await {
+                    Ok((entries, cursor)) => Some((entries.into_iter().map(Ok).collect::<Vec<_>>(), cursor)),
+                    Err(failure) => Some((vec![Err(status(failure))], None)),
+                }
+            }
+        });
+        let entries = stream::iter(entries.into_iter().map(Ok)).chain(pages.flat_map(stream::iter));
+        Ok(Response::new(entries.boxed()))
+    }
+}
+
+/// Reads a page of the entries under `prefix` with a `SCAN` command, starting at `cursor`.
+///
+/// # Returns
+///
+/// The live entries of the page, and the cursor of the next page if there is one.
+async fn scan_page(engine: &Arc<DbEngine>, prefix: &str, cursor: usize) -> Result<(Vec<Entry>, Option<usize>), Failure>
+{
+    let mut scan = command("SCAN", if prefix.is_empty() { vec![] } else { vec![prefix] });
+    scan.args = Some(vec![json!(cursor), json!(SCAN_PAGE_SIZE)]);
+    let response = run(engine, scan).await?;
+
+    // `SCAN` does not return expiry times, they are read from the current map along with whether the entry expired
+    let map = engine.connection.read();
+    let now = unix_millis();
+    let entries = match response.value {
+        Some(JsonValue::Array(entries)) => entries,
+        _ => vec![],
+    };
+    let entries = entries
+        .into_iter()
+        .filter_map(|entry| {
+            let key = entry["key"].as_str()?;
+            let data = map.get(key).filter(|data| !data.is_expired(now))?;
+            Some(Entry {
+                key: key.to_string(),
+                value: entry["value"].to_string(),
+                expires_at: data.expires_at,
+            })
+        })
+        .collect();
+    Ok((entries, response.cursor))
+}
+
+/// Returns the gRPC status of a command that failed.
+fn status(failure: Failure) -> Status
+{
+    match failure {
+        Failure::Invalid(message) => Status::invalid_argument(message),
+        Failure::Disabled(message) => Status::failed_precondition(message),
+        Failure::Unavailable(message) => Status::unavailable(message),
+        Failure::TimedOut(message) => Status::deadline_exceeded(message),
+    }
+}
+
+/// Parses the JSON text of the value of an entry.
+fn parse_value(entry: &Entry) -> Result<DbValue, String>
+{
+    let value =
+        serde_json::from_str(&entry.value).map_err(|e| format!("The value of '{}' is not valid JSON: {}", entry.key, e))?;
+    Ok(DbValue {
+        value,
+        expires_at: entry.expires_at,
+    })
+}
+
+#[cfg(test)]
+mod test
+{
+    use clap::Parser;
+
+    use super::*;
+    use crate::cli::Cli;
+
+    fn entry(key: &str, value: &str) -> Entry
+    {
+        Entry {
+            key: key.to_string(),
+            value: value.to_string(),
+            expires_at: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_service()
+    {
+        let service = PhoenixService {
+            engine: Arc::new(DbEngine::new(Cli::parse_from(["phoenix-db", "--experimental", "SCAN"]))),
+        };
+
+        let entries = vec![
+            entry("user:1", r#"{"name":"jamal"}"#),
+            entry("user:2", "2"),
+            entry("post:1", "\"hi\""),
+        ];
+        let inserted = service
+            .bulk_insert(Request::new(BulkInsertRequest { entries }))
+            .await
+            .unwrap();
+        assert_eq!(inserted.into_inner().inserted, 3);
+
+        let lookup = |key: &str| LookupRequest { key: key.to_string() };
+        let value = service
+            .lookup(Request::new(lookup("user:1")))
+            .await
+            .unwrap()
+            .into_inner()
+            .value;
+        assert_eq!(value.as_deref(), Some(r#"{"name":"jamal"}"#));
+
+        let mut scanned: Vec<String> = service
+            .scan(Request::new(ScanRequest {
+                prefix: "user:".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .map(|entry| entry.unwrap().key)
+            .collect()
+            .await;
+        scanned.sort();
+        assert_eq!(scanned, ["user:1", "user:2"]);
+
+        let delete = |key: &str| DeleteRequest { key: key.to_string() };
+        assert!(
+            service
+                .delete(Request::new(delete("user:1")))
+                .await
+                .unwrap()
+                .into_inner()
+                .deleted
+        );
+        assert!(
+            !service
+                .delete(Request::new(delete("user:1")))
+                .await
+                .unwrap()
+                .into_inner()
+                .deleted
+        );
+        assert_eq!(
+            service
+                .lookup(Request::new(lookup("user:1")))
+                .await
+                .unwrap()
+                .into_inner()
+                .value,
+            None
+        );
+
+        let invalid = InsertRequest {
+            entry: Some(entry("user:3", "not json")),
+        };
+        let status = service.insert(Request::new(invalid)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn test_scan_pages_and_errors()
+    {
+        let service = PhoenixService {
+            engine: Arc::new(DbEngine::new(Cli::parse_from([
+                "phoenix-db",
+                "--experimental",
+                "SCAN",
+                "--max-memory",
+                "1",
+            ]))),
+        };
+        let entries = (0..2500).map(|i| entry(&format!("key:{}", i), "1")).collect();
+        service
+            .bulk_insert(Request::new(BulkInsertRequest { entries }))
+            .await
+            .unwrap();
+
+        // The entries span several pages
+        let scanned = service
+            .scan(Request::new(ScanRequest {
+                prefix: "key:".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .map(|entry| entry.unwrap().key)
+            .collect::<std::collections::HashSet<_>>()
+            .await;
+        assert_eq!(scanned.len(), 2500);
+
+        // Writes rejected for memory are unavailable rather than invalid
+        service.engine.memory.update(2);
+        let insert = InsertRequest {
+            entry: Some(entry("key:0", "2")),
+        };
+        let status = service.insert(Request::new(insert)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unavailable);
+
+        // Scans are experimental, like the `SCAN` command they run
+        let service = PhoenixService {
+            engine: Arc::new(DbEngine::new(Cli::parse_from(["phoenix-db"]))),
+        };
+        let scan = ScanRequest {
+            prefix: "key:".to_string(),
+        };
+        let status = service.scan(Request::new(scan)).await.err().unwrap();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+    }
+}
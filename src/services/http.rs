@@ -0,0 +1,228 @@
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::body::Body;
+use axum::extract::{Path, Query, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use futures::stream;
+use serde::Deserialize;
+use serde_json::json;
+use tokio::net::TcpListener;
+
+use crate::commands::namespace::ExportEntry;
+use crate::protocol::{expiry_after, unix_millis, DbEngine, DbValue, JsonValue};
+use crate::services::{command, handle};
+
+/// The header giving the number of seconds a value written with `PUT /keys/{key}` lives for.
+const TTL_HEADER: &str = "x-ttl";
+
+/// Serves the HTTP API on `listener`, until the server shuts down. Requests in progress then still complete.
+///
+/// * `GET /keys/{key}` replies with the value of a key, as JSON.
+/// * `PUT /keys/{key}` stores the JSON body as the value of a key, expiring after the seconds of the `X-TTL`
+///   header if given.
+/// * `DELETE /keys/{key}` deletes a key.
+/// * `POST /bulk` inserts a list of entries at once, in the format of `EXPORT`.
+/// * `GET /export?prefix=...` streams the entries whose key starts with the prefix, one JSON document per line in
+///   the format of `EXPORT`.
+///
+/// Requests run through the regular command handler, as if they were sent over the TCP protocol, except exports
+/// which read the map directly.
+pub async fn serve(listener: TcpListener, engine: Arc<DbEngine>) -> io::Result<()>
+{
+    let mut shutdown = engine.shutdown.subscribe();
+    axum::serve(listener, router(engine))
+        .with_graceful_shutdown(async move { drop(shutdown.wait_for(|shutdown| *shutdown).await) })
+        .await
+}
+
+/// Routes the requests of the HTTP API.
+fn router(engine: Arc<DbEngine>) -> Router
+{
+    Router::new()
+        .route("/keys/:key", get(lookup).put(insert).delete(delete))
+        .route("/bulk", post(bulk_insert))
+        .route("/export", get(export))
+        .with_state(engine)
+}
+
+async fn lookup(State(engine): State<Arc<DbEngine>>, Path(key): Path<String>) -> Response
+{
+    match handle(&engine, command("LOOKUP", vec![&key])).await {
+        Ok(Some(value)) if !value.is_null() => Json(value).into_response(),
+        Ok(_) => error(StatusCode::NOT_FOUND, format!("Key '{}' not found.", key)),
+        Err(message) => error(StatusCode::BAD_REQUEST, message),
+    }
+}
+
+async fn insert(
+    State(engine): State<Arc<DbEngine>>,
+    Path(key): Path<String>,
+    headers: HeaderMap,
+    Json(value): Json<JsonValue>,
+) -> Response
+{
+    let expires_at = match headers.get(TTL_HEADER) {
+        Some(ttl) => match ttl.to_str().ok().and_then(|ttl| ttl.parse().ok()) {
+            Some(seconds) => Some(expiry_after(Duration::from_secs(seconds))),
+            None => return error(StatusCode::BAD_REQUEST, "X-TTL must be a number of seconds.".to_string()),
+        },
+        None => None,
+    };
+
+    let mut insert = command("INSERT", vec![&key]);
+    insert.values = Some(vec![DbValue { value, expires_at }]);
+    match handle(&engine, insert).await {
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(message) => error(StatusCode::BAD_REQUEST, message),
+    }
+}
+
+async fn delete(State(engine): State<Arc<DbEngine>>, Path(key): Path<String>) -> Response
+{
+    // `DELETE *` lists the keys it deleted rather than failing on missing ones
+    match handle(&engine, command("DELETE *", vec![&key])).await {
+        Ok(Some(JsonValue::Array(deleted))) if !deleted.is_empty() => StatusCode::NO_CONTENT.into_response(),
+        Ok(_) => error(StatusCode::NOT_FOUND, format!("Key '{}' not found.", key)),
+        Err(message) => error(StatusCode::BAD_REQUEST, message),
+    }
+}
+
+async fn bulk_insert(State(engine): State<Arc<DbEngine>>, Json(entries): Json<Vec<ExportEntry>>) -> Response
+{
+    let (keys, values): (Vec<&str>, Vec<DbValue>) =
+        entries.iter().map(|entry| (entry.key.as_str(), entry.data.clone())).unzip();
+
+    let mut insert = command("INSERT *", keys);
+    insert.values = Some(values);
+    match handle(&engine, insert).await {
+        Ok(_) => Json(json!({ "inserted": entries.len() })).into_response(),
+        Err(message) => error(StatusCode::BAD_REQUEST, message),
+    }
+}
+
+/// The query of `GET /export`.
+#[derive(Deserialize)]
+struct ExportQuery
+{
+    /// The prefix of the keys to export, all of them if empty.
+    #[serde(default)]
+    prefix: String,
+}
+
+async fn export(State(engine): State<Arc<DbEngine>>, Query(query): Query<ExportQuery>) -> Response
+{
+    // The entries come from the map as it was when the request arrived, however long the response takes to send
+    let map = engine.connection.read().as_ref().clone();
+    let now = unix_millis();
+    let lines = map
+        .into_iter()
+        .filter(move |(key, data)| key.starts_with(&query.prefix) && !data.is_expired(now))
+        .map(|(key, data)| {
+            let mut line = serde_json::to_vec(&ExportEntry { key, data })?;
+            line.push(b'\n');
+            Ok::<_, serde_json::Error>(line)
+        });
+    (
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(stream::iter(lines)),
+    )
+        .into_response()
+}
+
+/// Builds an error response holding the message under `error`.
+fn error(status: StatusCode, message: String) -> Response
+{
+    (status, Json(json!({ "error": message }))).into_response()
+}
+
+#[cfg(test)]
+mod test
+{
+    use clap::Parser;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    use super::*;
+    use crate::cli::Cli;
+
+    /// Sends a request and returns the status line and body of the response.
+    async fn request(addr: &str, method: &str, path: &str, headers: &str, body: &str) -> (String, String)
+    {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let head = format!(
+            "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Length: {}\r\n",
+            method,
+            path,
+            addr,
+            body.len()
+        );
+        let request = format!(
+            "{}Content-Type: application/json\r\nConnection: close\r\n{}\r\n{}",
+            head, headers, body
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        (head.lines().next().unwrap().to_string(), body.to_string())
+    }
+
+    #[tokio::test]
+    async fn test_http_api()
+    {
+        let engine = Arc::new(DbEngine::new(Cli::parse_from(["phoenix-db"])));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(serve(listener, engine.clone()));
+
+        let (status, _) = request(&addr, "GET", "/keys/name", "", "").await;
+        assert_eq!(status, "HTTP/1.1 404 Not Found");
+
+        let (status, _) = request(&addr, "PUT", "/keys/name", "X-TTL: 60\r\n", r#"{"first":"jamal"}"#).await;
+        assert_eq!(status, "HTTP/1.1 204 No Content");
+        let (status, body) = request(&addr, "GET", "/keys/name", "", "").await;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert_eq!(body, r#"{"first":"jamal"}"#);
+        assert!(engine.connection.read().get("name").unwrap().expires_at.is_some());
+
+        let (status, _) = request(&addr, "PUT", "/keys/name", "X-TTL: soon\r\n", "1").await;
+        assert_eq!(status, "HTTP/1.1 400 Bad Request");
+
+        let bulk = r#"[{"key":"a","value":1},{"key":"b","value":[2]}]"#;
+        let (status, body) = request(&addr, "POST", "/bulk", "", bulk).await;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert_eq!(body, r#"{"inserted":2}"#);
+
+        let (status, _) = request(&addr, "DELETE", "/keys/a", "", "").await;
+        assert_eq!(status, "HTTP/1.1 204 No Content");
+        let (status, _) = request(&addr, "DELETE", "/keys/a", "", "").await;
+        assert_eq!(status, "HTTP/1.1 404 Not Found");
+    }
+
+    #[tokio::test]
+    async fn test_export()
+    {
+        let engine = Arc::new(DbEngine::new(Cli::parse_from(["phoenix-db"])));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(serve(listener, engine.clone()));
+
+        let bulk = r#"[{"key":"user:1","value":1},{"key":"user:2","value":2,"expires_at":1},{"key":"team:1","value":3}]"#;
+        request(&addr, "POST", "/bulk", "", bulk).await;
+
+        // The body is sent in chunks, each holding whole lines
+        let (status, body) = request(&addr, "GET", "/export?prefix=user:", "", "").await;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        let lines: Vec<&str> = body.lines().filter(|line| line.starts_with('{')).collect();
+        assert_eq!(lines, [r#"{"key":"user:1","value":1,"expires_at":null}"#]);
+
+        let (_, body) = request(&addr, "GET", "/export", "", "").await;
+        assert_eq!(body.lines().filter(|line| line.starts_with('{')).count(), 2);
+    }
+}
@@ -0,0 +1,71 @@
+use std::sync::Arc;
+
+use futures::future::{BoxFuture, FutureExt};
+use tracing::{error, info, warn};
+
+use crate::diagnostics::{DiagnosticKind, DiagnosticLevel};
+use crate::protocol::DbEngine;
+use crate::services::scheduler::{Job, Reschedule};
+
+/// A job that keeps the primary lease of an HA pair, acquiring it when it expires.
+///
+/// The lease is renewed four times per lease duration, so the primary keeps accepting writes through a missed
+/// renewal. Stops if election is disabled.
+pub struct LeaseRenewal
+{
+    /// The database engine holding the lease.
+    engine: Arc<DbEngine>,
+    /// Whether this node was the primary after the last run.
+    was_primary: bool,
+}
+
+impl LeaseRenewal
+{
+    /// Creates the renewal job of the lease of `engine`.
+    pub fn new(engine: Arc<DbEngine>) -> Self
+    {
+        Self {
+            engine,
+            was_primary: false,
+        }
+    }
+}
+
+impl Job for LeaseRenewal
+{
+    fn run(&mut self) -> BoxFuture<'_, Reschedule>
+    {
+        async move {
+            let engine = &self.engine;
+            if !engine.lease.is_enabled() {
+                return Reschedule::Stop;
+            }
+
+            let lease_engine = engine.clone();
+            match tokio::task::spawn_blocking(move || lease_engine.lease.renew()).await {
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => error!("Failed to renew the primary lease: {}", e),
+                Err(e) => error!("Failed to renew the primary lease: {}", e),
+            }
+
+            let is_primary = engine.lease.is_primary();
+            if is_primary != self.was_primary {
+                let message = match engine.lease.epoch() {
+                    Some(epoch) => format!("Became the primary in epoch {}, accepting writes", epoch),
+                    None => "Lost the primary lease, rejecting writes".to_string(),
+                };
+                let level = if is_primary {
+                    info!("{}", message);
+                    DiagnosticLevel::Info
+                } else {
+                    warn!("{}", message);
+                    DiagnosticLevel::Warn
+                };
+                engine.diagnostics.emit(level, DiagnosticKind::Election, message);
+                self.was_primary = is_primary;
+            }
+            Reschedule::Interval
+        }
+        .boxed()
+    }
+}
@@ -0,0 +1,221 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+use serde::Serialize;
+use tokio::sync::watch;
+use tracing::info;
+
+/// What a background service is doing.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ServiceState
+{
+    /// The service runs whenever it has work to do.
+    Running,
+    /// The service was stopped, it finishes the work in progress and then waits to be started again.
+    Stopped,
+    /// The service has nothing left to do and will not run again.
+    Finished,
+}
+
+/// The status of a background service, as returned by `SERVICES`.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ServiceStatus
+{
+    /// The name of the service.
+    pub name: &'static str,
+    /// Whether the service is running, stopped or finished.
+    pub state: ServiceState,
+    /// Whether the service is doing some work right now.
+    pub busy: bool,
+    /// How many times the service did some work since the server started.
+    pub runs: u64,
+}
+
+/// A background service, such as the TTL sweep, that can be stopped and started again while the server runs.
+///
+/// Services do their work in runs, one at a time through [`Service::run`]. Stopping a service never interrupts a
+/// run in progress, it only keeps the next one from starting until the service is started again.
+#[derive(Debug)]
+pub struct Service
+{
+    /// Whether the service may start a run.
+    enabled: watch::Sender<bool>,
+    /// Whether a run is in progress.
+    busy: watch::Sender<bool>,
+    /// The number of runs done so far.
+    runs: AtomicU64,
+    /// Set once the service has nothing left to do.
+    finished: AtomicBool,
+}
+
+impl Service
+{
+    fn new() -> Self
+    {
+        Self {
+            enabled: watch::Sender::new(true),
+            busy: watch::Sender::new(false),
+            runs: AtomicU64::new(0),
+            finished: AtomicBool::new(false),
+        }
+    }
+
+    /// Does one run of the service's work, first waiting for the service to be started if it is stopped.
+    pub async fn run<F: Future>(&self, work: F) -> F::Output
+    {
+        // Busy is set before checking the service is still enabled, so a shutdown either waits for the run or
+        // keeps it from starting
+        loop {
+            let _ = self.enabled.subscribe().wait_for(|enabled| *enabled).await;
+            self.busy.send_replace(true);
+            if *self.enabled.borrow() {
+                break;
+            }
+            self.busy.send_replace(false);
+        }
+        let output = work.await;
+        self.busy.send_replace(false);
+        self.runs.fetch_add(1, Ordering::Relaxed);
+        output
+    }
+
+    /// Marks the service as having nothing left to do.
+    pub fn finish(&self)
+    {
+        self.finished.store(true, Ordering::Relaxed);
+    }
+
+    fn state(&self) -> ServiceState
+    {
+        if self.finished.load(Ordering::Relaxed) {
+            ServiceState::Finished
+        } else if *self.enabled.borrow() {
+            ServiceState::Running
+        } else {
+            ServiceState::Stopped
+        }
+    }
+}
+
+/// Owns the background services of the server, letting them be listed, stopped and started again by name.
+#[derive(Debug, Default)]
+pub struct ServiceManager
+{
+    /// The services registered so far, keyed by their name.
+    services: RwLock<BTreeMap<&'static str, Arc<Service>>>,
+}
+
+impl ServiceManager
+{
+    /// Registers a service under `name`, running until it is stopped.
+    ///
+    /// # Returns
+    ///
+    /// The service, through which its task does its runs.
+    pub fn register(&self, name: &'static str) -> Arc<Service>
+    {
+        let service = Arc::new(Service::new());
+        self.services.write().unwrap().insert(name, service.clone());
+        service
+    }
+
+    /// Returns the status of every service, sorted by name.
+    pub fn status(&self) -> Vec<ServiceStatus>
+    {
+        self.services
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, service)| ServiceStatus {
+                name,
+                state: service.state(),
+                busy: *service.busy.borrow(),
+                runs: service.runs.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    /// Starts a stopped service again, or stops a running one, once its run in progress if any is done.
+    pub fn set_enabled(&self, name: &str, enabled: bool) -> Result<(), String>
+    {
+        let services = self.services.read().unwrap();
+        let service = services.get(name).ok_or_else(|| {
+            let names: Vec<&str> = services.keys().copied().collect();
+            format!("Unknown service '{}', the services are: {}.", name, names.join(", "))
+        })?;
+        if service.state() == ServiceState::Finished {
+            return Err(format!("The {} service has finished and cannot be started or stopped.", name));
+        }
+
+        service.enabled.send_replace(enabled);
+        info!("{} the {} service", if enabled { "Started" } else { "Stopped" }, name);
+        Ok(())
+    }
+
+    /// Stops every service, then waits for the runs in progress to be done.
+    pub async fn shutdown(&self)
+    {
+        let services: Vec<Arc<Service>> = self.services.read().unwrap().values().cloned().collect();
+        for service in &services {
+            service.enabled.send_replace(false);
+        }
+        for service in services {
+            let _ = service.busy.subscribe().wait_for(|busy| !*busy).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use std::time::Duration;
+
+    use tokio::sync::oneshot;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_stop_and_start()
+    {
+        let manager = ServiceManager::default();
+        let service = manager.register("test");
+        assert!(manager.set_enabled("other", false).is_err());
+
+        // A stopped service does not start its next run until it is started again
+        manager.set_enabled("test", false).unwrap();
+        let (tx, mut rx) = oneshot::channel();
+        let task = tokio::spawn(async move { service.run(async { tx.send(()).unwrap() }).await });
+        assert!(tokio::time::timeout(Duration::from_millis(50), &mut rx).await.is_err());
+        assert_eq!(manager.status()[0].state, ServiceState::Stopped);
+
+        manager.set_enabled("test", true).unwrap();
+        task.await.unwrap();
+        rx.await.unwrap();
+        let status = &manager.status()[0];
+        assert_eq!((status.state, status.busy, status.runs), (ServiceState::Running, false, 1));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_waits_for_runs()
+    {
+        let manager = ServiceManager::default();
+        let service = manager.register("test");
+
+        let (tx, rx) = oneshot::channel::<()>();
+        let run = tokio::spawn(async move { service.run(rx).await });
+        tokio::task::yield_now().await;
+        assert!(manager.status()[0].busy);
+
+        // The run in progress holds the shutdown up until it is done
+        let shutdown = manager.shutdown();
+        tokio::pin!(shutdown);
+        assert!(tokio::time::timeout(Duration::from_millis(50), &mut shutdown).await.is_err());
+        tx.send(()).unwrap();
+        shutdown.await;
+        run.await.unwrap().unwrap();
+        assert_eq!(manager.status()[0].state, ServiceState::Stopped);
+    }
+}
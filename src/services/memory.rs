@@ -0,0 +1,44 @@
+use std::sync::Arc;
+
+use tracing::{info, warn};
+
+use crate::commands::memory::estimate_bytes;
+use crate::diagnostics::{DiagnosticKind, DiagnosticLevel};
+use crate::protocol::DbEngine;
+use crate::services::scheduler::Reschedule;
+
+/// Estimates the memory used by the database, run periodically by the scheduler. Stops if `--max-memory` is not
+/// set.
+///
+/// Writes start being rejected once the estimate reaches the limit and are accepted again once it falls back
+/// below the resume threshold. Both changes are logged and pushed to `DIAGNOSTICS SUBSCRIBE`.
+///
+/// # Arguments
+///
+/// * `engine` - The database engine holding the memory guard.
+pub async fn check(engine: Arc<DbEngine>) -> Reschedule
+{
+    let memory = &engine.memory;
+    if !memory.is_enabled() {
+        return Reschedule::Stop;
+    }
+
+    // The memory reserved for bulk loads counts as used, so other writes cannot take it
+    let used = estimate_bytes(&engine).saturating_add(engine.loads.reserved_bytes());
+    if memory.update(used) {
+        if memory.is_rejecting() {
+            let message = format!("Memory use of {} bytes reached --max-memory, rejecting writes", used);
+            warn!("{}", message);
+            engine
+                .diagnostics
+                .emit(DiagnosticLevel::Warn, DiagnosticKind::Memory, message);
+        } else {
+            let message = format!("Memory use fell to {} bytes, accepting writes", used);
+            info!("{}", message);
+            engine
+                .diagnostics
+                .emit(DiagnosticLevel::Info, DiagnosticKind::Memory, message);
+        }
+    }
+    Reschedule::Interval
+}
@@ -1,17 +1,185 @@
 use std::sync::Arc;
 use std::time::Duration;
 
-use crate::protocol::DbEngine;
+use crate::commands::handler;
+use crate::protocol::{DbEngine, JsonValue, NetActions, NetCommand, NetResponse};
+use crate::services::scheduler::Scheduler;
 
+pub mod access;
+pub mod compact;
+pub mod flush;
+pub mod grpc;
+pub mod http;
+pub mod lease;
+pub mod manager;
+pub mod memory;
+pub mod replication;
+pub mod resp;
+pub mod scheduler;
 pub mod tcp;
+pub mod telemetry;
 pub mod ttl;
+pub mod uploads;
+pub mod upstream;
 
+/// Starts the background jobs of the server.
 pub async fn execute(engine: Arc<DbEngine>) -> Result<(), Box<dyn std::error::Error>>
 {
+    let mut scheduler = Scheduler::default();
+
     // Manages TTL key clean-up
-    tokio::spawn(async move {
-        ttl::execute(engine.connection.clone(), Duration::from_secs(60)).await;
+    let min_interval = Duration::from_secs(engine.db_config.ttl_sweep_min.max(1));
+    let max_interval = Duration::from_secs(engine.db_config.ttl_sweep_max).max(min_interval);
+    scheduler.every(
+        "ttl",
+        max_interval,
+        Duration::from_millis(250),
+        ttl::TtlSweep::new(engine.clone(), min_interval, max_interval),
+    );
+
+    // Discards abandoned chunked uploads
+    let uploads_engine = engine.clone();
+    scheduler.every("uploads", Duration::from_secs(60), Duration::from_secs(1), move || {
+        uploads::discard_stale(uploads_engine.clone())
+    });
+
+    // Syncs the write-ahead log to disk every second, if it is enabled with the `everysec` durability
+    let flush_engine = engine.clone();
+    scheduler.every("flush", Duration::from_secs(1), Duration::ZERO, move || {
+        flush::sync(flush_engine.clone())
     });
 
+    // Ages the access tracker used to find hot keys
+    let access_engine = engine.clone();
+    scheduler.every("access", Duration::from_secs(1), Duration::from_millis(100), move || {
+        access::age(access_engine.clone())
+    });
+
+    // Keeps the primary lease of an HA pair, if election is enabled. Renewals are timed by the lease, not jittered
+    let lease_interval = engine.lease.ttl() / 4;
+    scheduler.every(
+        "lease",
+        lease_interval,
+        Duration::ZERO,
+        lease::LeaseRenewal::new(engine.clone()),
+    );
+
+    // Estimates memory use to reject writes past `--max-memory`, if it is set
+    let memory_engine = engine.clone();
+    scheduler.every("memory", Duration::from_secs(1), Duration::from_millis(100), move || {
+        memory::check(memory_engine.clone())
+    });
+
+    // Compacts the write-ahead log once it grows too large, if it is enabled
+    scheduler.every(
+        "compact",
+        Duration::from_secs(10),
+        Duration::from_secs(1),
+        compact::WalCompaction::new(engine.clone()),
+    );
+
+    // Sends anonymous usage statistics, only while telemetry is enabled
+    let telemetry_engine = engine.clone();
+    scheduler.every(
+        "telemetry",
+        Duration::from_secs(24 * 60 * 60),
+        Duration::from_secs(60 * 60),
+        move || telemetry::send(telemetry_engine.clone()),
+    );
+
+    scheduler.start(&engine.services);
+
+    // Mirrors writes to the upstream tier, if one is configured. Runs whenever a write is queued, not periodically
+    if engine.upstream.is_some() {
+        let service = engine.services.register("upstream");
+        tokio::spawn(upstream::execute(engine, service));
+    }
+
     Ok(())
 }
+
+/// Builds a command on the given keys, for the listeners of other protocols to run it.
+pub fn command<'a>(name: &'a str, keys: Vec<&'a str>) -> NetCommand<'a>
+{
+    NetCommand {
+        name,
+        keys: Some(keys),
+        values: None,
+        ttls: None,
+        args: None,
+        tags: None,
+        batch: None,
+        durability: None,
+        fields: None,
+        progress: None,
+        id: None,
+    }
+}
+
+/// Why a command run through the regular handler failed.
+#[derive(Debug, PartialEq)]
+pub enum Failure
+{
+    /// The command or its arguments are invalid.
+    Invalid(String),
+    /// The command is disabled, or experimental and not enabled.
+    Disabled(String),
+    /// The node cannot take the command right now, because it is not the primary or is out of memory.
+    Unavailable(String),
+    /// The command ran out of time.
+    TimedOut(String),
+}
+
+impl Failure
+{
+    /// Tells why a command failed from the value of its error response.
+    fn of(response: NetResponse) -> Self
+    {
+        let message = response.error.unwrap_or_default();
+        let value = response.value.unwrap_or_default();
+        match value["reason"].as_str() {
+            _ if value.get("timeout_ms").is_some() => Failure::TimedOut(message),
+            Some("disabled") => Failure::Disabled(message),
+            Some(_) => Failure::Unavailable(message),
+            None => Failure::Invalid(message),
+        }
+    }
+
+    /// Returns the error message of the failure.
+    pub fn into_message(self) -> String
+    {
+        match self {
+            Failure::Invalid(message)
+            | Failure::Disabled(message)
+            | Failure::Unavailable(message)
+            | Failure::TimedOut(message) => message,
+        }
+    }
+}
+
+/// Runs a command through the regular handler, as if it was received over the TCP protocol.
+///
+/// # Returns
+///
+/// The whole response, or why the command failed.
+pub async fn run(engine: &Arc<DbEngine>, command: NetCommand<'_>) -> Result<NetResponse, Failure>
+{
+    let response: NetResponse = handler(command, engine.clone()).await;
+    match response.action {
+        NetActions::Error => Err(Failure::of(response)),
+        _ => Ok(response),
+    }
+}
+
+/// Runs a command through the regular handler, as if it was received over the TCP protocol.
+///
+/// # Returns
+///
+/// The value of the response, or its error message.
+pub async fn handle(engine: &Arc<DbEngine>, command: NetCommand<'_>) -> Result<Option<JsonValue>, String>
+{
+    run(engine, command)
+        .await
+        .map(|response| response.value)
+        .map_err(Failure::into_message)
+}
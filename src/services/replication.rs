@@ -0,0 +1,90 @@
+use std::sync::Arc;
+
+use serde_json::json;
+use tokio::io::AsyncReadExt;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::commands::dump::DumpLine;
+use crate::commands::namespace::ExportEntry;
+use crate::protocol::{DbEngine, NetActions, NetResponse, WireCodec};
+use crate::services::tcp::{send_error_response, send_response, stream_entries, ClientStream};
+use crate::wal::WalRecord;
+
+/// Feeds a replica that sent `SYNC`: streams the current snapshot of the database, then every write as it happens.
+///
+/// The snapshot is sent in responses with the `Chunk` action holding entries in the format of `EXPORT`, followed by
+/// a `Command` response with the number of `keys` and `chunks` sent. Every batch of writes recorded to the
+/// write-ahead log from then on is pushed as a response with the `Event` action, holding its lines in the format of
+/// `BACKUP INCREMENTAL`. A replica falling too far behind gets an error and has to sync again. Requires the server
+/// to run with `--wal-path`, the log being the source of the writes.
+///
+/// # Arguments
+///
+/// * `stream` - The TCP stream representing the replica connection.
+/// * `engine` - The database engine to replicate.
+///
+/// # Returns
+///
+/// A `Result` indicating success or failure of feeding the replica. Errors are returned as `String`.
+pub async fn execute(stream: &mut ClientStream, codec: &dyn WireCodec, engine: Arc<DbEngine>) -> Result<(), String>
+{
+    // Holding the write lock keeps writes from landing between the snapshot and the start of the feed
+    let subscription = {
+        let _db_write = engine.connection.write().await;
+        engine.wal.subscribe().map(|feed| (engine.connection.read(), feed))
+    };
+    let Some((map, mut feed)) = subscription else {
+        return send_error_response(stream, codec, "SYNC requires the server to run with --wal-path.").await;
+    };
+
+    let chunks = stream_entries(stream, codec, &engine, &map).await?;
+    let mut response = NetResponse {
+        action: NetActions::Command,
+        value: Some(json!({ "keys": map.len(), "chunks": chunks })),
+        error: None,
+        ..Default::default()
+    };
+    drop(map);
+    let mut buffer = vec![0; 1024];
+
+    loop {
+        send_response(stream, codec, &engine, &response).await?;
+
+        response = loop {
+            tokio::select! {
+                records = feed.recv() => match records {
+                    Ok(records) => break NetResponse {
+                        action: NetActions::Event,
+                        value: Some(json!(records.iter().map(feed_line).collect::<Vec<_>>())),
+                        error: None,
+                        ..Default::default()
+                    },
+                    Err(RecvError::Lagged(missed)) => {
+                        let message = format!("The replica fell {} writes behind and has to sync again.", missed);
+                        return send_error_response(stream, codec, &message).await;
+                    }
+                    Err(RecvError::Closed) => return Ok(()),
+                },
+                read = stream.read(&mut buffer) => match read {
+                    Ok(0) | Err(_) => return Ok(()),
+                    Ok(_) => continue,
+                },
+            }
+        };
+    }
+}
+
+/// Returns the line of the replication feed for a record of the write-ahead log.
+fn feed_line(record: &WalRecord) -> DumpLine
+{
+    match record {
+        WalRecord::Insert(key, data) => DumpLine::Entry(ExportEntry {
+            key: key.clone(),
+            data: data.clone(),
+        }),
+        WalRecord::Delete(key) => DumpLine::Deleted {
+            key: key.clone(),
+            deleted: true,
+        },
+    }
+}
@@ -0,0 +1,361 @@
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinSet;
+use tracing::{debug, warn};
+
+use crate::protocol::{expiry_after, DbEngine, DbValue, JsonValue};
+use crate::services::{command, handle};
+
+/// The longest bulk string a client may send, so a bogus length cannot exhaust memory.
+const MAX_BULK_LEN: usize = 64 * 1024 * 1024;
+
+/// The most arguments a single command may have.
+const MAX_ARGUMENTS: usize = 1024 * 1024;
+
+/// The most bytes the arguments of a single command may add up to.
+const MAX_COMMAND_LEN: usize = 256 * 1024 * 1024;
+
+/// The longest line a client may send, an inline command or the header of an array or a bulk string.
+const MAX_LINE_LEN: usize = 64 * 1024;
+
+/// A reply in the Redis serialization protocol.
+#[derive(Debug, PartialEq)]
+enum Reply
+{
+    /// A simple string, such as `OK`.
+    Status(String),
+    /// An error message.
+    Error(String),
+    Integer(i64),
+    /// A bulk string, or the null bulk string if `None`.
+    Bulk(Option<String>),
+    Array(Vec<Reply>),
+}
+
+impl Reply
+{
+    /// Writes the reply in the RESP2 format.
+    fn encode(&self, out: &mut Vec<u8>)
+    {
+        match self {
+            Reply::Status(status) => out.extend_from_slice(format!("+{}\r\n", status).as_bytes()),
+            Reply::Error(message) => {
+                out.extend_from_slice(format!("-ERR {}\r\n", message.replace(['\r', '\n'], " ")).as_bytes())
+            }
+            Reply::Integer(n) => out.extend_from_slice(format!(":{}\r\n", n).as_bytes()),
+            Reply::Bulk(None) => out.extend_from_slice(b"$-1\r\n"),
+            Reply::Bulk(Some(data)) => {
+                out.extend_from_slice(format!("${}\r\n", data.len()).as_bytes());
+                out.extend_from_slice(data.as_bytes());
+                out.extend_from_slice(b"\r\n");
+            }
+            Reply::Array(replies) => {
+                out.extend_from_slice(format!("*{}\r\n", replies.len()).as_bytes());
+                for reply in replies {
+                    reply.encode(out);
+                }
+            }
+        }
+    }
+}
+
+/// Serves the Redis protocol on `listener`, so Redis clients such as `redis-cli` can run the basic key commands.
+///
+/// `GET`, `SET`, `DEL`, `EXPIRE` and `TTL` are mapped onto the regular commands, with values stored as JSON
+/// strings. Once the server shuts down, stops accepting and returns when every connection has ended. Aborting it
+/// closes the connections still open.
+pub async fn listen(listener: TcpListener, engine: Arc<DbEngine>)
+{
+    let mut shutdown = engine.shutdown.subscribe();
+    let mut connections = JoinSet::new();
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            Some(_) = connections.join_next() => continue,
+            _ = shutdown.wait_for(|shutdown| *shutdown) => break,
+        };
+        match accepted {
+            Ok((stream, _)) => {
+                let engine = engine.clone();
+                connections.spawn(async move {
+                    if let Err(e) = execute(stream, engine).await {
+                        debug!("Redis protocol connection ended: {}", e);
+                    }
+                });
+            }
+            Err(e) => {
+                engine.metrics.accept_failures.increment();
+                warn!("Failed to accept Redis protocol connection: {}", e);
+            }
+        }
+    }
+
+    drop(listener);
+    while connections.join_next().await.is_some() {}
+}
+
+/// Handles a single Redis protocol connection until the client disconnects or sends `QUIT`, or the server shuts
+/// down while it waits for the next command.
+async fn execute(mut stream: TcpStream, engine: Arc<DbEngine>) -> io::Result<()>
+{
+    let (read, mut write) = stream.split();
+    let mut reader = BufReader::new(read);
+    let mut out = vec![];
+    let mut shutdown = engine.shutdown.subscribe();
+
+    loop {
+        tokio::select! {
+            biased;
+            _ = reader.fill_buf() => {}
+            _ = shutdown.wait_for(|shutdown| *shutdown) => break,
+        }
+        let Some(arguments) = read_command(&mut reader).await? else {
+            break;
+        };
+        engine
+            .metrics
+            .bytes_read
+            .add(arguments.iter().map(|argument| argument.len() as u64).sum());
+        let Some(name) = arguments.first() else {
+            continue;
+        };
+        let name = name.to_uppercase();
+
+        let reply = run(&name, &arguments[1..], &engine).await;
+        out.clear();
+        reply.encode(&mut out);
+        engine.metrics.bytes_written.add(out.len() as u64);
+        write.write_all(&out).await?;
+
+        if name == "QUIT" {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Reads the next command, sent either as an array of bulk strings or inline as words separated by spaces.
+///
+/// # Returns
+///
+/// The name of the command followed by its arguments, or `None` once the client disconnected.
+async fn read_command(reader: &mut (impl AsyncBufReadExt + Unpin)) -> io::Result<Option<Vec<String>>>
+{
+    let Some(line) = read_line(reader).await? else {
+        return Ok(None);
+    };
+    let Some(count) = line.strip_prefix('*') else {
+        return Ok(Some(line.split_whitespace().map(str::to_string).collect()));
+    };
+
+    // Memory grows with the data actually received, never with the lengths a header announces
+    let count = parse_length(count, MAX_ARGUMENTS)?;
+    let mut arguments = Vec::with_capacity(count.min(64));
+    let mut total = 0;
+    for _ in 0..count {
+        let line = read_line(reader).await?.ok_or(io::ErrorKind::UnexpectedEof)?;
+        let len = match line.strip_prefix('$') {
+            Some(len) => parse_length(len, MAX_BULK_LEN)?,
+            None => return Err(protocol_error("expected a bulk string")),
+        };
+        total += len;
+        if total > MAX_COMMAND_LEN {
+            return Err(protocol_error("command too large"));
+        }
+
+        let mut data = vec![];
+        if reader.take(len as u64 + 2).read_to_end(&mut data).await? != len + 2 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        data.truncate(len);
+        arguments.push(String::from_utf8(data).map_err(|_| protocol_error("arguments must be UTF-8"))?);
+    }
+    Ok(Some(arguments))
+}
+
+/// Reads a line ended by `\r\n`, or `None` at the end of the stream. Lines longer than `MAX_LINE_LEN` are
+/// refused before they are read whole.
+async fn read_line(reader: &mut (impl AsyncBufReadExt + Unpin)) -> io::Result<Option<String>>
+{
+    let mut line = String::new();
+    if reader.take(MAX_LINE_LEN as u64 + 2).read_line(&mut line).await? == 0 {
+        return Ok(None);
+    }
+    if !line.ends_with('\n') && line.len() > MAX_LINE_LEN {
+        return Err(protocol_error("line too long"));
+    }
+    Ok(Some(line.trim_end_matches(['\r', '\n']).to_string()))
+}
+
+/// Parses the length of an array or a bulk string, refusing lengths above `max`.
+fn parse_length(len: &str, max: usize) -> io::Result<usize>
+{
+    match len.parse::<usize>() {
+        Ok(len) if len <= max => Ok(len),
+        _ => Err(protocol_error("invalid length")),
+    }
+}
+
+fn protocol_error(message: &str) -> io::Error
+{
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Runs a Redis command through the regular command handler.
+async fn run(name: &str, arguments: &[String], engine: &Arc<DbEngine>) -> Reply
+{
+    let keys: Vec<&str> = arguments.iter().map(String::as_str).collect();
+    match (name, keys.as_slice()) {
+        ("PING", []) => Reply::Status("PONG".to_string()),
+        ("PING", [message]) => Reply::Bulk(Some(message.to_string())),
+        ("QUIT", _) => Reply::Status("OK".to_string()),
+        // Sent by `redis-cli` when it starts, to learn the commands it can complete
+        ("COMMAND", _) => Reply::Array(vec![]),
+        ("GET", [key]) => match handle(engine, command("LOOKUP", vec![key])).await {
+            Ok(Some(JsonValue::String(value))) => Reply::Bulk(Some(value)),
+            Ok(None | Some(JsonValue::Null)) => Reply::Bulk(None),
+            Ok(Some(value)) => Reply::Bulk(Some(value.to_string())),
+            Err(message) => Reply::Error(message),
+        },
+        ("SET", [key, value, options @ ..]) => {
+            let expires_at = match parse_expiry(options) {
+                Ok(expires_at) => expires_at,
+                Err(message) => return Reply::Error(message),
+            };
+            let mut insert = command("INSERT", vec![key]);
+            insert.values = Some(vec![DbValue {
+                value: JsonValue::String(value.to_string()),
+                expires_at,
+            }]);
+            match handle(engine, insert).await {
+                Ok(_) => Reply::Status("OK".to_string()),
+                Err(message) => Reply::Error(message),
+            }
+        }
+        ("DEL", [_, ..]) => match handle(engine, command("DELETE *", keys.clone())).await {
+            Ok(Some(JsonValue::Array(deleted))) => Reply::Integer(deleted.len() as i64),
+            Ok(_) => Reply::Integer(0),
+            Err(message) => Reply::Error(message),
+        },
+        ("EXPIRE", [key, seconds]) => {
+            let Ok(seconds) = seconds.parse::<u64>() else {
+                return Reply::Error("value is not an integer or out of range".to_string());
+            };
+            let mut touch = command("TOUCH", vec![key]);
+            touch.ttls = Some(vec![Duration::from_secs(seconds)]);
+            match handle(engine, touch).await {
+                Ok(touched) => Reply::Integer(touched.and_then(|touched| touched.as_i64()).unwrap_or(0)),
+                Err(message) => Reply::Error(message),
+            }
+        }
+        ("TTL", [key]) => match engine.connection.read().get(*key) {
+            None => Reply::Integer(-2),
+            Some(data) => match data.ttl() {
+                None => Reply::Integer(-1),
+                Some(ttl) => Reply::Integer(((ttl.as_millis() + 500) / 1000) as i64),
+            },
+        },
+        ("GET" | "SET" | "DEL" | "EXPIRE" | "TTL" | "PING", _) => {
+            Reply::Error(format!("wrong number of arguments for '{}' command", name.to_lowercase()))
+        }
+        _ => Reply::Error(format!("unknown command '{}'", name.to_lowercase())),
+    }
+}
+
+/// Parses the `EX seconds` or `PX milliseconds` options of `SET`.
+fn parse_expiry(options: &[&str]) -> Result<Option<u64>, String>
+{
+    match options {
+        [] => Ok(None),
+        [unit, amount] => {
+            let amount = amount
+                .parse::<u64>()
+                .map_err(|_| "value is not an integer or out of range".to_string())?;
+            match unit.to_uppercase().as_str() {
+                "EX" => Ok(Some(expiry_after(Duration::from_secs(amount)))),
+                "PX" => Ok(Some(expiry_after(Duration::from_millis(amount)))),
+                _ => Err("syntax error".to_string()),
+            }
+        }
+        _ => Err("syntax error".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use clap::Parser;
+
+    use super::*;
+    use crate::cli::Cli;
+
+    async fn run_command(engine: &Arc<DbEngine>, line: &str) -> Reply
+    {
+        let arguments: Vec<String> = line.split_whitespace().map(str::to_string).collect();
+        run(&arguments[0].to_uppercase(), &arguments[1..], engine).await
+    }
+
+    #[tokio::test]
+    async fn test_read_command()
+    {
+        let mut input: &[u8] = b"*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$5\r\na b\r\n\r\nPING\r\n";
+        assert_eq!(
+            read_command(&mut input).await.unwrap(),
+            Some(vec!["SET".to_string(), "key".to_string(), "a b\r\n".to_string()])
+        );
+        assert_eq!(read_command(&mut input).await.unwrap(), Some(vec!["PING".to_string()]));
+        assert_eq!(read_command(&mut input).await.unwrap(), None);
+
+        // Oversized lines and headers are refused before their data is read
+        let long = format!("PING {}\r\n", "a".repeat(MAX_LINE_LEN));
+        assert!(read_command(&mut long.as_bytes()).await.is_err());
+        let headers = format!("*{}\r\n", MAX_ARGUMENTS + 1);
+        assert!(read_command(&mut headers.as_bytes()).await.is_err());
+        let headers = format!("*1\r\n${}\r\n", MAX_BULK_LEN + 1);
+        assert!(read_command(&mut headers.as_bytes()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_commands()
+    {
+        let engine = Arc::new(DbEngine::new(Cli::parse_from(["phoenix-db"])));
+
+        assert_eq!(run_command(&engine, "GET name").await, Reply::Bulk(None));
+        assert_eq!(run_command(&engine, "SET name jamal").await, Reply::Status("OK".to_string()));
+        assert_eq!(run_command(&engine, "GET name").await, Reply::Bulk(Some("jamal".to_string())));
+        assert_eq!(run_command(&engine, "TTL name").await, Reply::Integer(-1));
+        assert_eq!(run_command(&engine, "EXPIRE name 100").await, Reply::Integer(1));
+        assert_eq!(run_command(&engine, "TTL name").await, Reply::Integer(100));
+        assert_eq!(run_command(&engine, "DEL name other").await, Reply::Integer(1));
+        assert_eq!(run_command(&engine, "TTL name").await, Reply::Integer(-2));
+        assert!(matches!(run_command(&engine, "SET name jamal NX").await, Reply::Error(_)));
+
+        let mut out = vec![];
+        Reply::Array(vec![Reply::Bulk(Some("a".to_string())), Reply::Bulk(None)]).encode(&mut out);
+        assert_eq!(out, b"*2\r\n$1\r\na\r\n$-1\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_closes_connections()
+    {
+        let engine = Arc::new(DbEngine::new(Cli::parse_from(["phoenix-db"])));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(listen(listener, engine.clone()));
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"PING\r\n").await.unwrap();
+        let mut reply = [0; 7];
+        stream.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b"+PONG\r\n");
+
+        // The idle connection is closed, then the listener returns
+        engine.shutdown.send_replace(true);
+        assert_eq!(stream.read(&mut reply).await.unwrap(), 0);
+        tokio::time::timeout(Duration::from_secs(1), server).await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,155 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::{BoxFuture, FutureExt};
+use rand::Rng;
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+use tracing::debug;
+
+use crate::services::manager::{Service, ServiceManager};
+
+/// When a job runs again after a run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reschedule
+{
+    /// After the interval the job was registered with.
+    Interval,
+    /// After the given duration, for jobs adapting how often they run.
+    After(Duration),
+    /// Never, the job has nothing left to do.
+    Stop,
+}
+
+/// A periodic background job, such as the TTL sweep.
+///
+/// Jobs keeping state between runs implement this trait on a struct, stateless ones can be closures returning a
+/// future.
+pub trait Job: Send + 'static
+{
+    /// Runs the job once, returning when it should run again.
+    fn run(&mut self) -> BoxFuture<'_, Reschedule>;
+}
+
+impl<F, Fut> Job for F
+where
+    F: FnMut() -> Fut + Send + 'static,
+    Fut: Future<Output = Reschedule> + Send + 'static,
+{
+    fn run(&mut self) -> BoxFuture<'_, Reschedule>
+    {
+        self().boxed()
+    }
+}
+
+/// A job along with how often it runs.
+struct ScheduledJob
+{
+    /// The name the job is logged under.
+    name: &'static str,
+    /// How long to wait between two runs.
+    interval: Duration,
+    /// The most the wait before each run is extended by, at random.
+    jitter: Duration,
+    /// The job itself.
+    job: Box<dyn Job>,
+}
+
+/// Runs the periodic background jobs of the server.
+///
+/// Every job runs on its own task, so a slow job such as a compaction never delays the others. A job first runs
+/// right after the scheduler starts, then waits its interval between runs. The wait is extended by a random share
+/// of its jitter, so jobs started together do not keep running in lockstep.
+///
+/// Each job is registered as a service under its name, so it can be stopped and started again with `SERVICES`.
+#[derive(Default)]
+pub struct Scheduler
+{
+    /// The jobs registered so far.
+    jobs: Vec<ScheduledJob>,
+}
+
+impl Scheduler
+{
+    /// Registers a job to run every `interval`, extended by up to `jitter`.
+    pub fn every(&mut self, name: &'static str, interval: Duration, jitter: Duration, job: impl Job) -> &mut Self
+    {
+        self.jobs.push(ScheduledJob {
+            name,
+            interval,
+            jitter,
+            job: Box::new(job),
+        });
+        self
+    }
+
+    /// Starts every registered job as a service of `services`, returning the handles of their tasks.
+    pub fn start(self, services: &ServiceManager) -> Vec<JoinHandle<()>>
+    {
+        self.jobs
+            .into_iter()
+            .map(|job| {
+                let service = services.register(job.name);
+                tokio::spawn(run(job, service))
+            })
+            .collect()
+    }
+}
+
+/// Runs a job until it stops.
+async fn run(mut scheduled: ScheduledJob, service: Arc<Service>)
+{
+    debug!("Starting the {} job every {:?}", scheduled.name, scheduled.interval);
+    let mut delay = Duration::ZERO;
+
+    loop {
+        let jitter = rand::thread_rng().gen_range(Duration::ZERO..=scheduled.jitter);
+        sleep(delay + jitter).await;
+
+        delay = match service.run(scheduled.job.run()).await {
+            Reschedule::Interval => scheduled.interval,
+            Reschedule::After(delay) => delay,
+            Reschedule::Stop => {
+                debug!("Stopped the {} job", scheduled.name);
+                service.finish();
+                return;
+            }
+        };
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_scheduler_runs_jobs_until_they_stop()
+    {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let job_runs = runs.clone();
+
+        let mut scheduler = Scheduler::default();
+        scheduler.every("test", Duration::from_millis(5), Duration::from_millis(5), move || {
+            let runs = job_runs.clone();
+            async move {
+                match runs.fetch_add(1, Ordering::Relaxed) {
+                    0 => Reschedule::After(Duration::from_millis(1)),
+                    1 => Reschedule::Interval,
+                    _ => Reschedule::Stop,
+                }
+            }
+        });
+
+        let services = ServiceManager::default();
+        for handle in scheduler.start(&services) {
+            handle.await.unwrap();
+        }
+        assert_eq!(runs.load(Ordering::Relaxed), 3);
+        assert_eq!(services.status()[0].runs, 3);
+    }
+}
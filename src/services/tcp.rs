@@ -1,78 +1,834 @@
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::stream::{FuturesUnordered, StreamExt};
+use serde_json::json;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
 use tokio::net::TcpStream;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Instant};
+use tokio_rustls::server::TlsStream;
 use tracing::{debug, error};
 
-use crate::protocol::{Database, NetActions, NetCommand, NetResponse};
+use crate::commands::dump::{apply_dump, parse_mode, DumpLine};
+use crate::commands::namespace::ExportEntry;
+use crate::commands::progress::Progress;
+use crate::commands::{admit, normalize_command_name, run, Effect};
+use crate::frame::{has_frame, push_frame, read_frame, write_frame};
+use crate::metrics::Gauge;
+use crate::protocol::{
+    compressed, json_size, wire_codec, DbEngine, DbKey, JsonCodec, JsonValue, NetActions, NetCommand, NetResponse,
+    WireCodec, WriteDurability, COMPRESSION_HANDSHAKE, COMPRESSION_REFUSED,
+};
+use crate::services::replication;
+use crate::store::DbMap;
+
+/// The commands that take over the connection to stream their responses, rather than replying once.
+const STREAMING_COMMANDS: [&str; 4] = ["DIAGNOSTICS SUBSCRIBE", "SYNC", "BACKUP STREAM", "RESTORE STREAM"];
+
+/// The smallest frame size `HELLO` agrees on, so error responses still fit.
+const MIN_FRAME_LEN: usize = 1024;
+
+/// The size in bytes of the entries sent in a single chunk of `BACKUP STREAM`.
+const BACKUP_CHUNK_SIZE: usize = 64 * 1024;
+
+/// The most commands carrying an `id` a single connection runs at once. Past it, the next commands are not read
+/// until one of them finishes.
+const MAX_CONCURRENT_COMMANDS: usize = 128;
+
+/// The most entries and deleted keys a `RESTORE STREAM` buffers before it is applied.
+const MAX_RESTORE_ENTRIES: usize = 1_000_000;
+
+/// The most bytes of keys and values a `RESTORE STREAM` buffers before it is applied.
+const MAX_RESTORE_SIZE: usize = 1024 * 1024 * 1024;
+
+/// A client connection, either a plain TCP stream or one wrapped in TLS.
+pub trait Connection: AsyncRead + AsyncWrite + Unpin + Send
+{
+    /// Returns the address of the client.
+    fn peer_addr(&self) -> io::Result<SocketAddr>;
+}
+
+impl Connection for TcpStream
+{
+    fn peer_addr(&self) -> io::Result<SocketAddr>
+    {
+        TcpStream::peer_addr(self)
+    }
+}
+
+impl Connection for TlsStream<TcpStream>
+{
+    fn peer_addr(&self) -> io::Result<SocketAddr>
+    {
+        self.get_ref().0.peer_addr()
+    }
+}
+
+/// The stream of a client connection, buffered so the handshake byte can be looked at before it is read.
+pub type ClientStream = BufReader<Box<dyn Connection>>;
 
 /// Handles a single client connection over a TCP stream.
 ///
 /// This function reads commands from the client, processes them using the `handler` function,
 /// and sends back responses or error messages. It runs in a loop until the client disconnects.
 ///
+/// Clients may pipeline commands, sending several without waiting for their responses. Commands run one at a time
+/// in the order they were sent and their responses come back in that order. While the next command has already
+/// been received whole, responses are queued and written together once the client may be waiting for them.
+///
+/// Commands carrying an `id` are the exception: they run in the background while the following commands are read,
+/// and their responses, holding the same `id`, are written as soon as they are ready, in any order. At most
+/// `MAX_CONCURRENT_COMMANDS` run at once, the following commands are read once one of them finishes.
+///
+/// With `--idle-timeout`, a client sending nothing for that long while none of its commands run is sent an error
+/// and disconnected. Clients keep quiet connections open with `PING`.
+///
+/// Once the server shuts down, the commands already received are answered, then the client is sent a
+/// `ShuttingDown` response instead of waiting for the next command and the connection is closed.
+///
 /// # Arguments
 ///
-/// * `stream` - The TCP stream representing the client connection.
-/// * `db` - The database instance used to process commands.
+/// * `stream` - The TCP stream representing the client connection, wrapped in TLS if it is enabled.
+/// * `engine` - The database engine used to process commands.
 ///
 /// # Returns
 ///
 /// A `Result` indicating success or failure of handling the stream. Errors are returned as `String`.
-pub async fn execute(mut stream: TcpStream, db: Database) -> Result<(), String>
+pub async fn execute(stream: impl Connection + 'static, engine: Arc<DbEngine>) -> Result<(), String>
 {
     let client_addr = stream
         .peer_addr()
         .unwrap_or_else(|_| "unknown address".to_string().parse().unwrap());
+    let mut stream: ClientStream = BufReader::new(Box::new(stream));
 
     debug!("New client connected: {}", client_addr);
 
-    let mut buffer = vec![0; 1024];
+    let idle_timeout = engine.db_config.idle_timeout.map(Duration::from_secs);
+    let mut shutdown = engine.shutdown.subscribe();
+
+    // A client that has not sent anything yet is closed when idle or on shutdown as well
+    tokio::select! {
+        biased;
+        _ = stream.fill_buf() => {}
+        _ = async { drop(shutdown.wait_for(|shutdown| *shutdown).await) } => {
+            return send_response(&mut stream, &JsonCodec, &engine, &shutting_down()).await;
+        }
+        _ = sleep(idle_timeout.unwrap_or(Duration::MAX)), if idle_timeout.is_some() => {
+            return send_error_response(&mut stream, &JsonCodec, &idle_error(idle_timeout)).await;
+        }
+    }
+
+    let (codec, compress) = match read_handshake(&mut stream, &engine).await {
+        Ok(handshake) => handshake,
+        Err(e) => return Err(format!("Failed to read from stream: {}", e)),
+    };
+    let _counted = CountedConnection::new(engine.clone(), codec, compress);
+    let codec = if compress { compressed(codec) } else { codec };
+
+    // The responses of pipelined commands not written yet, as frames
+    let mut queued = vec![];
+    // The largest frame exchanged with the client, lowered by `HELLO`
+    let mut max_frame = engine.db_config.max_message_size;
+    // The commands with an `id` still running, each resolving to its encoded response
+    let mut concurrent = FuturesUnordered::new();
 
     loop {
-        match stream.read(&mut buffer).await {
-            Ok(size) => {
-                if size == 0 {
-                    // Client has disconnected
-                    debug!("Client disconnected: {}", client_addr);
-                    return Ok(());
-                }
+        // A client at the limit of concurrent commands is not read from until one of them finishes
+        if concurrent.len() >= MAX_CONCURRENT_COMMANDS {
+            if let Some(encoded) = concurrent.next().await {
+                queue_concurrent(&mut queued, &engine, encoded)?;
+            }
+            write_queued(&mut stream, &mut queued).await?;
+        }
 
-                // Deserialize the incoming data into a `NetCommand` struct
-                match serde_json::from_slice::<NetCommand>(&buffer[..size]) {
-                    Ok(command) => {
-                        // Process the command and get the response
-                        let response = crate::commands::handler(command, db.clone()).await;
-
-                        // Serialize the response to JSON format
-                        match serde_json::to_string(&response) {
-                            Ok(response_json) => {
-                                // Write the response back to the client
-                                if let Err(e) = stream.write_all(response_json.as_bytes()).await {
-                                    error!("Failed to write to stream: {}", e);
-                                    send_error_response(&mut stream, &e.to_string()).await?;
-                                    return Err(format!("Failed to write to stream: {}", e));
-                                }
-                            }
-                            Err(e) => {
-                                error!("Failed to serialize response: {}", e);
-                                send_error_response(&mut stream, &e.to_string()).await?;
-                                return Err(format!("Failed to serialize response: {}", e));
-                            }
+        if !has_frame(stream.buffer()) {
+            write_queued(&mut stream, &mut queued).await?;
+
+            // Write the responses of concurrent commands as they finish, until the client sends more
+            let idle = sleep(idle_timeout.unwrap_or(Duration::MAX));
+            tokio::pin!(idle);
+            loop {
+                tokio::select! {
+                    biased;
+                    Some(encoded) = concurrent.next() => {
+                        queue_concurrent(&mut queued, &engine, encoded)?;
+                        write_queued(&mut stream, &mut queued).await?;
+                        // A client that was waiting for a response is not idle
+                        if let Some(idle_timeout) = idle_timeout {
+                            idle.as_mut().reset(Instant::now() + idle_timeout);
                         }
                     }
-                    Err(e) => {
-                        error!("Failed to deserialize command: {}", e);
-                        send_error_response(&mut stream, &e.to_string()).await?;
-                        return Err(format!("Failed to deserialize command: {}", e));
+                    _ = stream.fill_buf() => break,
+                    _ = async { drop(shutdown.wait_for(|shutdown| *shutdown).await) }, if concurrent.is_empty() => {
+                        debug!("Closing connection for shutdown: {}", client_addr);
+                        return send_response(&mut stream, codec, &engine, &shutting_down()).await;
+                    }
+                    _ = &mut idle, if idle_timeout.is_some() && concurrent.is_empty() => {
+                        debug!("Closing idle connection: {}", client_addr);
+                        return send_error_response(&mut stream, codec, &idle_error(idle_timeout)).await;
                     }
                 }
             }
+        }
+
+        let frame = match read_frame(&mut stream, max_frame).await {
+            Ok(Some(frame)) => frame,
+            Ok(None) => {
+                // Client has disconnected, or only stopped sending and still reads the last responses
+                finish_concurrent(&mut concurrent, &mut queued, &engine).await?;
+                let _ = write_queued(&mut stream, &mut queued).await;
+                debug!("Client disconnected: {}", client_addr);
+                return Ok(());
+            }
             Err(e) => {
                 error!("Failed to read from stream: {}", e);
-                send_error_response(&mut stream, &e.to_string()).await?;
+                write_queued(&mut stream, &mut queued).await?;
+                send_error_response(&mut stream, codec, &e.to_string()).await?;
                 return Err(format!("Failed to read from stream: {}", e));
             }
+        };
+
+        engine.metrics.bytes_read.add(frame.len() as u64);
+        let frame = match codec.unpack(frame, max_frame) {
+            Ok(frame) => frame,
+            Err(e) => {
+                error!("{}", e);
+                write_queued(&mut stream, &mut queued).await?;
+                send_error_response(&mut stream, codec, &e).await?;
+                return Err(e);
+            }
+        };
+
+        // Deserialize the incoming data into a `NetCommand` struct
+        let command = codec.decode(&frame);
+
+        // Commands writing to the stream themselves go after the responses queued before them
+        let queues_response = matches!(&command, Ok(command) if command.progress.is_none()
+            && !STREAMING_COMMANDS.contains(&normalize_command_name(command.name).as_str()));
+        if !queues_response {
+            finish_concurrent(&mut concurrent, &mut queued, &engine).await?;
+            write_queued(&mut stream, &mut queued).await?;
+        }
+
+        if queues_response
+            && matches!(&command, Ok(command) if command.id.is_some()
+            && normalize_command_name(command.name) != "HELLO")
+        {
+            // The command borrows the frame, so the task decodes it again from the frame it owns
+            drop(command);
+            concurrent.push(spawn_concurrent(frame, codec, engine.clone(), max_frame));
+            continue;
+        }
+
+        match command {
+            Ok(command) if normalize_command_name(command.name) == "DIAGNOSTICS SUBSCRIBE" => {
+                debug!("Client subscribed to diagnostics: {}", client_addr);
+                return subscribe_diagnostics(&mut stream, codec, engine).await;
+            }
+            Ok(command) if normalize_command_name(command.name) == "SYNC" => {
+                debug!("Replica started syncing: {}", client_addr);
+                return replication::execute(&mut stream, codec, engine).await;
+            }
+            Ok(command) if normalize_command_name(command.name) == "BACKUP STREAM" => {
+                stream_backup(&mut stream, codec, &engine).await?;
+            }
+            Ok(command) if normalize_command_name(command.name) == "RESTORE STREAM" => {
+                let durability = command.durability;
+                receive_restore(&mut stream, codec, &engine, command.args.unwrap_or_default(), durability).await?;
+            }
+            Ok(command) => {
+                // Process the command and get the response, reporting its progress if asked to
+                let id = command.id;
+                let mut response = if normalize_command_name(command.name) == "HELLO" {
+                    hello(&command, &engine, &mut max_frame)
+                } else {
+                    match command.progress {
+                        Some(every) => handle_with_progress(&mut stream, codec, &engine, command, every).await?,
+                        None => crate::commands::handler(command, engine.clone()).await,
+                    }
+                };
+                response.id = id;
+
+                // Serialize the response to JSON format, replacing it with an error if the client cannot take it
+                match encode_response(codec, &response, max_frame) {
+                    Ok(encoded) => {
+                        // Queue the response, it is written before the next command is waited for
+                        engine.metrics.bytes_written.add(encoded.len() as u64);
+                        if let Err(e) = push_frame(&mut queued, &encoded) {
+                            error!("Failed to write to stream: {}", e);
+                            write_queued(&mut stream, &mut queued).await?;
+                            send_error_response(&mut stream, codec, &e.to_string()).await?;
+                            return Err(format!("Failed to write to stream: {}", e));
+                        }
+                    }
+                    Err(e) => {
+                        error!("Failed to serialize response: {}", e);
+                        write_queued(&mut stream, &mut queued).await?;
+                        send_error_response(&mut stream, codec, &e.to_string()).await?;
+                        return Err(format!("Failed to serialize response: {}", e));
+                    }
+                }
+            }
+            Err(e) => {
+                error!("Failed to deserialize command: {}", e);
+                send_error_response(&mut stream, codec, &e.to_string()).await?;
+                return Err(format!("Failed to deserialize command: {}", e));
+            }
+        }
+    }
+}
+
+/// The response sent to clients whose connection is closed because the server shuts down.
+fn shutting_down() -> NetResponse
+{
+    NetResponse {
+        action: NetActions::ShuttingDown,
+        value: None,
+        error: Some("The server is shutting down.".to_string()),
+        ..Default::default()
+    }
+}
+
+/// The error sent to clients whose connection is closed for staying silent past `idle_timeout`.
+fn idle_error(idle_timeout: Option<Duration>) -> String
+{
+    format!(
+        "The connection was idle for more than {:?}.",
+        idle_timeout.unwrap_or_default()
+    )
+}
+
+/// Handles a `HELLO` command, agreeing with the client on the largest frame either side may send.
+///
+/// The client sends the largest frame it accepts as `args: [{ "max_frame_size": bytes }]`, and both sides use the
+/// smaller of it and `--max-message-size` from then on. Larger commands are refused as soon as their length is
+/// read, and larger responses are replaced with an error.
+///
+/// # Returns
+///
+/// A response with the agreed `max_frame_size` and the version of the server.
+fn hello(command: &NetCommand<'_>, engine: &DbEngine, max_frame: &mut usize) -> NetResponse
+{
+    let requested = command
+        .args
+        .as_ref()
+        .and_then(|args| args.first())
+        .and_then(|options| options["max_frame_size"].as_u64());
+    *max_frame = match requested {
+        Some(requested) => engine.db_config.max_message_size.min(requested as usize).max(MIN_FRAME_LEN),
+        None => engine.db_config.max_message_size,
+    };
+
+    NetResponse {
+        action: NetActions::Command,
+        value: Some(json!({ "max_frame_size": max_frame, "version": env!("CARGO_PKG_VERSION") })),
+        error: None,
+        ..Default::default()
+    }
+}
+
+/// Encodes a response, replacing it with an error if it is larger than the frames the client accepts.
+fn encode_response(codec: &dyn WireCodec, response: &NetResponse, max_frame: usize) -> Result<Vec<u8>, String>
+{
+    let encoded = codec.encode(response)?;
+    if encoded.len() <= max_frame {
+        return Ok(encoded);
+    }
+    codec.encode(&NetResponse {
+        action: NetActions::Error,
+        value: None,
+        error: Some(format!(
+            "The response of {} bytes is larger than the frame size of {} bytes.",
+            encoded.len(),
+            max_frame
+        )),
+        id: response.id,
+        ..Default::default()
+    })
+}
+
+/// Runs a command carrying an `id` in the background.
+///
+/// # Returns
+///
+/// A handle resolving to the encoded response of the command.
+fn spawn_concurrent(
+    frame: Vec<u8>,
+    codec: &'static dyn WireCodec,
+    engine: Arc<DbEngine>,
+    max_frame: usize,
+) -> JoinHandle<Result<Vec<u8>, String>>
+{
+    tokio::spawn(async move {
+        let command = codec.decode(&frame)?;
+        let id = command.id;
+        let mut response = crate::commands::handler(command, engine).await;
+        response.id = id;
+        encode_response(codec, &response, max_frame)
+    })
+}
+
+/// Queues the response of a command that ran in the background.
+fn queue_concurrent(
+    queued: &mut Vec<u8>,
+    engine: &DbEngine,
+    encoded: Result<Result<Vec<u8>, String>, tokio::task::JoinError>,
+) -> Result<(), String>
+{
+    let encoded = encoded
+        .map_err(|e| e.to_string())
+        .and_then(|encoded| encoded)
+        .map_err(|e| format!("Failed to run command: {}", e))?;
+    engine.metrics.bytes_written.add(encoded.len() as u64);
+    push_frame(queued, &encoded).map_err(|e| format!("Failed to write to stream: {}", e))
+}
+
+/// Waits for every command running in the background and queues their responses.
+async fn finish_concurrent(
+    concurrent: &mut FuturesUnordered<JoinHandle<Result<Vec<u8>, String>>>,
+    queued: &mut Vec<u8>,
+    engine: &DbEngine,
+) -> Result<(), String>
+{
+    while let Some(encoded) = concurrent.next().await {
+        queue_concurrent(queued, engine, encoded)?;
+    }
+    Ok(())
+}
+
+/// Writes the queued responses of pipelined commands to the client.
+async fn write_queued(stream: &mut ClientStream, queued: &mut Vec<u8>) -> Result<(), String>
+{
+    if queued.is_empty() {
+        return Ok(());
+    }
+    stream
+        .write_all(queued)
+        .await
+        .map_err(|e| format!("Failed to write to stream: {}", e))?;
+    stream
+        .flush()
+        .await
+        .map_err(|e| format!("Failed to write to stream: {}", e))?;
+    queued.clear();
+    Ok(())
+}
+
+/// Reads the handshake bytes a client may send when it connects to choose how its messages are encoded.
+///
+/// A client asking for compression sends `Z` first, which the server accepts by sending it back, or refuses with
+/// `N` while compression is turned off, and may then choose an encoding as any other client.
+///
+/// # Returns
+///
+/// The encoding chosen by the client, or JSON if it sent no handshake for it, and whether it is compressed.
+async fn read_handshake(stream: &mut ClientStream, engine: &DbEngine) -> io::Result<(&'static dyn WireCodec, bool)>
+{
+    let mut compress = false;
+    if stream.fill_buf().await?.first() == Some(&COMPRESSION_HANDSHAKE) {
+        stream.consume(1);
+        compress = engine.compression.load(Ordering::Relaxed);
+        if !compress {
+            engine.metrics.compression_refused.increment();
+        }
+        let reply = if compress {
+            COMPRESSION_HANDSHAKE
+        } else {
+            COMPRESSION_REFUSED
+        };
+        stream.write_all(&[reply]).await?;
+        stream.flush().await?;
+    }
+
+    let codec = match stream.fill_buf().await?.first().and_then(|&first| wire_codec(first)) {
+        Some(codec) => {
+            stream.consume(1);
+            codec
+        }
+        None => &JsonCodec,
+    };
+    Ok((codec, compress))
+}
+
+/// Counts an open connection under its encoding, and its compression if any, in the metrics until it is dropped.
+struct CountedConnection
+{
+    engine: Arc<DbEngine>,
+    codec: &'static dyn WireCodec,
+    compressed: bool,
+}
+
+impl CountedConnection
+{
+    fn new(engine: Arc<DbEngine>, codec: &'static dyn WireCodec, compressed: bool) -> Self
+    {
+        let counted = Self {
+            engine,
+            codec,
+            compressed,
+        };
+        counted.gauges().for_each(Gauge::increment);
+        counted
+    }
+
+    /// Returns the gauges the connection is counted in.
+    fn gauges(&self) -> impl Iterator<Item = &Gauge>
+    {
+        let metrics = &self.engine.metrics;
+        let encoding = match self.codec.handshake() {
+            b'M' => &metrics.message_pack_connections,
+            _ => &metrics.json_connections,
+        };
+        [Some(encoding), self.compressed.then_some(&metrics.compressed_connections)]
+            .into_iter()
+            .flatten()
+    }
+}
+
+impl Drop for CountedConnection
+{
+    fn drop(&mut self)
+    {
+        self.gauges().for_each(Gauge::decrement);
+    }
+}
+
+/// Handles a command that asked for progress, sending the progress responses of its bulk commands to the client
+/// while it runs.
+///
+/// # Returns
+///
+/// The final response of the command, sent after every progress response.
+async fn handle_with_progress(
+    stream: &mut ClientStream,
+    codec: &dyn WireCodec,
+    engine: &Arc<DbEngine>,
+    command: NetCommand<'_>,
+    every: usize,
+) -> Result<NetResponse, String>
+{
+    let (progress, mut frames) = Progress::new(every);
+    let handling = progress.scope(crate::commands::handler(command, engine.clone()));
+    tokio::pin!(handling);
+
+    loop {
+        tokio::select! {
+            biased;
+            Some(frame) = frames.recv() => send_response(stream, codec, engine, &frame).await?,
+            response = &mut handling => {
+                while let Ok(frame) = frames.try_recv() {
+                    send_response(stream, codec, engine, &frame).await?;
+                }
+                return Ok(response);
+            }
+        }
+    }
+}
+
+/// Turns the connection into a `DIAGNOSTICS SUBSCRIBE` subscription, pushing every server event to the client
+/// as a response with the `Event` action until it disconnects. Anything the client sends afterwards is ignored.
+///
+/// # Arguments
+///
+/// * `stream` - The TCP stream representing the client connection.
+/// * `codec` - The encoding chosen by the client.
+/// * `engine` - The database engine emitting the events.
+///
+/// # Returns
+///
+/// A `Result` indicating success or failure of pushing the events. Errors are returned as `String`.
+async fn subscribe_diagnostics(stream: &mut ClientStream, codec: &dyn WireCodec, engine: Arc<DbEngine>)
+    -> Result<(), String>
+{
+    let mut events = engine.diagnostics.subscribe();
+    let mut response = NetResponse {
+        action: NetActions::Command,
+        value: Some("OK".to_string().into()),
+        error: None,
+        ..Default::default()
+    };
+    let mut buffer = vec![0; 1024];
+
+    loop {
+        let encoded = codec.encode(&response)?;
+        engine.metrics.bytes_written.add(encoded.len() as u64);
+        if let Err(e) = write_frame(stream, &encoded).await {
+            return Err(format!("Failed to write to stream: {}", e));
+        }
+
+        response = loop {
+            tokio::select! {
+                event = events.recv() => match event {
+                    Ok(event) => break NetResponse {
+                        action: NetActions::Event,
+                        value: Some(json!(event)),
+                        error: None,
+                        ..Default::default()
+                    },
+                    Err(RecvError::Lagged(missed)) => break NetResponse {
+                        action: NetActions::Event,
+                        value: None,
+                        error: None,
+                        warnings: vec![format!("{} events were missed because the client fell behind.", missed)],
+                        ..Default::default()
+                    },
+                    Err(RecvError::Closed) => return Ok(()),
+                },
+                read = stream.read(&mut buffer) => match read {
+                    Ok(0) | Err(_) => return Ok(()),
+                    Ok(_) => continue,
+                },
+            }
+        };
+    }
+}
+
+/// Streams a `BACKUP STREAM` of every entry of the database to the client, in the format of `EXPORT`.
+///
+/// The entries are sent in responses with the `Chunk` action, each holding a list of entries about
+/// `BACKUP_CHUNK_SIZE` bytes large, followed by a `Command` response with the number of keys and chunks sent. The
+/// backup is taken when the command runs, writes made while it is streamed are not part of it.
+///
+/// # Arguments
+///
+/// * `stream` - The TCP stream representing the client connection.
+/// * `codec` - The encoding chosen by the client.
+/// * `engine` - The database engine to back up.
+///
+/// # Returns
+///
+/// A `Result` indicating success or failure of streaming the backup. Errors are returned as `String`.
+async fn stream_backup(stream: &mut ClientStream, codec: &dyn WireCodec, engine: &DbEngine) -> Result<(), String>
+{
+    let map = engine.connection.read();
+    let chunks = stream_entries(stream, codec, engine, &map).await?;
+
+    let response = NetResponse {
+        action: NetActions::Command,
+        value: Some(json!({ "keys": map.len(), "chunks": chunks })),
+        error: None,
+        ..Default::default()
+    };
+    send_response(stream, codec, engine, &response).await
+}
+
+/// Sends every entry of `map` to the client in responses with the `Chunk` action, each holding a list of entries
+/// about `BACKUP_CHUNK_SIZE` bytes large.
+///
+/// # Returns
+///
+/// The number of chunks sent.
+pub(crate) async fn stream_entries(
+    stream: &mut ClientStream,
+    codec: &dyn WireCodec,
+    engine: &DbEngine,
+    map: &DbMap,
+) -> Result<usize, String>
+{
+    let mut chunk = vec![];
+    let mut chunk_size = 0;
+    let mut chunks = 0;
+
+    for (key, data) in map.iter() {
+        chunk_size += key.len() + json_size(&data.value);
+        chunk.push(json!(ExportEntry {
+            key: key.clone(),
+            data: data.clone(),
+        }));
+
+        if chunk_size >= BACKUP_CHUNK_SIZE {
+            send_chunk(stream, codec, engine, std::mem::take(&mut chunk)).await?;
+            chunk_size = 0;
+            chunks += 1;
         }
     }
+    if !chunk.is_empty() {
+        send_chunk(stream, codec, engine, chunk).await?;
+        chunks += 1;
+    }
+    Ok(chunks)
+}
+
+/// Sends a part of a streamed response to the client.
+async fn send_chunk(
+    stream: &mut ClientStream,
+    codec: &dyn WireCodec,
+    engine: &DbEngine,
+    values: Vec<JsonValue>,
+) -> Result<(), String>
+{
+    let response = NetResponse {
+        action: NetActions::Chunk,
+        value: Some(JsonValue::Array(values)),
+        error: None,
+        ..Default::default()
+    };
+    send_response(stream, codec, engine, &response).await
+}
+
+/// Receives a `RESTORE STREAM [mode]` pushed by the client, where `mode` is `merge` or `replace` as for `IMPORT`.
+///
+/// Once the stream is accepted, the client sends `RESTORE CHUNK` commands whose arguments are lines of an
+/// `EXPORT` or `BACKUP INCREMENTAL` file, waiting for each to be acknowledged with the number of lines received
+/// so far. `RESTORE END` applies the restore at once and `RESTORE ABORT` discards it, as does disconnecting. A
+/// chunk holding an invalid line is rejected as a whole and can be sent again.
+///
+/// The restore is admitted and acknowledged like `IMPORT`: it is refused when the feature is disabled, on a node
+/// that is not the primary and past `--max-memory`, both when it starts and when it is applied. A restore
+/// buffering more than `MAX_RESTORE_ENTRIES` lines or `MAX_RESTORE_SIZE` bytes is aborted with an error.
+///
+/// # Arguments
+///
+/// * `stream` - The TCP stream representing the client connection.
+/// * `codec` - The encoding chosen by the client.
+/// * `engine` - The database engine to restore into.
+/// * `args` - The arguments of `RESTORE STREAM`.
+/// * `durability` - How durable the client asked the restore to be before it is acknowledged.
+///
+/// # Returns
+///
+/// A `Result` indicating success or failure of receiving the restore. Errors are returned as `String`.
+async fn receive_restore(
+    stream: &mut ClientStream,
+    codec: &dyn WireCodec,
+    engine: &Arc<DbEngine>,
+    args: Vec<JsonValue>,
+    durability: Option<WriteDurability>,
+) -> Result<(), String>
+{
+    if let Err(response) = admit("RESTORE STREAM", Effect::Write, engine, false) {
+        return send_response(stream, codec, engine, &response).await;
+    }
+    let replace = match parse_mode(args.first()) {
+        Ok(replace) => replace,
+        Err(error) => return send_error_response(stream, codec, &error).await,
+    };
+
+    let mut response = NetResponse {
+        action: NetActions::Command,
+        value: Some("OK".to_string().into()),
+        error: None,
+        ..Default::default()
+    };
+    let mut entries: Vec<ExportEntry> = vec![];
+    let mut removed: Vec<DbKey> = vec![];
+    let mut size = 0;
+
+    loop {
+        send_response(stream, codec, engine, &response).await?;
+
+        let frame = match read_frame(stream, engine.db_config.max_message_size).await {
+            Ok(Some(frame)) => frame,
+            Ok(None) => return Ok(()),
+            Err(e) => return Err(format!("Failed to read from stream: {}", e)),
+        };
+        engine.metrics.bytes_read.add(frame.len() as u64);
+
+        let frame = match codec.unpack(frame, engine.db_config.max_message_size) {
+            Ok(frame) => frame,
+            Err(e) => return send_error_response(stream, codec, &e).await,
+        };
+        let command = match codec.decode(&frame) {
+            Ok(command) => command,
+            Err(e) => return send_error_response(stream, codec, &e.to_string()).await,
+        };
+        response = match normalize_command_name(command.name).as_str() {
+            "RESTORE CHUNK" => {
+                let lines: Result<Vec<DumpLine>, _> = command
+                    .args
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(serde_json::from_value)
+                    .collect();
+                match lines {
+                    Ok(lines)
+                        if lines
+                            .iter()
+                            .any(|line| matches!(line, DumpLine::Deleted { deleted: false, .. })) =>
+                    {
+                        restore_error("A deleted key must have 'deleted' set to true.".to_string())
+                    }
+                    Ok(lines) => {
+                        for line in lines {
+                            match line {
+                                DumpLine::Entry(entry) => {
+                                    size += entry.key.len() + json_size(&entry.data.value);
+                                    entries.push(entry);
+                                }
+                                DumpLine::Deleted { key, .. } => {
+                                    size += key.len();
+                                    removed.push(key);
+                                }
+                            }
+                        }
+                        if entries.len() + removed.len() > MAX_RESTORE_ENTRIES || size > MAX_RESTORE_SIZE {
+                            let error = format!(
+                                "The restore is larger than {} lines or {} bytes and was aborted.",
+                                MAX_RESTORE_ENTRIES, MAX_RESTORE_SIZE
+                            );
+                            return send_error_response(stream, codec, &error).await;
+                        }
+                        NetResponse {
+                            action: NetActions::Command,
+                            value: Some(json!({ "received": entries.len() + removed.len() })),
+                            error: None,
+                            ..Default::default()
+                        }
+                    }
+                    Err(e) => restore_error(format!("Invalid restore chunk: {}", e)),
+                }
+            }
+            "RESTORE END" => {
+                let response = match admit("RESTORE STREAM", Effect::Write, engine, false) {
+                    Ok(warnings) => {
+                        let restore_engine = engine.clone();
+                        let restore = async move { apply_dump(&restore_engine, entries, removed, replace).await };
+                        run("RESTORE STREAM", Effect::Write, durability, warnings, engine, restore).await
+                    }
+                    Err(response) => *response,
+                };
+                return send_response(stream, codec, engine, &response).await;
+            }
+            "RESTORE ABORT" => {
+                response.value = Some("OK".to_string().into());
+                return send_response(stream, codec, engine, &response).await;
+            }
+            _ => {
+                restore_error("Only RESTORE CHUNK, RESTORE END and RESTORE ABORT are accepted during a restore.".to_string())
+            }
+        };
+    }
+}
+
+/// Builds an error response for a command sent during a `RESTORE STREAM`.
+fn restore_error(message: String) -> NetResponse
+{
+    NetResponse {
+        action: NetActions::Error,
+        value: None,
+        error: Some(message),
+        ..Default::default()
+    }
+}
+
+/// Sends a response to the client.
+pub(crate) async fn send_response(
+    stream: &mut ClientStream,
+    codec: &dyn WireCodec,
+    engine: &DbEngine,
+    response: &NetResponse,
+) -> Result<(), String>
+{
+    let encoded = codec.encode(response)?;
+    engine.metrics.bytes_written.add(encoded.len() as u64);
+    write_frame(stream, &encoded)
+        .await
+        .map_err(|e| format!("Failed to write to stream: {}", e))
 }
 
 /// Sends an error response back to the client.
@@ -82,25 +838,31 @@ pub async fn execute(mut stream: TcpStream, db: Database) -> Result<(), String>
 /// # Arguments
 ///
 /// * `stream` - The TCP stream representing the client connection.
+/// * `codec` - The encoding chosen by the client.
 /// * `error_message` - The error message to include in the response.
 ///
 /// # Returns
 ///
 /// A `Result` indicating success or failure of sending the error response. Errors are returned as `String`.
-async fn send_error_response(stream: &mut TcpStream, error_message: &str) -> Result<(), String>
+pub(crate) async fn send_error_response(
+    stream: &mut ClientStream,
+    codec: &dyn WireCodec,
+    error_message: &str,
+) -> Result<(), String>
 {
     // Create an error response with the provided error message
     let error_response = NetResponse {
         action: NetActions::Error,
         value: None,
         error: Some(error_message.to_string()),
+        ..Default::default()
     };
 
     // Serialize the error response to JSON format
-    match serde_json::to_string(&error_response) {
-        Ok(response_json) => {
+    match codec.encode(&error_response) {
+        Ok(encoded) => {
             // Write the error response back to the client
-            if let Err(e) = stream.write_all(response_json.as_bytes()).await {
+            if let Err(e) = write_frame(stream, &encoded).await {
                 error!("Failed to write error response to stream: {}", e);
                 return Err(format!("Failed to write error response to stream: {}", e));
             }
@@ -113,3 +875,250 @@ async fn send_error_response(stream: &mut TcpStream, error_message: &str) -> Res
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test
+{
+    use clap::Parser;
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::cli::Cli;
+    use crate::frame::MAX_FRAME_LEN;
+
+    #[tokio::test]
+    async fn test_pipelining()
+    {
+        let engine = Arc::new(DbEngine::new(Cli::parse_from(["phoenix-db"])));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            execute(stream, engine).await
+        });
+
+        // Every command is sent before any response is read
+        let mut commands = vec![];
+        for n in 0..10 {
+            let insert = format!(r#"{{"name":"INSERT","keys":["n"],"values":[{{"value":{}}}]}}"#, n);
+            push_frame(&mut commands, insert.as_bytes()).unwrap();
+            push_frame(&mut commands, br#"{"name":"LOOKUP","keys":["n"]}"#).unwrap();
+        }
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(&commands).await.unwrap();
+
+        for n in 0..10 {
+            read_frame(&mut stream, MAX_FRAME_LEN).await.unwrap().unwrap();
+            let lookup: JsonValue =
+                serde_json::from_slice(&read_frame(&mut stream, MAX_FRAME_LEN).await.unwrap().unwrap()).unwrap();
+            assert_eq!(lookup["value"], n);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_commands()
+    {
+        let engine = Arc::new(DbEngine::new(Cli::parse_from(["phoenix-db"])));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            execute(stream, engine).await
+        });
+
+        // The blocked read does not hold up the commands sent after it
+        let mut commands = vec![];
+        push_frame(
+            &mut commands,
+            br#"{"name":"XREAD","keys":["events"],"args":["$",10,5000],"id":1}"#,
+        )
+        .unwrap();
+        push_frame(&mut commands, br#"{"name":"XADD","keys":["events"],"args":["login"],"id":2}"#).unwrap();
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(&commands).await.unwrap();
+
+        let mut responses = vec![];
+        for _ in 0..2 {
+            let response: JsonValue =
+                serde_json::from_slice(&read_frame(&mut stream, MAX_FRAME_LEN).await.unwrap().unwrap()).unwrap();
+            responses.push(response);
+        }
+        assert_eq!(responses[0]["id"], 2);
+        assert_eq!(responses[1]["id"], 1);
+        assert_eq!(responses[1]["value"][0]["value"], "login");
+    }
+
+    #[tokio::test]
+    async fn test_restore_stream_is_refused_out_of_memory()
+    {
+        let engine = Arc::new(DbEngine::new(Cli::parse_from(["phoenix-db", "--max-memory", "1000"])));
+        engine.memory.update(1000);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            execute(stream, engine).await
+        });
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        write_frame(&mut stream, br#"{"name":"RESTORE STREAM"}"#).await.unwrap();
+        let response: JsonValue =
+            serde_json::from_slice(&read_frame(&mut stream, MAX_FRAME_LEN).await.unwrap().unwrap()).unwrap();
+        assert_eq!(response["action"], "Error");
+        assert_eq!(response["value"]["reason"], "out_of_memory");
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_commands_are_capped()
+    {
+        let engine = Arc::new(DbEngine::new(Cli::parse_from(["phoenix-db"])));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            execute(stream, engine).await
+        });
+
+        // Blocked reads fill up the limit, so the command sent after them waits for one to time out
+        let mut commands = vec![];
+        for id in 0..MAX_CONCURRENT_COMMANDS {
+            let xread = format!(r#"{{"name":"XREAD","keys":["events"],"args":["$",10,200],"id":{}}}"#, id);
+            push_frame(&mut commands, xread.as_bytes()).unwrap();
+        }
+        let ping = format!(r#"{{"name":"PING","id":{}}}"#, MAX_CONCURRENT_COMMANDS);
+        push_frame(&mut commands, ping.as_bytes()).unwrap();
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(&commands).await.unwrap();
+
+        let first: JsonValue =
+            serde_json::from_slice(&read_frame(&mut stream, MAX_FRAME_LEN).await.unwrap().unwrap()).unwrap();
+        assert!(first["id"].as_u64().unwrap() < MAX_CONCURRENT_COMMANDS as u64);
+    }
+
+    #[tokio::test]
+    async fn test_compression()
+    {
+        let engine = Arc::new(DbEngine::new(Cli::parse_from(["phoenix-db"])));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            execute(stream, engine).await
+        });
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(&[COMPRESSION_HANDSHAKE]).await.unwrap();
+        assert_eq!(stream.read_u8().await.unwrap(), COMPRESSION_HANDSHAKE);
+
+        // Repetitive bulk payloads shrink a lot on the wire
+        let keys: Vec<String> = (0..1000).map(|n| format!("user:{}", n)).collect();
+        let values: Vec<JsonValue> = (0..1000).map(|_| json!({ "value": { "plan": "free" } })).collect();
+        let insert = serde_json::to_vec(&json!({ "name": "INSERT *", "keys": keys, "values": values })).unwrap();
+        let packed = zstd::bulk::compress(&insert, 0).unwrap();
+        assert!(packed.len() * 10 < insert.len());
+        write_frame(&mut stream, &packed).await.unwrap();
+        read_frame(&mut stream, MAX_FRAME_LEN).await.unwrap().unwrap();
+
+        let lookup = serde_json::to_vec(&json!({ "name": "LOOKUP *", "keys": keys })).unwrap();
+        write_frame(&mut stream, &zstd::bulk::compress(&lookup, 0).unwrap())
+            .await
+            .unwrap();
+        let frame = read_frame(&mut stream, MAX_FRAME_LEN).await.unwrap().unwrap();
+        let response: JsonValue = serde_json::from_slice(&zstd::bulk::decompress(&frame, MAX_FRAME_LEN).unwrap()).unwrap();
+        assert_eq!(response["value"].as_array().unwrap().len(), 1000);
+    }
+
+    #[tokio::test]
+    async fn test_compression_turned_off()
+    {
+        let engine = Arc::new(DbEngine::new(Cli::parse_from(["phoenix-db"])));
+        engine.compression.store(false, Ordering::Relaxed);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_engine = engine.clone();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            execute(stream, server_engine).await
+        });
+
+        // The client is told compression is refused and goes on uncompressed, in the encoding it chose
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(&[COMPRESSION_HANDSHAKE, b'J']).await.unwrap();
+        assert_eq!(stream.read_u8().await.unwrap(), COMPRESSION_REFUSED);
+        write_frame(&mut stream, br#"{"name":"PING"}"#).await.unwrap();
+        let frame = read_frame(&mut stream, MAX_FRAME_LEN).await.unwrap().unwrap();
+        assert_eq!(serde_json::from_slice::<JsonValue>(&frame).unwrap()["action"], "Command");
+
+        let metrics = &engine.metrics;
+        assert_eq!(metrics.compression_refused.get(), 1);
+        assert_eq!((metrics.json_connections.get(), metrics.compressed_connections.get()), (1, 0));
+        drop(stream);
+        for _ in 0..100 {
+            if metrics.json_connections.get() == 0 {
+                return;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        panic!("the closed connection is still counted");
+    }
+
+    #[tokio::test]
+    async fn test_idle_timeout()
+    {
+        let engine = Arc::new(DbEngine::new(Cli::parse_from(["phoenix-db", "--idle-timeout", "1"])));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            execute(stream, engine).await
+        });
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        write_frame(&mut stream, br#"{"name":"PING"}"#).await.unwrap();
+        let pong: JsonValue =
+            serde_json::from_slice(&read_frame(&mut stream, MAX_FRAME_LEN).await.unwrap().unwrap()).unwrap();
+        assert_eq!(pong["value"]["reply"], "PONG");
+
+        // Staying silent gets the connection closed with an error
+        let started = Instant::now();
+        let error: JsonValue =
+            serde_json::from_slice(&read_frame(&mut stream, MAX_FRAME_LEN).await.unwrap().unwrap()).unwrap();
+        assert_eq!(error["action"], "Error");
+        assert!(started.elapsed() >= Duration::from_millis(900));
+        assert!(read_frame(&mut stream, MAX_FRAME_LEN).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_closes_connections()
+    {
+        let engine = Arc::new(DbEngine::new(Cli::parse_from(["phoenix-db"])));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_engine = engine.clone();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            execute(stream, server_engine).await
+        });
+
+        // The command still running when the server shuts down is answered first
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        write_frame(
+            &mut stream,
+            br#"{"name":"XREAD","keys":["events"],"args":["$",10,200],"id":1}"#,
+        )
+        .await
+        .unwrap();
+        sleep(Duration::from_millis(50)).await;
+        engine.shutdown.send_replace(true);
+
+        let mut responses = vec![];
+        for _ in 0..2 {
+            let response: JsonValue =
+                serde_json::from_slice(&read_frame(&mut stream, MAX_FRAME_LEN).await.unwrap().unwrap()).unwrap();
+            responses.push(response);
+        }
+        assert_eq!(responses[0]["id"], 1);
+        assert_eq!(responses[1]["action"], "ShuttingDown");
+        assert!(read_frame(&mut stream, MAX_FRAME_LEN).await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,193 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde_json::json;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tracing::{debug, warn};
+
+use crate::cli::Cli;
+use crate::protocol::{DbEngine, JsonValue};
+use crate::services::scheduler::Reschedule;
+
+/// How long sending a report may take before it is given up.
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Opt-in reporting of anonymous usage statistics to the maintainers.
+///
+/// Reports only hold aggregates: the version, the uptime, how many commands of each class ran and the order of
+/// magnitude of the number of keys. Never any key, value or address. Reporting is off unless the server is started
+/// with `--telemetry`, and can be turned on and off at runtime with `TELEMETRY ON` and `TELEMETRY OFF`.
+#[derive(Debug)]
+pub struct Telemetry
+{
+    /// The `http://` URL reports are posted to, reporting is impossible without one.
+    endpoint: Option<String>,
+    /// Whether reports are currently sent.
+    enabled: AtomicBool,
+    /// When the server started, to report its uptime.
+    started: Instant,
+}
+
+impl Telemetry
+{
+    /// Creates the telemetry of a server, enabled only if it was started with `--telemetry` and an endpoint.
+    pub fn new(args: &Cli) -> Self
+    {
+        Self {
+            endpoint: args.telemetry_endpoint.clone(),
+            enabled: AtomicBool::new(args.telemetry && args.telemetry_endpoint.is_some()),
+            started: Instant::now(),
+        }
+    }
+
+    /// Returns whether reports are currently sent.
+    pub fn is_enabled(&self) -> bool
+    {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Turns reporting on or off, failing to turn it on without an endpoint.
+    pub fn set_enabled(&self, enabled: bool) -> Result<(), String>
+    {
+        if enabled && self.endpoint.is_none() {
+            return Err("Telemetry needs the server to be started with --telemetry-endpoint.".to_string());
+        }
+        self.enabled.store(enabled, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Returns the URL reports are posted to, if any.
+    pub fn endpoint(&self) -> Option<&str>
+    {
+        self.endpoint.as_deref()
+    }
+}
+
+/// Builds the report sent to the telemetry endpoint, also shown by `TELEMETRY STATUS`.
+pub fn report(engine: &DbEngine) -> JsonValue
+{
+    let metrics = &engine.metrics;
+    json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "uptime_secs": engine.telemetry.started.elapsed().as_secs(),
+        "commands": {
+            "read": metrics.read_commands.get(),
+            "write": metrics.write_commands.get(),
+            "bulk": metrics.bulk_commands.get(),
+            "admin": metrics.admin_commands.get(),
+        },
+        "keys_magnitude": magnitude(engine.connection.read().len()),
+    })
+}
+
+/// Sends a report to the telemetry endpoint if reporting is enabled, run periodically by the scheduler.
+///
+/// # Arguments
+///
+/// * `engine` - The database engine to report on.
+pub async fn send(engine: Arc<DbEngine>) -> Reschedule
+{
+    let Some(endpoint) = engine.telemetry.endpoint().filter(|_| engine.telemetry.is_enabled()) else {
+        return Reschedule::Interval;
+    };
+
+    let body = report(&engine).to_string();
+    match tokio::time::timeout(SEND_TIMEOUT, post(endpoint, body.as_bytes())).await {
+        Ok(Ok(())) => debug!("Sent a telemetry report to {}", endpoint),
+        Ok(Err(e)) => warn!("Failed to send a telemetry report to {}: {}", endpoint, e),
+        Err(_) => warn!("Failed to send a telemetry report to {}: timed out", endpoint),
+    }
+    Reschedule::Interval
+}
+
+/// Rounds a count down to a power of ten, so reports do not reveal the exact size of the database.
+fn magnitude(count: usize) -> u64
+{
+    match count {
+        0 => 0,
+        count => 10u64.pow(count.ilog10()),
+    }
+}
+
+/// Posts a JSON `body` to an `http://host[:port][/path]` URL.
+async fn post(endpoint: &str, body: &[u8]) -> io::Result<()>
+{
+    let url = endpoint
+        .strip_prefix("http://")
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "the endpoint must be an http:// URL"))?;
+    let (host, path) = match url.find('/') {
+        Some(index) => url.split_at(index),
+        None => (url, "/"),
+    };
+    let addr = if host.contains(':') {
+        host.to_string()
+    } else {
+        format!("{}:80", host)
+    };
+
+    let mut stream = TcpStream::connect(addr).await?;
+    let head = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        path,
+        host,
+        body.len()
+    );
+    let mut request = head.into_bytes();
+    request.extend_from_slice(body);
+    stream.write_all(&request).await?;
+
+    let mut response = vec![];
+    stream.read_to_end(&mut response).await?;
+    let response = String::from_utf8_lossy(&response);
+    let status = response.split_whitespace().nth(1).unwrap_or_default();
+    if !status.starts_with('2') {
+        return Err(io::Error::other(format!("the endpoint replied with status {:?}", status)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test
+{
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn test_magnitude()
+    {
+        assert_eq!(magnitude(0), 0);
+        assert_eq!(magnitude(7), 1);
+        assert_eq!(magnitude(10), 10);
+        assert_eq!(magnitude(98_765), 10_000);
+    }
+
+    #[tokio::test]
+    async fn test_post()
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}/v1/report", listener.local_addr().unwrap());
+
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = vec![];
+            let mut buffer = vec![0; 4096];
+            while !request.ends_with(b"}") {
+                let size = stream.read(&mut buffer).await.unwrap();
+                request.extend_from_slice(&buffer[..size]);
+            }
+            stream.write_all(b"HTTP/1.1 204 No Content\r\n\r\n").await.unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        post(&endpoint, b"{\"version\":\"1\"}").await.unwrap();
+        let request = server.await.unwrap();
+        assert!(request.starts_with("POST /v1/report HTTP/1.1\r\n"));
+        assert!(request.ends_with("\r\n\r\n{\"version\":\"1\"}"));
+
+        assert!(post("https://example.com", b"{}").await.is_err());
+    }
+}
@@ -1,47 +1,275 @@
+use std::str::FromStr;
+use std::sync::Arc;
 use std::time::Duration;
 
-use tokio::time::{interval, Instant};
+use futures::future::{BoxFuture, FutureExt};
+use serde_json::json;
 use tracing::debug;
 
-use crate::protocol::Database;
+use crate::commands::derived::refresh_derived;
+use crate::protocol::{unix_millis, DbEngine, DbKey, JsonValue};
+use crate::services::scheduler::{Job, Reschedule};
 
-/// A background task that periodically cleans up expired entries in the database.
+/// The share of entries with a TTL that has to be found expired for the sweeper to speed up, as a fraction.
+const TTL_PRESSURE_RATIO: f64 = 0.25;
+
+/// Chooses how long to wait before the next sweep, based on how many entries the last one removed.
 ///
-/// This function runs an infinite loop, using a configurable interval to determine how often
-/// the cleanup should occur. During each iteration, it acquires a write lock on the database,
-/// checks the expiration times of all entries, and removes those that have expired based on
-/// their `expires_at` timestamp.
+/// The interval is halved when at least a quarter of the entries with a TTL had expired, and doubled when none
+/// had, staying within `min_interval` and `max_interval`.
+fn next_interval(
+    current: Duration,
+    expired: usize,
+    with_ttl: usize,
+    min_interval: Duration,
+    max_interval: Duration,
+) -> Duration
+{
+    let next = if expired == 0 {
+        current * 2
+    } else if expired as f64 >= with_ttl as f64 * TTL_PRESSURE_RATIO {
+        current / 2
+    } else {
+        current
+    };
+
+    next.clamp(min_interval, max_interval)
+}
+
+/// Publishes the entries expiring under a key pattern to a stream, configured with `--expiry-stream
+/// PATTERN=STREAM`, so applications can react to expirations by reading the stream instead of polling keys.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpiryStream
+{
+    /// The keys matched, every key starting with the prefix if `prefix` is set.
+    pattern: String,
+    prefix: bool,
+    /// The stream expired entries are published to.
+    stream: DbKey,
+}
+
+impl ExpiryStream
+{
+    /// Returns whether expired entries of `key` are published to the stream.
+    pub fn matches(&self, key: &str) -> bool
+    {
+        if self.prefix {
+            key.starts_with(&self.pattern)
+        } else {
+            key == self.pattern
+        }
+    }
+}
+
+impl FromStr for ExpiryStream
+{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>
+    {
+        let (pattern, stream) = s
+            .split_once('=')
+            .filter(|(pattern, stream)| !pattern.is_empty() && !stream.is_empty())
+            .ok_or_else(|| format!("expected PATTERN=STREAM, found '{}'", s))?;
+
+        let (pattern, prefix) = match pattern.strip_suffix('*') {
+            Some(prefix) => (prefix, true),
+            None => (pattern, false),
+        };
+        Ok(Self {
+            pattern: pattern.to_string(),
+            prefix,
+            stream: stream.to_string(),
+        })
+    }
+}
+
+/// A job that periodically cleans up expired entries in the database.
+///
+/// Each run acquires a write lock on the database, checks the expiration times of all entries, and removes those
+/// that have expired based on their `expires_at` timestamp.
 ///
-/// The task will continue running indefinitely, ensuring that expired entries are regularly
-/// removed from the database without requiring manual intervention.
+/// The sweeper starts at `max_interval`, shortens the interval while many entries are found expired so they do
+/// not pile up, and backs off again once few are.
 ///
-/// # Arguments
+/// Removed entries matching an `--expiry-stream` pattern are published to its stream as `{ key, value,
+/// expired_at }` messages, once the database is unlocked again, and the derived keys computed from them are
+/// recomputed. Their tags are removed before the database is unlocked.
 ///
-/// * `db` - A reference to the database instance (`Database`) that the cleanup task operates on.
-/// * `check_interval` - The duration to wait between each cleanup iteration.
-pub async fn execute(db: Database, check_interval: Duration)
+/// Expired entries are not mirrored to the upstream server: it was sent the same expiration time with the value and
+/// expires the entry on its own.
+pub struct TtlSweep
+{
+    /// The engine holding the database the sweep operates on, and the streams expired entries are published to.
+    engine: Arc<DbEngine>,
+    /// How long to wait before the next sweep.
+    current: Duration,
+    /// The shortest duration to wait between two sweeps.
+    min_interval: Duration,
+    /// The longest duration to wait between two sweeps.
+    max_interval: Duration,
+}
+
+impl TtlSweep
 {
-    let mut interval = interval(check_interval);
-    let mut started = 0;
+    /// Creates the sweep of the database of `engine`, waiting between `min_interval` and `max_interval` between two
+    /// runs.
+    pub fn new(engine: Arc<DbEngine>, min_interval: Duration, max_interval: Duration) -> Self
+    {
+        Self {
+            engine,
+            current: max_interval,
+            min_interval,
+            max_interval,
+        }
+    }
+}
 
-    loop {
-        interval.tick().await;
+impl Job for TtlSweep
+{
+    fn run(&mut self) -> BoxFuture<'_, Reschedule>
+    {
+        async move {
+            // A bulk load asked for the database to be left alone until it ends
+            if self.engine.loads.pauses_background() {
+                return Reschedule::After(self.min_interval);
+            }
 
-        let mut db = db.write().await;
-        let now = Instant::now();
+            let mut expired: Vec<DbKey> = vec![];
+            let mut with_ttl = 0;
+            let rules = &self.engine.db_config.expiry_stream;
+            let mut published: Vec<Vec<JsonValue>> = vec![vec![]; rules.len()];
+            {
+                let mut db = self.engine.connection.write().await;
+                let now = unix_millis();
 
-        db.retain(|_, v| match v.expires_at() {
-            // Remove expired entries
-            Some(expiry) if now >= expiry => false,
-            // Keep non-expired entries
-            _ => true,
-        });
+                db.retain(|k, v| match v.expires_at {
+                    // Remove expired entries, keeping those to publish
+                    Some(expires_at) if v.is_expired(now) => {
+                        expired.push(k.clone());
+                        with_ttl += 1;
+                        for (rule, messages) in rules.iter().zip(published.iter_mut()) {
+                            if rule.matches(k) {
+                                messages.push(json!({ "key": k, "value": v.value, "expired_at": expires_at }));
+                            }
+                        }
+                        false
+                    }
+                    // Keep non-expired entries
+                    Some(_) => {
+                        with_ttl += 1;
+                        true
+                    }
+                    None => true,
+                });
 
-        if started == 0 {
-            debug!("Starting TTL Service");
-            started = 1;
-        } else {
-            debug!("TTL Service Ticked")
+                let mut tags = self.engine.tags.write().await;
+                for key in &expired {
+                    tags.remove_key(key);
+                }
+            }
+
+            for (rule, messages) in rules.iter().zip(published) {
+                if !messages.is_empty() {
+                    self.engine.streams.publish(&rule.stream, messages).await;
+                }
+            }
+            refresh_derived(&self.engine, &expired).await;
+
+            let expired = expired.len();
+            self.current = next_interval(self.current, expired, with_ttl, self.min_interval, self.max_interval);
+            debug!(
+                "TTL Service Ticked, removed {} entries, next sweep in {:?}",
+                expired, self.current
+            );
+            Reschedule::After(self.current)
         }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use clap::Parser;
+
+    use super::*;
+    use crate::cli::Cli;
+    use crate::commands::stream::xrange_command;
+    use crate::commands::CommandArgs;
+    use crate::protocol::DbValue;
+
+    #[test]
+    fn test_next_interval()
+    {
+        let min = Duration::from_secs(1);
+        let max = Duration::from_secs(60);
+
+        // Backs off while nothing expires
+        assert_eq!(
+            next_interval(Duration::from_secs(20), 0, 100, min, max),
+            Duration::from_secs(40)
+        );
+        assert_eq!(next_interval(Duration::from_secs(40), 0, 100, min, max), max);
+
+        // Speeds up while many entries expire
+        assert_eq!(
+            next_interval(Duration::from_secs(60), 50, 100, min, max),
+            Duration::from_secs(30)
+        );
+        assert_eq!(next_interval(Duration::from_secs(1), 50, 100, min, max), min);
+
+        // Holds steady in between
+        assert_eq!(
+            next_interval(Duration::from_secs(30), 5, 100, min, max),
+            Duration::from_secs(30)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_expiry_streams()
+    {
+        assert!("session:*".parse::<ExpiryStream>().is_err());
+        assert!("=expired".parse::<ExpiryStream>().is_err());
+
+        let engine = Arc::new(DbEngine::new(Cli::parse_from([
+            "phoenix-db",
+            "--expiry-stream",
+            "session:*=expired:sessions",
+            "--expiry-stream",
+            "lock=expired:locks",
+        ])));
+        {
+            let mut db = engine.connection.write().await;
+            for (key, expires_at) in [
+                ("session:1", Some(1)),
+                ("session:2", None),
+                ("lock", Some(1)),
+                ("user:1", Some(1)),
+            ] {
+                db.insert(
+                    key.to_string(),
+                    DbValue {
+                        value: json!(key),
+                        expires_at,
+                    },
+                );
+            }
+        }
+
+        let mut sweep = TtlSweep::new(engine.clone(), Duration::from_secs(1), Duration::from_secs(60));
+        sweep.run().await;
+        assert_eq!(engine.connection.read().len(), 1);
+
+        let args = CommandArgs::WithArgs(Some("expired:sessions".to_string()), vec![]);
+        let sessions = xrange_command(args, engine.clone()).await.unwrap().value.unwrap();
+        assert_eq!(sessions.as_array().unwrap().len(), 1);
+        assert_eq!(
+            sessions[0]["value"],
+            json!({ "key": "session:1", "value": "session:1", "expired_at": 1 })
+        );
+        let args = CommandArgs::WithArgs(Some("expired:locks".to_string()), vec![]);
+        let locks = xrange_command(args, engine.clone()).await.unwrap().value.unwrap();
+        assert_eq!(locks[0]["value"]["key"], "lock");
     }
 }
@@ -0,0 +1,27 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use tracing::debug;
+
+use crate::protocol::DbEngine;
+use crate::services::scheduler::Reschedule;
+
+/// How long a chunked upload may go without receiving a command before it is discarded.
+pub const UPLOAD_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Discards abandoned chunked uploads, run periodically by the scheduler.
+///
+/// Clients that disconnect in the middle of a `PUT BEGIN` / `PUT CHUNK` sequence never send `PUT COMMIT`
+/// or `PUT ABORT`, so their partially received data would otherwise be kept in memory forever.
+///
+/// # Arguments
+///
+/// * `engine` - The database engine holding the pending uploads.
+pub async fn discard_stale(engine: Arc<DbEngine>) -> Reschedule
+{
+    let removed = engine.uploads.remove_stale(UPLOAD_IDLE_TIMEOUT).await;
+    if removed > 0 {
+        debug!("Discarded {} abandoned uploads", removed);
+    }
+    Reschedule::Interval
+}
@@ -0,0 +1,98 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::time::sleep;
+use tracing::{debug, error, warn};
+
+use crate::client::Client;
+use crate::diagnostics::{DiagnosticKind, DiagnosticLevel};
+use crate::protocol::{DbEngine, NetActions, NetCommand};
+use crate::services::manager::Service;
+use crate::upstream::UpstreamWrite;
+
+/// The number of times a write is sent to the upstream server before it is dropped.
+const WRITE_ATTEMPTS: u32 = 3;
+
+/// How long to wait before sending a write again.
+const WRITE_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// A background task that mirrors the writes made on this server to the upstream server, in order.
+///
+/// # Arguments
+///
+/// * `engine` - The database engine holding the upstream tier. The task returns straight away if there is none.
+/// * `service` - The service the writes are mirrored as runs of, holding them back while it is stopped.
+pub async fn execute(engine: Arc<DbEngine>, service: Arc<Service>)
+{
+    let Some(upstream) = &engine.upstream else {
+        return;
+    };
+    let Some(mut writes) = upstream.take_writes() else {
+        return;
+    };
+
+    debug!("Starting write-behind service for upstream {}", upstream.addr());
+    let mut client = Client::new(upstream.addr());
+
+    while let Some(write) = writes.recv().await {
+        let command = match &write {
+            UpstreamWrite::Insert(key, value) => NetCommand {
+                name: "INSERT",
+                keys: Some(vec![key]),
+                values: Some(vec![value.clone()]),
+                ttls: None,
+                args: None,
+                tags: None,
+                batch: None,
+                durability: None,
+                fields: None,
+                progress: None,
+                id: None,
+            },
+            UpstreamWrite::Delete(key) => NetCommand {
+                name: "DELETE",
+                keys: Some(vec![key]),
+                values: None,
+                ttls: None,
+                args: None,
+                tags: None,
+                batch: None,
+                durability: None,
+                fields: None,
+                progress: None,
+                id: None,
+            },
+        };
+
+        // Writes wait while the service is stopped, queued in order
+        service
+            .run(async {
+                let mut attempt = 1;
+                loop {
+                    match client.request(&command).await {
+                        Ok(response) => {
+                            // Deleting a key the upstream server does not have is not a failure worth retrying
+                            if response.action == NetActions::Error {
+                                debug!("Upstream rejected {:?}: {:?}", write, response.error);
+                            }
+                            break;
+                        }
+                        Err(e) if attempt >= WRITE_ATTEMPTS => {
+                            let message = format!("Dropped {:?} after {} attempts: {}", write, attempt, e);
+                            error!("{}", message);
+                            engine
+                                .diagnostics
+                                .emit(DiagnosticLevel::Error, DiagnosticKind::Upstream, message);
+                            break;
+                        }
+                        Err(e) => {
+                            warn!("Failed to mirror {:?} to upstream: {}", write, e);
+                            attempt += 1;
+                            sleep(WRITE_RETRY_DELAY).await;
+                        }
+                    }
+                }
+            })
+            .await;
+    }
+}
@@ -0,0 +1,563 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use serde_json::{Map, Number};
+
+use crate::crypto::Cipher;
+use crate::protocol::{expiry_after, DbEngine, DbKey, DbValue, JsonValue};
+use crate::store::DbMap;
+use crate::upgrade::{Format, Migration};
+
+// A snapshot starts with the magic bytes and the format version, followed by the number of entries and the
+// entries themselves. Since version 2 each entry is framed by its length and followed by its CRC32, so a
+// corrupted snapshot is refused when it is loaded instead of being partially restored. Lengths and unsigned
+// integers are written as LEB128 varints, other numbers as 8 little endian bytes, and JSON values as a one byte
+// tag followed by their content. The expiry of an entry is written as a one byte flag: 0 for none, 2 followed by
+// a UNIX time in milliseconds, or 1 followed by the seconds and nanoseconds left, as written by older versions.
+// Since version 3 the version is followed by a flags byte. With the encrypted flag set each entry is written as a
+// random nonce followed by its AES-256-GCM ciphertext, the checksum then covering the ciphertext.
+
+/// The bytes every snapshot starts with.
+const MAGIC: &[u8; 5] = b"PHXDB";
+
+/// The version of the snapshot format written by [`write`].
+const VERSION: u8 = 3;
+
+/// The version of the snapshot format without the flags byte, which can still be read.
+const VERSION_UNFLAGGED: u8 = 2;
+
+/// The version of the snapshot format without checksums, which can still be read.
+const VERSION_UNCHECKED: u8 = 1;
+
+/// The flag set when the entries are encrypted.
+const FLAG_ENCRYPTED: u8 = 1;
+
+/// The snapshot format, upgraded on start up.
+pub const FORMAT: Format = Format {
+    name: "snapshot",
+    current: VERSION,
+    version: read_version,
+    migrations: &[
+        Migration {
+            from: VERSION_UNCHECKED,
+            description: "add a checksum to every entry",
+            run: rewrite,
+        },
+        Migration {
+            from: VERSION_UNFLAGGED,
+            description: "add the flags byte",
+            run: rewrite,
+        },
+    ],
+};
+
+/// How deeply values can nest, so a corrupted snapshot cannot overflow the stack while being read.
+const MAX_DEPTH: usize = 128;
+
+const TAG_NULL: u8 = 0;
+const TAG_FALSE: u8 = 1;
+const TAG_TRUE: u8 = 2;
+const TAG_UNSIGNED: u8 = 3;
+const TAG_SIGNED: u8 = 4;
+const TAG_FLOAT: u8 = 5;
+const TAG_STRING: u8 = 6;
+const TAG_ARRAY: u8 = 7;
+const TAG_OBJECT: u8 = 8;
+
+const NO_EXPIRY: u8 = 0;
+const EXPIRES_IN: u8 = 1;
+const EXPIRES_AT: u8 = 2;
+
+/// Writes a snapshot of the database to `path`.
+///
+/// The snapshot is first written next to the destination and then renamed over it, so a crash while saving
+/// never leaves a partially written snapshot behind.
+///
+/// # Arguments
+///
+/// * `map` - The version of the database to save.
+/// * `path` - Where to write the snapshot.
+/// * `cipher` - Encrypts the entries, if given.
+///
+/// # Returns
+///
+/// The size of the snapshot in bytes.
+pub fn write(map: &DbMap, path: &Path, cipher: Option<&Cipher>) -> io::Result<u64>
+{
+    let temp_path = path.with_extension("tmp");
+
+    let file = File::create(&temp_path)?;
+    let mut writer = BufWriter::new(file);
+    writer.write_all(MAGIC)?;
+    writer.write_all(&[VERSION, if cipher.is_some() { FLAG_ENCRYPTED } else { 0 }])?;
+    write_varint(&mut writer, map.len() as u64)?;
+    let mut record = vec![];
+    for (key, data) in map.iter() {
+        record.clear();
+        write_entry(&mut record, key, data)?;
+        if let Some(cipher) = cipher {
+            record = cipher.seal(&record)?;
+        }
+        write_varint(&mut writer, record.len() as u64)?;
+        writer.write_all(&record)?;
+        writer.write_all(&crc32(&record).to_le_bytes())?;
+    }
+    writer.flush()?;
+
+    let file = writer.into_inner().map_err(|e| e.into_error())?;
+    file.sync_all()?;
+    let size = file.metadata()?.len();
+
+    fs::rename(&temp_path, path)?;
+    Ok(size)
+}
+
+/// Reads a snapshot written by [`write`] from `path`.
+///
+/// # Arguments
+///
+/// * `path` - Where the snapshot was written.
+/// * `cipher` - Decrypts the entries, required if the snapshot is encrypted.
+///
+/// # Returns
+///
+/// The version of the database held by the snapshot.
+pub fn read(path: &Path, cipher: Option<&Cipher>) -> io::Result<DbMap>
+{
+    let mut reader = BufReader::new(File::open(path)?);
+
+    let version = read_header(&mut reader)?;
+    if !(VERSION_UNCHECKED..=VERSION).contains(&version) {
+        return Err(invalid("unsupported snapshot version"));
+    }
+    let flags = if version >= VERSION { read_u8(&mut reader)? } else { 0 };
+    let cipher = match (flags & FLAG_ENCRYPTED != 0, cipher) {
+        (true, None) => return Err(invalid("the snapshot is encrypted and no encryption key is configured")),
+        (true, cipher) => cipher,
+        (false, _) => None,
+    };
+
+    let mut map = DbMap::new();
+    for index in 0..read_varint(&mut reader)? {
+        let (key, data) = if version == VERSION_UNCHECKED {
+            read_entry(&mut reader)?
+        } else {
+            let len = read_varint(&mut reader)?;
+            let mut record = vec![];
+            reader.by_ref().take(len).read_to_end(&mut record)?;
+            let mut checksum = [0; 4];
+            reader.read_exact(&mut checksum)?;
+            if record.len() as u64 != len || crc32(&record) != u32::from_le_bytes(checksum) {
+                return Err(invalid(&format!("entry {} of the snapshot is corrupted", index)));
+            }
+            if let Some(cipher) = cipher {
+                record = cipher.open(&record)?;
+            }
+            read_entry(&mut record.as_slice())?
+        };
+        map.insert(key, data);
+    }
+
+    Ok(map)
+}
+
+/// Reads the magic bytes and the format version a snapshot starts with.
+fn read_header(reader: &mut impl Read) -> io::Result<u8>
+{
+    let mut header = [0; MAGIC.len() + 1];
+    reader.read_exact(&mut header)?;
+    if &header[..MAGIC.len()] != MAGIC {
+        return Err(invalid("not a phoenix-db snapshot"));
+    }
+    Ok(header[MAGIC.len()])
+}
+
+/// Returns the format version of the snapshot at `path`.
+fn read_version(path: &Path) -> io::Result<u8>
+{
+    read_header(&mut File::open(path)?)
+}
+
+/// Rewrites the snapshot at `path` in the current version, unencrypted as the snapshots of older versions are.
+fn rewrite(path: &Path) -> io::Result<()>
+{
+    write(&read(path, None)?, path, None).map(|_| ())
+}
+
+/// Loads the snapshot at `path` into the database, if there is one.
+///
+/// # Arguments
+///
+/// * `engine` - The database engine to load the snapshot into.
+/// * `path` - Where the snapshot was written.
+///
+/// # Returns
+///
+/// The number of entries loaded, or `None` if there is no snapshot at `path`.
+pub async fn restore(engine: &DbEngine, path: &Path) -> io::Result<Option<usize>>
+{
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let path = path.to_path_buf();
+    let cipher = engine.cipher.clone();
+    let map = tokio::task::spawn_blocking(move || read(&path, cipher.as_deref()))
+        .await
+        .map_err(io::Error::other)??;
+
+    let entries = map.len();
+    let mut db_write = engine.connection.write().await;
+    for (key, data) in map {
+        db_write.insert(key, data);
+    }
+    Ok(Some(entries))
+}
+
+/// Writes a key along with its value in the snapshot format, also used by the write-ahead log.
+pub(crate) fn write_entry(writer: &mut impl Write, key: &str, data: &DbValue) -> io::Result<()>
+{
+    write_str(writer, key)?;
+    write_value(writer, &data.value)?;
+    match data.expires_at {
+        Some(expires_at) => {
+            writer.write_all(&[EXPIRES_AT])?;
+            write_varint(writer, expires_at)
+        }
+        None => writer.write_all(&[NO_EXPIRY]),
+    }
+}
+
+/// Reads a key along with its value written by [`write_entry`].
+pub(crate) fn read_entry(reader: &mut impl Read) -> io::Result<(DbKey, DbValue)>
+{
+    let key = read_str(reader)?;
+    let value = read_value(reader, 0)?;
+    let expires_at = match read_u8(reader)? {
+        NO_EXPIRY => None,
+        EXPIRES_AT => Some(read_varint(reader)?),
+        EXPIRES_IN => {
+            // Older versions wrote the time left, counted from when the entry is read back
+            let secs = read_varint(reader)?;
+            let nanos = read_varint(reader)?;
+            Some(expiry_after(Duration::new(secs, nanos as u32)))
+        }
+        _ => return Err(invalid("unknown expiry flag")),
+    };
+
+    Ok((key, DbValue { value, expires_at }))
+}
+
+/// The CRC32 (IEEE) lookup table, one entry per byte value.
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut crc = n as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb88320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[n] = crc;
+        n += 1;
+    }
+    table
+};
+
+/// Computes the CRC32 (IEEE) of `bytes`, the checksum used by zip and gzip.
+fn crc32(bytes: &[u8]) -> u32
+{
+    !bytes.iter().fold(!0, |crc: u32, byte| {
+        CRC32_TABLE[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+pub(crate) fn invalid(message: &str) -> io::Error
+{
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn write_varint(writer: &mut impl Write, mut n: u64) -> io::Result<()>
+{
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            return writer.write_all(&[byte]);
+        }
+        writer.write_all(&[byte | 0x80])?;
+    }
+}
+
+fn read_varint(reader: &mut impl Read) -> io::Result<u64>
+{
+    let mut n = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = read_u8(reader)?;
+        n |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(n);
+        }
+    }
+    Err(invalid("varint is too long"))
+}
+
+pub(crate) fn read_u8(reader: &mut impl Read) -> io::Result<u8>
+{
+    let mut byte = [0];
+    reader.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+pub(crate) fn write_str(writer: &mut impl Write, s: &str) -> io::Result<()>
+{
+    write_varint(writer, s.len() as u64)?;
+    writer.write_all(s.as_bytes())
+}
+
+pub(crate) fn read_str(reader: &mut impl Read) -> io::Result<String>
+{
+    let len = read_varint(reader)?;
+    let mut bytes = Vec::new();
+    // `take` keeps a corrupted length from allocating more than the file holds
+    reader.take(len).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    String::from_utf8(bytes).map_err(|_| invalid("string is not valid UTF-8"))
+}
+
+fn write_value(writer: &mut impl Write, value: &JsonValue) -> io::Result<()>
+{
+    match value {
+        JsonValue::Null => writer.write_all(&[TAG_NULL]),
+        JsonValue::Bool(false) => writer.write_all(&[TAG_FALSE]),
+        JsonValue::Bool(true) => writer.write_all(&[TAG_TRUE]),
+        JsonValue::Number(n) => {
+            if let Some(n) = n.as_u64() {
+                writer.write_all(&[TAG_UNSIGNED])?;
+                write_varint(writer, n)
+            } else if let Some(n) = n.as_i64() {
+                writer.write_all(&[TAG_SIGNED])?;
+                writer.write_all(&n.to_le_bytes())
+            } else {
+                writer.write_all(&[TAG_FLOAT])?;
+                writer.write_all(&n.as_f64().unwrap_or_default().to_le_bytes())
+            }
+        }
+        JsonValue::String(s) => {
+            writer.write_all(&[TAG_STRING])?;
+            write_str(writer, s)
+        }
+        JsonValue::Array(items) => {
+            writer.write_all(&[TAG_ARRAY])?;
+            write_varint(writer, items.len() as u64)?;
+            items.iter().try_for_each(|item| write_value(writer, item))
+        }
+        JsonValue::Object(fields) => {
+            writer.write_all(&[TAG_OBJECT])?;
+            write_varint(writer, fields.len() as u64)?;
+            fields.iter().try_for_each(|(name, value)| {
+                write_str(writer, name)?;
+                write_value(writer, value)
+            })
+        }
+    }
+}
+
+fn read_value(reader: &mut impl Read, depth: usize) -> io::Result<JsonValue>
+{
+    if depth > MAX_DEPTH {
+        return Err(invalid("value is nested too deeply"));
+    }
+
+    let value = match read_u8(reader)? {
+        TAG_NULL => JsonValue::Null,
+        TAG_FALSE => JsonValue::Bool(false),
+        TAG_TRUE => JsonValue::Bool(true),
+        TAG_UNSIGNED => read_varint(reader)?.into(),
+        TAG_SIGNED => {
+            let mut bytes = [0; 8];
+            reader.read_exact(&mut bytes)?;
+            i64::from_le_bytes(bytes).into()
+        }
+        TAG_FLOAT => {
+            let mut bytes = [0; 8];
+            reader.read_exact(&mut bytes)?;
+            Number::from_f64(f64::from_le_bytes(bytes))
+                .map(JsonValue::Number)
+                .ok_or_else(|| invalid("number is not finite"))?
+        }
+        TAG_STRING => JsonValue::String(read_str(reader)?),
+        TAG_ARRAY => {
+            let len = read_varint(reader)?;
+            let items = (0..len).map(|_| read_value(reader, depth + 1)).collect::<io::Result<_>>()?;
+            JsonValue::Array(items)
+        }
+        TAG_OBJECT => {
+            let len = read_varint(reader)?;
+            let mut fields = Map::new();
+            for _ in 0..len {
+                let name = read_str(reader)?;
+                fields.insert(name, read_value(reader, depth + 1)?);
+            }
+            JsonValue::Object(fields)
+        }
+        _ => return Err(invalid("unknown value tag")),
+    };
+
+    Ok(value)
+}
+
+#[cfg(test)]
+mod test
+{
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_write_snapshot()
+    {
+        let path = std::env::temp_dir().join(format!("phoenix-db-test-{}.snapshot", std::process::id()));
+        let mut map = DbMap::new();
+        map.insert(
+            "key".to_string(),
+            DbValue {
+                value: json!("value"),
+                expires_at: None,
+            },
+        );
+        map.insert(
+            "document".to_string(),
+            DbValue {
+                value: json!({ "n": [0, 300, -5, 1.5, u64::MAX], "flags": [true, false, null], "name": "jamal" }),
+                expires_at: Some(1_700_000_000_000),
+            },
+        );
+
+        let size = write(&map, &path, None).unwrap();
+        let contents = fs::read(&path).unwrap();
+        let saved = read(&path, None).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(size, contents.len() as u64);
+        assert!(contents.starts_with(MAGIC));
+        assert_eq!(saved, map);
+    }
+
+    #[test]
+    fn test_write_encrypted_snapshot()
+    {
+        let path = std::env::temp_dir().join(format!("phoenix-db-test-{}-encrypted.snapshot", std::process::id()));
+        let mut map = DbMap::new();
+        map.insert(
+            "secret".to_string(),
+            DbValue {
+                value: json!("hunter2"),
+                expires_at: None,
+            },
+        );
+        let cipher = Cipher::new(&[1; 32]);
+
+        write(&map, &path, Some(&cipher)).unwrap();
+        let contents = fs::read(&path).unwrap();
+        let without_key = read(&path, None);
+        let with_other_key = read(&path, Some(&Cipher::new(&[2; 32])));
+        let saved = read(&path, Some(&cipher)).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert!(!contents.windows(7).any(|window| window == b"hunter2"));
+        assert_eq!(without_key.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(with_other_key.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(saved, map);
+    }
+
+    #[test]
+    fn test_crc32()
+    {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+    }
+
+    #[test]
+    fn test_read_detects_corruption()
+    {
+        let path = std::env::temp_dir().join(format!("phoenix-db-test-{}-corrupt.snapshot", std::process::id()));
+        let mut map = DbMap::new();
+        map.insert(
+            "key".to_string(),
+            DbValue {
+                value: json!("value"),
+                expires_at: None,
+            },
+        );
+        write(&map, &path, None).unwrap();
+
+        // Flip a bit of the value
+        let mut contents = fs::read(&path).unwrap();
+        let position = contents.len() - 6;
+        contents[position] ^= 1;
+        fs::write(&path, &contents).unwrap();
+
+        let result = read(&path, None);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_read_unchecked_version()
+    {
+        let path = std::env::temp_dir().join(format!("phoenix-db-test-{}-v1.snapshot", std::process::id()));
+        let data = DbValue {
+            value: json!([1, "two"]),
+            expires_at: None,
+        };
+
+        let mut contents = MAGIC.to_vec();
+        contents.push(VERSION_UNCHECKED);
+        write_varint(&mut contents, 1).unwrap();
+        write_entry(&mut contents, "key", &data).unwrap();
+        fs::write(&path, &contents).unwrap();
+
+        let map = read(&path, None).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(map.get("key"), Some(&data));
+    }
+
+    #[test]
+    fn test_read_relative_expiry()
+    {
+        // Entries written by older versions hold the time left rather than when they expire
+        let mut contents = vec![];
+        write_str(&mut contents, "key").unwrap();
+        write_value(&mut contents, &json!(1)).unwrap();
+        contents.push(EXPIRES_IN);
+        write_varint(&mut contents, 60).unwrap();
+        write_varint(&mut contents, 0).unwrap();
+
+        let before = expiry_after(Duration::from_secs(60));
+        let (key, data) = read_entry(&mut contents.as_slice()).unwrap();
+        let after = expiry_after(Duration::from_secs(60));
+
+        assert_eq!(key, "key");
+        assert!((before..=after).contains(&data.expires_at.unwrap()));
+    }
+
+    #[test]
+    fn test_read_rejects_other_files()
+    {
+        let path = std::env::temp_dir().join(format!("phoenix-db-test-{}-invalid.snapshot", std::process::id()));
+        fs::write(&path, b"{\"key\": 1}").unwrap();
+
+        let result = read(&path, None);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,424 @@
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+use tokio::sync::{Mutex, MutexGuard};
+
+use crate::metrics::LatencyHistogram;
+use crate::prefix::{PrefixStat, PrefixTree};
+use crate::protocol::{json_size, DbKey, DbValue};
+
+/// The map holding every entry of the database.
+///
+/// This is a persistent map: cloning it is `O(1)` and an update only copies the nodes on the path to the
+/// changed entry, leaving the rest shared with older versions.
+pub type DbMap = im::HashMap<DbKey, DbValue>;
+
+/// The keys of the database in lexicographic order, kept when the server runs with `--ordered-keys`.
+///
+/// Like `DbMap`, cloning it is `O(1)` so every write batch updates a private copy.
+pub type KeyOrder = im::OrdSet<DbKey>;
+
+/// The generation of the last change to every key, kept when the server runs with `--incremental-backups`.
+///
+/// Every write batch is a new generation. Deleted keys keep the generation of their deletion until a backup has
+/// recorded it, see [`Store::forget_deleted`].
+#[derive(Debug, Default)]
+struct ChangeLog
+{
+    /// The generation of the last write batch.
+    generation: u64,
+    /// The generation each key was last inserted or deleted in.
+    keys: HashMap<DbKey, u64>,
+}
+
+/// The keys changed after a given generation, along with the map they were read from.
+pub struct ChangeSet
+{
+    /// The map as of `generation`.
+    pub map: Arc<DbMap>,
+    /// The keys inserted or deleted since the requested generation. Deleted keys are missing from `map`.
+    pub keys: Vec<DbKey>,
+    /// The generation of the last write batch included.
+    pub generation: u64,
+}
+
+/// The storage behind the database, optimized for read-mostly workloads.
+///
+/// Readers never wait: `read` hands out the current immutable snapshot of the map. Writers are serialized
+/// through a mutex, apply their changes to a private copy of the map and publish it as the new snapshot once
+/// their guard is dropped. Readers holding an older snapshot keep seeing it until they drop it.
+#[derive(Debug)]
+pub struct Store
+{
+    /// The most recently published version of the map.
+    current: ArcSwap<DbMap>,
+    /// Serializes writers so that no update is lost between copying and publishing the map.
+    writer: Mutex<()>,
+    /// How long acquiring `writer` took. Readers never wait, so there is no such histogram for them.
+    write_waits: LatencyHistogram,
+    /// Key counts and sizes by prefix, updated by writers as they change entries.
+    prefixes: std::sync::Mutex<PrefixTree>,
+    /// The keys in order, if the store keeps them. Published right after the map.
+    ordered: Option<ArcSwap<KeyOrder>>,
+    /// When each key last changed, if the store tracks it. Only read and written while holding `writer`.
+    changes: Option<std::sync::Mutex<ChangeLog>>,
+}
+
+impl Default for Store
+{
+    fn default() -> Self
+    {
+        Self::new(false, false)
+    }
+}
+
+impl Store
+{
+    /// Creates an empty store, keeping its keys in order if `ordered` is set and tracking when each key changed
+    /// if `track_changes` is set.
+    ///
+    /// Ordered keys enable range queries and ordered scans, and tracked changes enable incremental backups, each
+    /// at the cost of maintaining another structure on every write.
+    pub fn new(ordered: bool, track_changes: bool) -> Self
+    {
+        Self {
+            current: ArcSwap::from_pointee(DbMap::new()),
+            writer: Mutex::new(()),
+            write_waits: LatencyHistogram::default(),
+            prefixes: std::sync::Mutex::new(PrefixTree::default()),
+            ordered: ordered.then(|| ArcSwap::from_pointee(KeyOrder::new())),
+            changes: track_changes.then(|| std::sync::Mutex::new(ChangeLog::default())),
+        }
+    }
+
+    /// Returns a snapshot of the map. It does not change if writes happen while it is held.
+    pub fn read(&self) -> Arc<DbMap>
+    {
+        self.current.load_full()
+    }
+
+    /// Returns the keys in order, or `None` if the store does not keep them.
+    ///
+    /// The keys are published after the map, so when both are read the keys should be read first: the map is
+    /// then at least as recent, and a key deleted in between is simply missing from it.
+    pub fn read_ordered(&self) -> Option<Arc<KeyOrder>>
+    {
+        self.ordered.as_ref().map(|ordered| ordered.load_full())
+    }
+
+    /// Returns how long acquiring the writer lock took, showing how much writers hold each other back.
+    pub fn write_waits(&self) -> &LatencyHistogram
+    {
+        &self.write_waits
+    }
+
+    /// Waits for the current writer to finish, recording how long that took.
+    async fn lock_writer(&self) -> MutexGuard<'_, ()>
+    {
+        let started = std::time::Instant::now();
+        let lock = self.writer.lock().await;
+        self.write_waits.record(started.elapsed());
+        lock
+    }
+
+    /// Returns the key counts and sizes of every prefix up to `depth` segments long, largest first.
+    pub fn prefix_stats(&self, depth: usize) -> Vec<PrefixStat>
+    {
+        self.prefixes.lock().unwrap().stats(depth)
+    }
+
+    /// Returns the keys changed after `generation`, or `None` if the store does not track changes.
+    ///
+    /// Waits for the current writer, so the result never holds part of a write batch.
+    pub async fn changes_since(&self, generation: u64) -> Option<ChangeSet>
+    {
+        let changes = self.changes.as_ref()?;
+        let _lock = self.lock_writer().await;
+
+        let changes = changes.lock().unwrap();
+        Some(ChangeSet {
+            map: self.read(),
+            keys: changes
+                .keys
+                .iter()
+                .filter(|(_, changed)| **changed > generation)
+                .map(|(key, _)| key.clone())
+                .collect(),
+            generation: changes.generation,
+        })
+    }
+
+    /// Stops remembering the keys deleted up to `generation`, once a backup has recorded their deletion.
+    pub async fn forget_deleted(&self, generation: u64)
+    {
+        if let Some(changes) = &self.changes {
+            let _lock = self.lock_writer().await;
+            let map = self.read();
+            changes
+                .lock()
+                .unwrap()
+                .keys
+                .retain(|key, changed| *changed > generation || map.contains_key(key));
+        }
+    }
+
+    /// Waits for other writers to finish and returns a guard used to update the map.
+    /// Every change made through the guard is published at once when it is dropped.
+    pub async fn write(&self) -> StoreWriteGuard<'_>
+    {
+        let lock = self.lock_writer().await;
+        let map = DbMap::clone(&self.current.load());
+        let keys = self.ordered.as_ref().map(|ordered| KeyOrder::clone(&ordered.load()));
+        let generation = self.changes.as_ref().map_or(0, |changes| {
+            let mut changes = changes.lock().unwrap();
+            changes.generation += 1;
+            changes.generation
+        });
+
+        StoreWriteGuard {
+            store: self,
+            map,
+            keys,
+            generation,
+            _lock: lock,
+        }
+    }
+}
+
+/// A pending batch of changes to a `Store`, published when dropped.
+pub struct StoreWriteGuard<'a>
+{
+    store: &'a Store,
+    map: DbMap,
+    keys: Option<KeyOrder>,
+    generation: u64,
+    _lock: MutexGuard<'a, ()>,
+}
+
+impl StoreWriteGuard<'_>
+{
+    /// Records that `key` changed in this batch, if the store tracks changes.
+    fn changed(&self, key: &str)
+    {
+        if let Some(changes) = &self.store.changes {
+            changes.lock().unwrap().keys.insert(key.to_string(), self.generation);
+        }
+    }
+
+    /// Inserts an entry, returning the one it replaced.
+    pub fn insert(&mut self, key: DbKey, value: DbValue) -> Option<DbValue>
+    {
+        let mut prefixes = self.store.prefixes.lock().unwrap();
+        prefixes.add(&key, entry_size(&key, &value));
+
+        self.changed(&key);
+        let previous = self.map.insert(key.clone(), value);
+        match &previous {
+            Some(previous) => prefixes.remove(&key, entry_size(&key, previous)),
+            None => {
+                if let Some(keys) = &mut self.keys {
+                    keys.insert(key);
+                }
+            }
+        }
+        previous
+    }
+
+    /// Removes an entry, returning it if it existed.
+    pub fn remove(&mut self, key: &str) -> Option<DbValue>
+    {
+        let previous = self.map.remove(key);
+        if let Some(previous) = &previous {
+            self.changed(key);
+            self.store.prefixes.lock().unwrap().remove(key, entry_size(key, previous));
+            if let Some(keys) = &mut self.keys {
+                keys.remove(key);
+            }
+        }
+        previous
+    }
+
+    /// Keeps only the entries for which `keep` returns `true`.
+    pub fn retain<F>(&mut self, mut keep: F)
+    where
+        F: FnMut(&DbKey, &DbValue) -> bool,
+    {
+        let mut prefixes = self.store.prefixes.lock().unwrap();
+        let mut changes = self.store.changes.as_ref().map(|changes| changes.lock().unwrap());
+        let generation = self.generation;
+        let keys = &mut self.keys;
+        self.map.retain(|key, value| {
+            let kept = keep(key, value);
+            if !kept {
+                if let Some(changes) = &mut changes {
+                    changes.keys.insert(key.clone(), generation);
+                }
+                prefixes.remove(key, entry_size(key, value));
+                if let Some(keys) = keys {
+                    keys.remove(key);
+                }
+            }
+            kept
+        });
+    }
+}
+
+/// Returns the size an entry is accounted for in the prefix statistics.
+fn entry_size(key: &str, value: &DbValue) -> u64
+{
+    (key.len() + json_size(&value.value)) as u64
+}
+
+impl Deref for StoreWriteGuard<'_>
+{
+    type Target = DbMap;
+
+    fn deref(&self) -> &Self::Target
+    {
+        &self.map
+    }
+}
+
+impl Drop for StoreWriteGuard<'_>
+{
+    fn drop(&mut self)
+    {
+        // The writer lock is still held here, so no other writer can publish in between.
+        self.store.current.store(Arc::new(std::mem::take(&mut self.map)));
+        if let (Some(ordered), Some(keys)) = (&self.store.ordered, self.keys.take()) {
+            ordered.store(Arc::new(keys));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use serde_json::json;
+
+    use super::*;
+
+    fn value(n: i32) -> DbValue
+    {
+        DbValue {
+            value: json!(n),
+            expires_at: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_writes_are_published_on_drop()
+    {
+        let store = Store::default();
+
+        let mut db_write = store.write().await;
+        db_write.insert("key".to_string(), value(1));
+
+        // Readers do not see a batch until it is published
+        assert!(store.read().get("key").is_none());
+
+        drop(db_write);
+        assert_eq!(store.read().get("key"), Some(&value(1)));
+    }
+
+    #[tokio::test]
+    async fn test_snapshots_are_isolated_from_later_writes()
+    {
+        let store = Store::default();
+        store.write().await.insert("key".to_string(), value(1));
+
+        let snapshot = store.read();
+        store.write().await.insert("key".to_string(), value(2));
+
+        assert_eq!(snapshot.get("key"), Some(&value(1)));
+        assert_eq!(store.read().get("key"), Some(&value(2)));
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_writers_do_not_lose_updates()
+    {
+        let store = Arc::new(Store::default());
+
+        let tasks: Vec<_> = (0..8)
+            .map(|i| {
+                let store = store.clone();
+                tokio::spawn(async move {
+                    for j in 0..50 {
+                        store.write().await.insert(format!("{}:{}", i, j), value(j));
+                    }
+                })
+            })
+            .collect();
+
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        assert_eq!(store.read().len(), 8 * 50);
+    }
+
+    #[tokio::test]
+    async fn test_ordered_keys()
+    {
+        let store = Store::new(true, false);
+
+        let mut db_write = store.write().await;
+        for key in ["b", "c", "a", "d"] {
+            db_write.insert(key.to_string(), value(1));
+        }
+        db_write.remove("c");
+        drop(db_write);
+        store.write().await.retain(|key, _| key != "d");
+
+        let keys: Vec<DbKey> = store.read_ordered().unwrap().iter().cloned().collect();
+        assert_eq!(keys, ["a", "b"]);
+        assert!(Store::default().read_ordered().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_changes_since()
+    {
+        let store = Store::new(false, true);
+        store.write().await.insert("a".to_string(), value(1));
+        store.write().await.insert("b".to_string(), value(1));
+        let generation = store.changes_since(0).await.unwrap().generation;
+
+        let mut db_write = store.write().await;
+        db_write.insert("c".to_string(), value(1));
+        db_write.remove("a");
+        drop(db_write);
+
+        let mut changes = store.changes_since(generation).await.unwrap();
+        changes.keys.sort();
+        assert_eq!(changes.keys, ["a", "c"]);
+
+        // Deletions are forgotten once recorded, while inserted keys keep their generation
+        store.forget_deleted(changes.generation).await;
+        let mut changes = store.changes_since(0).await.unwrap();
+        changes.keys.sort();
+        assert_eq!(changes.keys, ["b", "c"]);
+        assert!(Store::default().changes_since(0).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_writes_update_prefix_stats()
+    {
+        let store = Store::default();
+
+        let mut db_write = store.write().await;
+        db_write.insert("user:1".to_string(), value(1));
+        db_write.insert("user:2".to_string(), value(2));
+        db_write.insert("user:2".to_string(), value(22));
+        db_write.remove("user:1");
+        drop(db_write);
+
+        let stats = store.prefix_stats(1);
+        assert_eq!(stats.len(), 1);
+        assert_eq!((stats[0].keys, stats[0].bytes), (1, "user:2".len() as u64 + 2));
+
+        store.write().await.retain(|_, _| false);
+        assert!(store.prefix_stats(1).is_empty());
+    }
+}
@@ -0,0 +1,41 @@
+use std::sync::Arc;
+
+use clap::Parser;
+use tokio::net::TcpListener;
+
+use crate::cli::Cli;
+use crate::protocol::DbEngine;
+
+/// Creates a new in-memory database engine with the default options.
+pub fn create_fake_engine() -> Arc<DbEngine>
+{
+    create_engine_with(&[])
+}
+
+/// Creates a new in-memory database engine with the given command line options.
+pub fn create_engine_with(args: &[&str]) -> Arc<DbEngine>
+{
+    let args = std::iter::once("phoenix-db").chain(args.iter().copied());
+    Arc::new(DbEngine::new(Cli::parse_from(args)))
+}
+
+/// Creates a new in-memory database engine whose files are read and written in the temporary directory.
+pub fn create_engine_in_temp_dir() -> Arc<DbEngine>
+{
+    let data_dir = std::env::temp_dir();
+    create_engine_with(&["--data-dir", data_dir.to_str().unwrap()])
+}
+
+/// Starts a server for `engine` on a random port and returns its address.
+pub async fn start_server(engine: Arc<DbEngine>) -> String
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(crate::services::tcp::execute(stream, engine.clone()));
+        }
+    });
+    addr
+}
@@ -0,0 +1,105 @@
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+use std::sync::Arc;
+
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::TlsAcceptor;
+
+/// Builds the TLS acceptor client connections are wrapped in, from a PEM certificate chain and private key.
+///
+/// # Arguments
+///
+/// * `cert_path` - The file holding the certificate chain, the certificate of the server first.
+/// * `key_path` - The file holding the private key of the certificate, in PKCS#1, PKCS#8 or SEC1 format.
+///
+/// # Returns
+///
+/// The acceptor, or an error if the files cannot be read or do not hold a matching certificate and key.
+pub fn acceptor(cert_path: &Path, key_path: &Path) -> io::Result<TlsAcceptor>
+{
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(cert_path)?)).collect::<Result<Vec<_>, _>>()?;
+    if certs.is_empty() {
+        return Err(invalid_data(format!("no certificate found in {}", cert_path.display())));
+    }
+    let key = rustls_pemfile::private_key(&mut BufReader::new(File::open(key_path)?))?
+        .ok_or_else(|| invalid_data(format!("no private key found in {}", key_path.display())))?;
+
+    let config = ServerConfig::builder_with_provider(Arc::new(tokio_rustls::rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .and_then(|builder| builder.with_no_client_auth().with_single_cert(certs, key))
+        .map_err(|e| invalid_data(e.to_string()))?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+fn invalid_data(message: String) -> io::Error
+{
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod test
+{
+    use std::fs;
+
+    use clap::Parser;
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_rustls::rustls::crypto::ring::default_provider;
+    use tokio_rustls::rustls::pki_types::ServerName;
+    use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+    use tokio_rustls::TlsConnector;
+
+    use super::*;
+    use crate::cli::Cli;
+    use crate::frame::{read_frame, write_frame, MAX_FRAME_LEN};
+    use crate::protocol::{DbEngine, JsonValue};
+    use crate::services::tcp;
+
+    #[tokio::test]
+    async fn test_acceptor()
+    {
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let cert_path = std::env::temp_dir().join(format!("phoenix-db-test-{}.crt", std::process::id()));
+        let key_path = std::env::temp_dir().join(format!("phoenix-db-test-{}.key", std::process::id()));
+        fs::write(&cert_path, certified.cert.pem()).unwrap();
+        fs::write(&key_path, certified.signing_key.serialize_pem()).unwrap();
+
+        assert!(acceptor(&key_path, &key_path).is_err());
+        let acceptor = acceptor(&cert_path, &key_path).unwrap();
+        fs::remove_file(&cert_path).unwrap();
+        fs::remove_file(&key_path).unwrap();
+
+        let engine = Arc::new(DbEngine::new(Cli::parse_from(["phoenix-db"])));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            tcp::execute(acceptor.accept(stream).await.unwrap(), engine).await
+        });
+
+        let mut roots = RootCertStore::empty();
+        roots.add(certified.cert.der().clone()).unwrap();
+        let config = ClientConfig::builder_with_provider(Arc::new(default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let mut stream = TlsConnector::from(Arc::new(config))
+            .connect(
+                ServerName::try_from("localhost").unwrap(),
+                TcpStream::connect(addr).await.unwrap(),
+            )
+            .await
+            .unwrap();
+
+        // Commands run over TLS as they do over a plain connection
+        write_frame(&mut stream, br#"{"name":"INSERT","keys":["a"],"values":[{"value":1}]}"#)
+            .await
+            .unwrap();
+        read_frame(&mut stream, MAX_FRAME_LEN).await.unwrap().unwrap();
+        write_frame(&mut stream, br#"{"name":"LOOKUP","keys":["a"]}"#).await.unwrap();
+        let response: JsonValue =
+            serde_json::from_slice(&read_frame(&mut stream, MAX_FRAME_LEN).await.unwrap().unwrap()).unwrap();
+        assert_eq!(response["value"], 1);
+    }
+}
@@ -0,0 +1,115 @@
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+use tracing::info;
+
+use crate::cli::Cli;
+use crate::{snapshot, wal};
+
+/// A versioned file format, along with the migrations upgrading its older versions.
+pub struct Format
+{
+    /// What the files are, used in logs and errors.
+    pub name: &'static str,
+    /// The version written by this server.
+    pub current: u8,
+    /// Reads the version of the file at a path.
+    pub version: fn(&Path) -> io::Result<u8>,
+    /// The migrations from each older version to the next one.
+    pub migrations: &'static [Migration],
+}
+
+/// A step upgrading a file from one version of its format to a later one.
+pub struct Migration
+{
+    /// The version upgraded from.
+    pub from: u8,
+    /// What the migration changes, used in logs.
+    pub description: &'static str,
+    /// Rewrites the file at a path in a later version.
+    pub run: fn(&Path) -> io::Result<()>,
+}
+
+/// Upgrades the snapshot and the write-ahead log to the versions written by this server, before they are loaded.
+pub fn upgrade_files(args: &Cli) -> io::Result<()>
+{
+    upgrade(&snapshot::FORMAT, &args.snapshot_path)?;
+    if let Some(wal_path) = &args.wal_path {
+        upgrade(&wal::FORMAT, wal_path)?;
+    }
+    Ok(())
+}
+
+/// Upgrades the file at `path` to the current version of `format`, one migration at a time.
+///
+/// The original file is first copied next to it as `<path>.v<version>.bak`, so it can be put back if a migration
+/// goes wrong. Each migration replaces the file at once, so a crash leaves it at a version the next start upgrades
+/// from.
+///
+/// # Returns
+///
+/// The version the file was upgraded from, or `None` if there is no file at `path` or it is already current.
+pub fn upgrade(format: &Format, path: &Path) -> io::Result<Option<u8>>
+{
+    if !path.exists() {
+        return Ok(None);
+    }
+    let original = (format.version)(path)?;
+    if original == format.current {
+        return Ok(None);
+    }
+    if original > format.current {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "the {} is version {}, newer than the version {} this server supports",
+                format.name, original, format.current
+            ),
+        ));
+    }
+
+    let backup = backup_path(path, original);
+    if !backup.exists() {
+        fs::copy(path, &backup)?;
+    }
+    info!("Backed up the {} {} to {}", format.name, path.display(), backup.display());
+
+    // A migration may rewrite the file in the current version rather than the next one
+    let mut version = original;
+    while version < format.current {
+        let Some(migration) = format.migrations.iter().find(|migration| migration.from == version) else {
+            return Err(io::Error::other(format!(
+                "no migration upgrades the {} from version {}",
+                format.name, version
+            )));
+        };
+        (migration.run)(path)?;
+
+        let upgraded = (format.version)(path)?;
+        if upgraded <= version {
+            return Err(io::Error::other(format!(
+                "the migration of the {} from version {} did not upgrade it",
+                format.name, version
+            )));
+        }
+        info!(
+            "Upgraded the {} {} from version {} to {}: {}",
+            format.name,
+            path.display(),
+            version,
+            upgraded,
+            migration.description
+        );
+        version = upgraded;
+    }
+
+    Ok(Some(original))
+}
+
+/// Returns where the original of a file upgraded from `version` is kept.
+pub fn backup_path(path: &Path, version: u8) -> PathBuf
+{
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".v{}.bak", version));
+    PathBuf::from(name)
+}
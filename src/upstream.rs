@@ -0,0 +1,239 @@
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+use tokio::sync::mpsc::{self, Receiver, Sender};
+use tracing::warn;
+
+use crate::client::Client;
+use crate::protocol::{DbKey, DbValue, JsonValue, NetActions, NetCommand};
+
+/// The number of writes that can wait to be mirrored before new ones are dropped.
+const WRITE_BEHIND_CAPACITY: usize = 10_000;
+
+/// The number of idle connections kept open to the upstream server for lookups.
+const LOOKUP_POOL_SIZE: usize = 8;
+
+/// A write waiting to be mirrored to the upstream server.
+#[derive(Debug)]
+pub enum UpstreamWrite
+{
+    Insert(DbKey, DbValue),
+    Delete(DbKey),
+}
+
+/// Another phoenix-db server used as the tier behind this one.
+///
+/// Lookups missing here are read through to the upstream server, and every committed insert and delete is mirrored
+/// to it in the background by the write-behind service, so clients never wait for the upstream server on writes.
+#[derive(Debug)]
+pub struct Upstream
+{
+    /// The `host:port` of the upstream server.
+    addr: String,
+    /// Queues writes for the write-behind service.
+    writes: Sender<UpstreamWrite>,
+    /// The end of the queue, until the write-behind service takes it.
+    pending: Mutex<Option<Receiver<UpstreamWrite>>>,
+    /// Idle connections to the upstream server, reused by the following lookups.
+    lookups: Mutex<Vec<Client>>,
+    /// The keys being looked up, removed when a write to them is queued in the meantime.
+    reading: Mutex<HashSet<DbKey>>,
+}
+
+impl Upstream
+{
+    /// Creates the upstream tier for the server at `addr`.
+    pub fn new(addr: String) -> Self
+    {
+        let (writes, pending) = mpsc::channel(WRITE_BEHIND_CAPACITY);
+
+        Self {
+            addr,
+            writes,
+            pending: Mutex::new(Some(pending)),
+            lookups: Mutex::new(Vec::new()),
+            reading: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Returns the `host:port` of the upstream server.
+    pub fn addr(&self) -> &str
+    {
+        &self.addr
+    }
+
+    /// Takes the queue of writes to mirror. Only the first call returns it.
+    pub fn take_writes(&self) -> Option<Receiver<UpstreamWrite>>
+    {
+        self.pending.lock().unwrap().take()
+    }
+
+    /// Queues a write to be mirrored to the upstream server.
+    /// The write is dropped with a warning if the queue is full, which means the upstream server cannot keep up.
+    pub fn write_behind(&self, write: UpstreamWrite)
+    {
+        let (UpstreamWrite::Insert(key, _) | UpstreamWrite::Delete(key)) = &write;
+        self.reading.lock().unwrap().remove(key);
+        if let Err(e) = self.writes.try_send(write) {
+            warn!("Dropped a write for upstream {}: {}", self.addr, e);
+        }
+    }
+
+    /// Looks up a key on the upstream server.
+    /// The lookup reuses an idle connection if there is one, and keeps its connection for the following lookups.
+    ///
+    /// Once the value is kept or discarded, [`Upstream::finish_lookup`] must be called with the same key.
+    pub async fn lookup(&self, key: &str) -> Result<Option<JsonValue>, String>
+    {
+        self.reading.lock().unwrap().insert(key.to_string());
+
+        let command = NetCommand {
+            name: "LOOKUP",
+            keys: Some(vec![key]),
+            values: None,
+            ttls: None,
+            args: None,
+            tags: None,
+            batch: None,
+            durability: None,
+            fields: None,
+            progress: None,
+            id: None,
+        };
+
+        let pooled = self.lookups.lock().unwrap().pop();
+        let mut client = pooled.unwrap_or_else(|| Client::new(self.addr.as_str()));
+        let response = match client.request(&command).await {
+            Ok(response) => response,
+            Err(e) => {
+                self.finish_lookup(key);
+                return Err(e);
+            }
+        };
+
+        let mut lookups = self.lookups.lock().unwrap();
+        if lookups.len() < LOOKUP_POOL_SIZE {
+            lookups.push(client);
+        }
+        drop(lookups);
+
+        match response.action {
+            NetActions::Command | NetActions::Event | NetActions::Chunk | NetActions::Progress => Ok(response.value),
+            NetActions::Error | NetActions::ShuttingDown => {
+                self.finish_lookup(key);
+                Err(response.error.unwrap_or_else(|| "unknown error".to_string()))
+            }
+        }
+    }
+
+    /// Ends the lookup of a key.
+    ///
+    /// # Returns
+    ///
+    /// `false` if a write to the key was queued since the lookup started, in which case the value looked up is
+    /// stale and must not be kept.
+    pub fn finish_lookup(&self, key: &str) -> bool
+    {
+        self.reading.lock().unwrap().remove(key)
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use clap::Parser;
+    use serde_json::json;
+
+    use crate::cli::Cli;
+    use crate::commands::delete::delete_command;
+    use crate::commands::insert::insert_command;
+    use crate::commands::lookup::lookup_command;
+    use crate::commands::range::setrange_command;
+    use crate::commands::{CommandArgs, CommandParams};
+    use crate::protocol::{DbEngine, DbValue};
+    use crate::testing::start_server;
+    use crate::upstream::UpstreamWrite;
+
+    #[tokio::test]
+    async fn test_read_through_and_write_behind()
+    {
+        let upstream = Arc::new(DbEngine::new(Cli::parse_from(["phoenix-db"])));
+        let addr = start_server(upstream.clone()).await;
+        let engine = Arc::new(DbEngine::new(Cli::parse_from(["phoenix-db", "--upstream", &addr])));
+        let service = engine.services.register("upstream");
+        tokio::spawn(crate::services::upstream::execute(engine.clone(), service));
+
+        let value = DbValue {
+            value: json!("upstream"),
+            expires_at: None,
+        };
+        upstream.connection.write().await.insert("cold".to_string(), value.clone());
+
+        // Missing keys are read through and kept until they expire
+        let args = CommandArgs::Single(Some("cold".to_string()), None);
+        let response = lookup_command(args, engine.clone()).await.unwrap();
+        assert_eq!(response.value, Some(json!("upstream")));
+        let kept = engine.connection.read().get("cold").cloned().unwrap();
+        assert_eq!(kept.value, value.value);
+        assert!(kept.expires_at.is_some());
+
+        // The lookup connection is kept for the next lookups
+        let tier = engine.upstream.as_ref().unwrap();
+        assert_eq!(tier.lookups.lock().unwrap().len(), 1);
+
+        // A write queued while a key is looked up makes the value looked up stale
+        tier.lookup("cold").await.unwrap();
+        tier.write_behind(UpstreamWrite::Delete("cold".to_string()));
+        assert!(!tier.finish_lookup("cold"));
+        tier.lookup("cold").await.unwrap();
+        assert!(tier.finish_lookup("cold"));
+
+        // Writes reach the upstream server in the background
+        let args = CommandArgs::Single(Some("new".to_string()), Some(value.clone()));
+        insert_command(args, engine.clone()).await.unwrap();
+
+        for _ in 0..100 {
+            if upstream.connection.read().contains_key("new") {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("the write was not mirrored to the upstream server");
+    }
+
+    #[tokio::test]
+    async fn test_only_committed_writes_are_mirrored()
+    {
+        let engine = Arc::new(DbEngine::new(Cli::parse_from(["phoenix-db", "--upstream", "127.0.0.1:1"])));
+        let mut writes = engine.upstream.as_ref().unwrap().take_writes().unwrap();
+
+        let value = DbValue {
+            value: json!("value"),
+            expires_at: None,
+        };
+        engine.connection.write().await.insert("present".to_string(), value);
+
+        // Keys missing from the database are not deleted upstream
+        let params = ["present", "missing"]
+            .into_iter()
+            .map(|key| CommandParams {
+                key: Some(key.to_string()),
+                value: None,
+                expires_at: None,
+            })
+            .collect();
+        delete_command(CommandArgs::Many(params), engine.clone()).await.unwrap();
+        assert!(matches!(writes.try_recv(), Ok(UpstreamWrite::Delete(key)) if key == "present"));
+        assert!(writes.try_recv().is_err());
+
+        // Commands other than INSERT and DELETE are mirrored as well
+        let args = CommandArgs::WithArgs(Some("range".to_string()), vec![json!(0), json!("ab")]);
+        setrange_command(args, engine.clone()).await.unwrap();
+        assert!(
+            matches!(writes.try_recv(), Ok(UpstreamWrite::Insert(key, data)) if data.value == json!("ab") && key == "range")
+        );
+    }
+}
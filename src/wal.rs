@@ -0,0 +1,887 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::broadcast;
+use tracing::{error, info};
+
+use crate::cli::Durability;
+use crate::crypto::Cipher;
+use crate::diagnostics::{DiagnosticKind, DiagnosticLevel};
+use crate::protocol::{unix_millis, DbEngine, DbKey, DbValue};
+use crate::snapshot;
+use crate::store::{DbMap, StoreWriteGuard};
+use crate::upgrade::{Format, Migration};
+
+/// The bytes every log starts with, followed by the format version and, since version 3, a flags byte.
+const MAGIC: &[u8; 5] = b"PHXWL";
+
+/// The version of the log format written by [`Wal`]. Logs of version 1 start with their first record.
+const VERSION: u8 = 3;
+
+/// The length of the header holding the magic bytes, the version and the flags.
+const HEADER_LEN: u64 = MAGIC.len() as u64 + 2;
+
+/// The flag set when the payload of every record is encrypted.
+const FLAG_ENCRYPTED: u8 = 1;
+
+/// The write-ahead log format, upgraded on start up.
+pub const FORMAT: Format = Format {
+    name: "write-ahead log",
+    current: VERSION,
+    version: read_version,
+    migrations: &[
+        Migration {
+            from: 1,
+            description: "add a header holding the format version",
+            run: add_header,
+        },
+        Migration {
+            from: 2,
+            description: "add the flags byte to the header",
+            run: add_flags,
+        },
+    ],
+};
+
+const TAG_INSERT: u8 = 1;
+const TAG_DELETE: u8 = 2;
+
+/// The largest record the log holds, in bytes. Appending a larger one fails, so a longer length read back is damaged.
+const MAX_RECORD_LEN: u64 = 1024 * 1024 * 1024;
+
+/// The number of appended batches buffered for each feed subscriber before it is considered too far behind.
+const FEED_CAPACITY: usize = 1024;
+
+/// A write to the database, as recorded in the write-ahead log.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WalRecord
+{
+    Insert(DbKey, DbValue),
+    Delete(DbKey),
+}
+
+/// The write-ahead log, recording every write before it is applied so it can be replayed after a crash.
+///
+/// Each record is framed by its length and a checksum, so a record torn by a crash is detected and dropped
+/// when the log is replayed. The log is disabled unless the server is started with `--wal-path`.
+#[derive(Debug, Default)]
+pub struct Wal
+{
+    /// Where the log is written, if it is enabled.
+    path: Option<PathBuf>,
+    /// When appended records are synced to disk.
+    durability: Option<Durability>,
+    /// The open log, appended to by the writes from the blocking thread pool.
+    state: Option<Arc<Mutex<WalState>>>,
+    /// Every appended batch of records, sent to the replicas following the log with `SYNC`.
+    feed: Option<broadcast::Sender<Arc<[WalRecord]>>>,
+    /// Encrypts the appended records, if an encryption key is configured.
+    cipher: Option<Arc<Cipher>>,
+    /// When the log was last synced to disk, in seconds since the UNIX epoch, or 0 if it never was.
+    last_sync: Arc<AtomicU64>,
+}
+
+/// The open log along with the progress of a compaction.
+#[derive(Debug)]
+struct WalState
+{
+    /// Appends records to the log.
+    writer: BufWriter<File>,
+    /// The size of the log in bytes.
+    size: u64,
+    /// Whether records were appended since the log was last synced.
+    dirty: bool,
+    /// The records appended since a running compaction or checkpoint started, copied to the rewritten log once it
+    /// is written.
+    compacting: Option<Vec<u8>>,
+}
+
+/// The size of the write-ahead log before and after it was compacted.
+#[derive(Debug, Clone, Copy)]
+pub struct CompactReport
+{
+    /// The size of the log in bytes before it was compacted.
+    pub size_before: u64,
+    /// The size of the log in bytes once compacted.
+    pub size_after: u64,
+}
+
+impl WalRecord
+{
+    fn encode(&self) -> io::Result<Vec<u8>>
+    {
+        let mut payload = vec![];
+        match self {
+            WalRecord::Insert(key, data) => {
+                payload.push(TAG_INSERT);
+                snapshot::write_entry(&mut payload, key, data)?;
+            }
+            WalRecord::Delete(key) => {
+                payload.push(TAG_DELETE);
+                snapshot::write_str(&mut payload, key)?;
+            }
+        }
+        Ok(payload)
+    }
+
+    fn decode(payload: &[u8]) -> io::Result<WalRecord>
+    {
+        let mut reader = Cursor::new(payload);
+        match snapshot::read_u8(&mut reader)? {
+            TAG_INSERT => {
+                let (key, data) = snapshot::read_entry(&mut reader)?;
+                Ok(WalRecord::Insert(key, data))
+            }
+            TAG_DELETE => Ok(WalRecord::Delete(snapshot::read_str(&mut reader)?)),
+            _ => Err(snapshot::invalid("unknown record")),
+        }
+    }
+
+    /// Returns the key the record writes.
+    pub fn key(&self) -> &DbKey
+    {
+        let (WalRecord::Insert(key, _) | WalRecord::Delete(key)) = self;
+        key
+    }
+
+    /// Applies the record to the database.
+    pub fn apply(self, db_write: &mut StoreWriteGuard<'_>)
+    {
+        match self {
+            WalRecord::Insert(key, data) => {
+                db_write.insert(key, data);
+            }
+            WalRecord::Delete(key) => {
+                db_write.remove(&key);
+            }
+        }
+    }
+}
+
+/// Replays the log at `path` into the database, then opens it so the following writes are recorded.
+///
+/// Records are replayed on top of the snapshot loaded at start up. The log is cut back every time a snapshot is
+/// saved, so it only holds the writes made since the last snapshot started. Writes made while it was being saved
+/// are part of both and applied again in the same order, which leaves the database as it was.
+///
+/// # Arguments
+///
+/// * `engine` - The database engine to recover, before it starts serving clients.
+/// * `path` - Where the log is written.
+///
+/// # Returns
+///
+/// The number of records replayed.
+pub async fn recover(engine: &mut DbEngine, path: &Path) -> io::Result<usize>
+{
+    let replay_path = path.to_path_buf();
+    let cipher = engine.cipher.clone();
+    let records = tokio::task::spawn_blocking(move || Wal::replay(&replay_path, cipher.as_deref()))
+        .await
+        .map_err(io::Error::other)??;
+
+    let count = records.len();
+    {
+        let mut db_write = engine.connection.write().await;
+        for record in records {
+            record.apply(&mut db_write);
+        }
+    }
+
+    engine.wal = Wal::open(path, engine.db_config.durability, engine.cipher.clone())?;
+    // A log written before encryption was turned on or off is rewritten the way new records are
+    if read_encrypted(path)? != engine.cipher.is_some() {
+        engine.wal.rewrite(&engine.connection.read())?;
+    }
+    Ok(count)
+}
+
+/// Rewrites the write-ahead log to hold a single insert per live key, dropping overwritten and deleted entries.
+///
+/// The live keys are taken from the database when the compaction starts, the log is then rewritten next to the
+/// current one while writes keep being appended to it. Those writes are also copied to the new log, which finally
+/// replaces the current one.
+///
+/// # Arguments
+///
+/// * `engine` - The database engine holding the log.
+///
+/// # Returns
+///
+/// The size of the log before and after the compaction.
+pub async fn compact(engine: Arc<DbEngine>) -> io::Result<CompactReport>
+{
+    let Some(state) = &engine.wal.state else {
+        return Err(io::Error::other("the write-ahead log is disabled"));
+    };
+
+    let map = {
+        // Holding the write lock keeps writers from appending between reading the map and starting the compaction
+        let _db_write = engine.connection.write().await;
+        let mut state = state.lock().unwrap();
+        if state.compacting.is_some() {
+            return Err(io::Error::other("the write-ahead log is already being compacted"));
+        }
+        state.compacting = Some(vec![]);
+        engine.connection.read()
+    };
+
+    let wal_engine = engine.clone();
+    let result = tokio::task::spawn_blocking(move || wal_engine.wal.rewrite(&map))
+        .await
+        .map_err(io::Error::other)
+        .and_then(|result| result);
+
+    match &result {
+        Ok(report) => {
+            let message = format!(
+                "Compacted the write-ahead log from {} to {} bytes",
+                report.size_before, report.size_after
+            );
+            info!("{}", message);
+            engine
+                .diagnostics
+                .emit(DiagnosticLevel::Info, DiagnosticKind::Compaction, message);
+        }
+        Err(e) => {
+            state.lock().unwrap().compacting = None;
+            let message = format!("Failed to compact the write-ahead log: {}", e);
+            error!("{}", message);
+            engine
+                .diagnostics
+                .emit(DiagnosticLevel::Error, DiagnosticKind::Compaction, message);
+        }
+    }
+    result
+}
+
+/// Builds the error for a record of the log that is corrupted, rather than torn by a crash.
+fn corrupted(offset: u64) -> io::Error
+{
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "the write-ahead log is corrupted at byte {}, it must be repaired or removed",
+            offset
+        ),
+    )
+}
+
+/// Writes the header a log starts with.
+fn write_header(writer: &mut impl Write, encrypted: bool) -> io::Result<()>
+{
+    writer.write_all(MAGIC)?;
+    writer.write_all(&[VERSION, if encrypted { FLAG_ENCRYPTED } else { 0 }])
+}
+
+/// Parses the header at the start of a log, which logs of version 1 do not have.
+///
+/// # Returns
+///
+/// Whether the records are encrypted, and the length of the header.
+fn parse_header(bytes: &[u8]) -> io::Result<(bool, u64)>
+{
+    match bytes.strip_prefix(MAGIC.as_slice()) {
+        None => Ok((false, 0)),
+        Some([VERSION, flags, ..]) => Ok((flags & FLAG_ENCRYPTED != 0, HEADER_LEN)),
+        Some(_) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "unsupported write-ahead log version",
+        )),
+    }
+}
+
+/// Returns whether the records of the log at `path` are encrypted.
+fn read_encrypted(path: &Path) -> io::Result<bool>
+{
+    let mut header = vec![];
+    File::open(path)?.take(HEADER_LEN).read_to_end(&mut header)?;
+    parse_header(&header).map(|(encrypted, _)| encrypted)
+}
+
+/// Returns the format version of the log at `path`. An empty log is written in the current version once opened.
+fn read_version(path: &Path) -> io::Result<u8>
+{
+    let mut header = vec![];
+    File::open(path)?.take(MAGIC.len() as u64 + 1).read_to_end(&mut header)?;
+    Ok(match header.strip_prefix(MAGIC.as_slice()) {
+        Some([version]) => *version,
+        _ if header.is_empty() => VERSION,
+        _ => 1,
+    })
+}
+
+/// Rewrites a log of version 1 with the header of the current version in front of its records.
+fn add_header(path: &Path) -> io::Result<()>
+{
+    replace_header(path, 0)
+}
+
+/// Rewrites a log of version 2 with the header of the current version, which adds the flags byte.
+fn add_flags(path: &Path) -> io::Result<()>
+{
+    replace_header(path, MAGIC.len() as u64 + 1)
+}
+
+/// Rewrites a log with the header of the current version in place of its first `old_len` bytes.
+fn replace_header(path: &Path, old_len: u64) -> io::Result<()>
+{
+    let temp_path = path.with_extension("upgrade");
+
+    let mut writer = BufWriter::new(File::create(&temp_path)?);
+    write_header(&mut writer, false)?;
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(old_len))?;
+    io::copy(&mut file, &mut writer)?;
+    writer.flush()?;
+    writer.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+
+    fs::rename(&temp_path, path)
+}
+
+/// Writes a record framed by its length and checksum, encrypting its payload with `cipher` if given.
+/// Records larger than `MAX_RECORD_LEN` are refused.
+fn write_record(writer: &mut impl Write, record: &WalRecord, cipher: Option<&Cipher>) -> io::Result<()>
+{
+    let mut payload = record.encode()?;
+    if let Some(cipher) = cipher {
+        payload = cipher.seal(&payload)?;
+    }
+    if payload.len() as u64 > MAX_RECORD_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "a record of {} bytes is larger than the limit of {} bytes",
+                payload.len(),
+                MAX_RECORD_LEN
+            ),
+        ));
+    }
+    writer.write_all(&(payload.len() as u32).to_le_bytes())?;
+    writer.write_all(&checksum(&payload).to_le_bytes())?;
+    writer.write_all(&payload)
+}
+
+/// Records that a log was just synced to disk in its `last_sync` time.
+fn synced(last_sync: &AtomicU64)
+{
+    last_sync.store(unix_millis() / 1000, Ordering::Relaxed);
+}
+
+/// FNV-1a, enough to tell a torn record apart from a complete one.
+fn checksum(payload: &[u8]) -> u32
+{
+    payload
+        .iter()
+        .fold(0x811c9dc5, |hash: u32, byte| (hash ^ *byte as u32).wrapping_mul(0x01000193))
+}
+
+impl Wal
+{
+    /// Opens the log at `path` for appending, creating it if needed. Appended records are encrypted with `cipher`
+    /// if given.
+    pub fn open(path: &Path, durability: Durability, cipher: Option<Arc<Cipher>>) -> io::Result<Self>
+    {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let mut size = file.metadata()?.len();
+        let mut writer = BufWriter::new(file);
+        if size == 0 {
+            write_header(&mut writer, cipher.is_some())?;
+            writer.flush()?;
+            size = HEADER_LEN;
+        }
+        Ok(Self {
+            path: Some(path.to_path_buf()),
+            durability: Some(durability),
+            state: Some(Arc::new(Mutex::new(WalState {
+                writer,
+                size,
+                dirty: false,
+                compacting: None,
+            }))),
+            feed: Some(broadcast::channel(FEED_CAPACITY).0),
+            cipher,
+            last_sync: Arc::default(),
+        })
+    }
+
+    /// Returns the size of the log in bytes, or `None` if it is disabled.
+    pub fn size(&self) -> Option<u64>
+    {
+        self.state.as_ref().map(|state| state.lock().unwrap().size)
+    }
+
+    /// Returns when the log was last synced to disk, in seconds since the UNIX epoch, or `None` if it never was.
+    pub fn last_sync(&self) -> Option<u64>
+    {
+        Some(self.last_sync.load(Ordering::Relaxed)).filter(|time| *time > 0)
+    }
+
+    /// Records that the log was just synced to disk.
+    fn synced(&self)
+    {
+        synced(&self.last_sync);
+    }
+
+    /// Records writes before they are applied. Does nothing if the log is disabled.
+    ///
+    /// Callers hold the database write lock while appending, so the records are in the order the writes are
+    /// applied in. The records are written from the blocking thread pool, so the runtime keeps serving other
+    /// connections meanwhile. With `Durability::Always` they are synced to disk before returning, otherwise they
+    /// are left to [`Wal::sync`] or the operating system.
+    pub async fn append(&self, records: &[WalRecord]) -> io::Result<()>
+    {
+        let Some(state) = self.state.clone().filter(|_| !records.is_empty()) else {
+            return Ok(());
+        };
+
+        let mut bytes = vec![];
+        for record in records {
+            write_record(&mut bytes, record, self.cipher.as_deref())?;
+        }
+
+        let durability = self.durability;
+        let last_sync = self.last_sync.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut state = state.lock().unwrap();
+            state.writer.write_all(&bytes)?;
+            state.writer.flush()?;
+            state.size += bytes.len() as u64;
+            match durability {
+                Some(Durability::Always) => {
+                    state.writer.get_ref().sync_data()?;
+                    synced(&last_sync);
+                }
+                _ => state.dirty = true,
+            }
+            if let Some(compacting) = &mut state.compacting {
+                compacting.extend_from_slice(&bytes);
+            }
+            Ok::<_, io::Error>(())
+        })
+        .await
+        .map_err(io::Error::other)??;
+
+        if let Some(feed) = self.feed.as_ref().filter(|feed| feed.receiver_count() > 0) {
+            // Sending only fails once every subscriber is gone
+            let _ = feed.send(records.into());
+        }
+        Ok(())
+    }
+
+    /// Records a batch of writes in the log, then applies them to the database through `db_write`.
+    ///
+    /// Every write to the database goes through here, so the log and the replicas following it see each change in
+    /// the order it is applied. Nothing is applied if the records cannot be appended.
+    pub async fn commit(&self, db_write: &mut StoreWriteGuard<'_>, records: Vec<WalRecord>) -> io::Result<()>
+    {
+        self.append(&records).await?;
+        for record in records {
+            record.apply(db_write);
+        }
+        Ok(())
+    }
+
+    /// Subscribes to the batches of records appended from now on, or returns `None` if the log is disabled.
+    ///
+    /// Subscribing while holding the database write lock ensures no write is missed between reading the
+    /// database and following the log.
+    pub fn subscribe(&self) -> Option<broadcast::Receiver<Arc<[WalRecord]>>>
+    {
+        self.feed.as_ref().map(broadcast::Sender::subscribe)
+    }
+
+    /// Syncs the records appended since the last sync to disk, used by the `everysec` flusher.
+    ///
+    /// The sync happens without holding the log, so writes are not held back while it runs.
+    pub fn sync(&self) -> io::Result<()>
+    {
+        let Some(state) = &self.state else {
+            return Ok(());
+        };
+
+        let file = {
+            let mut state = state.lock().unwrap();
+            if !state.dirty {
+                return Ok(());
+            }
+            state.dirty = false;
+            state.writer.get_ref().try_clone()?
+        };
+        file.sync_data()?;
+        self.synced();
+        Ok(())
+    }
+
+    /// Syncs every record appended so far to disk, even if the `everysec` flusher is already syncing, used by the
+    /// writes asking to be acknowledged once synced.
+    pub fn sync_now(&self) -> io::Result<()>
+    {
+        let Some(state) = &self.state else {
+            return Ok(());
+        };
+
+        let file = {
+            let mut state = state.lock().unwrap();
+            state.dirty = false;
+            state.writer.get_ref().try_clone()?
+        };
+        file.sync_data()?;
+        self.synced();
+        Ok(())
+    }
+
+    /// Starts a checkpoint along with a snapshot of the database, so the log can be cut back to the records appended
+    /// from now on once the snapshot is saved.
+    ///
+    /// Callers hold the database write lock while starting it, and take the snapshot before releasing it.
+    ///
+    /// # Returns
+    ///
+    /// Whether the checkpoint started, which it does not if the log is disabled or being compacted.
+    pub fn begin_checkpoint(&self) -> bool
+    {
+        let Some(state) = &self.state else {
+            return false;
+        };
+
+        let mut state = state.lock().unwrap();
+        if state.compacting.is_some() {
+            return false;
+        }
+        state.compacting = Some(vec![]);
+        true
+    }
+
+    /// Ends a checkpoint started with [`Wal::begin_checkpoint`]. If the snapshot was saved, the log is rewritten
+    /// with only the records appended since the checkpoint started, the others being part of the snapshot.
+    pub fn end_checkpoint(&self, saved: bool) -> io::Result<()>
+    {
+        let result = match saved {
+            true => self.rewrite(&DbMap::default()).map(|_| ()),
+            false => Ok(()),
+        };
+        // A rewritten log is done with the records kept aside, otherwise they are dropped here
+        if !saved || result.is_err() {
+            if let Some(state) = &self.state {
+                state.lock().unwrap().compacting = None;
+            }
+        }
+        result
+    }
+
+    /// Returns when appended records are synced to disk, or `None` if the log is disabled.
+    pub fn durability(&self) -> Option<Durability>
+    {
+        self.durability
+    }
+
+    /// Writes the compacted log holding `map` next to the current one, then swaps it in.
+    fn rewrite(&self, map: &DbMap) -> io::Result<CompactReport>
+    {
+        let (Some(path), Some(state)) = (&self.path, &self.state) else {
+            return Err(io::Error::other("the write-ahead log is disabled"));
+        };
+        let temp_path = path.with_extension("compact");
+
+        let mut writer = BufWriter::new(File::create(&temp_path)?);
+        write_header(&mut writer, self.cipher.is_some())?;
+        for (key, data) in map.iter() {
+            write_record(
+                &mut writer,
+                &WalRecord::Insert(key.clone(), data.clone()),
+                self.cipher.as_deref(),
+            )?;
+        }
+        writer.flush()?;
+
+        // Appends are held back from here on, until the compacted log has replaced the current one
+        let mut state = state.lock().unwrap();
+        writer.write_all(state.compacting.as_deref().unwrap_or_default())?;
+        writer.flush()?;
+        let file = writer.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()?;
+        let size_after = file.metadata()?.len();
+        fs::rename(&temp_path, path)?;
+
+        let size_before = state.size;
+        state.writer = BufWriter::new(OpenOptions::new().append(true).open(path)?);
+        state.size = size_after;
+        state.dirty = false;
+        state.compacting = None;
+
+        Ok(CompactReport { size_before, size_after })
+    }
+
+    /// Reads the records of the log at `path`.
+    ///
+    /// Reading stops at a last record that is incomplete or does not match its checksum, which is what a crash in
+    /// the middle of an append leaves behind. The log is truncated there so later appends are not lost behind it.
+    /// A record followed by others that does not match its checksum or cannot be decoded is not torn but corrupted,
+    /// and fails the replay without changing the log, rather than dropping every write after it. So does a record
+    /// whose length reaches past the end of the log while more than `MAX_RECORD_LEN` bytes remain after it.
+    ///
+    /// The records of an encrypted log are decrypted with `cipher`, failing if it is not given or is not the one the
+    /// log was written with.
+    ///
+    /// # Returns
+    ///
+    /// The records in the order they were appended, or none if there is no log at `path`.
+    pub fn replay(path: &Path, cipher: Option<&Cipher>) -> io::Result<Vec<WalRecord>>
+    {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e),
+        };
+        let len = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+
+        let mut records = vec![];
+        let (encrypted, mut offset) = parse_header(reader.fill_buf()?)?;
+        reader.consume(offset as usize);
+        let cipher = match (encrypted, cipher) {
+            (true, None) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "the write-ahead log is encrypted and no encryption key is configured",
+                ))
+            }
+            (true, cipher) => cipher,
+            (false, _) => None,
+        };
+        loop {
+            let mut header = [0; 8];
+            if reader.read_exact(&mut header).is_err() {
+                break;
+            }
+            let size = u32::from_le_bytes(header[..4].try_into().unwrap()) as u64;
+            let expected = u32::from_le_bytes(header[4..].try_into().unwrap());
+
+            // A record reaching past the end was torn by a crash, unless no record is that large: then its length
+            // is damaged, and the records after it must not be dropped
+            let remaining = len - offset - 8;
+            if size > remaining {
+                if remaining > MAX_RECORD_LEN {
+                    return Err(corrupted(offset));
+                }
+                break;
+            }
+
+            let mut payload = vec![0; size as usize];
+            reader.read_exact(&mut payload)?;
+            if checksum(&payload) != expected {
+                if size == remaining {
+                    break;
+                }
+                return Err(corrupted(offset));
+            }
+            // A complete record that cannot be decrypted was written with another key, it is not torn
+            if let Some(cipher) = cipher {
+                payload = cipher.open(&payload)?;
+            }
+            records.push(WalRecord::decode(&payload).map_err(|_| corrupted(offset))?);
+            offset += 8 + size;
+        }
+
+        if offset < len {
+            OpenOptions::new().write(true).open(path)?.set_len(offset)?;
+        }
+        Ok(records)
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use serde_json::json;
+
+    use super::*;
+    use crate::store::Store;
+    use crate::upgrade::{backup_path, upgrade};
+
+    fn value(n: i32) -> DbValue
+    {
+        DbValue {
+            value: json!(n),
+            expires_at: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replay_drops_torn_records()
+    {
+        let path = std::env::temp_dir().join(format!("phoenix-db-test-{}.wal", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let records = vec![
+            WalRecord::Insert("a".to_string(), value(1)),
+            WalRecord::Insert("b".to_string(), value(2)),
+            WalRecord::Delete("a".to_string()),
+        ];
+        Wal::open(&path, Durability::Always, None)
+            .unwrap()
+            .append(&records)
+            .await
+            .unwrap();
+
+        // A crash in the middle of an append leaves part of a record behind
+        let mut contents = fs::read(&path).unwrap();
+        let complete = contents.len() as u64;
+        contents.extend_from_slice(&[9, 0, 0, 0, 1, 2]);
+        fs::write(&path, &contents).unwrap();
+
+        assert_eq!(Wal::replay(&path, None).unwrap(), records);
+        assert_eq!(fs::metadata(&path).unwrap().len(), complete);
+
+        let store = Store::default();
+        {
+            let mut db_write = store.write().await;
+            for record in Wal::replay(&path, None).unwrap() {
+                record.apply(&mut db_write);
+            }
+        }
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(store.read().get("b"), Some(&value(2)));
+        assert!(store.read().get("a").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_replay_fails_on_corrupted_records()
+    {
+        let path = std::env::temp_dir().join(format!("phoenix-db-corrupted-{}.wal", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let records = vec![
+            WalRecord::Insert("a".to_string(), value(1)),
+            WalRecord::Insert("b".to_string(), value(2)),
+        ];
+        Wal::open(&path, Durability::Always, None)
+            .unwrap()
+            .append(&records)
+            .await
+            .unwrap();
+
+        // A damaged record followed by others is not a torn append, the records after it are kept on disk
+        let mut contents = fs::read(&path).unwrap();
+        contents[HEADER_LEN as usize + 8] ^= 0xff;
+        fs::write(&path, &contents).unwrap();
+
+        let error = Wal::replay(&path, None).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(fs::read(&path).unwrap(), contents);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_replay_fails_on_damaged_lengths()
+    {
+        let path = std::env::temp_dir().join(format!("phoenix-db-damaged-length-{}.wal", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let records = vec![
+            WalRecord::Insert("a".to_string(), value(1)),
+            WalRecord::Insert("b".to_string(), value(2)),
+        ];
+        Wal::open(&path, Durability::Always, None)
+            .unwrap()
+            .append(&records)
+            .await
+            .unwrap();
+
+        // A length reaching past the end of a log longer than any record is damaged rather than torn
+        let mut contents = fs::read(&path).unwrap();
+        contents[HEADER_LEN as usize..HEADER_LEN as usize + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        fs::write(&path, &contents).unwrap();
+        let len = contents.len() as u64 + MAX_RECORD_LEN;
+        OpenOptions::new().write(true).open(&path).unwrap().set_len(len).unwrap();
+
+        let error = Wal::replay(&path, None).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(fs::metadata(&path).unwrap().len(), len);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_cuts_back_the_log()
+    {
+        let path = std::env::temp_dir().join(format!("phoenix-db-checkpoint-{}.wal", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let wal = Wal::open(&path, Durability::No, None).unwrap();
+        wal.append(&[WalRecord::Insert("a".to_string(), value(1))]).await.unwrap();
+        assert_eq!(wal.last_sync(), None);
+        wal.sync().unwrap();
+        assert!(wal.last_sync().is_some());
+
+        // A failed save keeps the whole log
+        assert!(wal.begin_checkpoint());
+        wal.end_checkpoint(false).unwrap();
+        assert_eq!(Wal::replay(&path, None).unwrap().len(), 1);
+
+        // Records appended while the snapshot is saved are kept, the older ones are part of the snapshot
+        assert!(wal.begin_checkpoint());
+        let later = vec![WalRecord::Delete("a".to_string())];
+        wal.append(&later).await.unwrap();
+        wal.end_checkpoint(true).unwrap();
+        wal.append(&[WalRecord::Insert("b".to_string(), value(2))]).await.unwrap();
+        assert_eq!(wal.size(), Some(fs::metadata(&path).unwrap().len()));
+
+        let records = Wal::replay(&path, None).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            records,
+            [
+                WalRecord::Delete("a".to_string()),
+                WalRecord::Insert("b".to_string(), value(2))
+            ]
+        );
+        assert!(!Wal::default().begin_checkpoint());
+    }
+
+    #[test]
+    fn test_upgrade_log_without_header()
+    {
+        let path = std::env::temp_dir().join(format!("phoenix-db-upgrade-{}.wal", std::process::id()));
+        let records = vec![
+            WalRecord::Insert("a".to_string(), value(1)),
+            WalRecord::Delete("b".to_string()),
+        ];
+        let mut contents = vec![];
+        for record in &records {
+            write_record(&mut contents, record, None).unwrap();
+        }
+        fs::write(&path, &contents).unwrap();
+
+        assert_eq!(upgrade(&FORMAT, &path).unwrap(), Some(1));
+        assert_eq!(upgrade(&FORMAT, &path).unwrap(), None);
+
+        let backup = backup_path(&path, 1);
+        assert_eq!(fs::read(&backup).unwrap(), contents);
+        assert!(fs::read(&path).unwrap().starts_with(MAGIC));
+        assert_eq!(Wal::replay(&path, None).unwrap(), records);
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&backup).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_receives_appended_records()
+    {
+        let path = std::env::temp_dir().join(format!("phoenix-db-feed-{}.wal", std::process::id()));
+        let wal = Wal::open(&path, Durability::No, None).unwrap();
+        let mut feed = wal.subscribe().unwrap();
+
+        let records = vec![
+            WalRecord::Insert("a".to_string(), value(1)),
+            WalRecord::Delete("b".to_string()),
+        ];
+        wal.append(&records).await.unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(*feed.try_recv().unwrap(), *records);
+        assert!(Wal::default().subscribe().is_none());
+    }
+}